@@ -1,7 +1,20 @@
+use std::{env, process::Command};
+
 fn main() {
   let toolchain_toml = include_str!("rust-toolchain.toml");
   let toolchain_table = toolchain_toml.parse::<toml::Table>().unwrap();
   let toolchain = toolchain_table["toolchain"].as_table().unwrap();
   let channel = toolchain["channel"].as_str().unwrap();
   println!("cargo:rustc-env=RUSTC_CHANNEL={channel}");
+
+  // The commit of the compiler this crate is built with, which must be the compiler
+  // that the plugin runs with.
+  let rustc = env::var("RUSTC").unwrap();
+  let output = Command::new(rustc).arg("-vV").output().unwrap();
+  let version = String::from_utf8(output.stdout).unwrap();
+  let commit_hash = version
+    .lines()
+    .find_map(|line| line.strip_prefix("commit-hash: "))
+    .unwrap_or("unknown");
+  println!("cargo:rustc-env=RUSTC_COMMIT_HASH={commit_hash}");
 }
@@ -1,4 +1,6 @@
+#![feature(rustc_private)]
+
 fn main() {
-  env_logger::init();
+  rustc_plugin::init_logging();
   rustc_plugin::driver_main(print_all_items::PrintAllItemsPlugin);
 }
@@ -0,0 +1,6 @@
+#![feature(rustc_private)]
+
+fn main() {
+  rustc_plugin::init_logging();
+  rustc_plugin::driver_main(print_all_items::plugin_group());
+}
@@ -2,21 +2,38 @@
 
 #![feature(rustc_private)]
 extern crate rustc_driver;
+extern crate rustc_hir;
 extern crate rustc_interface;
 extern crate rustc_middle;
 extern crate rustc_session;
+extern crate rustc_span;
 
-use std::{borrow::Cow, env, process::Command};
+use std::{
+  borrow::Cow,
+  process::Command,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    OnceLock,
+  },
+};
 
 use clap::Parser;
-use rustc_middle::ty::TyCtxt;
-use rustc_plugin::{CrateFilter, RustcPlugin, RustcPluginArgs, Utf8Path};
+use rustc_hir::ItemKind;
+use rustc_middle::{mir::BorrowCheckResult, ty::TyCtxt, util::Providers};
+use rustc_plugin::{
+  diagnostics::PluginDiagnostic, BuildMode, CrateFilter, CrateInfo, CrateResults,
+  PluginCallbacks, PluginGroup, RustcPlugin, RustcPluginArgs, Utf8Path,
+};
+use rustc_session::Session;
+use rustc_span::def_id::{LocalDefId, LOCAL_CRATE};
 use serde::{Deserialize, Serialize};
 
 // This struct is the plugin provided to the rustc_plugin framework,
 // and it must be exported for use by the CLI/driver binaries.
 pub struct PrintAllItemsPlugin;
 
+const PREFIX_VAR: &str = "PRINT_ALL_ITEMS_PREFIX";
+
 // To parse CLI arguments, we use Clap for this example. But that
 // detail is up to you.
 #[derive(Parser, Serialize, Deserialize)]
@@ -24,6 +41,48 @@ pub struct PrintAllItemsPluginArgs {
   #[arg(short, long)]
   allcaps: bool,
 
+  #[arg(long)]
+  json: bool,
+
+  #[arg(long)]
+  warn: bool,
+
+  #[arg(long)]
+  save: bool,
+
+  #[arg(long)]
+  only: Option<String>,
+
+  #[arg(long)]
+  build: bool,
+
+  #[arg(long)]
+  packages: bool,
+
+  #[arg(long)]
+  panic_on: Option<String>,
+
+  #[arg(long)]
+  hang_on: Option<String>,
+
+  #[arg(long)]
+  guarded_panic_on: Option<String>,
+
+  #[arg(long)]
+  prefix: Option<String>,
+
+  #[arg(long)]
+  kinds: bool,
+
+  #[arg(long)]
+  summaries: bool,
+
+  #[arg(long)]
+  count_borrowck: bool,
+
+  #[arg(long, allow_hyphen_values = true)]
+  rustflags: Option<String>,
+
   #[clap(last = true)]
   cargo_args: Vec<String>,
 }
@@ -39,18 +98,85 @@ impl RustcPlugin for PrintAllItemsPlugin {
     "print-all-items-driver".into()
   }
 
+  // The plugin only prints to stdout, so its output can be replayed for crates
+  // that did not change since the last run.
+  fn incremental(&self) -> bool {
+    true
+  }
+
   // In the CLI, we ask Clap to parse arguments and also specify a CrateFilter.
   // If one of the CLI arguments was a specific file to analyze, then you
-  // could provide a different filter.
+  // could provide a different filter. Flags handled by rustc_plugin, like
+  // --features, are removed from the arguments by `plugin_args`.
   fn args(&self, _target_dir: &Utf8Path) -> RustcPluginArgs<Self::Args> {
-    let args = PrintAllItemsPluginArgs::parse_from(env::args().skip(1));
-    let filter = CrateFilter::AllCrates;
-    RustcPluginArgs { args, filter }
+    let args = PrintAllItemsPluginArgs::parse_from(
+      rustc_plugin::plugin_args().into_iter().skip(1),
+    );
+    let filter = match &args.only {
+      Some(glob) => CrateFilter::Matching(glob.clone()),
+      None => CrateFilter::AllCrates,
+    };
+    // With --build, crates are compiled to real artifacts in addition to being
+    // analyzed, as an instrumenting plugin would need.
+    let mode = if args.build {
+      BuildMode::Build
+    } else {
+      BuildMode::Check
+    };
+    RustcPluginArgs::new(args, filter).with_mode(mode)
+  }
+
+  // With --packages, the plugin looks up the package of each crate in the metadata
+  // of the workspace.
+  fn uses_cargo_metadata(&self, args: &Self::Args) -> bool {
+    args.packages
+  }
+
+  // With --prefix, every printed item starts with the prefix. It is passed to the
+  // driver in an environment variable, as a plugin would pass its configuration.
+  fn env(&self) -> Vec<(String, String)> {
+    let args = PrintAllItemsPluginArgs::parse_from(
+      rustc_plugin::plugin_args().into_iter().skip(1),
+    );
+    args
+      .prefix
+      .map(|prefix| (PREFIX_VAR.to_string(), prefix))
+      .into_iter()
+      .collect()
   }
 
   // Pass Cargo arguments (like --feature) from the top-level CLI to Cargo.
   fn modify_cargo(&self, cargo: &mut Command, args: &Self::Args) {
     cargo.args(&args.cargo_args);
+    // With --rustflags, the flags are passed to every crate. rustc_plugin adds them to
+    // the flags of the user, instead of replacing them.
+    if let Some(flags) = &args.rustflags {
+      cargo.env("RUSTFLAGS", flags);
+    }
+  }
+
+  // Crates analyzed by the plugin are compiled with `--cfg print_all_items`, so they
+  // can include code only for the plugin to see.
+  fn modify_rustc_args(&self, crate_info: &CrateInfo, args: &mut Vec<String>) {
+    if crate_info.runs_plugin {
+      args.extend(["--cfg".into(), "print_all_items".into()]);
+    }
+  }
+
+  // With --json, each crate emits the names of its items instead of printing them,
+  // and the CLI combines them into a single report once every crate is checked.
+  fn aggregate(&self, results: CrateResults) {
+    for result in results.deserialize::<Vec<String>>().unwrap() {
+      for item in result.result {
+        println!("{}: {item}", result.crate_name);
+      }
+    }
+  }
+
+  // Providing callbacks lets the plugin run alongside other plugins in a PluginGroup,
+  // sharing a single compilation of each crate.
+  fn callbacks(&self, plugin_args: Self::Args) -> Option<PluginCallbacks> {
+    Some(Box::new(PrintAllItemsCallbacks { args: plugin_args }))
   }
 
   // In the driver, we use the Rustc API to start a compiler session
@@ -71,6 +197,13 @@ struct PrintAllItemsCallbacks {
 }
 
 impl rustc_driver::Callbacks for PrintAllItemsCallbacks {
+  // With --count-borrowck, the plugin overrides a query before compilation starts.
+  fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+    if self.args.count_borrowck {
+      config.override_queries = Some(items_override_queries);
+    }
+  }
+
   // At the top-level, the Rustc API uses an event-based interface for
   // accessing the compiler at different stages of compilation. In this callback,
   // all the type-checking has completed.
@@ -85,6 +218,12 @@ impl rustc_driver::Callbacks for PrintAllItemsCallbacks {
       .global_ctxt()
       .unwrap()
       .enter(|tcx| print_all_items(tcx, &self.args));
+    if self.args.count_borrowck {
+      println!(
+        "Borrow-checked bodies seen by items: {}",
+        ITEMS_BORROWCK.count()
+      );
+    }
 
     // Note that you should generally allow compilation to continue. If
     // your plugin is being invoked on a dependency, then you need to ensure
@@ -98,17 +237,270 @@ impl rustc_driver::Callbacks for PrintAllItemsCallbacks {
 // I recommend reading the Rustc Development Guide to better understand which compiler APIs
 // are relevant to whatever task you have.
 fn print_all_items(tcx: TyCtxt, args: &PrintAllItemsPluginArgs) {
+  // For trying out --crate-timeout, --on-failure and panic reports on a crate.
+  let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
+  if args.hang_on.as_ref() == Some(&crate_name) {
+    std::thread::sleep(std::time::Duration::from_secs(60));
+  }
+
+  // Crates of the standard library, rebuilt with -Zbuild-std, have too many items to
+  // print.
+  let crate_info = CrateInfo::current();
+  if crate_info.sysroot_crate {
+    println!("Crate `{crate_name}` is a sysroot crate");
+    return;
+  }
+
+  // With --kinds, the plugin prints what each invocation compiles, e.g. to see that
+  // build scripts and proc macros are analyzed too.
+  if args.kinds {
+    println!("Crate `{crate_name}` is a {}", crate_info.kind);
+  }
+
+  if args.packages {
+    print_packages(tcx);
+  }
+
+  if args.summaries {
+    exchange_summaries(tcx);
+  }
+
+  if let Some(name) = &args.guarded_panic_on {
+    analyze_bodies(tcx, name);
+  }
+
   let hir = tcx.hir();
+  // Files written by the plugin go in the crate's output directory, so crates
+  // analyzed in parallel don't overwrite each other's files.
+  if args.save {
+    let names = hir
+      .items()
+      .map(|item_id| format!("{}\n", hir.item(item_id).ident))
+      .collect::<String>();
+    let dir = rustc_plugin::crate_output_dir().unwrap();
+    std::fs::write(dir.join("items.txt"), names).unwrap();
+  }
+
+  if args.json {
+    let names = hir
+      .items()
+      .map(|item_id| hir.item(item_id).ident.to_string())
+      .collect::<Vec<_>>();
+    rustc_plugin::emit_result(&names).unwrap();
+    return;
+  }
+
   for item_id in hir.items() {
     let item = hir.item(item_id);
+    // If the plugin panics, the report says which item it was processing.
+    let _processing = rustc_plugin::processing(tcx.def_path_str(item.owner_id));
+    if args.panic_on.as_ref() == Some(&crate_name)
+      && matches!(item.kind, ItemKind::Fn(..))
+    {
+      panic!("told to panic on {crate_name}");
+    }
+
+    // Diagnostics are rendered by Cargo like any compiler warning.
+    if args.warn {
+      PluginDiagnostic::warning(item.span, format!("found item `{}`", item.ident))
+        .label(item.ident.span, "named here")
+        .emit(tcx);
+    }
+
     let mut msg = format!(
       "There is an item \"{}\" of type \"{}\"",
       item.ident,
       item.kind.descr()
     );
+    if let Ok(prefix) = std::env::var(PREFIX_VAR) {
+      msg = format!("{prefix}{msg}");
+    }
     if args.allcaps {
       msg = msg.to_uppercase();
     }
     println!("{msg}");
   }
 }
+
+// With --summaries, the plugin saves the items of each crate, and prints the items of
+// the crates it depends on, like a bottom-up analysis would use their results.
+fn exchange_summaries(tcx: TyCtxt) {
+  let hir = tcx.hir();
+  let names = hir
+    .items()
+    .filter(|item_id| matches!(hir.item(*item_id).kind, ItemKind::Fn(..)))
+    .map(|item_id| hir.item(item_id).ident.to_string())
+    .collect::<Vec<_>>();
+  rustc_plugin::write_crate_summary(tcx, &names).unwrap();
+
+  for &krate in tcx.crates(()) {
+    if let Some(names) =
+      rustc_plugin::read_crate_summary::<Vec<String>>(tcx, krate).unwrap()
+    {
+      println!(
+        "Crate `{}` uses the summary of `{}`: {}",
+        tcx.crate_name(LOCAL_CRATE),
+        tcx.crate_name(krate),
+        names.join(", ")
+      );
+    }
+  }
+}
+
+// With --guarded-panic-on, the plugin analyzes the MIR of each body and panics on the
+// body named `name`, which fails the analysis of that body but not of the others.
+fn analyze_bodies(tcx: TyCtxt, name: &str) {
+  let results = rustc_plugin::run_per_body_guarded(tcx, |def_id, body| {
+    let path = tcx.def_path_str(def_id);
+    if path == name {
+      panic!("told to panic on {path}");
+    }
+    (path, body.basic_blocks.len())
+  });
+  for (_, (path, blocks)) in results.results {
+    println!("Body `{path}` has {blocks} blocks");
+  }
+  for (def_id, failure) in results.failures {
+    println!(
+      "Analysis of `{}` failed: {failure}",
+      tcx.def_path_str(def_id)
+    );
+  }
+}
+
+// Prints the package of each crate used by the current crate, from its name.
+fn print_packages(tcx: TyCtxt) {
+  let metadata = rustc_plugin::CargoMetadata::load().unwrap();
+  let crate_name = tcx.crate_name(LOCAL_CRATE);
+  for cnum in tcx.crates(()) {
+    let dep_name = tcx.crate_name(*cnum);
+    if let Some(pkg) = metadata.package_for_crate(dep_name.as_str()) {
+      println!(
+        "Crate `{crate_name}` uses `{dep_name}` from package {} v{}",
+        pkg.name, pkg.version
+      );
+    }
+  }
+}
+
+type MirBorrowck =
+  for<'tcx> fn(TyCtxt<'tcx>, LocalDefId) -> &'tcx BorrowCheckResult<'tcx>;
+
+// Counts the bodies borrow-checked by the `mir_borrowck` provider it wraps. Both plugins
+// wrap `mir_borrowck` with their own counter, and a PluginGroup applies the overrides
+// of all its plugins, so both counters see every body.
+struct BorrowckCounter {
+  prev: OnceLock<MirBorrowck>,
+  count: AtomicUsize,
+}
+
+impl BorrowckCounter {
+  const fn new() -> Self {
+    BorrowckCounter {
+      prev: OnceLock::new(),
+      count: AtomicUsize::new(0),
+    }
+  }
+
+  fn wrap(&self, providers: &mut Providers, provider: MirBorrowck) {
+    let _ = self.prev.set(providers.mir_borrowck);
+    providers.mir_borrowck = provider;
+  }
+
+  fn mir_borrowck<'tcx>(
+    &self,
+    tcx: TyCtxt<'tcx>,
+    def_id: LocalDefId,
+  ) -> &'tcx BorrowCheckResult<'tcx> {
+    self.count.fetch_add(1, Ordering::SeqCst);
+    (self.prev.get().unwrap())(tcx, def_id)
+  }
+
+  fn count(&self) -> usize {
+    self.count.load(Ordering::SeqCst)
+  }
+}
+
+static ITEMS_BORROWCK: BorrowckCounter = BorrowckCounter::new();
+
+fn items_override_queries(_session: &Session, providers: &mut Providers) {
+  ITEMS_BORROWCK.wrap(providers, items_mir_borrowck);
+}
+
+fn items_mir_borrowck(tcx: TyCtxt<'_>, def_id: LocalDefId) -> &BorrowCheckResult<'_> {
+  ITEMS_BORROWCK.mir_borrowck(tcx, def_id)
+}
+
+static COUNT_BORROWCK: BorrowckCounter = BorrowckCounter::new();
+
+fn count_override_queries(_session: &Session, providers: &mut Providers) {
+  COUNT_BORROWCK.wrap(providers, count_mir_borrowck);
+}
+
+fn count_mir_borrowck(tcx: TyCtxt<'_>, def_id: LocalDefId) -> &BorrowCheckResult<'_> {
+  COUNT_BORROWCK.mir_borrowck(tcx, def_id)
+}
+
+// A second plugin, which only counts the items of each crate. It takes no arguments,
+// so it accepts the flags of the other plugins in its group.
+pub struct CountItemsPlugin;
+
+impl RustcPlugin for CountItemsPlugin {
+  type Args = ();
+
+  fn version(&self) -> Cow<'static, str> {
+    env!("CARGO_PKG_VERSION").into()
+  }
+
+  fn driver_name(&self) -> Cow<'static, str> {
+    "print-all-items-group-driver".into()
+  }
+
+  fn args(&self, _target_dir: &Utf8Path) -> RustcPluginArgs<Self::Args> {
+    RustcPluginArgs::new((), CrateFilter::OnlyWorkspace)
+  }
+
+  fn callbacks(&self, _plugin_args: Self::Args) -> Option<PluginCallbacks> {
+    Some(Box::new(CountItemsCallbacks))
+  }
+
+  fn run(
+    self,
+    compiler_args: Vec<String>,
+    _plugin_args: Self::Args,
+  ) -> rustc_interface::interface::Result<()> {
+    rustc_driver::RunCompiler::new(&compiler_args, &mut CountItemsCallbacks).run()
+  }
+}
+
+struct CountItemsCallbacks;
+
+impl rustc_driver::Callbacks for CountItemsCallbacks {
+  fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+    config.override_queries = Some(count_override_queries);
+  }
+
+  fn after_analysis<'tcx>(
+    &mut self,
+    _compiler: &rustc_interface::interface::Compiler,
+    queries: &'tcx rustc_interface::Queries<'tcx>,
+  ) -> rustc_driver::Compilation {
+    queries.global_ctxt().unwrap().enter(|tcx| {
+      let count = tcx.hir().items().count();
+      println!("There are {count} items");
+    });
+    println!(
+      "Borrow-checked bodies seen by count: {}",
+      COUNT_BORROWCK.count()
+    );
+    rustc_driver::Compilation::Continue
+  }
+}
+
+// Both plugins, run over the same compilation by the `cargo print-all-items-group`
+// binary. `--plugins items` or `--plugins count` runs just one of them.
+pub fn plugin_group() -> PluginGroup {
+  PluginGroup::new("print-all-items-group-driver", env!("CARGO_PKG_VERSION"))
+    .add("items", PrintAllItemsPlugin)
+    .add("count", CountItemsPlugin)
+}
@@ -0,0 +1,155 @@
+//! Collecting structured results from every crate into a single report.
+//!
+//! Cargo runs one driver process per crate, so output printed by a plugin is
+//! interleaved between crates. Instead, a plugin can call [`emit_result`] in its
+//! callbacks. Each driver appends its results to a file for its crate, and after
+//! `cargo` finishes, `cargo <plugin>` passes the results of all crates to
+//! [`RustcPlugin::aggregate`](crate::RustcPlugin::aggregate).
+
+use std::{
+  env,
+  fs::{self, OpenOptions},
+  io::{self, Write},
+  path::{Path, PathBuf},
+  process,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// The name of the environment variable containing the directory of result files.
+pub const RESULTS_DIR: &str = "RUSTC_PLUGIN_RESULTS_DIR";
+
+/// A result emitted by the plugin on one crate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrateResult<T> {
+  /// The package containing the crate.
+  pub package: String,
+  /// The name of the crate, e.g. `my_crate`.
+  pub crate_name: String,
+  /// The result passed to [`emit_result`].
+  pub result: T,
+}
+
+/// Returns a name identifying the compilation with `compiler_args`, used for the files
+/// saved for each crate.
+pub(crate) fn crate_key<T: AsRef<str>>(compiler_args: &[T]) -> String {
+  let package = env::var("CARGO_PKG_NAME").unwrap_or_default();
+  let crate_name = crate_name(compiler_args).unwrap_or("unknown");
+  let test = if compiler_args.iter().any(|arg| arg.as_ref() == "--test") {
+    "-test"
+  } else {
+    ""
+  };
+  format!("{package}-{crate_name}{test}")
+}
+
+fn crate_name<T: AsRef<str>>(compiler_args: &[T]) -> Option<&str> {
+  compiler_args
+    .windows(2)
+    .find(|pair| pair[0].as_ref() == "--crate-name")
+    .map(|pair| pair[1].as_ref())
+}
+
+/// The file that the driver process `pid` writes its results to.
+pub(crate) fn results_path(pid: u32) -> Option<PathBuf> {
+  let dir = env::var(RESULTS_DIR).ok()?;
+  let args = env::args().collect::<Vec<_>>();
+  Some(Path::new(&dir).join(format!("{}-{pid}.jsonl", crate_key(&args))))
+}
+
+/// Emits a result of the plugin for the crate being compiled, to be aggregated by
+/// [`RustcPlugin::aggregate`](crate::RustcPlugin::aggregate).
+///
+/// Can be called any number of times per crate. If the driver was not started by
+/// `cargo <plugin>`, the result is printed to stdout as JSON instead.
+pub fn emit_result<T: Serialize>(result: &T) -> io::Result<()> {
+  let args = env::args().collect::<Vec<_>>();
+  let line = CrateResult {
+    package: env::var("CARGO_PKG_NAME").unwrap_or_default(),
+    crate_name: crate_name(&args).unwrap_or("unknown").to_string(),
+    result,
+  };
+  let line = serde_json::to_string(&line)?;
+
+  match results_path(process::id()) {
+    Some(path) => append_lines(&path, [line]),
+    None => {
+      println!("{line}");
+      Ok(())
+    }
+  }
+}
+
+pub(crate) fn append_lines(
+  path: &Path,
+  lines: impl IntoIterator<Item = String>,
+) -> io::Result<()> {
+  fs::create_dir_all(path.parent().unwrap())?;
+  let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+  for line in lines {
+    writeln!(file, "{line}")?;
+  }
+  Ok(())
+}
+
+/// The results emitted by the plugin on every crate.
+pub struct CrateResults {
+  results: Vec<CrateResult<serde_json::Value>>,
+}
+
+impl CrateResults {
+  /// Reads every result file in `dir`, ordered by package and crate.
+  pub(crate) fn collect(dir: &Path) -> io::Result<Self> {
+    let mut results = Vec::new();
+    let entries = match fs::read_dir(dir) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        return Ok(CrateResults { results })
+      }
+      Err(e) => return Err(e),
+    };
+    for entry in entries {
+      let contents = fs::read_to_string(entry?.path())?;
+      for line in contents.lines() {
+        results.push(serde_json::from_str(line)?);
+      }
+    }
+    results.sort_by(|a: &CrateResult<_>, b| {
+      (&a.package, &a.crate_name).cmp(&(&b.package, &b.crate_name))
+    });
+    Ok(CrateResults { results })
+  }
+
+  /// The number of emitted results.
+  pub fn len(&self) -> usize {
+    self.results.len()
+  }
+
+  /// Returns true if no results were emitted.
+  pub fn is_empty(&self) -> bool {
+    self.results.is_empty()
+  }
+
+  /// Deserializes every result as a `T`.
+  pub fn deserialize<T: DeserializeOwned>(
+    &self,
+  ) -> serde_json::Result<Vec<CrateResult<T>>> {
+    self
+      .results
+      .iter()
+      .map(|result| {
+        Ok(CrateResult {
+          package: result.package.clone(),
+          crate_name: result.crate_name.clone(),
+          result: T::deserialize(&result.result)?,
+        })
+      })
+      .collect()
+  }
+
+  /// Returns the results as a JSON array of objects with `package`, `crate_name` and
+  /// `result` fields.
+  pub fn to_json(&self) -> serde_json::Value {
+    serde_json::to_value(&self.results).unwrap()
+  }
+}
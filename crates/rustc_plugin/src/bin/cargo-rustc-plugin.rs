@@ -0,0 +1,169 @@
+//! `cargo rustc-plugin new <name>`, which creates a new plugin with the layout that
+//! rustc_plugin expects: a library implementing `RustcPlugin`, a `cargo-<name>`
+//! binary, a `<name>-driver` binary, the toolchain of rustc_plugin, and a smoke test.
+
+use std::{env, fs, path::PathBuf, process::exit};
+
+const TEMPLATES: &[(&str, &str)] = &[
+  (
+    "Cargo.toml",
+    include_str!("../../templates/Cargo.toml.tmpl"),
+  ),
+  (
+    "rust-toolchain.toml",
+    include_str!("../../templates/rust-toolchain.toml.tmpl"),
+  ),
+  (
+    ".cargo/config.toml",
+    include_str!("../../templates/cargo-config.toml.tmpl"),
+  ),
+  (".gitignore", include_str!("../../templates/gitignore.tmpl")),
+  ("src/lib.rs", include_str!("../../templates/lib.rs.tmpl")),
+  (
+    "src/bin/cargo-{{name}}.rs",
+    include_str!("../../templates/cargo-bin.rs.tmpl"),
+  ),
+  (
+    "src/bin/{{name}}-driver.rs",
+    include_str!("../../templates/driver-bin.rs.tmpl"),
+  ),
+  (
+    "tests/smoke.rs",
+    include_str!("../../templates/smoke.rs.tmpl"),
+  ),
+  (
+    "tests/workspace/Cargo.toml",
+    include_str!("../../templates/workspace-Cargo.toml.tmpl"),
+  ),
+  (
+    "tests/workspace/src/lib.rs",
+    include_str!("../../templates/workspace-lib.rs.tmpl"),
+  ),
+];
+
+const USAGE: &str = "\
+Creates a new rustc plugin.
+
+Usage: cargo rustc-plugin new <name> [--path <rustc_plugin>]
+
+Options:
+  --path <rustc_plugin>  Depend on a local checkout of rustc_plugin instead of the
+                         version on crates.io";
+
+/// The options of `cargo rustc-plugin new`.
+struct NewArgs {
+  name: String,
+  rustc_plugin_path: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<NewArgs, String> {
+  let mut args = env::args().skip(1).peekable();
+  // Cargo passes the name of the subcommand.
+  if args.peek().map(String::as_str) == Some("rustc-plugin") {
+    args.next();
+  }
+  if args.next().as_deref() != Some("new") {
+    return Err("expected the `new` command".into());
+  }
+  let mut name = None;
+  let mut rustc_plugin_path = None;
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--path" => match args.next() {
+        Some(path) => rustc_plugin_path = Some(PathBuf::from(path)),
+        None => return Err("`--path` needs a value".into()),
+      },
+      _ if arg.starts_with('-') => return Err(format!("unexpected option `{arg}`")),
+      _ if name.is_none() => name = Some(arg),
+      _ => return Err(format!("unexpected argument `{arg}`")),
+    }
+  }
+  let name = name.ok_or("expected the name of the plugin")?;
+  let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+  if !valid {
+    return Err(format!(
+      "`{name}` is not a valid package name, use letters, digits, `-` and `_`"
+    ));
+  }
+  Ok(NewArgs {
+    name,
+    rustc_plugin_path,
+  })
+}
+
+/// The name of the plugin's type, e.g. `MyLintPlugin` for `my-lint`.
+fn plugin_type(name: &str) -> String {
+  let mut ty = name
+    .split(['-', '_'])
+    .map(|word| {
+      let mut chars = word.chars();
+      chars.next().map_or(String::new(), |first| {
+        first.to_ascii_uppercase().to_string() + chars.as_str()
+      })
+    })
+    .collect::<String>();
+  if !ty.ends_with("Plugin") {
+    ty.push_str("Plugin");
+  }
+  ty
+}
+
+fn new_plugin(args: &NewArgs) -> Result<PathBuf, String> {
+  let dir = PathBuf::from(&args.name);
+  if dir.exists() {
+    return Err(format!("destination `{}` already exists", dir.display()));
+  }
+
+  let rustc_plugin = match &args.rustc_plugin_path {
+    Some(path) => {
+      let path = path
+        .canonicalize()
+        .map_err(|e| format!("invalid path `{}`: {e}", path.display()))?;
+      format!("{{ path = {:?} }}", path.display().to_string())
+    }
+    None => format!("\"{}\"", env!("CARGO_PKG_VERSION")),
+  };
+  let render = |template: &str| {
+    template
+      .replace("{{name}}", &args.name)
+      .replace("{{crate_name}}", &args.name.replace('-', "_"))
+      .replace("{{plugin}}", &plugin_type(&args.name))
+      .replace("{{channel}}", env!("RUSTC_CHANNEL"))
+      .replace("{{rustc_plugin}}", &rustc_plugin)
+  };
+
+  for (path, template) in TEMPLATES {
+    let path = dir.join(render(path));
+    let written = fs::create_dir_all(path.parent().unwrap())
+      .and_then(|()| fs::write(&path, render(template)));
+    written.map_err(|e| format!("failed to write `{}`: {e}", path.display()))?;
+  }
+  Ok(dir)
+}
+
+fn main() {
+  let args = match parse_args() {
+    Ok(args) => args,
+    Err(e) => {
+      eprintln!("error: {e}\n\n{USAGE}");
+      exit(1);
+    }
+  };
+  match new_plugin(&args) {
+    Ok(dir) => {
+      eprintln!("Created plugin `{}` in {}", args.name, dir.display());
+      eprintln!(
+        "Install it with `cargo install --path {name}`, then run it in a workspace with \
+         `cargo {name}`.",
+        name = args.name
+      );
+    }
+    Err(e) => {
+      eprintln!("error: {e}");
+      exit(1);
+    }
+  }
+}
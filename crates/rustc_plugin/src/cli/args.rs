@@ -0,0 +1,342 @@
+//! Command-line flags of `cargo <plugin>` that are handled by the framework.
+
+use std::{
+  env,
+  path::PathBuf,
+  process::{exit, Command},
+  time::Duration,
+};
+
+use cargo_metadata::{
+  camino::{Utf8Path, Utf8PathBuf},
+  CargoOpt, MetadataCommand,
+};
+
+use crate::{FailurePolicy, Verbosity};
+
+/// Flags of `cargo <plugin>` that are forwarded to the underlying `cargo` invocation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CargoArgs {
+  /// The values of `--features` (or `-F`) flags, e.g. `"a,b"`.
+  pub features: Vec<String>,
+
+  /// True if `--all-features` was passed.
+  pub all_features: bool,
+
+  /// True if `--no-default-features` was passed.
+  pub no_default_features: bool,
+
+  /// The target triple passed with `--target`, if any.
+  pub target: Option<String>,
+
+  /// The profile passed with `--profile`, e.g. `release`, so the crates are analyzed
+  /// with the same settings as the user's builds.
+  pub profile: Option<String>,
+
+  /// The packages passed with `-p` (or `--package`).
+  pub packages: Vec<String>,
+
+  /// True if `--lib` was passed.
+  pub lib: bool,
+
+  /// The binaries passed with `--bin`.
+  pub bins: Vec<String>,
+
+  /// True if `--tests` was passed.
+  pub tests: bool,
+
+  /// True if `--examples` was passed.
+  pub examples: bool,
+
+  /// True if `--benches` was passed.
+  pub benches: bool,
+
+  /// The value of `--message-format`, e.g. `json`.
+  pub message_format: Option<String>,
+
+  /// The verbosity from `-q` (or `--quiet`), `-v` (or `--verbose`) and `-vv`.
+  pub verbosity: Verbosity,
+
+  /// True if `--rustc-wrapper` was passed, so the driver is installed as
+  /// `RUSTC_WRAPPER` instead of `RUSTC_WORKSPACE_WRAPPER`. Not forwarded to `cargo`.
+  pub rustc_wrapper: bool,
+
+  /// True if `--doc` was passed, to run the plugin on crates as they are documented
+  /// with `cargo doc`. Not forwarded to `cargo`.
+  pub doc: bool,
+
+  /// True if `--dry-run` was passed, to list the compilations that the plugin would
+  /// run on instead of running it. Not forwarded to `cargo`.
+  pub dry_run: bool,
+
+  /// True if `--check-first` was passed, to run a plain `cargo check` before the
+  /// plugin, and only run the plugin if the check succeeds. Not forwarded to `cargo`.
+  pub check_first: bool,
+
+  /// True if `--topological` was passed, to run the plugin on the workspace members one
+  /// at a time, dependencies first. Not forwarded to `cargo`.
+  pub topological: bool,
+
+  /// True if `--progress` was passed, to print a line for each crate as the plugin
+  /// starts running on it. Not forwarded to `cargo`.
+  pub progress: bool,
+
+  /// True if `--watch` was passed, to run the plugin again whenever a file in the
+  /// workspace changes. Not forwarded to `cargo`.
+  pub watch: bool,
+
+  /// The crates passed with `-Zbuild-std[=<crates>]`, empty for the default set, to
+  /// rebuild the standard library. Requires a nightly `cargo`.
+  pub build_std: Option<Vec<String>>,
+
+  /// The file passed with `--file`, to run the plugin on just that file without
+  /// Cargo. Not forwarded to `cargo`.
+  pub file: Option<PathBuf>,
+
+  /// The timeout passed with `--crate-timeout <secs>`, after which the plugin is
+  /// stopped on a crate. Not forwarded to `cargo`.
+  pub crate_timeout: Option<Duration>,
+
+  /// The policy passed with `--on-failure`, for crates that the plugin fails or times
+  /// out on. Not forwarded to `cargo`.
+  pub failure_policy: Option<FailurePolicy>,
+
+  /// The limit passed with `--plugin-jobs`, on the number of crates that the plugin
+  /// runs on concurrently. Not forwarded to `cargo`.
+  pub plugin_jobs: Option<usize>,
+
+  /// The plugins passed with `--plugins`, for a [`PluginGroup`](crate::PluginGroup).
+  /// Not forwarded to `cargo`.
+  pub plugins: Vec<String>,
+}
+
+impl CargoArgs {
+  /// Extracts the framework flags from `args`, returning them along with the remaining
+  /// arguments in their original order.
+  ///
+  /// Arguments after a `--` separator are never extracted.
+  pub fn parse(args: impl IntoIterator<Item = String>) -> (Self, Vec<String>) {
+    let mut cargo_args = CargoArgs::default();
+    let mut rest = Vec::new();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+      let (flag, value) = match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+        _ => (arg.as_str(), None),
+      };
+      match flag {
+        "--" => {
+          rest.push(arg);
+          rest.extend(args);
+          break;
+        }
+        "--features" | "-F" => match value.or_else(|| args.next()) {
+          Some(features) => cargo_args.features.push(features),
+          None => rest.push(arg),
+        },
+        "--target" => match value.or_else(|| args.next()) {
+          Some(target) => cargo_args.target = Some(target),
+          None => rest.push(arg),
+        },
+        "--profile" => match value.or_else(|| args.next()) {
+          Some(profile) => cargo_args.profile = Some(profile),
+          None => rest.push(arg),
+        },
+        "--message-format" => match value.or_else(|| args.next()) {
+          Some(format) => cargo_args.message_format = Some(format),
+          None => rest.push(arg),
+        },
+        "--package" | "-p" => match value.or_else(|| args.next()) {
+          Some(package) => cargo_args.packages.push(package),
+          None => rest.push(arg),
+        },
+        "--file" => match value.or_else(|| args.next()) {
+          Some(file) => cargo_args.file = Some(PathBuf::from(file)),
+          None => rest.push(arg),
+        },
+        "--crate-timeout" => match value.or_else(|| args.next()) {
+          Some(secs) => match secs.parse() {
+            Ok(secs) => cargo_args.crate_timeout = Some(Duration::from_secs(secs)),
+            Err(_) => {
+              invalid_value("--crate-timeout", &format!("`{secs}` is not a number"))
+            }
+          },
+          None => rest.push(arg),
+        },
+        "--on-failure" => match value.or_else(|| args.next()) {
+          Some(policy) => match policy.parse() {
+            Ok(policy) => cargo_args.failure_policy = Some(policy),
+            Err(e) => invalid_value("--on-failure", &e),
+          },
+          None => rest.push(arg),
+        },
+        "--plugin-jobs" => match value.or_else(|| args.next()) {
+          Some(jobs) => match jobs.parse() {
+            Ok(jobs) if jobs > 0 => cargo_args.plugin_jobs = Some(jobs),
+            _ => invalid_value(
+              "--plugin-jobs",
+              &format!("`{jobs}` is not a positive number"),
+            ),
+          },
+          None => rest.push(arg),
+        },
+        "--plugins" => match value.or_else(|| args.next()) {
+          Some(plugins) => cargo_args
+            .plugins
+            .extend(plugins.split(',').map(str::to_string)),
+          None => rest.push(arg),
+        },
+        "--bin" => match value.or_else(|| args.next()) {
+          Some(bin) => cargo_args.bins.push(bin),
+          None => rest.push(arg),
+        },
+        "-Z" if args.peek().and_then(|z| build_std(z)).is_some() => {
+          cargo_args.build_std = args.next().as_deref().and_then(build_std);
+        }
+        _ if flag.strip_prefix("-Z").and_then(build_std).is_some() => {
+          cargo_args.build_std = flag.strip_prefix("-Z").and_then(build_std);
+        }
+        "--quiet" | "-q" => cargo_args.verbosity = Verbosity::Quiet,
+        "--verbose" | "-v" => {
+          cargo_args.verbosity = match cargo_args.verbosity {
+            Verbosity::Verbose | Verbosity::VeryVerbose => Verbosity::VeryVerbose,
+            _ => Verbosity::Verbose,
+          }
+        }
+        "-vv" => cargo_args.verbosity = Verbosity::VeryVerbose,
+        "--lib" => cargo_args.lib = true,
+        "--tests" => cargo_args.tests = true,
+        "--examples" => cargo_args.examples = true,
+        "--benches" => cargo_args.benches = true,
+        "--rustc-wrapper" => cargo_args.rustc_wrapper = true,
+        "--doc" => cargo_args.doc = true,
+        "--dry-run" => cargo_args.dry_run = true,
+        "--check-first" => cargo_args.check_first = true,
+        "--topological" => cargo_args.topological = true,
+        "--progress" => cargo_args.progress = true,
+        "--watch" => cargo_args.watch = true,
+        "--all-features" => cargo_args.all_features = true,
+        "--no-default-features" => cargo_args.no_default_features = true,
+        _ => rest.push(arg),
+      }
+    }
+    (cargo_args, rest)
+  }
+
+  /// Adds the flags to a `cargo` command.
+  pub fn apply(&self, cmd: &mut Command) {
+    for features in &self.features {
+      cmd.args(["--features", features]);
+    }
+    if self.all_features {
+      cmd.arg("--all-features");
+    }
+    if self.no_default_features {
+      cmd.arg("--no-default-features");
+    }
+    if let Some(target) = &self.target {
+      cmd.args(["--target", target]);
+    }
+    if let Some(profile) = &self.profile {
+      cmd.args(["--profile", profile]);
+    }
+    match self.build_std.as_deref() {
+      Some([]) => {
+        cmd.arg("-Zbuild-std");
+      }
+      Some(crates) => {
+        cmd.arg(format!("-Zbuild-std={}", crates.join(",")));
+      }
+      None => {}
+    }
+    if let Some(format) = &self.message_format {
+      cmd.args(["--message-format", format]);
+    }
+    for package in &self.packages {
+      cmd.args(["-p", package]);
+    }
+    if self.lib {
+      cmd.arg("--lib");
+    }
+    for bin in &self.bins {
+      cmd.args(["--bin", bin]);
+    }
+    for (flag, enabled) in [
+      ("--tests", self.tests),
+      ("--examples", self.examples),
+      ("--benches", self.benches),
+    ] {
+      if enabled {
+        cmd.arg(flag);
+      }
+    }
+  }
+
+  /// Returns a `cargo metadata` command for the full dependency graph, resolved with
+  /// the same features and target as the compilation.
+  pub fn metadata_command(&self) -> MetadataCommand {
+    let mut cmd = MetadataCommand::new();
+    if !self.features.is_empty() {
+      cmd.features(CargoOpt::SomeFeatures(self.features.clone()));
+    }
+    if self.all_features {
+      cmd.features(CargoOpt::AllFeatures);
+    }
+    if self.no_default_features {
+      cmd.features(CargoOpt::NoDefaultFeatures);
+    }
+    if let Some(target) = &self.target {
+      cmd.other_options(vec!["--filter-platform".to_string(), target.clone()]);
+    }
+    cmd
+  }
+
+  /// The name of the directory containing the artifacts of the selected profile, e.g.
+  /// `debug` for the `dev` and `test` profiles.
+  pub fn profile_dir(&self) -> &str {
+    match self.profile.as_deref() {
+      None | Some("dev" | "test") => "debug",
+      Some("release" | "bench") => "release",
+      Some(profile) => profile,
+    }
+  }
+
+  /// The directory containing the artifacts of the `profile` build, which is nested in
+  /// a directory for the target triple when cross-compiling.
+  pub fn artifact_dir(&self, target_dir: &Utf8Path, profile: &str) -> Utf8PathBuf {
+    match &self.target {
+      Some(target) => target_dir.join(target).join(profile),
+      None => target_dir.join(profile),
+    }
+  }
+}
+
+/// Parses the crates from `build-std[=<crates>]`, the value of a `-Z` flag.
+fn build_std(flag: &str) -> Option<Vec<String>> {
+  match flag.strip_prefix("build-std")? {
+    "" => Some(Vec::new()),
+    crates => Some(
+      crates
+        .strip_prefix('=')?
+        .split(',')
+        .map(str::to_string)
+        .collect(),
+    ),
+  }
+}
+
+fn invalid_value(flag: &str, error: &str) -> ! {
+  eprintln!("error: invalid value for {flag}: {error}");
+  exit(1);
+}
+
+/// Returns the command-line arguments of `cargo <plugin>` without the flags handled by
+/// the framework, such as `--features`.
+///
+/// Like [`std::env::args`], the first argument is the path of the executable.
+/// Plugins should parse their own CLI arguments from this in
+/// [`RustcPlugin::args`](crate::RustcPlugin::args), so they do not reject the
+/// framework's flags.
+pub fn plugin_args() -> Vec<String> {
+  CargoArgs::parse(env::args()).1
+}
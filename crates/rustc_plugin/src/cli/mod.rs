@@ -0,0 +1,507 @@
+use std::{
+  env, fs,
+  path::{Path, PathBuf},
+  process::{exit, Command, Stdio},
+};
+
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
+
+pub use self::{
+  args::{plugin_args, CargoArgs},
+  rustflags::USER_RUSTFLAGS,
+  selection::{SelectedTarget, SELECTED_TARGETS},
+};
+use super::plugin::{self, RustcPlugin};
+use crate::{
+  aggregate::{CrateResults, RESULTS_DIR},
+  driver,
+  failure::{CRATE_TIMEOUT, FAILURE_POLICY},
+  fingerprint::{self, CARGO_METADATA_SEED, FINGERPRINT_DIR},
+  jobs::{JobServer, JOBS_ADDR},
+  logging::VERBOSITY,
+  metadata::{self, CARGO_METADATA_FILE},
+  output::OUTPUT_DIR,
+  progress::{ProgressReporter, PROGRESS_FILE},
+  rustdoc::RUSTDOC_PATH,
+  summary::{Summary, STATUS_DIR},
+  toolchain, BuildMode, CrateFilter, FailurePolicy, Verbosity,
+};
+
+mod args;
+mod plan;
+mod rustflags;
+mod schedule;
+mod selection;
+mod watch;
+
+pub const RUN_ON_ALL_CRATES: &str = "RUSTC_PLUGIN_ALL_TARGETS";
+pub const SPECIFIC_CRATE: &str = "SPECIFIC_CRATE";
+pub const SPECIFIC_TARGET: &str = "SPECIFIC_TARGET";
+pub const CARGO_VERBOSE: &str = "CARGO_VERBOSE";
+pub const TARGET_TRIPLE: &str = "RUSTC_PLUGIN_TARGET";
+pub const CHAINED_WRAPPER: &str = "RUSTC_PLUGIN_CHAINED_WRAPPER";
+pub const SELECTED_PACKAGES: &str = "RUSTC_PLUGIN_SELECTED_PACKAGES";
+
+/// The top-level function that should be called in your user-facing binary.
+pub fn cli_main<T: RustcPlugin>(plugin: T) {
+  if env::args().any(|arg| arg == "-V") {
+    println!("{}", plugin.version());
+    return;
+  }
+
+  toolchain::verify();
+
+  let (cargo_args, _) = CargoArgs::parse(env::args());
+  let plugin_env = plugin.env();
+  if let Some(file) = &cargo_args.file {
+    // The compiler runs in this process, so it sees the variables of the plugin here.
+    for (name, value) in &plugin_env {
+      env::set_var(name, value);
+    }
+    let out_dir = driver::standalone_dir(&plugin.driver_name());
+    let out_dir = Utf8PathBuf::from_path_buf(out_dir).expect("temp dir is not UTF-8");
+    let args = plugin.args(&out_dir);
+    exit(rustc_driver::catch_with_exit_code(|| {
+      driver::run_on_file(plugin, file, args.args)
+    }));
+  }
+
+  let metadata = cargo_metadata::MetadataCommand::new()
+    .no_deps()
+    .other_options(["--all-features".to_string(), "--offline".to_string()])
+    .exec()
+    .unwrap();
+  if cargo_args.watch {
+    watch::watch(
+      metadata.workspace_root.as_std_path(),
+      metadata.target_directory.as_std_path(),
+    );
+  }
+
+  // Compile errors are reported by rustc alone, before the plugin runs on code that
+  // doesn't compile.
+  if cargo_args.check_first {
+    let mut check = Command::new("cargo");
+    check.arg("check");
+    if cargo_args.packages.is_empty() {
+      check.arg("--workspace");
+    }
+    cargo_args.apply(&mut check);
+    let status = check.status().expect("failed to run cargo check");
+    if !status.success() {
+      exit(status.code().unwrap_or(-1));
+    }
+  }
+
+  let plugin_subdir = format!("plugin-{}", env!("RUSTC_CHANNEL"));
+  let target_dir = metadata.target_directory.join(plugin_subdir);
+
+  let args = plugin.args(&target_dir);
+
+  let mut cmd = Command::new("cargo");
+  cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+
+  let mut path = env::current_exe()
+    .expect("current executable path invalid")
+    .with_file_name(plugin.driver_name().as_ref());
+
+  if cfg!(windows) {
+    path.set_extension("exe");
+  }
+
+  // A seed set by the user, e.g. when building rustc, is kept.
+  let mut seed = env::var(CARGO_METADATA_SEED).unwrap_or_default();
+  seed.push_str(&fingerprint::plugin_hash(
+    &plugin.version(),
+    &path,
+    &plugin_env,
+  ));
+  cmd.env(CARGO_METADATA_SEED, seed);
+  cmd.envs(plugin_env);
+
+  if cargo_args.doc {
+    let rustdoc = env::var_os("RUSTDOC").unwrap_or_else(|| "rustdoc".into());
+    cmd.env(RUSTDOC_PATH, rustdoc).env("RUSTDOC", path);
+  } else if cargo_args.rustc_wrapper
+    // Crates of the standard library rebuilt with `-Zbuild-std` are not workspace
+    // members, so only `RUSTC_WRAPPER` is used for them.
+    || (cargo_args.build_std.is_some() && matches!(args.filter, CrateFilter::AllCrates))
+  {
+    // An existing wrapper like sccache is chained by the driver for every compilation
+    // that doesn't run the plugin.
+    let existing = env::var_os("RUSTC_WRAPPER")
+      .filter(|wrapper| !wrapper.is_empty() && Path::new(wrapper) != path);
+    if let Some(existing) = existing {
+      log::debug!("Chaining RUSTC_WRAPPER={}", existing.to_string_lossy());
+      cmd.env(CHAINED_WRAPPER, existing);
+    }
+    cmd.env("RUSTC_WRAPPER", path);
+  } else {
+    cmd.env("RUSTC_WORKSPACE_WRAPPER", path);
+  }
+
+  // When building, artifacts go in the normal target directory so they can be run.
+  let build_dir = match args.mode {
+    _ if cargo_args.doc => {
+      cmd.args(["doc", "--target-dir"]).arg(&target_dir);
+      target_dir.clone()
+    }
+    BuildMode::Check => {
+      cmd.args(["check", "--target-dir"]).arg(&target_dir);
+      target_dir.clone()
+    }
+    BuildMode::Build => {
+      cmd.arg("build");
+      metadata.target_directory.clone()
+    }
+  };
+
+  // CARGO_VERBOSE is the older way to pass `-vv`.
+  let verbosity = if env::var(CARGO_VERBOSE).is_ok() {
+    Verbosity::VeryVerbose
+  } else {
+    cargo_args.verbosity
+  };
+  cmd.arg(verbosity.cargo_flag());
+  cmd.env(VERBOSITY, verbosity.to_string());
+
+  cargo_args.apply(&mut cmd);
+  if let Some(target) = &cargo_args.target {
+    cmd.env(TARGET_TRIPLE, target);
+  }
+
+  let workspace_members = metadata
+    .workspace_members
+    .iter()
+    .map(|pkg_id| {
+      metadata
+        .packages
+        .iter()
+        .find(|pkg| &pkg.id == pkg_id)
+        .unwrap()
+    })
+    .collect::<Vec<_>>();
+
+  let uses_metadata = plugin.uses_cargo_metadata(&args.args);
+  let topological =
+    cargo_args.topological && !matches!(args.filter, CrateFilter::CrateContainingFile(_));
+  let full_metadata = (uses_metadata
+    || topological
+    || !matches!(
+      args.filter,
+      CrateFilter::AllCrates
+        | CrateFilter::OnlyWorkspace
+        | CrateFilter::CrateContainingFile(_)
+    ))
+  .then(|| {
+    cargo_args
+      .metadata_command()
+      .exec()
+      .expect("failed to get cargo metadata")
+  });
+  let metadata_file = match &full_metadata {
+    Some(full_metadata) if uses_metadata => {
+      let path = metadata::write(full_metadata, target_dir.as_std_path())
+        .expect("failed to write cargo metadata");
+      cmd.env(CARGO_METADATA_FILE, &path);
+      Some(path)
+    }
+    _ => None,
+  };
+
+  // The dependencies between members are only known from the full metadata.
+  let order = topological.then(|| {
+    let selected = workspace_members
+      .iter()
+      .filter(|pkg| {
+        cargo_args.packages.is_empty()
+          || cargo_args.packages.iter().any(|spec| {
+            let name = spec.split(['@', ':']).next().unwrap();
+            name == pkg.name
+          })
+      })
+      .copied()
+      .collect::<Vec<_>>();
+    let order = schedule::topological_order(full_metadata.as_ref().unwrap(), &selected);
+    log::debug!(
+      "Topological order: {:?}",
+      order.iter().map(|pkg| &pkg.name).collect::<Vec<_>>()
+    );
+    order
+  });
+
+  match args.filter {
+    CrateFilter::CrateContainingFile(file_path) => {
+      only_run_on_file(
+        &mut cmd,
+        file_path,
+        &workspace_members,
+        &cargo_args.artifact_dir(&build_dir, cargo_args.profile_dir()),
+      );
+    }
+    CrateFilter::AllCrates | CrateFilter::OnlyWorkspace => {
+      if cargo_args.packages.is_empty() {
+        cmd.arg("--all");
+      }
+      match args.filter {
+        CrateFilter::AllCrates => {
+          cmd.env(RUN_ON_ALL_CRATES, "");
+        }
+        CrateFilter::OnlyWorkspace => {}
+        _ => unreachable!(),
+      }
+    }
+    filter => {
+      if cargo_args.packages.is_empty() {
+        cmd.arg("--all");
+      }
+      // Dependencies are only known from the metadata of the full dependency graph.
+      let full_metadata = full_metadata.as_ref().unwrap();
+      let packages = full_metadata
+        .packages
+        .iter()
+        .filter(|pkg| filter.selects_package(full_metadata, pkg))
+        .map(|pkg| format!("{} {}", pkg.name, pkg.version))
+        .collect::<Vec<_>>();
+      log::debug!("Selected packages: {packages:?}");
+      cmd
+        .env(RUN_ON_ALL_CRATES, "")
+        .env(SELECTED_PACKAGES, serde_json::to_string(&packages).unwrap());
+    }
+  }
+
+  if let Some(selected) = cargo_args.selected_targets(&workspace_members) {
+    log::debug!("Selected targets: {selected:?}");
+    cmd.env(SELECTED_TARGETS, serde_json::to_string(&selected).unwrap());
+  }
+
+  let plugin_dir = metadata
+    .target_directory
+    .join(plugin.driver_name().as_ref());
+  cmd.env(OUTPUT_DIR, &plugin_dir);
+  if plugin.incremental() && args.mode == BuildMode::Check {
+    cmd.env(FINGERPRINT_DIR, plugin_dir.join("fingerprints"));
+  }
+
+  let args_str = serde_json::to_string(&args.args).unwrap();
+  let args_file = plugin::send_plugin_args(&mut cmd, args_str, target_dir.as_std_path())
+    .expect("failed to write plugin args");
+
+  // HACK: if running on the rustc codebase, this env var needs to exist
+  // for the code to compile
+  if workspace_members.iter().any(|pkg| pkg.name == "rustc-main") {
+    cmd.env("CFG_RELEASE", "");
+  }
+
+  let results_dir = target_dir
+    .join("results")
+    .join(std::process::id().to_string());
+  let _ = fs::remove_dir_all(&results_dir);
+  cmd.env(RESULTS_DIR, &results_dir);
+
+  let status_dir = target_dir
+    .join("status")
+    .join(std::process::id().to_string());
+  let _ = fs::remove_dir_all(&status_dir);
+  cmd.env(STATUS_DIR, &status_dir);
+  if let Some(timeout) = cargo_args.crate_timeout {
+    cmd.env(CRATE_TIMEOUT, timeout.as_secs().to_string());
+  }
+  if let Some(policy) = cargo_args.failure_policy {
+    cmd.env(FAILURE_POLICY, policy.to_string());
+    if policy == FailurePolicy::SkipAndContinue {
+      cmd.arg("--keep-going");
+    }
+  }
+
+  // The user's flags are read before the plugin can set `RUSTFLAGS` for the command.
+  let user_rustflags = rustflags::user_rustflags(cargo_args.target.as_deref());
+  cmd.env(
+    USER_RUSTFLAGS,
+    serde_json::to_string(&user_rustflags).unwrap(),
+  );
+  plugin.modify_cargo(&mut cmd, &args.args);
+  rustflags::merge(&mut cmd, &user_rustflags);
+
+  let temp_files = args_file
+    .into_iter()
+    .chain(metadata_file)
+    .collect::<Vec<_>>();
+  if cargo_args.dry_run {
+    plan::print_plan(&cmd);
+    for file in temp_files {
+      let _ = fs::remove_file(file);
+    }
+    return;
+  }
+
+  let progress = cargo_args.progress.then(|| {
+    let total = plan::plan(&cmd)
+      .iter()
+      .filter(|compilation| compilation.run_plugin)
+      .count();
+    let path = target_dir.join(format!("progress-{}.jsonl", std::process::id()));
+    cmd.env(PROGRESS_FILE, &path);
+    ProgressReporter::start(path.into_std_path_buf(), total)
+      .expect("failed to create progress file")
+  });
+
+  // The server lives until this process exits.
+  if let Some(jobs) = cargo_args.plugin_jobs {
+    let server = JobServer::start(jobs).expect("failed to start the job server");
+    cmd.env(JOBS_ADDR, server.addr().to_string());
+  }
+
+  let exit_status = match &order {
+    Some(order) if !order.is_empty() => {
+      let keep_going = cargo_args.failure_policy == Some(FailurePolicy::SkipAndContinue);
+      schedule::run_in_order(&cmd, order, keep_going)
+    }
+    _ => cmd.status(),
+  }
+  .expect("failed to wait for cargo?");
+  if let Some(progress) = progress {
+    progress.finish();
+  }
+  for file in temp_files {
+    let _ = fs::remove_file(file);
+  }
+
+  let results = CrateResults::collect(results_dir.as_std_path())
+    .expect("failed to read plugin results");
+  let _ = fs::remove_dir_all(&results_dir);
+  if !results.is_empty() {
+    plugin.aggregate(results);
+  }
+
+  let summary =
+    Summary::collect(status_dir.as_std_path()).expect("failed to read crate statuses");
+  let _ = fs::remove_dir_all(&status_dir);
+  if verbosity != Verbosity::Quiet {
+    summary.print();
+  }
+
+  exit(summary.exit_code(exit_status.success()));
+}
+
+fn only_run_on_file(
+  cmd: &mut Command,
+  file_path: PathBuf,
+  workspace_members: &[&cargo_metadata::Package],
+  artifact_dir: &Utf8Path,
+) {
+  // We compare this against canonicalized paths, so it must be canonicalized too
+  let file_path = file_path.canonicalize().unwrap();
+
+  // Find the package and target that corresponds to a given file path
+  let mut matching = workspace_members
+    .iter()
+    .filter_map(|pkg| {
+      let targets = pkg
+        .targets
+        .iter()
+        .filter(|target| {
+          let src_path = target.src_path.canonicalize().unwrap();
+          log::trace!("Package {} has src path {}", pkg.name, src_path.display());
+          file_path.starts_with(src_path.parent().unwrap())
+        })
+        .collect::<Vec<_>>();
+
+      let target = (match targets.len() {
+        0 => None,
+        1 => Some(targets[0]),
+        _ => {
+          // If there are multiple targets that match a given directory, e.g. `examples/whatever.rs`, then
+          // find the target whose name matches the file stem
+          let stem = file_path.file_stem().unwrap().to_string_lossy();
+          let name_matches_stem = targets
+            .clone()
+            .into_iter()
+            .find(|target| target.name == stem);
+
+          // Otherwise we're in a special case, e.g. "main.rs" corresponds to the bin target.
+          name_matches_stem.or_else(|| {
+            let only_bin = targets
+              .iter()
+              .all(|target| !target.kind.contains(&"lib".into()));
+            // TODO: this is a pile of hacks, and it seems like there is no reliable way to say
+            // which target a file will correspond to given only its filename. For example,
+            // if you have src/foo.rs it could either be imported by src/main.rs, or src/lib.rs, or
+            // even both!
+            if only_bin {
+              targets
+                .into_iter()
+                .find(|target| target.kind.contains(&"bin".into()))
+            } else {
+              let kind = (if stem == "main" { "bin" } else { "lib" }).to_string();
+              targets
+                .into_iter()
+                .find(|target| target.kind.contains(&kind))
+            }
+          })
+        }
+      })?;
+
+      Some((pkg, target))
+    })
+    .collect::<Vec<_>>();
+  let (pkg, target) = match matching.len() {
+    0 => panic!("Could not find target for path: {}", file_path.display()),
+    1 => matching.remove(0),
+    _ => panic!("Too many matching targets: {matching:?}"),
+  };
+
+  // Add compile filter to specify the target corresponding to the given file
+  cmd.arg("-p").arg(format!("{}:{}", pkg.name, pkg.version));
+
+  enum CompileKind {
+    Lib,
+    Bin,
+    ProcMacro,
+  }
+
+  // kind string should be one of the ones listed here:
+  // https://doc.rust-lang.org/cargo/reference/cargo-targets.html#the-crate-type-field
+  let kind_str = &target.kind[0];
+  let kind = match kind_str.as_str() {
+    "lib" | "rlib" | "dylib" | "staticlib" | "cdylib" => CompileKind::Lib,
+    "bin" => CompileKind::Bin,
+    "proc-macro" => CompileKind::ProcMacro,
+    _ => unreachable!("unexpected cargo crate type: {kind_str}"),
+  };
+
+  match kind {
+    CompileKind::Lib => {
+      // If the rmeta files were previously generated for the lib (e.g. by running the plugin
+      // on a reverse-dep), then we have to remove them or else Cargo will memoize the plugin.
+      let deps_dir = artifact_dir.join("deps");
+      if let Ok(entries) = fs::read_dir(deps_dir) {
+        let prefix = format!("lib{}", pkg.name.replace('-', "_"));
+        for entry in entries {
+          let path = entry.unwrap().path();
+          if let Some(file_name) = path.file_name() {
+            if file_name.to_string_lossy().starts_with(&prefix) {
+              fs::remove_file(path).unwrap();
+            }
+          }
+        }
+      }
+
+      cmd.arg("--lib");
+    }
+    CompileKind::Bin => {
+      cmd.args(["--bin", &target.name]);
+    }
+    CompileKind::ProcMacro => {}
+  }
+
+  cmd.env(SPECIFIC_CRATE, pkg.name.replace('-', "_"));
+  cmd.env(SPECIFIC_TARGET, kind_str);
+
+  log::debug!(
+    "Package: {}, target kind {}, target name {}",
+    pkg.name,
+    kind_str,
+    target.name
+  );
+}
@@ -0,0 +1,126 @@
+//! `cargo <plugin> --dry-run`, which lists the compilations that the plugin would run
+//! on without building anything.
+
+use std::{
+  collections::HashMap,
+  process::{exit, Command, Stdio},
+};
+
+use serde::Deserialize;
+
+use crate::driver::RunDecision;
+
+/// The output of `cargo build --build-plan`.
+#[derive(Deserialize)]
+struct BuildPlan {
+  invocations: Vec<Invocation>,
+}
+
+#[derive(Deserialize)]
+struct Invocation {
+  package_name: String,
+  package_version: String,
+  target_kind: Vec<String>,
+  args: Vec<String>,
+  env: HashMap<String, String>,
+}
+
+/// A compilation of `cargo check`, as planned by Cargo.
+pub struct PlannedCompilation {
+  pub package_name: String,
+  pub package_version: String,
+  pub crate_name: String,
+  pub target_kind: Vec<String>,
+  pub test: bool,
+  /// True if the driver would run the plugin on the compilation.
+  pub run_plugin: bool,
+}
+
+/// Returns the compilations of the `cargo check` or `cargo build` command `cmd`, and
+/// whether the plugin would run on each, based on the rustc arguments and environment
+/// that Cargo would pass to the driver.
+pub fn plan(cmd: &Command) -> Vec<PlannedCompilation> {
+  // Cargo only produces build plans for `cargo build`, which compiles the same crates
+  // as `cargo check`, just with a different `--emit`.
+  let mut plan_cmd = Command::new(cmd.get_program());
+  let mut replaced = false;
+  for arg in cmd.get_args() {
+    if !replaced && (arg == "check" || arg == "build") {
+      plan_cmd.args(["build", "--build-plan", "-Zunstable-options"]);
+      replaced = true;
+    } else {
+      plan_cmd.arg(arg);
+    }
+  }
+  let mut cmd_env = HashMap::new();
+  for (key, value) in cmd.get_envs() {
+    match value {
+      Some(value) => {
+        plan_cmd.env(key, value);
+        cmd_env.insert(key.to_string_lossy(), value.to_string_lossy());
+      }
+      None => {
+        plan_cmd.env_remove(key);
+      }
+    }
+  }
+  log::debug!("Build plan command: {plan_cmd:?}");
+
+  let output = plan_cmd
+    .stderr(Stdio::inherit())
+    .output()
+    .expect("failed to run cargo");
+  if !output.status.success() {
+    exit(output.status.code().unwrap_or(-1));
+  }
+  let plan: BuildPlan =
+    serde_json::from_slice(&output.stdout).expect("failed to parse the build plan");
+
+  plan
+    .invocations
+    .into_iter()
+    .map(|invocation| {
+      let var = |name: &str| {
+        invocation
+          .env
+          .get(name)
+          .cloned()
+          .or_else(|| cmd_env.get(name).map(|value| value.to_string()))
+          .or_else(|| std::env::var(name).ok())
+      };
+      let run_plugin = RunDecision::new(&invocation.args, var).run_plugin();
+      let crate_name = invocation
+        .args
+        .windows(2)
+        .find(|pair| pair[0] == "--crate-name")
+        .map_or(String::new(), |pair| pair[1].clone());
+      PlannedCompilation {
+        package_name: invocation.package_name,
+        package_version: invocation.package_version,
+        crate_name,
+        target_kind: invocation.target_kind,
+        test: invocation.args.iter().any(|arg| arg == "--test"),
+        run_plugin,
+      }
+    })
+    .collect()
+}
+
+/// Prints whether the plugin would run on each compilation of `cmd`.
+pub fn print_plan(cmd: &Command) {
+  for compilation in plan(cmd) {
+    let action = if compilation.run_plugin {
+      "plugin"
+    } else {
+      "rustc"
+    };
+    let mut kinds = compilation.target_kind.join(", ");
+    if compilation.test {
+      kinds.push_str(", test");
+    }
+    println!(
+      "{action:6} {} {}: {} ({kinds})",
+      compilation.package_name, compilation.package_version, compilation.crate_name
+    );
+  }
+}
@@ -0,0 +1,89 @@
+//! The flags that Cargo passes to every rustc invocation from `RUSTFLAGS` or
+//! `.cargo/config.toml`, and merging the flags set by the plugin with them.
+//!
+//! Cargo takes the flags from the first of `CARGO_ENCODED_RUSTFLAGS`, `RUSTFLAGS`,
+//! `target.<triple>.rustflags` and `build.rustflags` that is set. So a plugin that sets
+//! `RUSTFLAGS` in [`RustcPlugin::modify_cargo`](crate::RustcPlugin::modify_cargo) would
+//! silently drop the flags of the user, and `cargo <plugin>` appends them to the
+//! user's flags instead.
+
+use std::{
+  env,
+  process::{Command, Stdio},
+};
+
+/// The name of the environment variable containing the user's flags as a JSON array,
+/// see [`CrateInfo::user_rustflags`](crate::CrateInfo::user_rustflags).
+pub const USER_RUSTFLAGS: &str = "RUSTC_PLUGIN_USER_RUSTFLAGS";
+
+fn split_encoded(flags: &str) -> Vec<String> {
+  if flags.is_empty() {
+    return Vec::new();
+  }
+  flags.split('\x1f').map(str::to_string).collect()
+}
+
+fn split_whitespace(flags: &str) -> Vec<String> {
+  flags.split_whitespace().map(str::to_string).collect()
+}
+
+/// Reads the flags at `key` of the Cargo configuration, which are a string or an array
+/// of strings.
+fn config_flags(key: &str) -> Option<Vec<String>> {
+  let output = Command::new("cargo")
+    .args([
+      "-Zunstable-options",
+      "config",
+      "get",
+      key,
+      "--format",
+      "json-value",
+    ])
+    .stderr(Stdio::null())
+    .output()
+    .ok()?;
+  if !output.status.success() {
+    return None;
+  }
+  match serde_json::from_slice(&output.stdout).ok()? {
+    serde_json::Value::String(flags) => Some(split_whitespace(&flags)),
+    flags => serde_json::from_value(flags).ok(),
+  }
+}
+
+/// Returns the flags that Cargo passes to the compilations for `target`.
+///
+/// Only the `target.<triple>` table of the configuration is read, not the
+/// `target.<cfg>` tables.
+pub(super) fn user_rustflags(target: Option<&str>) -> Vec<String> {
+  if let Ok(flags) = env::var("CARGO_ENCODED_RUSTFLAGS") {
+    return split_encoded(&flags);
+  }
+  if let Ok(flags) = env::var("RUSTFLAGS") {
+    return split_whitespace(&flags);
+  }
+  target
+    .filter(|target| !target.ends_with(".json"))
+    .and_then(|target| config_flags(&format!("target.{target}.rustflags")))
+    .or_else(|| config_flags("build.rustflags"))
+    .unwrap_or_default()
+}
+
+/// Appends the flags that the plugin set in `cmd` with `CARGO_ENCODED_RUSTFLAGS` or
+/// `RUSTFLAGS` to the `user` flags.
+pub(super) fn merge(cmd: &mut Command, user: &[String]) {
+  let var = |name: &str| {
+    let (_, value) = cmd.get_envs().find(|(key, _)| *key == name)?;
+    value?.to_str().map(str::to_string)
+  };
+  let plugin = match (var("CARGO_ENCODED_RUSTFLAGS"), var("RUSTFLAGS")) {
+    (Some(flags), _) => split_encoded(&flags),
+    (None, Some(flags)) => split_whitespace(&flags),
+    (None, None) => return,
+  };
+  let flags = user.iter().cloned().chain(plugin).collect::<Vec<_>>();
+  log::debug!("Merged rustflags: {flags:?}");
+  cmd
+    .env_remove("RUSTFLAGS")
+    .env("CARGO_ENCODED_RUSTFLAGS", flags.join("\x1f"));
+}
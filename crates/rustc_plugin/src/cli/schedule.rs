@@ -0,0 +1,119 @@
+//! `cargo <plugin> --topological`, which runs the plugin on the workspace members one
+//! at a time, dependencies first.
+//!
+//! Cargo already compiles a crate after its dependencies, but it compiles independent
+//! crates in parallel and in no fixed order. Running `cargo` once per member in the
+//! topological order of the workspace makes the order deterministic, e.g. for
+//! analyses that read the [summaries](crate::read_crate_summary) of other members.
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  io,
+  process::{Command, ExitStatus},
+};
+
+use cargo_metadata::{DependencyKind, Metadata, Package, PackageId};
+
+/// Returns `members` sorted so that each package comes after the members it depends
+/// on, with ties broken by name.
+///
+/// Dev-dependencies are ignored, since members can be dev-dependencies of each other.
+pub(super) fn topological_order<'a>(
+  metadata: &Metadata,
+  members: &[&'a Package],
+) -> Vec<&'a Package> {
+  let is_member = |id: &PackageId| members.iter().any(|pkg| &pkg.id == id);
+  let mut deps = members
+    .iter()
+    .map(|pkg| {
+      let node = metadata
+        .resolve
+        .as_ref()
+        .and_then(|resolve| resolve.nodes.iter().find(|node| node.id == pkg.id));
+      let pkg_deps = node.map_or_else(BTreeSet::new, |node| {
+        node
+          .deps
+          .iter()
+          .filter(|dep| {
+            is_member(&dep.pkg)
+              && dep
+                .dep_kinds
+                .iter()
+                .any(|info| info.kind != DependencyKind::Development)
+          })
+          .map(|dep| &dep.pkg)
+          .collect()
+      });
+      (&pkg.id, pkg_deps)
+    })
+    .collect::<BTreeMap<_, _>>();
+
+  let mut order = Vec::new();
+  while !deps.is_empty() {
+    let mut ready = members
+      .iter()
+      .filter(|pkg| deps.get(&pkg.id).is_some_and(BTreeSet::is_empty))
+      .collect::<Vec<_>>();
+    // Cargo rejects cycles between normal and build dependencies, so a package is
+    // always ready.
+    ready.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    let next = ready[0];
+    deps.remove(&next.id);
+    for pkg_deps in deps.values_mut() {
+      pkg_deps.remove(&next.id);
+    }
+    order.push(*next);
+  }
+  order
+}
+
+/// Runs the `cargo` command `cmd` for each package of `order` in turn, stopping at the
+/// first failure unless `keep_going` is true. Returns the status of the first failed
+/// run, or of the last run.
+pub(super) fn run_in_order(
+  cmd: &Command,
+  order: &[&Package],
+  keep_going: bool,
+) -> io::Result<ExitStatus> {
+  // The packages are selected one at a time instead of with `--all` or `-p`.
+  let mut args = Vec::new();
+  let mut cmd_args = cmd.get_args();
+  while let Some(arg) = cmd_args.next() {
+    match arg.to_str() {
+      Some("--all") => {}
+      Some("-p") => {
+        cmd_args.next();
+      }
+      _ => args.push(arg),
+    }
+  }
+
+  let mut failed = None;
+  let mut last = None;
+  for pkg in order {
+    let mut run = Command::new(cmd.get_program());
+    run
+      .args(&args)
+      .args(["-p", &format!("{}:{}", pkg.name, pkg.version)]);
+    for (name, value) in cmd.get_envs() {
+      match value {
+        Some(value) => run.env(name, value),
+        None => run.env_remove(name),
+      };
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+      run.current_dir(dir);
+    }
+
+    log::debug!("Running the plugin on {} {}", pkg.name, pkg.version);
+    let status = run.status()?;
+    if !status.success() {
+      if !keep_going {
+        return Ok(status);
+      }
+      failed.get_or_insert(status);
+    }
+    last = Some(status);
+  }
+  Ok(failed.or(last).expect("no packages to run the plugin on"))
+}
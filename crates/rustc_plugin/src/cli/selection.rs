@@ -0,0 +1,120 @@
+//! Selection of the targets that the plugin runs on, from `-p`, `--lib`, `--bin`,
+//! `--tests`, `--examples` and `--benches`.
+
+use std::ops::Deref;
+
+use cargo_metadata::{Package, Target};
+use serde::{Deserialize, Serialize};
+
+use super::CargoArgs;
+
+/// The name of the environment variable containing the selected targets, if the
+/// user selected any.
+pub const SELECTED_TARGETS: &str = "RUSTC_PLUGIN_SELECTED_TARGETS";
+
+/// A compilation that the plugin should run on.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SelectedTarget {
+  /// The name of the package, as in `CARGO_PKG_NAME`.
+  pub package: String,
+  /// The name of the crate, as in `--crate-name`.
+  pub crate_name: String,
+  /// Whether the target is compiled as a test harness, i.e. with `--test`, or `None`
+  /// if either.
+  pub test: Option<bool>,
+}
+
+fn has_kind(target: &Target, kind: &str) -> bool {
+  target.kind.iter().any(|k| k == kind)
+}
+
+fn is_lib(target: &Target) -> bool {
+  target.kind.iter().any(|kind| {
+    matches!(
+      kind.as_str(),
+      "lib" | "rlib" | "dylib" | "staticlib" | "cdylib" | "proc-macro"
+    )
+  })
+}
+
+impl CargoArgs {
+  /// True if any flag selecting packages or targets was passed.
+  pub fn selects_targets(&self) -> bool {
+    !self.packages.is_empty()
+      || self.lib
+      || !self.bins.is_empty()
+      || self.tests
+      || self.examples
+      || self.benches
+  }
+
+  /// True if any flag selecting targets within packages was passed.
+  fn selects_target_kinds(&self) -> bool {
+    self.lib || !self.bins.is_empty() || self.tests || self.examples || self.benches
+  }
+
+  /// Returns the compilations of `workspace_members` selected by the flags, or `None`
+  /// if no flags were passed and the plugin should run on every crate.
+  pub fn selected_targets(
+    &self,
+    workspace_members: &[&Package],
+  ) -> Option<Vec<SelectedTarget>> {
+    if !self.selects_targets() {
+      return None;
+    }
+
+    let packages = workspace_members.iter().filter(|pkg| {
+      self.packages.is_empty() || self.packages.iter().any(|name| name == &pkg.name)
+    });
+    let selected = packages
+      .flat_map(|pkg| {
+        pkg.targets.iter().flat_map(move |target| {
+          let lib = is_lib(target);
+          let bin = has_kind(target, "bin");
+          let mut modes = Vec::new();
+          if !self.selects_target_kinds() {
+            // Cargo checks libraries and binaries by default.
+            if lib || bin {
+              modes.push(Some(false));
+            }
+          } else {
+            if (self.lib && lib) || (bin && self.bins.contains(&target.name)) {
+              modes.push(Some(false));
+            }
+            if self.tests && (lib || bin) && target.test {
+              modes.push(Some(true));
+            }
+            // These may or may not use the test harness.
+            if (self.tests && has_kind(target, "test"))
+              || (self.examples && has_kind(target, "example"))
+              || (self.benches && has_kind(target, "bench"))
+            {
+              modes.push(None);
+            }
+          }
+          modes.into_iter().map(|test| SelectedTarget {
+            package: pkg.name.clone(),
+            crate_name: target.name.replace('-', "_"),
+            test,
+          })
+        })
+      })
+      .collect();
+    Some(selected)
+  }
+}
+
+impl SelectedTarget {
+  /// Returns true if a rustc invocation with `args` for the package `package`
+  /// compiles this target.
+  pub fn matches<T: Deref<Target = str>>(&self, package: &str, args: &[T]) -> bool {
+    let crate_name = args
+      .iter()
+      .position(|arg| &**arg == "--crate-name")
+      .and_then(|i| args.get(i + 1));
+    let test = args.iter().any(|arg| &**arg == "--test");
+    self.package == package
+      && crate_name.is_some_and(|name| **name == self.crate_name)
+      && self.test.is_none_or(|t| t == test)
+  }
+}
@@ -0,0 +1,94 @@
+//! `cargo <plugin> --watch`, which runs the plugin again whenever a file in the
+//! workspace changes.
+//!
+//! Each run is a separate `cargo <plugin>` process with the same arguments, so the
+//! metadata of the workspace is read again, e.g. after a package is added. Cargo only
+//! recompiles the crates affected by a change, and if the plugin is
+//! [`incremental`](crate::RustcPlugin::incremental), its output on the others is
+//! replayed from their fingerprints.
+
+use std::{
+  collections::BTreeMap,
+  env, fs, io,
+  path::{Path, PathBuf},
+  process::Command,
+  thread,
+  time::{Duration, SystemTime},
+};
+
+/// How often the workspace is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The modification time of every file in the workspace.
+type Snapshot = BTreeMap<PathBuf, SystemTime>;
+
+/// Adds the files in `dir` to `files`, except for hidden files like `.git` and the
+/// target directory.
+fn scan(dir: &Path, target_dir: &Path, files: &mut Snapshot) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let path = entry.path();
+    if entry.file_name().to_string_lossy().starts_with('.')
+      || path.starts_with(target_dir)
+    {
+      continue;
+    }
+    let metadata = entry.metadata()?;
+    if metadata.is_dir() {
+      scan(&path, target_dir, files)?;
+    } else {
+      files.insert(path, metadata.modified()?);
+    }
+  }
+  Ok(())
+}
+
+fn snapshot(workspace_root: &Path, target_dir: &Path) -> Snapshot {
+  let mut files = Snapshot::new();
+  if let Err(e) = scan(workspace_root, target_dir, &mut files) {
+    log::warn!(
+      "Failed to scan {} for changes: {e}",
+      workspace_root.display()
+    );
+  }
+  files
+}
+
+/// Runs `cargo <plugin>` without `--watch` until the process is interrupted, starting
+/// again whenever a file in `workspace_root` changes.
+pub(super) fn watch(workspace_root: &Path, target_dir: &Path) -> ! {
+  let exe = env::current_exe().expect("current executable path invalid");
+  let mut args = env::args().skip(1).collect::<Vec<_>>();
+  let separator = args
+    .iter()
+    .position(|arg| arg == "--")
+    .unwrap_or(args.len());
+  if let Some(i) = args[.. separator].iter().position(|arg| arg == "--watch") {
+    args.remove(i);
+  }
+
+  // Files changed during a run are compared with the snapshot from before it, so they
+  // start another run.
+  let mut files = snapshot(workspace_root, target_dir);
+  loop {
+    let status = Command::new(&exe)
+      .args(&args)
+      .status()
+      .expect("failed to run the plugin");
+    let code = status
+      .code()
+      .map_or("none".to_string(), |code| code.to_string());
+    eprintln!(
+      "[exit code {code}] Watching {} for changes",
+      workspace_root.display()
+    );
+    loop {
+      thread::sleep(POLL_INTERVAL);
+      let changed = snapshot(workspace_root, target_dir);
+      if changed != files {
+        files = changed;
+        break;
+      }
+    }
+  }
+}
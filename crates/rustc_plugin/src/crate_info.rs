@@ -0,0 +1,161 @@
+//! Information about the crate compiled by a rustc invocation.
+
+use std::{env, fmt, ops::Deref, path::Path};
+
+use crate::{
+  cli::{TARGET_TRIPLE, USER_RUSTFLAGS},
+  driver::RunDecision,
+};
+
+/// Cargo passes `-Z force-unstable-if-unmarked` to every crate of the standard library
+/// that it builds, including dependencies like `compiler_builtins`.
+const SYSROOT_CRATE_FLAG: &str = "force-unstable-if-unmarked";
+
+/// The sources of the standard library in a sysroot, from the `rust-src` component.
+const RUST_SRC_LIBRARY: &str = "lib/rustlib/src/rust/library/";
+
+/// What a rustc invocation compiles, as determined by the driver from the arguments and
+/// environment that Cargo passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrateKind {
+  /// A target of a workspace member or of a package selected with `-p`, e.g. its
+  /// library, a binary or a test.
+  Workspace,
+
+  /// A crate of a package outside the workspace, or compiled without Cargo.
+  Dependency,
+
+  /// A procedural macro, which is compiled for the host and loaded by the compiler.
+  ProcMacro,
+
+  /// A build script, i.e. the `build.rs` of a package.
+  BuildScript,
+
+  /// A crate whose root file was generated by a build script, i.e. it is in the
+  /// `OUT_DIR` of the package.
+  CustomBuildOutput,
+}
+
+impl fmt::Display for CrateKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      CrateKind::Workspace => "workspace",
+      CrateKind::Dependency => "dependency",
+      CrateKind::ProcMacro => "proc-macro",
+      CrateKind::BuildScript => "build-script",
+      CrateKind::CustomBuildOutput => "custom-build-output",
+    })
+  }
+}
+
+/// The crate compiled by a rustc invocation, as passed to
+/// [`RustcPlugin::modify_rustc_args`](crate::RustcPlugin::modify_rustc_args).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrateInfo {
+  /// The name of the package containing the crate, or empty if the crate is not
+  /// compiled by Cargo.
+  pub package: String,
+  /// The version of the package, or empty if the crate is not compiled by Cargo.
+  pub package_version: String,
+  /// The name of the crate, e.g. `my_crate`.
+  pub crate_name: String,
+  /// The crate types passed with `--crate-type`, e.g. `lib` or `bin`.
+  pub crate_types: Vec<String>,
+  /// What the invocation compiles. Check this instead of guessing from the arguments,
+  /// e.g. to skip build scripts and proc macros.
+  pub kind: CrateKind,
+  /// True if the crate is compiled as a test harness, i.e. with `--test`.
+  pub test: bool,
+  /// True if the package is a member of the workspace, or was selected with `-p`.
+  pub primary_package: bool,
+  /// True if the plugin runs on the compilation. Otherwise, the crate is compiled by
+  /// rustc without the plugin, e.g. because it is a dependency outside the plugin's
+  /// [`CrateFilter`](crate::CrateFilter).
+  pub runs_plugin: bool,
+  /// True if the crate is part of the standard library, rebuilt by `cargo -Zbuild-std`
+  /// or for a custom sysroot, e.g. `core`, `alloc` or `compiler_builtins`.
+  pub sysroot_crate: bool,
+  /// The flags from `RUSTFLAGS` or the `rustflags` of `.cargo/config.toml` that Cargo
+  /// passed to the invocation, which are part of its arguments. When cross-compiling,
+  /// Cargo does not pass them to build scripts and proc macros. Empty if the crate is not
+  /// compiled by `cargo <plugin>`.
+  pub user_rustflags: Vec<String>,
+}
+
+impl CrateInfo {
+  /// Gets the information from the rustc arguments `args`, where `var` looks up the
+  /// environment variables of the invocation.
+  pub(crate) fn new<T: Deref<Target = str>>(
+    args: &[T],
+    var: impl Fn(&str) -> Option<String>,
+    runs_plugin: bool,
+  ) -> Self {
+    let values = |flag: &str| {
+      let mut values = Vec::new();
+      let mut args = args.iter().map(|arg| &**arg);
+      while let Some(arg) = args.next() {
+        if arg == flag {
+          values.extend(args.next().map(str::to_string));
+        } else if let Some(value) =
+          arg.strip_prefix(flag).and_then(|s| s.strip_prefix('='))
+        {
+          values.push(value.to_string());
+        }
+      }
+      values
+    };
+    let sysroot_crate = values("-Z").iter().any(|flag| flag == SYSROOT_CRATE_FLAG)
+      || args.iter().any(|arg| {
+        arg.strip_prefix("-Z") == Some(SYSROOT_CRATE_FLAG)
+          || (arg.ends_with(".rs") && arg.replace('\\', "/").contains(RUST_SRC_LIBRARY))
+      });
+    let crate_name = values("--crate-name").pop().unwrap_or_default();
+    let crate_types = values("--crate-type");
+    let primary_package = var("CARGO_PRIMARY_PACKAGE").is_some();
+    let generated = args
+      .iter()
+      .find(|arg| arg.ends_with(".rs"))
+      .zip(var("OUT_DIR"))
+      .is_some_and(|(input, out_dir)| Path::new(&**input).starts_with(out_dir));
+    // Cargo names the crate of a build script after its target, `build-script-build`
+    // by default.
+    let kind = if crate_name.starts_with("build_script_") {
+      CrateKind::BuildScript
+    } else if crate_types.iter().any(|ty| ty == "proc-macro") {
+      CrateKind::ProcMacro
+    } else if generated {
+      CrateKind::CustomBuildOutput
+    } else if primary_package {
+      CrateKind::Workspace
+    } else {
+      CrateKind::Dependency
+    };
+    // When cross-compiling, only the crates compiled for the target get the flags.
+    let gets_rustflags = var(TARGET_TRIPLE).is_none() || !values("--target").is_empty();
+    let user_rustflags = match var(USER_RUSTFLAGS) {
+      Some(flags) if gets_rustflags => serde_json::from_str(&flags).unwrap_or_default(),
+      _ => Vec::new(),
+    };
+    CrateInfo {
+      package: var("CARGO_PKG_NAME").unwrap_or_default(),
+      package_version: var("CARGO_PKG_VERSION").unwrap_or_default(),
+      crate_name,
+      crate_types,
+      kind,
+      test: args.iter().any(|arg| &**arg == "--test"),
+      primary_package,
+      runs_plugin,
+      sysroot_crate,
+      user_rustflags,
+    }
+  }
+
+  /// Gets the information about the crate compiled by the current driver process, e.g.
+  /// in the callbacks of the plugin.
+  pub fn current() -> Self {
+    let args = env::args().collect::<Vec<_>>();
+    let var = |name: &str| env::var(name).ok();
+    let runs_plugin = RunDecision::new(&args, var).run_plugin();
+    CrateInfo::new(&args, var, runs_plugin)
+  }
+}
@@ -0,0 +1,53 @@
+//! Summaries of the plugin's analysis of each crate, read back by its dependents.
+//!
+//! A bottom-up interprocedural analysis can save what it learned about a crate with
+//! [`write_crate_summary`], e.g. the effects of each public function, and use it when
+//! analyzing the crates that depend on it with [`read_crate_summary`]. Summaries are
+//! keyed by the `StableCrateId` of the crate, which is derived from the `-C metadata`
+//! hash, so each compilation of a crate has its own summary.
+//!
+//! Cargo compiles a crate after the crates it depends on, so the summaries of the
+//! dependencies are there as long as they are written in the plugin's callbacks. With
+//! `--topological`, `cargo <plugin>` also runs the plugin on the workspace members one at
+//! a time, in a deterministic order.
+
+use std::{env, fs, io, path::PathBuf};
+
+use rustc_middle::ty::TyCtxt;
+use rustc_span::def_id::{CrateNum, LOCAL_CRATE};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::output::OUTPUT_DIR;
+
+fn summary_path(tcx: TyCtxt, krate: CrateNum) -> io::Result<PathBuf> {
+  let dir = env::var_os(OUTPUT_DIR).ok_or_else(|| {
+    io::Error::new(io::ErrorKind::NotFound, format!("{OUTPUT_DIR} is not set"))
+  })?;
+  let file = format!(
+    "{}-{:016x}.json",
+    tcx.crate_name(krate),
+    tcx.stable_crate_id(krate).as_u64()
+  );
+  Ok(PathBuf::from(dir).join("summaries").join(file))
+}
+
+/// Saves the summary of the crate being compiled, for the analysis of its dependents.
+pub fn write_crate_summary<T: Serialize>(tcx: TyCtxt, summary: &T) -> io::Result<()> {
+  let path = summary_path(tcx, LOCAL_CRATE)?;
+  fs::create_dir_all(path.parent().unwrap())?;
+  fs::write(path, serde_json::to_string(summary)?)
+}
+
+/// Reads the summary of the dependency `krate`, or returns `None` if the plugin didn't
+/// write one, e.g. because it didn't run on the dependency.
+pub fn read_crate_summary<T: DeserializeOwned>(
+  tcx: TyCtxt,
+  krate: CrateNum,
+) -> io::Result<Option<T>> {
+  let path = summary_path(tcx, krate)?;
+  match fs::read_to_string(path) {
+    Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(e) => Err(e),
+  }
+}
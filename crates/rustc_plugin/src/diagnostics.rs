@@ -0,0 +1,119 @@
+//! Emitting warnings and errors from a plugin as compiler diagnostics.
+//!
+//! Diagnostics are emitted through rustc's [`DiagCtxt`](rustc_errors::DiagCtxt), so
+//! `cargo <plugin>` renders them like any other compiler diagnostic, with colors and
+//! code frames. Passing `--message-format=json` to `cargo <plugin>` prints them as
+//! JSON messages instead, for editor integration. Emitting an error makes the
+//! compilation of the crate fail. Every diagnostic counts as a finding in the summary
+//! of `cargo <plugin>`, which then exits with code 1.
+//!
+//! ```ignore
+//! PluginDiagnostic::warning(item.span, "function is never called")
+//!   .label(item.ident.span, "defined here")
+//!   .help("remove the function")
+//!   .emit(tcx);
+//! ```
+
+use rustc_errors::{Diag, EmissionGuarantee};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
+
+use crate::summary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+  Warning,
+  Error,
+}
+
+/// A diagnostic with a primary span, built up with labels, notes and help messages.
+#[derive(Debug, Clone)]
+pub struct PluginDiagnostic {
+  level: Level,
+  span: Span,
+  message: String,
+  labels: Vec<(Span, String)>,
+  notes: Vec<String>,
+  help: Vec<String>,
+}
+
+impl PluginDiagnostic {
+  fn new(level: Level, span: Span, message: impl Into<String>) -> Self {
+    PluginDiagnostic {
+      level,
+      span,
+      message: message.into(),
+      labels: Vec::new(),
+      notes: Vec::new(),
+      help: Vec::new(),
+    }
+  }
+
+  /// Creates a warning at `span`.
+  pub fn warning(span: Span, message: impl Into<String>) -> Self {
+    Self::new(Level::Warning, span, message)
+  }
+
+  /// Creates an error at `span`.
+  pub fn error(span: Span, message: impl Into<String>) -> Self {
+    Self::new(Level::Error, span, message)
+  }
+
+  /// Adds a label to a span in the code frame.
+  pub fn label(mut self, span: Span, label: impl Into<String>) -> Self {
+    self.labels.push((span, label.into()));
+    self
+  }
+
+  /// Adds a `note:` message.
+  pub fn note(mut self, note: impl Into<String>) -> Self {
+    self.notes.push(note.into());
+    self
+  }
+
+  /// Adds a `help:` message.
+  pub fn help(mut self, help: impl Into<String>) -> Self {
+    self.help.push(help.into());
+    self
+  }
+
+  fn decorate<G: EmissionGuarantee>(self, mut diag: Diag<'_, G>) {
+    for (span, label) in self.labels {
+      diag.span_label(span, label);
+    }
+    for note in self.notes {
+      diag.note(note);
+    }
+    for help in self.help {
+      diag.help(help);
+    }
+    diag.emit();
+  }
+
+  /// Emits the diagnostic in the current compilation.
+  pub fn emit(mut self, tcx: TyCtxt<'_>) {
+    summary::add_finding(self.level == Level::Error);
+    let dcx = tcx.dcx();
+    let message = std::mem::take(&mut self.message);
+    match self.level {
+      Level::Warning => {
+        let diag = dcx.struct_span_warn(self.span, message);
+        self.decorate(diag);
+      }
+      Level::Error => {
+        let diag = dcx.struct_span_err(self.span, message);
+        self.decorate(diag);
+      }
+    }
+  }
+}
+
+/// Emits a warning at `span`.
+pub fn warn(tcx: TyCtxt<'_>, span: Span, message: impl Into<String>) {
+  PluginDiagnostic::warning(span, message).emit(tcx);
+}
+
+/// Emits an error at `span`.
+pub fn error(tcx: TyCtxt<'_>, span: Span, message: impl Into<String>) {
+  PluginDiagnostic::error(span, message).emit(tcx);
+}
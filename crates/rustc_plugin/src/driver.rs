@@ -8,8 +8,21 @@ use std::{
 use rustc_session::{config::ErrorOutputType, EarlyDiagCtxt};
 use rustc_tools_util::VersionInfo;
 
-use super::plugin::{RustcPlugin, PLUGIN_ARGS};
-use crate::cli::{RUN_ON_ALL_CRATES, SPECIFIC_CRATE, SPECIFIC_TARGET};
+use super::plugin::{self, RustcPlugin};
+use crate::{
+  cli::{
+    SelectedTarget, CHAINED_WRAPPER, RUN_ON_ALL_CRATES, SELECTED_PACKAGES,
+    SELECTED_TARGETS, SPECIFIC_CRATE, SPECIFIC_TARGET, TARGET_TRIPLE,
+  },
+  crate_info::CrateInfo,
+  failure::{self, Supervision},
+  fingerprint::{self, Freshness},
+  jobs,
+  output::OUTPUT_DIR,
+  panic_report, progress,
+  rustdoc::{self, RUSTDOC_PATH},
+  summary,
+};
 
 /// If a command-line option matches `find_arg`, then apply the predicate `pred` on its value. If
 /// true, then return it. The parameter is assumed to be either `--arg=value` or `--arg value`.
@@ -44,7 +57,7 @@ fn toolchain_path(home: Option<String>, toolchain: Option<String>) -> Option<Pat
   })
 }
 
-fn get_sysroot(orig_args: &[String]) -> (bool, String) {
+pub(crate) fn get_sysroot(orig_args: &[String]) -> (bool, String) {
   // Get the sysroot, looking from most specific to this invocation to the least:
   // - command line
   // - runtime environment
@@ -98,6 +111,159 @@ fn get_sysroot(orig_args: &[String]) -> (bool, String) {
 struct DefaultCallbacks;
 impl rustc_driver::Callbacks for DefaultCallbacks {}
 
+/// Runs rustc without the plugin. If the driver was installed as `RUSTC_WRAPPER` in
+/// place of another wrapper, then the compilation is delegated to that wrapper with the
+/// path to `rustc` and the arguments `args`.
+fn run_rustc(
+  args: &[String],
+  rustc: Option<&str>,
+) -> rustc_interface::interface::Result<()> {
+  if let (Some(rustc), Ok(wrapper)) = (rustc, env::var(CHAINED_WRAPPER)) {
+    log::debug!("Delegating to {wrapper}");
+    let status = Command::new(&wrapper)
+      .arg(rustc)
+      .args(&args[1 ..])
+      .status()
+      .unwrap_or_else(|e| panic!("failed to run {wrapper}: {e}"));
+    exit(status.code().unwrap_or(-1));
+  }
+  rustc_driver::RunCompiler::new(args, &mut DefaultCallbacks).run()
+}
+
+/// The conditions under which the driver runs the plugin on a compilation.
+#[derive(Debug)]
+pub(crate) struct RunDecision {
+  normal_rustc: bool,
+  run_on_all_crates: bool,
+  primary_package: bool,
+  is_target_crate: bool,
+  is_target_compilation: bool,
+  is_selected: bool,
+  is_selected_package: bool,
+}
+
+impl RunDecision {
+  /// Evaluates the conditions for a rustc invocation with `args`, where `var` looks up
+  /// the environment variables of the invocation.
+  pub(crate) fn new<T: Deref<Target = str>>(
+    args: &[T],
+    var: impl Fn(&str) -> Option<String>,
+  ) -> Self {
+    // On a given invocation of rustc, we have to decide whether to act as rustc,
+    // or actually execute the plugin. There are three conditions for executing the plugin:
+    // 1. Either we're supposed to run on all crates, or CARGO_PRIMARY_PACKAGE is set.
+    // 2. --print and -vV are NOT passed, since Cargo does that to get info about rustc.
+    // 3. When cross-compiling, the crate is compiled for the target. Build scripts and
+    //    proc macros are compiled for the host, so Cargo does not pass --target for them.
+    let primary_package = var("CARGO_PRIMARY_PACKAGE").is_some();
+    let run_on_all_crates = var(RUN_ON_ALL_CRATES).is_some();
+    let normal_rustc = arg_value(args, "--print", |_| true).is_some()
+      || args.iter().any(|a| &**a == "-vV");
+    let is_target_crate = match (var(SPECIFIC_CRATE), var(SPECIFIC_TARGET)) {
+      (Some(krate), Some(target)) => {
+        arg_value(args, "--crate-name", |name| name == krate).is_some()
+          && arg_value(args, "--crate-type", |name| name == target).is_some()
+      }
+      _ => true,
+    };
+    let is_target_compilation = match var(TARGET_TRIPLE) {
+      Some(triple) => arg_value(args, "--target", |target| target == triple).is_some(),
+      None => true,
+    };
+    let is_selected = match var(SELECTED_TARGETS) {
+      Some(selected) => {
+        let selected: Vec<SelectedTarget> = serde_json::from_str(&selected).unwrap();
+        let package = var("CARGO_PKG_NAME").unwrap_or_default();
+        selected.iter().any(|target| target.matches(&package, args))
+      }
+      None => true,
+    };
+    let is_selected_package = match var(SELECTED_PACKAGES) {
+      Some(selected) => {
+        let selected: Vec<String> = serde_json::from_str(&selected).unwrap();
+        let package = format!(
+          "{} {}",
+          var("CARGO_PKG_NAME").unwrap_or_default(),
+          var("CARGO_PKG_VERSION").unwrap_or_default()
+        );
+        selected.contains(&package)
+      }
+      None => true,
+    };
+    RunDecision {
+      normal_rustc,
+      run_on_all_crates,
+      primary_package,
+      is_target_crate,
+      is_target_compilation,
+      is_selected,
+      is_selected_package,
+    }
+  }
+
+  /// Returns true if the plugin should run on the compilation.
+  pub(crate) fn run_plugin(&self) -> bool {
+    !self.normal_rustc
+      && (self.run_on_all_crates || self.primary_package)
+      && self.is_target_crate
+      && self.is_target_compilation
+      && self.is_selected
+      && self.is_selected_package
+  }
+}
+
+/// Lets the plugin modify the arguments of a compilation, unless Cargo only invoked
+/// rustc to get information about it.
+pub(crate) fn modify_rustc_args<T: RustcPlugin>(
+  plugin: &T,
+  decision: &RunDecision,
+  args: &mut Vec<String>,
+) {
+  if decision.normal_rustc {
+    return;
+  }
+  let crate_info =
+    CrateInfo::new(args, |name| env::var(name).ok(), decision.run_plugin());
+  plugin.modify_rustc_args(&crate_info, args);
+  log::debug!("Arguments after modify_rustc_args: {args:?}");
+}
+
+/// The directory that [`run_on_file`] writes the crate metadata to.
+pub(crate) fn standalone_dir(driver_name: &str) -> PathBuf {
+  env::temp_dir().join(driver_name)
+}
+
+/// Runs the plugin on a single source file without Cargo, e.g. to analyze a code
+/// snippet in a test or bug report. This is what `cargo <plugin> --file <file>` does.
+///
+/// The file is compiled as a library with the 2021 edition and the sysroot of the
+/// current toolchain. Unless [`OUTPUT_DIR`] is set, the output directory of the crate is
+/// in a temporary directory.
+pub fn run_on_file<T: RustcPlugin>(
+  plugin: T,
+  file: &Path,
+  plugin_args: T::Args,
+) -> rustc_interface::interface::Result<()> {
+  let out_dir = standalone_dir(&plugin.driver_name());
+  if env::var_os(OUTPUT_DIR).is_none() {
+    env::set_var(OUTPUT_DIR, &out_dir);
+  }
+  let mut args = vec![
+    "rustc".to_string(),
+    file.to_string_lossy().into_owned(),
+    "--edition=2021".into(),
+    "--crate-type=lib".into(),
+    "--emit=metadata".into(),
+    "--out-dir".into(),
+    out_dir.to_string_lossy().into_owned(),
+  ];
+  let (_, sys_root) = get_sysroot(&args);
+  args.extend(["--sysroot".into(), sys_root]);
+  let crate_info = CrateInfo::new(&args, |name| env::var(name).ok(), true);
+  plugin.modify_rustc_args(&crate_info, &mut args);
+  plugin.run(args, plugin_args)
+}
+
 /// The top-level function that should be called by your internal driver binary.
 pub fn driver_main<T: RustcPlugin>(plugin: T) {
   let early_dcx = EarlyDiagCtxt::new(ErrorOutputType::default());
@@ -106,6 +272,11 @@ pub fn driver_main<T: RustcPlugin>(plugin: T) {
   exit(rustc_driver::catch_with_exit_code(move || {
     let mut orig_args: Vec<String> = env::args().collect();
 
+    // In `--doc` mode, the driver stands in for rustdoc, including `--version`.
+    if let Ok(rustdoc) = env::var(RUSTDOC_PATH) {
+      return rustdoc::run_rustdoc(plugin, &rustdoc, &orig_args);
+    }
+
     let (have_sys_root_arg, sys_root) = get_sysroot(&orig_args);
 
     if orig_args.iter().any(|a| a == "--version" || a == "-V") {
@@ -119,10 +290,7 @@ pub fn driver_main<T: RustcPlugin>(plugin: T) {
     let wrapper_mode =
       orig_args.get(1).map(Path::new).and_then(Path::file_stem) == Some("rustc".as_ref());
 
-    if wrapper_mode {
-      // we still want to be able to invoke it normally though
-      orig_args.remove(1);
-    }
+    let rustc = wrapper_mode.then(|| orig_args.remove(1));
 
     // this conditional check for the --sysroot flag is there so users can call
     // the driver directly without having to pass --sysroot or anything
@@ -131,37 +299,37 @@ pub fn driver_main<T: RustcPlugin>(plugin: T) {
       args.extend(["--sysroot".into(), sys_root]);
     };
 
-    // On a given invocation of rustc, we have to decide whether to act as rustc,
-    // or actually execute the plugin. There are two conditions for executing the plugin:
-    // 1. Either we're supposed to run on all crates, or CARGO_PRIMARY_PACKAGE is set.
-    // 2. --print is NOT passed, since Cargo does that to get info about rustc.
-    let primary_package = env::var("CARGO_PRIMARY_PACKAGE").is_ok();
-    let run_on_all_crates = env::var(RUN_ON_ALL_CRATES).is_ok();
-    let normal_rustc = arg_value(&args, "--print", |_| true).is_some();
-    let is_target_crate = match (env::var(SPECIFIC_CRATE), env::var(SPECIFIC_TARGET)) {
-      (Ok(krate), Ok(target)) => {
-        arg_value(&args, "--crate-name", |name| name == krate).is_some()
-          && arg_value(&args, "--crate-type", |name| name == target).is_some()
+    let decision = RunDecision::new(&args, |name| env::var(name).ok());
+    modify_rustc_args(&plugin, &decision, &mut args);
+    if decision.run_plugin() {
+      progress::report_start(&args);
+      let plugin_args = plugin::plugin_args_json().expect("failed to read plugin args");
+      if plugin.incremental()
+        && matches!(
+          fingerprint::check(&plugin.version(), &plugin_args, &args),
+          Freshness::Fresh
+        )
+      {
+        return run_rustc(&args, rustc.as_deref());
+      }
+
+      // The slot is held by the process that runs the plugin, or supervises it. Since
+      // supervision is inside of fingerprinting, the process running the plugin is the
+      // one killed on timeout.
+      let _job = jobs::acquire();
+      if let Supervision::RetryWithoutPlugin = failure::supervise(&args) {
+        return run_rustc(&args, rustc.as_deref());
       }
-      _ => true,
-    };
-    let run_plugin =
-      !normal_rustc && (run_on_all_crates || primary_package) && is_target_crate;
 
-    if run_plugin {
       log::debug!("Running plugin...");
-      let plugin_args: T::Args =
-        serde_json::from_str(&env::var(PLUGIN_ARGS).unwrap()).unwrap();
-      plugin.run(args, plugin_args)
+      panic_report::install_hook(plugin.version().into_owned());
+      let plugin_args: T::Args = serde_json::from_str(&plugin_args).unwrap();
+      let result = plugin.run(args.clone(), plugin_args);
+      summary::record_run(&args, &result);
+      result
     } else {
-      log::debug!(
-        "Running normal Rust. Relevant variables:\
-normal_rustc={normal_rustc}, \
-run_on_all_crates={run_on_all_crates}, \
-primary_package={primary_package}, \
-is_target_crate={is_target_crate}"
-      );
-      rustc_driver::RunCompiler::new(&args, &mut DefaultCallbacks).run()
+      log::debug!("Running normal Rust. Relevant variables: {decision:?}");
+      run_rustc(&args, rustc.as_deref())
     }
   }))
 }
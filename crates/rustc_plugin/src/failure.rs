@@ -0,0 +1,175 @@
+//! Limiting the time the plugin runs on a crate, and handling crates it fails on.
+//!
+//! With `--crate-timeout <secs>` or `--on-failure <policy>`, the driver runs the plugin
+//! on each crate in a child process that it kills after the timeout. If the plugin
+//! fails or times out, the failure is recorded in the summary of `cargo <plugin>`, and
+//! handled by the [`FailurePolicy`].
+
+use std::{
+  env, fmt, fs, io,
+  process::{self, exit, Command},
+  str::FromStr,
+  thread,
+  time::{Duration, Instant},
+};
+
+use crate::{
+  progress::PROGRESS_FILE,
+  summary::{status_path, CrateStatus, Status},
+};
+
+/// The name of the environment variable containing the timeout in seconds.
+pub const CRATE_TIMEOUT: &str = "RUSTC_PLUGIN_CRATE_TIMEOUT";
+
+/// The name of the environment variable containing the [`FailurePolicy`].
+pub const FAILURE_POLICY: &str = "RUSTC_PLUGIN_FAILURE_POLICY";
+
+/// Set in the child process that runs the plugin.
+const SUPERVISED: &str = "RUSTC_PLUGIN_SUPERVISED";
+
+/// What to do when the plugin fails or times out on a crate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+  /// Fail the compilation of the crate, so `cargo` stops.
+  #[default]
+  AbortAll,
+
+  /// Fail the compilation of the crate, but keep compiling crates that don't depend
+  /// on it, with `cargo --keep-going`.
+  SkipAndContinue,
+
+  /// Compile the crate again without the plugin, so its dependents can still be
+  /// analyzed.
+  RetryWithoutPlugin,
+}
+
+impl FromStr for FailurePolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "abort-all" => Ok(FailurePolicy::AbortAll),
+      "skip-and-continue" => Ok(FailurePolicy::SkipAndContinue),
+      "retry-without-plugin" => Ok(FailurePolicy::RetryWithoutPlugin),
+      _ => Err(format!(
+        "invalid failure policy `{s}`, expected one of: abort-all, skip-and-continue, \
+         retry-without-plugin"
+      )),
+    }
+  }
+}
+
+impl fmt::Display for FailurePolicy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      FailurePolicy::AbortAll => "abort-all",
+      FailurePolicy::SkipAndContinue => "skip-and-continue",
+      FailurePolicy::RetryWithoutPlugin => "retry-without-plugin",
+    })
+  }
+}
+
+/// How the driver should continue after [`supervise`].
+pub enum Supervision {
+  /// Run the plugin in this process.
+  Run,
+  /// The plugin failed, so compile the crate without it.
+  RetryWithoutPlugin,
+}
+
+/// Runs the plugin on the crate compiled with `compiler_args` in a child process, if a
+/// timeout or failure policy is set.
+///
+/// If the child succeeds, or fails under a policy other than
+/// [`FailurePolicy::RetryWithoutPlugin`], then this exits with its exit code.
+pub fn supervise(compiler_args: &[String]) -> Supervision {
+  let timeout = env::var(CRATE_TIMEOUT)
+    .ok()
+    .map(|secs| Duration::from_secs(secs.parse().unwrap()));
+  let policy = env::var(FAILURE_POLICY)
+    .ok()
+    .map(|policy| policy.parse::<FailurePolicy>().unwrap());
+  if (timeout.is_none() && policy.is_none()) || env::var(SUPERVISED).is_ok() {
+    return Supervision::Run;
+  }
+  let policy = policy.unwrap_or_default();
+
+  let ChildExit { code, pid, failure } =
+    run_child(timeout).expect("failed to run the plugin");
+  let child_status = status_path(pid);
+  let Some((status, reason)) = failure else {
+    // The status recorded by the child is the status of this process for its parents.
+    if let (Some(from), Some(to)) = (child_status, status_path(process::id())) {
+      let _ = fs::rename(from, to);
+    }
+    exit(code);
+  };
+  // The child's own status is replaced by the failure.
+  if let Some(child_status) = child_status {
+    let _ = fs::remove_file(child_status);
+  }
+
+  let retried = policy == FailurePolicy::RetryWithoutPlugin;
+  let crate_status = CrateStatus::new(compiler_args, status, 0).failed(reason, retried);
+  eprintln!(
+    "error: the plugin {} on crate `{}`",
+    crate_status.reason().unwrap(),
+    crate_status.crate_name()
+  );
+  crate_status.record();
+
+  if retried {
+    Supervision::RetryWithoutPlugin
+  } else {
+    exit(if code == 0 { 1 } else { code });
+  }
+}
+
+/// How the child process running the plugin exited.
+struct ChildExit {
+  code: i32,
+  pid: u32,
+  /// The status and reason of a failure.
+  failure: Option<(Status, String)>,
+}
+
+/// Runs the current process again with the same arguments, killing it after `timeout`.
+fn run_child(timeout: Option<Duration>) -> io::Result<ChildExit> {
+  let mut child = Command::new(env::current_exe()?)
+    .args(env::args_os().skip(1))
+    .env(SUPERVISED, "")
+    // The progress of the crate was already reported by this process.
+    .env_remove(PROGRESS_FILE)
+    .spawn()?;
+
+  let start = Instant::now();
+  loop {
+    if let Some(status) = child.try_wait()? {
+      let code = status.code().unwrap_or(-1);
+      let failure = (!status.success()).then(|| match status.code() {
+        // The exit code of a Rust process that panicked.
+        Some(101) => (Status::Failed, "panicked".to_string()),
+        Some(code) => (Status::Failed, format!("failed with exit code {code}")),
+        None => (Status::Failed, "was terminated by a signal".to_string()),
+      });
+      return Ok(ChildExit {
+        code,
+        pid: child.id(),
+        failure,
+      });
+    }
+    if let Some(timeout) = timeout {
+      if start.elapsed() >= timeout {
+        child.kill()?;
+        child.wait()?;
+        let reason = format!("timed out after {}s", timeout.as_secs());
+        return Ok(ChildExit {
+          code: 1,
+          pid: child.id(),
+          failure: Some((Status::TimedOut, reason)),
+        });
+      }
+    }
+    thread::sleep(Duration::from_millis(50));
+  }
+}
@@ -0,0 +1,222 @@
+//! Skipping the plugin on crates that have not changed since its last run.
+//!
+//! The fingerprint of a crate hashes the plugin version and arguments, the compiler
+//! arguments, the sources of the crate's package and the metadata of its
+//! dependencies. When the plugin runs on a crate, the driver runs itself in a child
+//! process to capture the plugin's stdout and stderr, including its diagnostics, and
+//! saves them with the fingerprint. If a later invocation has the same fingerprint,
+//! the saved output is printed instead, and the crate is compiled without the plugin.
+//! Results emitted with [`emit_result`](crate::emit_result) are saved and replayed the
+//! same way.
+
+use std::{
+  collections::hash_map::DefaultHasher,
+  env, fs,
+  hash::{Hash, Hasher},
+  io::{self, Read, Write},
+  path::{Path, PathBuf},
+  process::{self, exit, Command, Stdio},
+  thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  aggregate,
+  progress::PROGRESS_FILE,
+  summary::{self, CrateStatus, Status},
+};
+
+/// The name of the environment variable containing the directory of fingerprints.
+pub const FINGERPRINT_DIR: &str = "RUSTC_PLUGIN_FINGERPRINT_DIR";
+
+/// Set in the child process that runs the plugin.
+const CAPTURING: &str = "RUSTC_PLUGIN_CAPTURING";
+
+#[derive(Serialize, Deserialize)]
+struct Fingerprint {
+  hash: String,
+  stdout: String,
+  /// Required, so fingerprints saved before stderr was captured are not replayed
+  /// without the diagnostics of the plugin.
+  stderr: String,
+  /// The lines of the results file of the crate.
+  #[serde(default)]
+  results: Vec<String>,
+  /// The number of diagnostics emitted by the plugin.
+  #[serde(default)]
+  findings: usize,
+}
+
+fn hash_sources(dir: &Path, hasher: &mut DefaultHasher) -> io::Result<()> {
+  let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+  entries.sort_by_key(|entry| entry.file_name());
+  for entry in entries {
+    let path = entry.path();
+    let name = entry.file_name();
+    let name = name.to_string_lossy();
+    if entry.file_type()?.is_dir() {
+      if name != "target" && !name.starts_with('.') {
+        hash_sources(&path, hasher)?;
+      }
+    } else if name.ends_with(".rs") || name == "Cargo.toml" {
+      path.hash(hasher);
+      fs::read(&path)?.hash(hasher);
+    }
+  }
+  Ok(())
+}
+
+fn compute(version: &str, plugin_args: &str, compiler_args: &[String]) -> String {
+  let mut hasher = DefaultHasher::new();
+  (version, plugin_args, compiler_args).hash(&mut hasher);
+
+  if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
+    if let Err(e) = hash_sources(Path::new(&manifest_dir), &mut hasher) {
+      log::warn!("Failed to hash sources in {manifest_dir}: {e}");
+    }
+  }
+
+  // Dependencies are identified by their compiled metadata, which changes whenever
+  // they are recompiled.
+  let externs = compiler_args
+    .windows(2)
+    .filter(|pair| pair[0] == "--extern")
+    .filter_map(|pair| pair[1].split_once('=').map(|(_, path)| path));
+  for path in externs {
+    if let Ok(metadata) = fs::metadata(path) {
+      (metadata.len(), metadata.modified().ok()).hash(&mut hasher);
+    }
+  }
+
+  format!("{:016x}", hasher.finish())
+}
+
+/// The environment variable that Cargo hashes into the `-C metadata` of every crate.
+pub const CARGO_METADATA_SEED: &str = "__CARGO_DEFAULT_LIB_METADATA";
+
+/// Returns a hash identifying a build of the plugin and its configuration, from its
+/// `version`, the size and modification time of its `driver` binary, and the `env`
+/// from [`RustcPlugin::env`](crate::RustcPlugin::env).
+///
+/// `cargo <plugin>` passes the hash in [`CARGO_METADATA_SEED`]. So when the plugin is
+/// upgraded, reinstalled or configured differently, Cargo gives every crate a new
+/// `-C metadata` and analyzes it again, instead of reusing metadata compiled by the old
+/// plugin. Since the compiler arguments change, the fingerprints of the crates change
+/// too.
+pub fn plugin_hash(version: &str, driver: &Path, env: &[(String, String)]) -> String {
+  let mut hasher = DefaultHasher::new();
+  (version, env).hash(&mut hasher);
+  match fs::metadata(driver) {
+    Ok(metadata) => (metadata.len(), metadata.modified().ok()).hash(&mut hasher),
+    Err(e) => log::warn!("Failed to read metadata of {}: {e}", driver.display()),
+  }
+  format!("{:016x}", hasher.finish())
+}
+
+fn fingerprint_path(dir: &Path, compiler_args: &[String]) -> PathBuf {
+  dir.join(format!("{}.json", aggregate::crate_key(compiler_args)))
+}
+
+/// Whether the plugin must run on a crate.
+pub enum Freshness {
+  /// Run the plugin in this process, e.g. because fingerprints are disabled.
+  Run,
+  /// The fingerprint is unchanged, and the saved output was printed.
+  Fresh,
+}
+
+/// Checks the fingerprint of the crate compiled with `compiler_args`.
+///
+/// If the fingerprint changed, then this runs the plugin in a child process, saves its
+/// output and exits with the exit code of the child.
+pub fn check(version: &str, plugin_args: &str, compiler_args: &[String]) -> Freshness {
+  let Ok(dir) = env::var(FINGERPRINT_DIR) else {
+    return Freshness::Run;
+  };
+  if env::var(CAPTURING).is_ok() {
+    return Freshness::Run;
+  }
+
+  let hash = compute(version, plugin_args, compiler_args);
+  let path = fingerprint_path(Path::new(&dir), compiler_args);
+  let saved = fs::read_to_string(&path)
+    .ok()
+    .and_then(|contents| serde_json::from_str::<Fingerprint>(&contents).ok());
+  if let Some(saved) = saved.filter(|saved| saved.hash == hash) {
+    log::debug!("Fingerprint {} is unchanged", path.display());
+    print!("{}", saved.stdout);
+    eprint!("{}", saved.stderr);
+    if let Some(results_path) = aggregate::results_path(process::id()) {
+      aggregate::append_lines(&results_path, saved.results)
+        .expect("failed to replay results");
+    }
+    CrateStatus::new(compiler_args, Status::Skipped, saved.findings).record();
+    return Freshness::Fresh;
+  }
+
+  let (code, stdout, stderr, pid) = run_capturing().expect("failed to run the plugin");
+  // A crate compiled without the plugin after a failure must be analyzed next time.
+  let status = summary::read_status(pid);
+  let failed = status
+    .as_ref()
+    .is_some_and(|status| matches!(status.status(), Status::Failed | Status::TimedOut));
+  if code == 0 && !failed {
+    let results = aggregate::results_path(pid)
+      .and_then(|results_path| fs::read_to_string(results_path).ok())
+      .map(|contents| contents.lines().map(str::to_string).collect())
+      .unwrap_or_default();
+    let fingerprint = Fingerprint {
+      hash,
+      stdout,
+      stderr,
+      results,
+      findings: status.map_or(0, |status| status.findings()),
+    };
+    let saved = fs::create_dir_all(&dir)
+      .and_then(|()| fs::write(&path, serde_json::to_string(&fingerprint).unwrap()));
+    if let Err(e) = saved {
+      log::warn!("Failed to save fingerprint {}: {e}", path.display());
+    }
+  }
+  exit(code);
+}
+
+/// Runs the current process again with the same arguments, forwarding its stdout and
+/// stderr while capturing them. Returns the exit code, stdout, stderr and process ID of
+/// the child.
+fn run_capturing() -> io::Result<(i32, String, String, u32)> {
+  let mut child = Command::new(env::current_exe()?)
+    .args(env::args_os().skip(1))
+    .env(CAPTURING, "")
+    // The progress of the crate was already reported by this process.
+    .env_remove(PROGRESS_FILE)
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+
+  // Both pipes are read at once, so the child never blocks on a full pipe.
+  let child_stderr = child.stderr.take().unwrap();
+  let stderr = thread::spawn(move || tee(child_stderr, io::stderr()));
+  let stdout = tee(child.stdout.take().unwrap(), io::stdout())?;
+  let stderr = stderr.join().unwrap()?;
+
+  let status = child.wait()?;
+  Ok((status.code().unwrap_or(-1), stdout, stderr, child.id()))
+}
+
+/// Copies `reader` to `writer`, returning everything that was copied.
+fn tee(mut reader: impl Read, mut writer: impl Write) -> io::Result<String> {
+  let mut captured = Vec::new();
+  let mut buf = [0; 4096];
+  loop {
+    let n = reader.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    writer.write_all(&buf[.. n])?;
+    captured.extend_from_slice(&buf[.. n]);
+  }
+  writer.flush()?;
+  Ok(String::from_utf8_lossy(&captured).into_owned())
+}
@@ -0,0 +1,333 @@
+//! Running several plugins over a single compilation.
+
+use std::{borrow::Cow, process::exit, sync::Mutex};
+
+use cargo_metadata::camino::Utf8Path;
+use rustc_driver::{Callbacks, Compilation};
+use rustc_interface::{interface, Queries};
+use rustc_middle::util::Providers;
+use rustc_session::Session;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  read_plugin_args, BuildMode, CargoArgs, CrateFilter, CrateInfo, RustcPlugin,
+  RustcPluginArgs,
+};
+
+/// The callbacks of a plugin, as returned by [`RustcPlugin::callbacks`].
+pub type PluginCallbacks = Box<dyn Callbacks + Send>;
+
+/// A [`RustcPlugin`] with its `Args` serialized, so plugins with different argument
+/// types can be stored together.
+trait ErasedPlugin {
+  fn version(&self) -> Cow<'static, str>;
+  fn args(&self, target_dir: &Utf8Path) -> (serde_json::Value, CrateFilter, BuildMode);
+  fn incremental(&self) -> bool;
+  fn uses_cargo_metadata(&self, args: &serde_json::Value) -> bool;
+  fn env(&self) -> Vec<(String, String)>;
+  fn modify_cargo(&self, cargo: &mut std::process::Command, args: &serde_json::Value);
+  fn modify_rustc_args(&self, crate_info: &CrateInfo, args: &mut Vec<String>);
+  fn callbacks(&self, args: serde_json::Value) -> Option<PluginCallbacks>;
+}
+
+impl<P: RustcPlugin> ErasedPlugin for P {
+  fn version(&self) -> Cow<'static, str> {
+    RustcPlugin::version(self)
+  }
+
+  fn args(&self, target_dir: &Utf8Path) -> (serde_json::Value, CrateFilter, BuildMode) {
+    let RustcPluginArgs { args, filter, mode } = RustcPlugin::args(self, target_dir);
+    (serde_json::to_value(args).unwrap(), filter, mode)
+  }
+
+  fn incremental(&self) -> bool {
+    RustcPlugin::incremental(self)
+  }
+
+  fn uses_cargo_metadata(&self, args: &serde_json::Value) -> bool {
+    let args = P::Args::deserialize(args).unwrap();
+    RustcPlugin::uses_cargo_metadata(self, &args)
+  }
+
+  fn env(&self) -> Vec<(String, String)> {
+    RustcPlugin::env(self)
+  }
+
+  fn modify_cargo(&self, cargo: &mut std::process::Command, args: &serde_json::Value) {
+    let args = P::Args::deserialize(args).unwrap();
+    RustcPlugin::modify_cargo(self, cargo, &args);
+  }
+
+  fn modify_rustc_args(&self, crate_info: &CrateInfo, args: &mut Vec<String>) {
+    RustcPlugin::modify_rustc_args(self, crate_info, args);
+  }
+
+  fn callbacks(&self, args: serde_json::Value) -> Option<PluginCallbacks> {
+    let args = serde_json::from_value(args).unwrap();
+    RustcPlugin::callbacks(self, args)
+  }
+}
+
+/// A plugin made of several plugins, which run over the same compilation of each crate.
+///
+/// The plugins to run are selected with `--plugins a,b`, and default to all of them.
+/// Each plugin must implement [`RustcPlugin::callbacks`], and parses its arguments
+/// from the same command line in [`RustcPlugin::args`], so it should accept the flags
+/// of the other plugins. The group runs on the crates selected by the most inclusive
+/// filter of its plugins, and builds them if any plugin uses [`BuildMode::Build`].
+///
+/// ```ignore
+/// let group = PluginGroup::new("print-all-items-group-driver", env!("CARGO_PKG_VERSION"))
+///   .add("items", PrintAllItemsPlugin)
+///   .add("count", CountItemsPlugin);
+/// rustc_plugin::cli_main(group);
+/// ```
+pub struct PluginGroup {
+  driver_name: Cow<'static, str>,
+  version: Cow<'static, str>,
+  plugins: Vec<(String, Box<dyn ErasedPlugin>)>,
+}
+
+/// The arguments of each plugin selected in a [`PluginGroup`].
+#[derive(Serialize, Deserialize)]
+pub struct PluginGroupArgs {
+  plugins: Vec<(String, serde_json::Value)>,
+}
+
+impl PluginGroup {
+  /// Creates an empty group, with the driver name and version used for all of its
+  /// plugins.
+  pub fn new(
+    driver_name: impl Into<Cow<'static, str>>,
+    version: impl Into<Cow<'static, str>>,
+  ) -> Self {
+    PluginGroup {
+      driver_name: driver_name.into(),
+      version: version.into(),
+      plugins: Vec::new(),
+    }
+  }
+
+  /// Adds a plugin that is selected with `--plugins <name>`.
+  pub fn add(
+    mut self,
+    name: impl Into<String>,
+    plugin: impl RustcPlugin + 'static,
+  ) -> Self {
+    self.plugins.push((name.into(), Box::new(plugin)));
+    self
+  }
+
+  fn plugin(&self, name: &str) -> &dyn ErasedPlugin {
+    let (_, plugin) = self.plugins.iter().find(|(n, _)| n == name).unwrap();
+    plugin.as_ref()
+  }
+
+  fn selected<'a>(
+    &'a self,
+    args: &'a PluginGroupArgs,
+  ) -> impl Iterator<Item = (&'a dyn ErasedPlugin, &'a serde_json::Value)> {
+    args
+      .plugins
+      .iter()
+      .map(|(name, args)| (self.plugin(name), args))
+  }
+}
+
+impl RustcPlugin for PluginGroup {
+  type Args = PluginGroupArgs;
+
+  fn version(&self) -> Cow<'static, str> {
+    let versions = self
+      .plugins
+      .iter()
+      .map(|(name, plugin)| format!("{name} {}", plugin.version()))
+      .collect::<Vec<_>>();
+    format!("{} ({})", self.version, versions.join(", ")).into()
+  }
+
+  fn driver_name(&self) -> Cow<'static, str> {
+    self.driver_name.clone()
+  }
+
+  fn args(&self, target_dir: &Utf8Path) -> RustcPluginArgs<Self::Args> {
+    let (cargo_args, _) = CargoArgs::parse(std::env::args());
+    let names = if cargo_args.plugins.is_empty() {
+      self.plugins.iter().map(|(name, _)| name.clone()).collect()
+    } else {
+      cargo_args.plugins
+    };
+
+    let mut plugins = Vec::new();
+    let mut filter: Option<CrateFilter> = None;
+    let mut mode = BuildMode::Check;
+    for name in names {
+      let Some((_, plugin)) = self.plugins.iter().find(|(n, _)| *n == name) else {
+        let known = self.plugins.iter().map(|(n, _)| n.as_str());
+        eprintln!(
+          "error: unknown plugin `{name}`, expected one of: {}",
+          known.collect::<Vec<_>>().join(", ")
+        );
+        exit(1);
+      };
+      let (args, plugin_filter, plugin_mode) = plugin.args(target_dir);
+      if plugin_mode == BuildMode::Build {
+        mode = BuildMode::Build;
+      }
+      filter = Some(match (filter, plugin_filter) {
+        (None, f) => f,
+        (Some(CrateFilter::AllCrates), _) | (_, CrateFilter::AllCrates) => {
+          CrateFilter::AllCrates
+        }
+        (
+          Some(CrateFilter::CrateContainingFile(a)),
+          CrateFilter::CrateContainingFile(b),
+        ) if a == b => CrateFilter::CrateContainingFile(a),
+        (
+          Some(CrateFilter::OnlyWorkspace | CrateFilter::CrateContainingFile(_)),
+          CrateFilter::OnlyWorkspace | CrateFilter::CrateContainingFile(_),
+        ) => CrateFilter::OnlyWorkspace,
+        (Some(a), b) => CrateFilter::Predicate(Box::new(move |metadata, pkg| {
+          a.selects_package(metadata, pkg) || b.selects_package(metadata, pkg)
+        })),
+      });
+      plugins.push((name, args));
+    }
+
+    RustcPluginArgs {
+      args: PluginGroupArgs { plugins },
+      filter: filter.unwrap_or(CrateFilter::OnlyWorkspace),
+      mode,
+    }
+  }
+
+  fn incremental(&self) -> bool {
+    self.plugins.iter().all(|(_, plugin)| plugin.incremental())
+  }
+
+  fn uses_cargo_metadata(&self, args: &Self::Args) -> bool {
+    self
+      .selected(args)
+      .any(|(plugin, args)| plugin.uses_cargo_metadata(args))
+  }
+
+  // The variables of every plugin are set, since the selected plugins are only known
+  // from the arguments.
+  fn env(&self) -> Vec<(String, String)> {
+    self
+      .plugins
+      .iter()
+      .flat_map(|(_, plugin)| plugin.env())
+      .collect()
+  }
+
+  fn modify_cargo(&self, cargo: &mut std::process::Command, args: &Self::Args) {
+    for (plugin, args) in self.selected(args) {
+      plugin.modify_cargo(cargo, args);
+    }
+  }
+
+  fn modify_rustc_args(&self, crate_info: &CrateInfo, args: &mut Vec<String>) {
+    let Ok(group_args) = read_plugin_args::<PluginGroupArgs>() else {
+      return;
+    };
+    for (plugin, _) in self.selected(&group_args) {
+      plugin.modify_rustc_args(crate_info, args);
+    }
+  }
+
+  fn run(
+    self,
+    compiler_args: Vec<String>,
+    plugin_args: Self::Args,
+  ) -> interface::Result<()> {
+    let callbacks = plugin_args
+      .plugins
+      .into_iter()
+      .map(|(name, args)| {
+        self.plugin(&name).callbacks(args).unwrap_or_else(|| {
+          panic!("plugin `{name}` does not implement RustcPlugin::callbacks")
+        })
+      })
+      .collect();
+    let mut callbacks = GroupCallbacks { callbacks };
+    rustc_driver::RunCompiler::new(&compiler_args, &mut callbacks).run()
+  }
+}
+
+/// Calls the callbacks of each plugin in order. Compilation stops after a phase if
+/// any plugin asks to stop.
+///
+/// The query overrides set by each plugin in [`Callbacks::config`] are all applied, in
+/// the order of the plugins, so each one sees the providers installed by the previous
+/// ones.
+struct GroupCallbacks {
+  callbacks: Vec<PluginCallbacks>,
+}
+
+impl GroupCallbacks {
+  fn each(
+    &mut self,
+    mut f: impl FnMut(&mut PluginCallbacks) -> Compilation,
+  ) -> Compilation {
+    let mut result = Compilation::Continue;
+    for callbacks in &mut self.callbacks {
+      if f(callbacks) == Compilation::Stop {
+        result = Compilation::Stop;
+      }
+    }
+    result
+  }
+}
+
+type OverrideQueries = fn(&Session, &mut Providers);
+
+/// The query overrides collected by [`GroupCallbacks::config`]. `override_queries` is
+/// a `fn` pointer, so it cannot capture them, but a driver only runs one compilation.
+static GROUP_OVERRIDES: Mutex<Vec<OverrideQueries>> = Mutex::new(Vec::new());
+
+fn override_queries(session: &Session, providers: &mut Providers) {
+  for override_queries in GROUP_OVERRIDES.lock().unwrap().iter() {
+    override_queries(session, providers);
+  }
+}
+
+impl Callbacks for GroupCallbacks {
+  fn config(&mut self, config: &mut interface::Config) {
+    // Take each plugin's override before the next plugin can replace it.
+    let mut overrides = Vec::new();
+    overrides.extend(config.override_queries.take());
+    for callbacks in &mut self.callbacks {
+      callbacks.config(config);
+      overrides.extend(config.override_queries.take());
+    }
+    if !overrides.is_empty() {
+      *GROUP_OVERRIDES.lock().unwrap() = overrides;
+      config.override_queries = Some(override_queries);
+    }
+  }
+
+  fn after_crate_root_parsing<'tcx>(
+    &mut self,
+    compiler: &interface::Compiler,
+    queries: &'tcx Queries<'tcx>,
+  ) -> Compilation {
+    self.each(|callbacks| callbacks.after_crate_root_parsing(compiler, queries))
+  }
+
+  fn after_expansion<'tcx>(
+    &mut self,
+    compiler: &interface::Compiler,
+    queries: &'tcx Queries<'tcx>,
+  ) -> Compilation {
+    self.each(|callbacks| callbacks.after_expansion(compiler, queries))
+  }
+
+  fn after_analysis<'tcx>(
+    &mut self,
+    compiler: &interface::Compiler,
+    queries: &'tcx Queries<'tcx>,
+  ) -> Compilation {
+    self.each(|callbacks| callbacks.after_analysis(compiler, queries))
+  }
+}
@@ -0,0 +1,168 @@
+//! Running an analysis on each body of a crate without letting one body stop the rest.
+//!
+//! Analyses often hit corners of the compiler that only a few bodies exercise, like
+//! coroutines with opaque types, and fail by panicking, by running into a query cycle,
+//! or by triggering a delayed bug that would become an ICE at the end of the
+//! compilation. [`run_per_body_guarded`] runs the analysis of each body separately,
+//! records how it failed on a body, and moves on to the next one.
+
+use std::{
+  cell::{Cell, RefCell},
+  fmt,
+  panic::{self, AssertUnwindSafe, PanicHookInfo},
+};
+
+use rustc_errors::FatalErrorMarker;
+use rustc_middle::{
+  mir::Body,
+  ty::{InstanceKind, TyCtxt},
+};
+use rustc_span::def_id::LocalDefId;
+
+use crate::panic_report;
+
+thread_local! {
+  static GUARDED: Cell<usize> = const { Cell::new(0) };
+  static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// How an analysis failed on a body, see [`run_guarded`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BodyFailure {
+  /// The analysis panicked with `message`, at `location` if the driver's panic hook
+  /// is installed.
+  Panicked {
+    message: String,
+    location: Option<String>,
+  },
+
+  /// The compiler aborted, e.g. after a query cycle that it can't recover from.
+  Aborted,
+
+  /// The analysis emitted `count` errors, e.g. for a query cycle.
+  Errors { count: usize },
+
+  /// The analysis triggered a delayed bug, which rustc reports as an ICE unless an
+  /// error is emitted before the end of the compilation.
+  DelayedBug,
+}
+
+impl fmt::Display for BodyFailure {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BodyFailure::Panicked { message, location } => {
+        write!(f, "panicked: {message}")?;
+        if let Some(location) = location {
+          write!(f, " at {location}")?;
+        }
+        Ok(())
+      }
+      BodyFailure::Aborted => write!(f, "the compiler aborted"),
+      BodyFailure::Errors { count: 1 } => write!(f, "emitted an error"),
+      BodyFailure::Errors { count } => write!(f, "emitted {count} errors"),
+      BodyFailure::DelayedBug => write!(f, "triggered a delayed bug"),
+    }
+  }
+}
+
+/// The results of [`run_per_body_guarded`], in the order of the bodies.
+#[derive(Debug)]
+pub struct GuardedResults<T> {
+  /// The result of each body the analysis succeeded on.
+  pub results: Vec<(LocalDefId, T)>,
+  /// The failure of each body the analysis failed on.
+  pub failures: Vec<(LocalDefId, BodyFailure)>,
+}
+
+/// Runs `analysis` on the body of `def_id`, returning how it failed if it panicked,
+/// made the compiler abort, emitted errors or triggered a delayed bug.
+///
+/// The body is recorded as [`processing`](crate::processing) while `analysis` runs,
+/// and a caught panic is neither written to a panic report nor printed. Errors and
+/// delayed bugs are still reported by the compiler, so the crate fails to compile.
+/// Delayed bugs are only detected if there were none before.
+pub fn run_guarded<T>(
+  tcx: TyCtxt<'_>,
+  def_id: LocalDefId,
+  analysis: impl FnOnce() -> T,
+) -> Result<T, BodyFailure> {
+  let dcx = tcx.dcx();
+  let errors = dcx.err_count();
+  let had_delayed_bugs = dcx.has_errors_or_delayed_bugs().is_some();
+
+  let _processing = panic_report::processing(tcx.def_path_str(def_id));
+  GUARDED.with(|guarded| guarded.set(guarded.get() + 1));
+  let result = panic::catch_unwind(AssertUnwindSafe(analysis));
+  GUARDED.with(|guarded| guarded.set(guarded.get() - 1));
+
+  let result = match result {
+    Ok(result) => result,
+    Err(payload) if payload.is::<FatalErrorMarker>() => return Err(BodyFailure::Aborted),
+    Err(payload) => {
+      let message = panic_report::payload_message(&*payload);
+      let location = PANIC_LOCATION.with(|location| location.borrow_mut().take());
+      return Err(BodyFailure::Panicked { message, location });
+    }
+  };
+
+  let new_errors = dcx.err_count().saturating_sub(errors);
+  if new_errors > 0 {
+    Err(BodyFailure::Errors { count: new_errors })
+  } else if !had_delayed_bugs && dcx.has_errors_or_delayed_bugs().is_some() {
+    Err(BodyFailure::DelayedBug)
+  } else {
+    Ok(result)
+  }
+}
+
+/// Runs `analysis` on every body of the crate with [`run_guarded`], logging a warning
+/// for each body it fails on.
+///
+/// Each body is given as the MIR used to run or evaluate it, i.e. the
+/// `optimized_mir` of a function and the `mir_for_ctfe` of a constant. The body is
+/// fetched within the guard, so the analysis of a body whose MIR can't be built fails
+/// like any other.
+///
+/// ```ignore
+/// let results = rustc_plugin::run_per_body_guarded(tcx, |_, body| analyze(tcx, body));
+/// for (def_id, failure) in &results.failures {
+///   rustc_plugin::diagnostics::warn(tcx, tcx.def_span(*def_id), failure.to_string());
+/// }
+/// ```
+pub fn run_per_body_guarded<'tcx, T>(
+  tcx: TyCtxt<'tcx>,
+  mut analysis: impl FnMut(LocalDefId, &'tcx Body<'tcx>) -> T,
+) -> GuardedResults<T> {
+  let mut results = GuardedResults {
+    results: Vec::new(),
+    failures: Vec::new(),
+  };
+  for def_id in tcx.hir().body_owners() {
+    let result = run_guarded(tcx, def_id, || {
+      let body = tcx.instance_mir(InstanceKind::Item(def_id.to_def_id()));
+      analysis(def_id, body)
+    });
+    match result {
+      Ok(result) => results.results.push((def_id, result)),
+      Err(failure) => {
+        log::warn!(
+          "The analysis failed on `{}`: {failure}",
+          tcx.def_path_str(def_id)
+        );
+        results.failures.push((def_id, failure));
+      }
+    }
+  }
+  results
+}
+
+/// Returns true if a panic on this thread is caught by [`run_guarded`], recording the
+/// location of the panic for its [`BodyFailure`].
+pub(crate) fn catch_panic(info: &PanicHookInfo) -> bool {
+  if GUARDED.with(Cell::get) == 0 {
+    return false;
+  }
+  let location = info.location().map(ToString::to_string);
+  PANIC_LOCATION.with(|slot| *slot.borrow_mut() = location);
+  true
+}
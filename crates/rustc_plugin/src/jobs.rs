@@ -0,0 +1,100 @@
+//! Limiting how many drivers run the plugin at the same time.
+//!
+//! With `--plugin-jobs <n>`, `cargo <plugin>` listens on a local TCP port, and each
+//! driver connects to it before running the plugin. The server grants at most `n`
+//! connections at a time, and a driver holds its slot until it exits and its
+//! connection closes, so a crashed or killed driver never keeps its slot. Compilations
+//! without the plugin are still only limited by `cargo -j`.
+
+use std::{
+  env,
+  io::{self, Read, Write},
+  net::{SocketAddr, TcpListener, TcpStream},
+  sync::{Arc, Condvar, Mutex},
+  thread,
+};
+
+/// The name of the environment variable containing the address of the job server.
+pub const JOBS_ADDR: &str = "RUSTC_PLUGIN_JOBS_ADDR";
+
+/// Grants slots to drivers. Runs until `cargo <plugin>` exits.
+pub struct JobServer {
+  addr: SocketAddr,
+}
+
+impl JobServer {
+  /// Starts a server that lets `jobs` drivers run the plugin concurrently.
+  pub fn start(jobs: usize) -> io::Result<Self> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let active = Arc::new((Mutex::new(0), Condvar::new()));
+    thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        let active = Arc::clone(&active);
+        thread::spawn(move || {
+          if let Err(e) = serve(stream, jobs, &active) {
+            log::warn!("Job server connection failed: {e}");
+          }
+        });
+      }
+    });
+    Ok(JobServer { addr })
+  }
+
+  /// The address that drivers connect to.
+  pub fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+}
+
+fn serve(
+  mut stream: TcpStream,
+  jobs: usize,
+  active: &(Mutex<usize>, Condvar),
+) -> io::Result<()> {
+  let (count, released) = active;
+  {
+    let mut count = released
+      .wait_while(count.lock().unwrap(), |count| *count >= jobs)
+      .unwrap();
+    *count += 1;
+  }
+
+  let result = stream.write_all(b"+").and_then(|()| {
+    // The driver never writes, so this returns when it exits.
+    let mut buf = [0; 1];
+    while stream.read(&mut buf)? > 0 {}
+    Ok(())
+  });
+
+  *count.lock().unwrap() -= 1;
+  released.notify_one();
+  result
+}
+
+/// A slot to run the plugin, released when dropped.
+pub struct Job {
+  _stream: TcpStream,
+}
+
+/// Waits for a slot to run the plugin, if `cargo <plugin>` limits the number of
+/// plugin jobs.
+///
+/// Child processes of the driver, e.g. the one running the plugin under a timeout, run
+/// within the slot of the driver, so [`JOBS_ADDR`] is removed from the environment.
+pub fn acquire() -> Option<Job> {
+  let addr = env::var(JOBS_ADDR).ok()?;
+  env::remove_var(JOBS_ADDR);
+  let job = TcpStream::connect(&addr).and_then(|mut stream| {
+    let mut granted = [0; 1];
+    stream.read_exact(&mut granted)?;
+    Ok(Job { _stream: stream })
+  });
+  match job {
+    Ok(job) => Some(job),
+    Err(e) => {
+      log::warn!("Failed to get a plugin job from {addr}, running anyway: {e}");
+      None
+    }
+  }
+}
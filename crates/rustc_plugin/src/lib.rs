@@ -6,15 +6,49 @@
 #![feature(rustc_private)]
 
 extern crate rustc_driver;
+extern crate rustc_errors;
 extern crate rustc_interface;
+extern crate rustc_middle;
 extern crate rustc_session;
+extern crate rustc_span;
 
+pub use aggregate::{emit_result, CrateResult, CrateResults};
 #[doc(hidden)]
 pub use cargo_metadata::camino::Utf8Path;
-pub use cli::cli_main;
-pub use driver::driver_main;
-pub use plugin::{CrateFilter, RustcPlugin, RustcPluginArgs};
+pub use cargo_metadata::{self, Metadata, Package};
+pub use cli::{cli_main, plugin_args, CargoArgs};
+pub use crate_info::{CrateInfo, CrateKind};
+pub use crate_summary::{read_crate_summary, write_crate_summary};
+pub use driver::{driver_main, run_on_file};
+pub use failure::FailurePolicy;
+pub use group::{PluginCallbacks, PluginGroup, PluginGroupArgs};
+pub use guarded::{run_guarded, run_per_body_guarded, BodyFailure, GuardedResults};
+pub use logging::{init_logging, Verbosity};
+pub use metadata::CargoMetadata;
+pub use output::crate_output_dir;
+pub use panic_report::{processing, ProcessingGuard};
+pub use plugin::{
+  plugin_args_json, read_plugin_args, BuildMode, CrateFilter, PackagePredicate,
+  RustcPlugin, RustcPluginArgs,
+};
 
+mod aggregate;
 mod cli;
+mod crate_info;
+mod crate_summary;
+pub mod diagnostics;
 mod driver;
+mod failure;
+mod fingerprint;
+mod group;
+mod guarded;
+mod jobs;
+mod logging;
+mod metadata;
+mod output;
+mod panic_report;
 mod plugin;
+mod progress;
+mod rustdoc;
+mod summary;
+mod toolchain;
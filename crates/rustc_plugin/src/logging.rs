@@ -0,0 +1,94 @@
+//! Verbosity of `cargo <plugin>`, and logging configured from it.
+//!
+//! `cargo <plugin>` accepts `-q`, `-v` and `-vv` like `cargo`, and passes the
+//! verbosity to every driver in [`VERBOSITY`]. [`init_logging`] uses it so the
+//! front-end and the drivers log at the same level.
+
+use std::{env, fmt, str::FromStr};
+
+use crate::CargoArgs;
+
+/// The name of the environment variable containing the [`Verbosity`] of
+/// `cargo <plugin>`, as `quiet`, `normal`, `verbose` or `very-verbose`.
+pub const VERBOSITY: &str = "RUSTC_PLUGIN_VERBOSITY";
+
+/// How much `cargo <plugin>` prints.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+  /// `-q`: only errors are logged.
+  Quiet,
+
+  /// The default: warnings and errors are logged.
+  #[default]
+  Normal,
+
+  /// `-v`: `cargo` prints the compiler invocations, and info messages are logged.
+  Verbose,
+
+  /// `-vv`: `cargo` also prints build script output, and debug messages are logged.
+  VeryVerbose,
+}
+
+impl Verbosity {
+  /// The verbosity of the current process: in the driver, the verbosity passed by
+  /// `cargo <plugin>`, and otherwise the verbosity from the command-line flags.
+  pub fn current() -> Self {
+    match env::var(VERBOSITY) {
+      Ok(verbosity) => verbosity.parse().unwrap_or_default(),
+      Err(_) => CargoArgs::parse(env::args()).0.verbosity,
+    }
+  }
+
+  /// The flag passed to `cargo`. `cargo <plugin>` hides the status lines of `cargo`
+  /// unless it is verbose.
+  pub(crate) fn cargo_flag(self) -> &'static str {
+    match self {
+      Verbosity::Quiet | Verbosity::Normal => "-q",
+      Verbosity::Verbose => "-v",
+      Verbosity::VeryVerbose => "-vv",
+    }
+  }
+
+  fn log_level(self) -> log::LevelFilter {
+    match self {
+      Verbosity::Quiet => log::LevelFilter::Error,
+      Verbosity::Normal => log::LevelFilter::Warn,
+      Verbosity::Verbose => log::LevelFilter::Info,
+      Verbosity::VeryVerbose => log::LevelFilter::Debug,
+    }
+  }
+}
+
+impl FromStr for Verbosity {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "quiet" => Ok(Verbosity::Quiet),
+      "normal" => Ok(Verbosity::Normal),
+      "verbose" => Ok(Verbosity::Verbose),
+      "very-verbose" => Ok(Verbosity::VeryVerbose),
+      _ => Err(format!("invalid verbosity `{s}`")),
+    }
+  }
+}
+
+impl fmt::Display for Verbosity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Verbosity::Quiet => "quiet",
+      Verbosity::Normal => "normal",
+      Verbosity::Verbose => "verbose",
+      Verbosity::VeryVerbose => "very-verbose",
+    })
+  }
+}
+
+/// Initializes an [`env_logger`] logger at the level of the [`Verbosity::current`],
+/// unless `RUST_LOG` is set. Call this at the start of both the CLI and the driver
+/// binaries, instead of initializing a logger yourself.
+pub fn init_logging() {
+  let env = env_logger::Env::default()
+    .default_filter_or(Verbosity::current().log_level().to_string());
+  env_logger::Builder::from_env(env).init();
+}
@@ -0,0 +1,95 @@
+//! The `cargo metadata` of the workspace, shared with the driver.
+//!
+//! If [`RustcPlugin::uses_cargo_metadata`](crate::RustcPlugin::uses_cargo_metadata)
+//! returns true, `cargo <plugin>` runs `cargo metadata` once with the same features
+//! and target as the compilation, and writes it to a file for the drivers. In the
+//! plugin's callbacks, [`CargoMetadata::load`] reads it back, e.g. to find the package
+//! of the crate defining a `DefId` from `tcx.crate_name(def_id.krate)`.
+
+use std::{env, fs, io, path::Path, process};
+
+use cargo_metadata::{Metadata, Node, Package};
+
+/// The name of the environment variable containing the path of the metadata file.
+pub const CARGO_METADATA_FILE: &str = "RUSTC_PLUGIN_CARGO_METADATA";
+
+/// Writes `metadata` to a file in `dir` for the drivers, returning its path.
+pub(crate) fn write(metadata: &Metadata, dir: &Path) -> io::Result<std::path::PathBuf> {
+  fs::create_dir_all(dir)?;
+  let path = dir.join(format!("cargo-metadata-{}.json", process::id()));
+  fs::write(&path, serde_json::to_string(metadata)?)?;
+  Ok(path)
+}
+
+/// The metadata of the workspace and all of its dependencies.
+pub struct CargoMetadata {
+  metadata: Metadata,
+}
+
+impl CargoMetadata {
+  /// Reads the metadata passed to the driver by `cargo <plugin>`.
+  pub fn load() -> io::Result<Self> {
+    let path = env::var_os(CARGO_METADATA_FILE).ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{CARGO_METADATA_FILE} is not set, does the plugin use cargo metadata?"),
+      )
+    })?;
+    let metadata = serde_json::from_str(&fs::read_to_string(path)?)?;
+    Ok(CargoMetadata { metadata })
+  }
+
+  /// The metadata, as returned by `cargo metadata`.
+  pub fn metadata(&self) -> &Metadata {
+    &self.metadata
+  }
+
+  fn node(&self, pkg: &Package) -> Option<&Node> {
+    let resolve = self.metadata.resolve.as_ref()?;
+    resolve.nodes.iter().find(|node| node.id == pkg.id)
+  }
+
+  /// The package of the crate being compiled.
+  pub fn current_package(&self) -> Option<&Package> {
+    let name = env::var("CARGO_PKG_NAME").ok()?;
+    let version = env::var("CARGO_PKG_VERSION").ok()?;
+    self
+      .metadata
+      .packages
+      .iter()
+      .find(|pkg| pkg.name == name && pkg.version.to_string() == version)
+  }
+
+  /// The package of the crate named `crate_name` in the compilation, as in
+  /// `tcx.crate_name(cnum)`.
+  ///
+  /// The name is looked up in the dependencies of the current package first, since
+  /// they can be renamed, and several versions of a package can be in the graph.
+  pub fn package_for_crate(&self, crate_name: &str) -> Option<&Package> {
+    let package = |id| self.metadata.packages.iter().find(|pkg| &pkg.id == id);
+    let current = self.current_package();
+    if let Some(node) = current.and_then(|pkg| self.node(pkg)) {
+      if let Some(dep) = node.deps.iter().find(|dep| dep.name == crate_name) {
+        return package(&dep.pkg);
+      }
+    }
+
+    let defines_crate = |pkg: &&Package| {
+      pkg
+        .targets
+        .iter()
+        .any(|target| target.name.replace('-', "_") == crate_name)
+    };
+    if let Some(current) = current.filter(defines_crate) {
+      return Some(current);
+    }
+    let mut candidates = self.metadata.packages.iter().filter(defines_crate);
+    let candidate = candidates.next()?;
+    candidates.next().is_none().then_some(candidate)
+  }
+
+  /// The features of `pkg` that are enabled in the compilation.
+  pub fn enabled_features(&self, pkg: &Package) -> &[String] {
+    self.node(pkg).map_or(&[], |node| &node.features)
+  }
+}
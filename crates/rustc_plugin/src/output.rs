@@ -0,0 +1,50 @@
+//! Directories where plugins can write their own files for each crate.
+
+use std::{
+  collections::hash_map::DefaultHasher,
+  env, fs,
+  hash::{Hash, Hasher},
+  io,
+  path::PathBuf,
+};
+
+/// The name of the environment variable containing the directory of per-crate output
+/// directories, i.e. `target/<driver_name>`.
+pub const OUTPUT_DIR: &str = "RUSTC_PLUGIN_OUTPUT_DIR";
+
+/// Returns the output directory of the crate being compiled, creating it if needed.
+///
+/// The directory is `target/<driver_name>/<crate-name>-<hash>/`, where the hash is the
+/// one Cargo gives the compilation in `-C metadata`. So each compilation of a crate,
+/// e.g. its library and its test harness, has its own directory, and plugins running
+/// on crates in parallel never write to the same files.
+pub fn crate_output_dir() -> io::Result<PathBuf> {
+  let root = env::var_os(OUTPUT_DIR).ok_or_else(|| {
+    io::Error::new(io::ErrorKind::NotFound, format!("{OUTPUT_DIR} is not set"))
+  })?;
+  let args = env::args().collect::<Vec<_>>();
+  let arg_value = |flag: &str| {
+    args
+      .windows(2)
+      .find(|pair| pair[0] == flag)
+      .map(|pair| pair[1].as_str())
+  };
+
+  let crate_name = arg_value("--crate-name").unwrap_or("unknown");
+  let metadata = args
+    .windows(2)
+    .filter(|pair| pair[0] == "-C")
+    .find_map(|pair| pair[1].strip_prefix("metadata="));
+  let hash = match metadata {
+    Some(metadata) => metadata.to_string(),
+    None => {
+      let mut hasher = DefaultHasher::new();
+      args.hash(&mut hasher);
+      format!("{:016x}", hasher.finish())
+    }
+  };
+
+  let dir = PathBuf::from(root).join(format!("{crate_name}-{hash}"));
+  fs::create_dir_all(&dir)?;
+  Ok(dir)
+}
@@ -0,0 +1,149 @@
+//! Reports of panics in the plugin.
+//!
+//! A panic in the plugin's callbacks would otherwise be printed as an internal compiler
+//! error, with a backtrace through rustc. Instead, the driver writes a report with the
+//! crate, the item the plugin was [`processing`], the plugin version and the backtrace
+//! to `target/<driver_name>/reports/`, and prints where to find it. The crate is then
+//! handled by the [`FailurePolicy`](crate::FailurePolicy) like any other failure.
+
+use std::{
+  any::Any,
+  backtrace::Backtrace,
+  cell::RefCell,
+  env, fs, io,
+  panic::{self, PanicHookInfo},
+  path::PathBuf,
+  process,
+};
+
+use serde::Serialize;
+
+use crate::{
+  aggregate, guarded,
+  output::OUTPUT_DIR,
+  summary::{self, CrateStatus, Status},
+  CrateInfo,
+};
+
+thread_local! {
+  static PROCESSING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Forgets the item passed to [`processing`] when dropped.
+pub struct ProcessingGuard {
+  _private: (),
+}
+
+impl Drop for ProcessingGuard {
+  fn drop(&mut self) {
+    PROCESSING.with(|items| items.borrow_mut().pop());
+  }
+}
+
+/// Records that the plugin is processing `item` on this thread, e.g. the path of a
+/// function, so it is included in the report if the plugin panics. Calls can be
+/// nested, and the item is forgotten when the guard is dropped.
+///
+/// ```ignore
+/// for def_id in tcx.hir().body_owners() {
+///   let _processing = rustc_plugin::processing(tcx.def_path_str(def_id));
+///   analyze(tcx, def_id);
+/// }
+/// ```
+pub fn processing(item: impl Into<String>) -> ProcessingGuard {
+  PROCESSING.with(|items| items.borrow_mut().push(item.into()));
+  ProcessingGuard { _private: () }
+}
+
+/// A panic of the plugin, as written to the report file.
+#[derive(Serialize)]
+struct PanicReport {
+  package: String,
+  package_version: String,
+  crate_name: String,
+  plugin_version: String,
+  /// The items passed to [`processing`], from the outermost.
+  processing: Vec<String>,
+  message: String,
+  location: Option<String>,
+  backtrace: String,
+}
+
+/// Replaces the panic hook, so panics in the plugin are written to a report. If the
+/// driver is not run by `cargo <plugin>`, panics are printed as before.
+pub(crate) fn install_hook(plugin_version: String) {
+  let previous = panic::take_hook();
+  panic::set_hook(Box::new(move |info| {
+    // Panics caught by `run_guarded` are part of its result instead.
+    if guarded::catch_panic(info) {
+      return;
+    }
+    match write_report(&plugin_version, info) {
+      Ok(Some(_)) => {}
+      Ok(None) => previous(info),
+      Err(e) => {
+        log::warn!("Failed to write the panic report: {e}");
+        previous(info);
+      }
+    }
+  }));
+}
+
+/// Returns the message of a panic with `payload`.
+pub(crate) fn payload_message(payload: &(dyn Any + Send)) -> String {
+  match (
+    payload.downcast_ref::<&str>(),
+    payload.downcast_ref::<String>(),
+  ) {
+    (Some(message), _) => message.to_string(),
+    (None, Some(message)) => message.clone(),
+    (None, None) => "Box<dyn Any>".to_string(),
+  }
+}
+
+/// Writes the report of the panic `info`, returning its path, if the driver is run by
+/// `cargo <plugin>`.
+fn write_report(
+  plugin_version: &str,
+  info: &PanicHookInfo,
+) -> io::Result<Option<PathBuf>> {
+  let Some(output_dir) = env::var_os(OUTPUT_DIR) else {
+    return Ok(None);
+  };
+  let args = env::args().collect::<Vec<_>>();
+  let crate_info = CrateInfo::new(&args, |name| env::var(name).ok(), true);
+  let message = payload_message(info.payload());
+  let report = PanicReport {
+    package: crate_info.package,
+    package_version: crate_info.package_version,
+    crate_name: crate_info.crate_name,
+    plugin_version: plugin_version.to_string(),
+    processing: PROCESSING.with(|items| items.borrow().clone()),
+    message,
+    location: info.location().map(ToString::to_string),
+    backtrace: Backtrace::force_capture().to_string(),
+  };
+
+  let dir = PathBuf::from(output_dir).join("reports");
+  fs::create_dir_all(&dir)?;
+  let path = dir.join(format!(
+    "{}-{}.json",
+    aggregate::crate_key(&args),
+    process::id()
+  ));
+  fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+
+  eprintln!(
+    "error: the plugin panicked on crate `{}`: {}",
+    report.crate_name, report.message
+  );
+  if let Some(item) = report.processing.last() {
+    eprintln!("note: while processing {item}");
+  }
+  eprintln!("note: the report was written to {}", path.display());
+
+  CrateStatus::new(&args, Status::Failed, summary::findings())
+    .failed("panicked".into(), false)
+    .record();
+  Ok(Some(path))
+}
@@ -1,11 +1,23 @@
-use std::{borrow::Cow, path::PathBuf, process::Command};
+use std::{
+  borrow::Cow,
+  env, fs, io,
+  path::{Path, PathBuf},
+  process::{self, Command},
+};
 
-use cargo_metadata::camino::Utf8Path;
+use cargo_metadata::{camino::Utf8Path, Metadata, Package, PackageId};
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::{CrateInfo, CrateResults, PluginCallbacks};
+
+/// A predicate for [`CrateFilter::Predicate`].
+pub type PackagePredicate = Box<dyn Fn(&Metadata, &Package) -> bool>;
+
 /// Specification of a set of crates.
 pub enum CrateFilter {
-  /// Every crate in the workspace and all transitive dependencies.
+  /// Every crate in the workspace and all transitive dependencies. With
+  /// `-Zbuild-std`, this includes the crates of the standard library, see
+  /// [`CrateInfo::sysroot_crate`](crate::CrateInfo::sysroot_crate).
   AllCrates,
 
   /// Just crates in the workspace.
@@ -13,15 +25,109 @@ pub enum CrateFilter {
 
   /// Only the crate containing a specific file.
   CrateContainingFile(PathBuf),
+
+  /// Packages whose name matches a glob, where `*` matches any sequence of characters
+  /// and `?` matches any single character, e.g. `serde*`.
+  Matching(String),
+
+  /// Crates in the workspace and their direct dependencies.
+  WorkspaceAndDirectDeps,
+
+  /// Packages for which the predicate returns true, given the metadata of the
+  /// workspace with all of its dependencies.
+  Predicate(PackagePredicate),
+}
+
+impl CrateFilter {
+  /// Returns true if the filter selects the package `pkg` of `metadata`. Filters on
+  /// files select every workspace member.
+  pub(crate) fn selects_package(&self, metadata: &Metadata, pkg: &Package) -> bool {
+    let is_member = |id: &PackageId| metadata.workspace_members.contains(id);
+    match self {
+      CrateFilter::AllCrates => true,
+      CrateFilter::OnlyWorkspace | CrateFilter::CrateContainingFile(_) => {
+        is_member(&pkg.id)
+      }
+      CrateFilter::Matching(glob) => glob_matches(glob, &pkg.name),
+      CrateFilter::WorkspaceAndDirectDeps => {
+        is_member(&pkg.id)
+          || metadata.resolve.as_ref().is_some_and(|resolve| {
+            resolve
+              .nodes
+              .iter()
+              .filter(|node| is_member(&node.id))
+              .any(|node| node.dependencies.contains(&pkg.id))
+          })
+      }
+      CrateFilter::Predicate(pred) => pred(metadata, pkg),
+    }
+  }
+}
+
+fn glob_matches(glob: &str, name: &str) -> bool {
+  match glob.chars().next() {
+    None => name.is_empty(),
+    Some('*') => {
+      let rest = &glob[1 ..];
+      name
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([name.len()])
+        .any(|i| glob_matches(rest, &name[i ..]))
+    }
+    Some(c) => name.chars().next().is_some_and(|n| {
+      (c == '?' || c == n) && glob_matches(&glob[c.len_utf8() ..], &name[n.len_utf8() ..])
+    }),
+  }
+}
+
+/// How `cargo <plugin>` compiles the crates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BuildMode {
+  /// Run `cargo check` in a separate target directory, so crates are only analyzed and
+  /// not code-generated.
+  #[default]
+  Check,
+
+  /// Run `cargo build` in the normal target directory, keeping the codegen of each
+  /// crate after the plugin's callbacks. For plugins like instrumenters that emit
+  /// runnable binaries. Fingerprints are not used in this mode, because a fresh crate
+  /// would be compiled without the plugin.
+  Build,
 }
 
 /// Arguments from your plugin to the rustc_plugin framework.
+///
+/// Prefer [`RustcPluginArgs::new`] over a struct literal, so a new field does not break
+/// your plugin. Plugins written before [`mode`](RustcPluginArgs::mode) was added can
+/// replace `RustcPluginArgs { args, filter }` with `RustcPluginArgs::new(args, filter)`.
 pub struct RustcPluginArgs<Args> {
   /// Whatever CLI arguments you want to pass along.
   pub args: Args,
 
   /// Which crates you want to run the plugin on.
   pub filter: CrateFilter,
+
+  /// Whether crates are checked or built.
+  pub mode: BuildMode,
+}
+
+impl<Args> RustcPluginArgs<Args> {
+  /// Creates the arguments for running the plugin on the crates selected by `filter`,
+  /// which are checked rather than built, see [`BuildMode::Check`].
+  pub fn new(args: Args, filter: CrateFilter) -> Self {
+    RustcPluginArgs {
+      args,
+      filter,
+      mode: BuildMode::default(),
+    }
+  }
+
+  /// Compiles the crates with `mode` instead.
+  pub fn with_mode(mut self, mode: BuildMode) -> Self {
+    self.mode = mode;
+    self
+  }
 }
 
 /// Interface between your plugin and the rustc_plugin framework.
@@ -46,10 +152,65 @@ pub trait RustcPlugin: Sized {
   /// Parses and returns the CLI arguments for the plugin.
   fn args(&self, target_dir: &Utf8Path) -> RustcPluginArgs<Self::Args>;
 
+  /// Returns true if the plugin should only be re-run on crates that changed since its
+  /// last run.
+  ///
+  /// The stdout and stderr of the plugin on each crate, including its diagnostics, are
+  /// saved under `target/<driver_name>/fingerprints` along with the results emitted with
+  /// [`emit_result`](crate::emit_result). They are replayed instead of running the
+  /// plugin if the sources, dependencies, compiler arguments, plugin arguments and
+  /// plugin version of the crate are unchanged. Only enable this if the plugin has no
+  /// other effects, e.g. it does not write files.
+  fn incremental(&self) -> bool {
+    false
+  }
+
+  /// Returns true if the plugin reads the metadata of the workspace with
+  /// [`CargoMetadata::load`](crate::CargoMetadata::load) in its callbacks, so
+  /// `cargo <plugin>` runs `cargo metadata` to pass it to the driver.
+  fn uses_cargo_metadata(&self, _args: &Self::Args) -> bool {
+    false
+  }
+
+  /// Combines the results emitted with [`emit_result`](crate::emit_result) on every
+  /// crate into a single report, after `cargo` finishes. Only called if any results
+  /// were emitted.
+  ///
+  /// By default, the results are printed as a JSON array, see
+  /// [`CrateResults::to_json`].
+  fn aggregate(&self, results: CrateResults) {
+    println!("{}", results.to_json());
+  }
+
+  /// Returns environment variables that `cargo <plugin>` sets for every rustc
+  /// invocation, e.g. to pass the path of a configuration file or a feature toggle to
+  /// the driver. The driver reads them back with [`std::env::var`].
+  ///
+  /// Changing the variables makes every crate be analyzed again, like upgrading the
+  /// plugin.
+  fn env(&self) -> Vec<(String, String)> {
+    Vec::new()
+  }
+
   /// Optionally modify the `cargo` command that launches rustc.
   /// For example, you could pass a `--feature` flag here.
   fn modify_cargo(&self, _cargo: &mut Command, _args: &Self::Args) {}
 
+  /// Optionally modify the arguments of rustc for a crate, before the driver starts
+  /// the compiler. For example, you could pass `-Zalways-encode-mir` to keep the MIR of
+  /// dependencies, or a `--cfg` value for the crates analyzed by the plugin.
+  ///
+  /// Called for every compilation that goes through the driver, including those that
+  /// don't run the plugin, see [`CrateInfo::runs_plugin`].
+  fn modify_rustc_args(&self, _crate_info: &CrateInfo, _args: &mut Vec<String>) {}
+
+  /// Returns the callbacks that run the plugin on a compilation, so the plugin can be
+  /// part of a [`PluginGroup`](crate::PluginGroup). Outside of a group, the plugin is
+  /// executed by [`RustcPlugin::run`] instead.
+  fn callbacks(&self, _plugin_args: Self::Args) -> Option<PluginCallbacks> {
+    None
+  }
+
   /// Executes the plugin with a set of compiler and plugin args.
   fn run(
     self,
@@ -61,3 +222,57 @@ pub trait RustcPlugin: Sized {
 /// The name of the environment variable shared between the CLI and the driver.
 /// Must not conflict with any other env var used by Cargo.
 pub const PLUGIN_ARGS: &str = "PLUGIN_ARGS";
+
+/// The name of the environment variable containing the path of a file with the plugin
+/// args, which is used instead of [`PLUGIN_ARGS`] if the args are too large.
+pub const PLUGIN_ARGS_FILE: &str = "PLUGIN_ARGS_FILE";
+
+/// If set for `cargo <plugin>`, the plugin args are always passed in a file.
+pub const PLUGIN_ARGS_IN_FILE: &str = "RUSTC_PLUGIN_ARGS_IN_FILE";
+
+/// Serialized args longer than this are passed in a file, since environment variables
+/// are limited to 32767 characters on Windows and to a fraction of the stack size on
+/// Linux.
+const MAX_ENV_ARGS_LEN: usize = 16 * 1024;
+
+/// Passes the serialized plugin args to the drivers launched by `cmd`, either in
+/// [`PLUGIN_ARGS`] or in a file in `dir`. Returns the path of the file, if any.
+pub(crate) fn send_plugin_args(
+  cmd: &mut Command,
+  args_json: String,
+  dir: &Path,
+) -> io::Result<Option<PathBuf>> {
+  if args_json.len() <= MAX_ENV_ARGS_LEN && env::var(PLUGIN_ARGS_IN_FILE).is_err() {
+    log::debug!("{PLUGIN_ARGS}={args_json}");
+    cmd.env(PLUGIN_ARGS, args_json);
+    return Ok(None);
+  }
+
+  fs::create_dir_all(dir)?;
+  let path = dir.join(format!("plugin-args-{}.json", process::id()));
+  fs::write(&path, args_json)?;
+  log::debug!("{PLUGIN_ARGS_FILE}={}", path.display());
+  cmd.env(PLUGIN_ARGS_FILE, &path);
+  Ok(Some(path))
+}
+
+/// Returns the serialized plugin args passed to the driver by `cargo <plugin>`.
+pub fn plugin_args_json() -> io::Result<String> {
+  match env::var(PLUGIN_ARGS) {
+    Ok(args) => Ok(args),
+    Err(_) => {
+      let path = env::var_os(PLUGIN_ARGS_FILE).ok_or_else(|| {
+        io::Error::new(
+          io::ErrorKind::NotFound,
+          format!("neither {PLUGIN_ARGS} nor {PLUGIN_ARGS_FILE} is set"),
+        )
+      })?;
+      fs::read_to_string(path)
+    }
+  }
+}
+
+/// Deserializes the plugin args passed to the driver by `cargo <plugin>`.
+pub fn read_plugin_args<A: DeserializeOwned>() -> io::Result<A> {
+  Ok(serde_json::from_str(&plugin_args_json()?)?)
+}
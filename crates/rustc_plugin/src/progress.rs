@@ -0,0 +1,144 @@
+//! Reporting the progress of `cargo <plugin>` across the crates of a workspace.
+//!
+//! With `--progress`, each driver appends an event to a shared file when it starts
+//! running the plugin on a crate. `cargo <plugin>` follows the file while `cargo` runs,
+//! and prints an `Analyzing` line for each event with the number of crates analyzed so
+//! far, out of the total from the build plan.
+
+use std::{
+  env,
+  fs::{self, File, OpenOptions},
+  io::{self, IsTerminal, Read, Write},
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  thread::{self, JoinHandle},
+  time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the environment variable containing the file of progress events.
+pub const PROGRESS_FILE: &str = "RUSTC_PLUGIN_PROGRESS_FILE";
+
+/// The event of a driver starting to run the plugin on a crate.
+#[derive(Serialize, Deserialize)]
+struct Started {
+  package: String,
+  version: String,
+  crate_name: String,
+  test: bool,
+}
+
+/// Reports that the driver started running the plugin on the crate compiled with
+/// `compiler_args`, if progress is enabled.
+pub fn report_start(compiler_args: &[String]) {
+  let Ok(path) = env::var(PROGRESS_FILE) else {
+    return;
+  };
+  let started = Started {
+    package: env::var("CARGO_PKG_NAME").unwrap_or_default(),
+    version: env::var("CARGO_PKG_VERSION").unwrap_or_default(),
+    crate_name: compiler_args
+      .windows(2)
+      .find(|pair| pair[0] == "--crate-name")
+      .map_or(String::new(), |pair| pair[1].clone()),
+    test: compiler_args.iter().any(|arg| arg == "--test"),
+  };
+  let line = format!("{}\n", serde_json::to_string(&started).unwrap());
+
+  // Drivers run in parallel, so each event is appended with a single write.
+  let written = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&path)
+    .and_then(|mut file| file.write_all(line.as_bytes()));
+  if let Err(e) = written {
+    log::warn!("Failed to report progress to {path}: {e}");
+  }
+}
+
+/// Prints the progress events of the drivers as they are reported.
+pub struct ProgressReporter {
+  path: PathBuf,
+  done: Arc<AtomicBool>,
+  thread: JoinHandle<()>,
+}
+
+impl ProgressReporter {
+  /// Starts following the progress file at `path`, where `total` is the number of
+  /// crates the plugin will run on.
+  pub fn start(path: PathBuf, total: usize) -> io::Result<Self> {
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    File::create(&path)?;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let thread = thread::spawn({
+      let path = path.clone();
+      let done = Arc::clone(&done);
+      move || follow(&path, total, &done)
+    });
+    Ok(ProgressReporter { path, done, thread })
+  }
+
+  /// Prints the remaining events and removes the progress file.
+  pub fn finish(self) {
+    self.done.store(true, Ordering::SeqCst);
+    let _ = self.thread.join();
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+fn follow(path: &Path, total: usize, done: &AtomicBool) {
+  let Ok(mut file) = File::open(path) else {
+    return;
+  };
+  let mut pending = String::new();
+  let mut analyzed = 0;
+  loop {
+    // Check before reading, so events written before `finish` are always printed.
+    let finished = done.load(Ordering::SeqCst);
+    let mut new = String::new();
+    if file.read_to_string(&mut new).is_err() {
+      return;
+    }
+    pending.push_str(&new);
+    while let Some(i) = pending.find('\n') {
+      let line = pending.drain(..= i).collect::<String>();
+      if let Ok(started) = serde_json::from_str::<Started>(&line) {
+        analyzed += 1;
+        print_started(&started, analyzed, total);
+      }
+    }
+    if finished {
+      return;
+    }
+    thread::sleep(Duration::from_millis(100));
+  }
+}
+
+fn print_started(started: &Started, analyzed: usize, total: usize) {
+  let mut name = format!("{} v{}", started.package, started.version);
+  if started.crate_name != started.package.replace('-', "_") {
+    name.push_str(&format!(" ({})", started.crate_name));
+  }
+  if started.test {
+    name.push_str(" (test)");
+  }
+
+  // Formatted like the status lines of Cargo.
+  let status = "Analyzing";
+  let mut stderr = io::stderr();
+  let _ = if stderr.is_terminal() {
+    writeln!(
+      stderr,
+      "\x1b[1;32m{status:>12}\x1b[0m {name} ({analyzed}/{total})"
+    )
+  } else {
+    writeln!(stderr, "{status:>12} {name} ({analyzed}/{total})")
+  };
+}
@@ -0,0 +1,110 @@
+//! `cargo <plugin> --doc`, which runs the plugin on crates as they are documented.
+//!
+//! In this mode, the driver is installed as `RUSTDOC`. For each crate documented by
+//! rustdoc, the driver runs the plugin on a rustc compilation with the same crate
+//! arguments and `--cfg doc`, so the plugin sees the items and doc attributes seen by
+//! rustdoc, with the same callbacks as in `cargo check`. Then it runs the real rustdoc.
+
+use std::{
+  env,
+  path::PathBuf,
+  process::{exit, Command},
+};
+
+use crate::{
+  driver::{get_sysroot, modify_rustc_args, standalone_dir, RunDecision},
+  jobs, plugin, summary, RustcPlugin,
+};
+
+/// The name of the environment variable containing the path of the real rustdoc.
+pub const RUSTDOC_PATH: &str = "RUSTC_PLUGIN_RUSTDOC";
+
+/// Flags of rustdoc that are also flags of rustc, and take a value.
+const RUSTC_FLAGS: &[&str] = &[
+  "--edition",
+  "--crate-type",
+  "--crate-name",
+  "--cfg",
+  "--check-cfg",
+  "--error-format",
+  "--json",
+  "--extern",
+  "--target",
+  "--cap-lints",
+  "--sysroot",
+  "--color",
+  "-C",
+  "-L",
+  "-Z",
+];
+
+/// Converts the arguments of a rustdoc invocation to those of a rustc invocation that
+/// checks the same crate. Flags that only exist for rustdoc are removed.
+fn rustc_args(rustdoc_args: &[String]) -> Vec<String> {
+  let mut args = Vec::new();
+  let mut rustdoc_args = rustdoc_args.iter();
+  while let Some(arg) = rustdoc_args.next() {
+    let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+    if RUSTC_FLAGS.contains(&flag) {
+      let mut arg = arg.clone();
+      if flag == arg {
+        let Some(value) = rustdoc_args.next() else {
+          break;
+        };
+        args.push(arg);
+        arg = value.clone();
+      }
+      // Artifact notifications would be taken by Cargo as outputs of rustdoc.
+      if flag == "--json" {
+        arg = arg
+          .split(',')
+          .filter(|kind| !kind.ends_with("artifacts"))
+          .collect::<Vec<_>>()
+          .join(",");
+      }
+      args.push(arg);
+    } else if !arg.starts_with('-') && arg.ends_with(".rs") {
+      args.push(arg.clone());
+    }
+  }
+  args
+}
+
+/// Runs the plugin on the crate documented by the rustdoc invocation `orig_args`, then
+/// runs `rustdoc` and exits with its exit code.
+pub fn run_rustdoc<T: RustcPlugin>(
+  plugin: T,
+  rustdoc: &str,
+  orig_args: &[String],
+) -> rustc_interface::interface::Result<()> {
+  let rustdoc_args = &orig_args[1 ..];
+  let out_dir = standalone_dir(&plugin.driver_name()).join("doc");
+  let mut args = vec!["rustc".to_string()];
+  args.extend(rustc_args(rustdoc_args));
+  args.extend(["--cfg".into(), "doc".into(), "--emit=metadata".into()]);
+  args.extend(["--out-dir".into(), out_dir.to_string_lossy().into_owned()]);
+
+  let decision = RunDecision::new(&args, |name| env::var(name).ok());
+  let has_input = args.iter().skip(1).any(|arg| PathBuf::from(arg).is_file());
+  if decision.run_plugin() && has_input {
+    let (have_sys_root_arg, sys_root) = get_sysroot(&args);
+    if !have_sys_root_arg {
+      args.extend(["--sysroot".into(), sys_root]);
+    }
+    modify_rustc_args(&plugin, &decision, &mut args);
+    log::debug!("Running plugin on rustdoc invocation: {args:?}");
+    let plugin_args = plugin::read_plugin_args().expect("failed to read plugin args");
+    let _job = jobs::acquire();
+    let result = plugin.run(args.clone(), plugin_args);
+    summary::record_run(&args, &result);
+    result?;
+  } else {
+    log::debug!("Running normal rustdoc. Relevant variables: {decision:?}");
+  }
+
+  let status = Command::new(rustdoc)
+    .args(rustdoc_args)
+    .status()
+    .unwrap_or_else(|e| panic!("failed to run {rustdoc}: {e}"));
+  exit(status.code().unwrap_or(-1));
+}
@@ -0,0 +1,268 @@
+//! The summary printed at the end of `cargo <plugin>`, and its exit code.
+//!
+//! Each driver that handles a crate for the plugin records the status of the crate in
+//! a file. After `cargo` finishes, `cargo <plugin>` prints the status of every crate
+//! and the number of findings, i.e. diagnostics emitted with
+//! [`diagnostics`](crate::diagnostics), and exits with:
+//!
+//! - `0` if the plugin ran on every crate without findings,
+//! - `1` if the plugin reported findings,
+//! - `2` if the plugin failed or timed out on a crate, or the build failed otherwise.
+
+use std::{
+  env, fmt, fs, io,
+  path::{Path, PathBuf},
+  process,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rustc_interface::interface;
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate;
+
+/// The name of the environment variable containing the directory of status files.
+pub const STATUS_DIR: &str = "RUSTC_PLUGIN_STATUS_DIR";
+
+/// The exit code of `cargo <plugin>` when the plugin reported findings.
+pub const EXIT_FINDINGS: i32 = 1;
+
+/// The exit code of `cargo <plugin>` when the plugin or the build failed.
+pub const EXIT_FAILURE: i32 = 2;
+
+static FINDINGS: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a diagnostic emitted by the plugin in this process.
+pub(crate) fn add_finding(is_error: bool) {
+  FINDINGS.fetch_add(1, Ordering::Relaxed);
+  if is_error {
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+/// The number of diagnostics emitted by the plugin in this process.
+pub(crate) fn findings() -> usize {
+  FINDINGS.load(Ordering::Relaxed)
+}
+
+/// Returns true if the plugin emitted an error in this process, which makes the
+/// compilation fail.
+pub(crate) fn emitted_errors() -> bool {
+  ERRORS.load(Ordering::Relaxed) > 0
+}
+
+/// What happened to a crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+  /// The plugin ran on the crate.
+  Analyzed,
+  /// The crate is unchanged since the last run, so the plugin's output was replayed.
+  Skipped,
+  /// The plugin or the compilation of the crate failed.
+  Failed,
+  /// The plugin was stopped after `--crate-timeout`.
+  TimedOut,
+}
+
+impl fmt::Display for Status {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Status::Analyzed => "analyzed",
+      Status::Skipped => "skipped",
+      Status::Failed => "failed",
+      Status::TimedOut => "timed out",
+    })
+  }
+}
+
+/// The status of a crate handled by the plugin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrateStatus {
+  package: String,
+  version: String,
+  crate_name: String,
+  test: bool,
+  status: Status,
+  findings: usize,
+  reason: Option<String>,
+  retried: bool,
+}
+
+impl CrateStatus {
+  /// Creates the status of the crate compiled with `compiler_args`.
+  pub(crate) fn new(compiler_args: &[String], status: Status, findings: usize) -> Self {
+    CrateStatus {
+      package: env::var("CARGO_PKG_NAME").unwrap_or_default(),
+      version: env::var("CARGO_PKG_VERSION").unwrap_or_default(),
+      crate_name: compiler_args
+        .windows(2)
+        .find(|pair| pair[0] == "--crate-name")
+        .map_or(String::new(), |pair| pair[1].clone()),
+      test: compiler_args.iter().any(|arg| arg == "--test"),
+      status,
+      findings,
+      reason: None,
+      retried: false,
+    }
+  }
+
+  /// Adds the reason the crate failed, and whether it was compiled again without the
+  /// plugin.
+  pub(crate) fn failed(mut self, reason: String, retried: bool) -> Self {
+    self.reason = Some(reason);
+    self.retried = retried;
+    self
+  }
+
+  pub(crate) fn status(&self) -> Status {
+    self.status
+  }
+
+  pub(crate) fn findings(&self) -> usize {
+    self.findings
+  }
+
+  pub(crate) fn reason(&self) -> Option<&str> {
+    self.reason.as_deref()
+  }
+
+  pub(crate) fn crate_name(&self) -> &str {
+    &self.crate_name
+  }
+
+  /// Records the status for the driver process, if `cargo <plugin>` collects them.
+  pub(crate) fn record(&self) {
+    let Some(path) = status_path(process::id()) else {
+      return;
+    };
+    let line = serde_json::to_string(self).unwrap();
+    let written =
+      fs::create_dir_all(path.parent().unwrap()).and_then(|()| fs::write(&path, line));
+    if let Err(e) = written {
+      log::warn!("Failed to record status in {}: {e}", path.display());
+    }
+  }
+}
+
+impl fmt::Display for CrateStatus {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let test = if self.test { ", test" } else { "" };
+    write!(
+      f,
+      "{:9} {} v{} ({}{test})",
+      self.status.to_string(),
+      self.package,
+      self.version,
+      self.crate_name
+    )?;
+    if let Some(reason) = &self.reason {
+      write!(f, ": the plugin {reason}")?;
+      if self.retried {
+        write!(f, ", compiled without the plugin")?;
+      }
+    }
+    match self.findings {
+      0 => Ok(()),
+      1 => write!(f, ", 1 finding"),
+      n => write!(f, ", {n} findings"),
+    }
+  }
+}
+
+/// Records the status of running the plugin on the crate compiled with
+/// `compiler_args` in this process.
+pub(crate) fn record_run(compiler_args: &[String], result: &interface::Result<()>) {
+  let status = CrateStatus::new(compiler_args, Status::Analyzed, findings());
+  match result {
+    Err(_) if !emitted_errors() => status
+      .failed("failed because the crate does not compile".into(), false)
+      .record(),
+    _ => status.record(),
+  }
+}
+
+/// The file that the driver process `pid` records the status of its crate in.
+pub(crate) fn status_path(pid: u32) -> Option<PathBuf> {
+  let dir = env::var(STATUS_DIR).ok()?;
+  let args = env::args().collect::<Vec<_>>();
+  Some(Path::new(&dir).join(format!("{}-{pid}.json", aggregate::crate_key(&args))))
+}
+
+/// Reads the status recorded by the driver process `pid`.
+pub(crate) fn read_status(pid: u32) -> Option<CrateStatus> {
+  let contents = fs::read_to_string(status_path(pid)?).ok()?;
+  serde_json::from_str(&contents).ok()
+}
+
+/// The statuses of every crate handled by the plugin.
+pub struct Summary {
+  crates: Vec<CrateStatus>,
+}
+
+impl Summary {
+  /// Reads every status file in `dir`, ordered by package and crate.
+  pub fn collect(dir: &Path) -> io::Result<Self> {
+    let mut crates = Vec::new();
+    let entries = match fs::read_dir(dir) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Summary { crates }),
+      Err(e) => return Err(e),
+    };
+    for entry in entries {
+      let contents = fs::read_to_string(entry?.path())?;
+      crates.push(serde_json::from_str(&contents)?);
+    }
+    crates.sort_by(|a: &CrateStatus, b| {
+      (&a.package, &a.crate_name, a.test).cmp(&(&b.package, &b.crate_name, b.test))
+    });
+    Ok(Summary { crates })
+  }
+
+  fn count(&self, status: Status) -> usize {
+    self
+      .crates
+      .iter()
+      .filter(|krate| krate.status == status)
+      .count()
+  }
+
+  fn findings(&self) -> usize {
+    self.crates.iter().map(|krate| krate.findings).sum()
+  }
+
+  /// Prints the status of each crate and the totals, if the plugin handled any crate.
+  pub fn print(&self) {
+    if self.crates.is_empty() {
+      return;
+    }
+    eprintln!("Summary:");
+    for krate in &self.crates {
+      eprintln!("  {krate}");
+    }
+    let findings = self.findings();
+    eprintln!(
+      "{} analyzed, {} skipped, {} failed, {} timed out; {findings} {}",
+      self.count(Status::Analyzed),
+      self.count(Status::Skipped),
+      self.count(Status::Failed),
+      self.count(Status::TimedOut),
+      if findings == 1 { "finding" } else { "findings" }
+    );
+  }
+
+  /// The exit code of `cargo <plugin>`, given whether `cargo` succeeded.
+  pub fn exit_code(&self, cargo_succeeded: bool) -> i32 {
+    let plugin_failed = self.count(Status::Failed) + self.count(Status::TimedOut) > 0;
+    let findings = self.findings() > 0;
+    // Errors emitted by the plugin make `cargo` fail, but are findings.
+    if plugin_failed || (!cargo_succeeded && !findings) {
+      EXIT_FAILURE
+    } else if findings {
+      EXIT_FINDINGS
+    } else {
+      0
+    }
+  }
+}
@@ -0,0 +1,97 @@
+//! Checking that `cargo <plugin>` runs with the toolchain the plugin was built with.
+//!
+//! The driver is linked against the compiler of a specific nightly, so running it with
+//! the standard library of any other toolchain fails with errors or ICEs. If the `rustc`
+//! that Cargo would use is a different compiler, then `cargo <plugin>` re-runs itself
+//! with `rustup run <channel>`, or fails with instructions to install the toolchain.
+
+use std::{
+  env,
+  process::{exit, Command},
+};
+
+const CHANNEL: &str = env!("RUSTC_CHANNEL");
+const COMMIT_HASH: &str = env!("RUSTC_COMMIT_HASH");
+
+/// The components needed to run the driver, besides the default ones.
+const COMPONENTS: &[&str] = &["rustc-dev", "llvm-tools"];
+
+/// Set when `cargo <plugin>` re-runs itself, to avoid doing so repeatedly.
+const REEXEC: &str = "RUSTC_PLUGIN_REEXEC";
+
+fn commit_hash(rustc: &str) -> Option<String> {
+  let output = Command::new(rustc).arg("-vV").output().ok()?;
+  if !output.status.success() {
+    return None;
+  }
+  let version = String::from_utf8(output.stdout).ok()?;
+  version
+    .lines()
+    .find_map(|line| line.strip_prefix("commit-hash: "))
+    .map(str::to_string)
+}
+
+/// Returns the required components that are not installed for the plugin's toolchain,
+/// or `None` if rustup or the toolchain are not installed.
+fn missing_components() -> Option<Vec<&'static str>> {
+  let output = Command::new("rustup")
+    .args(["component", "list", "--installed", "--toolchain", CHANNEL])
+    .output()
+    .ok()?;
+  if !output.status.success() {
+    return None;
+  }
+  let installed = String::from_utf8_lossy(&output.stdout);
+  let missing = COMPONENTS
+    .iter()
+    .copied()
+    .filter(|component| !installed.lines().any(|line| line.starts_with(component)))
+    .collect();
+  Some(missing)
+}
+
+/// Checks the toolchain of the `rustc` that Cargo would use, and re-runs the current
+/// process with the plugin's toolchain if it is different. Exits if this is not
+/// possible.
+pub fn verify() {
+  let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+  let found = commit_hash(&rustc);
+  if found.as_deref() == Some(COMMIT_HASH) {
+    return;
+  }
+
+  let missing = missing_components();
+  let installed = missing.as_ref().is_some_and(Vec::is_empty);
+  if installed && env::var(REEXEC).is_err() {
+    log::debug!("Found rustc commit {found:?}, re-running with toolchain {CHANNEL}");
+    let status = Command::new("rustup")
+      .args(["run", CHANNEL])
+      .arg(env::current_exe().expect("current executable path invalid"))
+      .args(env::args_os().skip(1))
+      .env(REEXEC, "")
+      .status()
+      .expect("failed to run rustup");
+    exit(status.code().unwrap_or(-1));
+  }
+
+  let found = match found {
+    Some(hash) => format!("`{rustc}` is built from commit {hash}"),
+    None => format!("`{rustc}` could not be run"),
+  };
+  eprintln!("error: this plugin must be run with the {CHANNEL} toolchain, but {found}.");
+  match missing {
+    None => eprintln!(
+      "Install the toolchain with:\n  rustup toolchain install {CHANNEL} --component {}",
+      COMPONENTS.join(" ")
+    ),
+    Some(missing) if !missing.is_empty() => eprintln!(
+      "Install the missing components with:\n  rustup component add --toolchain {CHANNEL} {}",
+      missing.join(" ")
+    ),
+    Some(_) => eprintln!(
+      "The toolchain is installed, but `{rustc}` is not from it. Check that the RUSTC \
+       environment variable is unset."
+    ),
+  }
+  exit(1);
+}
@@ -5,6 +5,64 @@ use anyhow::{ensure, Context, Result};
 static SETUP: Once = Once::new();
 
 fn run(dir: &str, f: impl FnOnce(&mut Command)) -> Result<String> {
+  run_with(dir, true, f)
+}
+
+fn run_with(dir: &str, clean: bool, f: impl FnOnce(&mut Command)) -> Result<String> {
+  run_bin("print-all-items", dir, clean, f)
+}
+
+fn run_bin(
+  bin: &str,
+  dir: &str,
+  clean: bool,
+  f: impl FnOnce(&mut Command),
+) -> Result<String> {
+  Ok(run_full(bin, dir, clean, f)?.0)
+}
+
+/// Returns the stdout and stderr of the plugin.
+fn run_full(
+  bin: &str,
+  dir: &str,
+  clean: bool,
+  f: impl FnOnce(&mut Command),
+) -> Result<(String, String)> {
+  let (code, stdout, stderr) = run_status(bin, dir, clean, f)?;
+  ensure!(
+    code == Some(0),
+    "Process exited with non-zero exit code. Stderr:\n{stderr}"
+  );
+  Ok((stdout, stderr))
+}
+
+/// Returns the exit code, stdout and stderr of the plugin.
+fn run_status(
+  bin: &str,
+  dir: &str,
+  clean: bool,
+  f: impl FnOnce(&mut Command),
+) -> Result<(Option<i32>, String, String)> {
+  let ws = Path::new(".").canonicalize()?.join("tests").join(dir);
+  let mut cmd = plugin_command(bin, &ws)?;
+
+  f(&mut cmd);
+
+  if clean {
+    let _ = fs::remove_dir_all(ws.join("target"));
+  }
+
+  let output = cmd.output().context("Process failed")?;
+  Ok((
+    output.status.code(),
+    String::from_utf8(output.stdout)?,
+    String::from_utf8(output.stderr)?,
+  ))
+}
+
+/// Returns a command running `cargo <bin>` in the workspace `ws`, after installing the
+/// example plugin.
+fn plugin_command(bin: &str, ws: &Path) -> Result<Command> {
   let root = env::temp_dir().join("rustc_plugin");
 
   let heredir = Path::new(".").canonicalize()?;
@@ -28,7 +86,7 @@ fn run(dir: &str, f: impl FnOnce(&mut Command)) -> Result<String> {
   });
 
   let mut cmd = Command::new("cargo");
-  cmd.arg("print-all-items");
+  cmd.arg(bin);
 
   let path = format!(
     "{}:{}",
@@ -36,22 +94,8 @@ fn run(dir: &str, f: impl FnOnce(&mut Command)) -> Result<String> {
     env::var("PATH").unwrap_or_else(|_| "".into())
   );
   cmd.env("PATH", path);
-
-  let ws = heredir.join("tests").join(dir);
-  cmd.current_dir(&ws);
-
-  f(&mut cmd);
-
-  let _ = fs::remove_dir_all(ws.join("target"));
-
-  let output = cmd.output().context("Process failed")?;
-  ensure!(
-    output.status.success(),
-    "Process exited with non-zero exit code. Stderr:\n{}",
-    String::from_utf8(output.stderr)?
-  );
-
-  Ok(String::from_utf8(output.stdout)?)
+  cmd.current_dir(ws);
+  Ok(cmd)
 }
 
 // TODO: why do these tests need to be run sequentially?
@@ -72,6 +116,15 @@ fn arg() -> Result<()> {
   Ok(())
 }
 
+#[test]
+fn arg_file() -> Result<()> {
+  let output = run("workspaces/basic", |cmd| {
+    cmd.arg("-a").env("RUSTC_PLUGIN_ARGS_IN_FILE", "");
+  })?;
+  assert!(output.contains(r#"THERE IS AN ITEM "ADD" OF TYPE "FUNCTION""#));
+  Ok(())
+}
+
 #[test]
 fn feature() -> Result<()> {
   let output = run("workspaces/basic", |cmd| {
@@ -84,8 +137,764 @@ fn feature() -> Result<()> {
   Ok(())
 }
 
+#[test]
+fn feature_flag() -> Result<()> {
+  let output = run("workspaces/basic", |cmd| {
+    cmd.args(["--features", "sub", "-a"]);
+  })?;
+  assert!(
+    output.contains(r#"THERE IS AN ITEM "SUB" OF TYPE "FUNCTION""#),
+    "output:\n{output}"
+  );
+  Ok(())
+}
+
+fn host() -> Result<String> {
+  let rustc = Command::new("rustc").arg("-vV").output()?;
+  String::from_utf8(rustc.stdout)?
+    .lines()
+    .find_map(|line| line.strip_prefix("host: ").map(str::to_string))
+    .context("rustc did not print its host")
+}
+
+#[test]
+fn target() -> Result<()> {
+  let host = host()?;
+  let output = run("workspaces/basic", |cmd| {
+    cmd.args(["--target", &host]);
+  })?;
+  assert!(output.contains(r#"There is an item "add" of type "function""#));
+  Ok(())
+}
+
+#[test]
+fn build_std() -> Result<()> {
+  let host = host()?;
+  let output = run("workspaces/no_std", |cmd| {
+    cmd.args(["-Zbuild-std=core", "--target", &host]);
+  })?;
+  assert!(
+    output.contains(r#"There is an item "add" of type "function""#),
+    "output:\n{output}"
+  );
+  assert!(
+    output.contains("Crate `core` is a sysroot crate"),
+    "output:\n{output}"
+  );
+  assert!(
+    !output.contains("Crate `no_std` is a sysroot crate"),
+    "output:\n{output}"
+  );
+  Ok(())
+}
+
+#[test]
+fn incremental() -> Result<()> {
+  let first = run("workspaces/incremental", |_cmd| {})?;
+  assert!(first.contains(r#"There is an item "unchanged" of type "function""#));
+  let target = Path::new("tests/workspaces/incremental/target");
+  assert!(target
+    .join("print-all-items-driver/fingerprints/incremental-incremental.json")
+    .exists());
+
+  // Force Cargo to recompile the crate, which replays the saved output.
+  for entry in fs::read_dir(target)? {
+    let path = entry?.path();
+    if path
+      .file_name()
+      .unwrap()
+      .to_string_lossy()
+      .starts_with("plugin-")
+    {
+      fs::remove_dir_all(path)?;
+    }
+  }
+  let second = run_with("workspaces/incremental", false, |_cmd| {})?;
+  assert_eq!(first, second);
+  Ok(())
+}
+
+#[test]
+fn incremental_findings() -> Result<()> {
+  let args = ["--warn", "--message-format=json"];
+  let found = |output: &str| {
+    output
+      .lines()
+      .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+      .any(|msg| {
+        msg["reason"] == "compiler-message"
+          && msg["message"]["message"] == "found item `unchanged`"
+      })
+  };
+  let (code, first, _) = run_status(
+    "print-all-items",
+    "workspaces/incremental-findings",
+    true,
+    |cmd| {
+      cmd.args(args);
+    },
+  )?;
+  assert_eq!(code, Some(1));
+  assert!(found(&first), "output:\n{first}");
+
+  // The diagnostics are replayed along with the rest of the output.
+  let target = Path::new("tests/workspaces/incremental-findings/target");
+  for entry in fs::read_dir(target)? {
+    let path = entry?.path();
+    if path
+      .file_name()
+      .unwrap()
+      .to_string_lossy()
+      .starts_with("plugin-")
+    {
+      fs::remove_dir_all(path)?;
+    }
+  }
+  let (code, second, _) = run_status(
+    "print-all-items",
+    "workspaces/incremental-findings",
+    false,
+    |cmd| {
+      cmd.args(args);
+    },
+  )?;
+  assert_eq!(code, Some(1));
+  assert!(found(&second), "output:\n{second}");
+  Ok(())
+}
+
 #[test]
 fn multi() -> Result<()> {
   run("workspaces/multi", |_cmd| {})?;
   Ok(())
 }
+
+#[cfg(unix)]
+#[test]
+fn rustc_wrapper() -> Result<()> {
+  use std::os::unix::fs::PermissionsExt;
+
+  // A wrapper that logs its arguments before running rustc, standing in for sccache.
+  let dir = env::temp_dir().join("rustc_plugin_wrapper");
+  fs::create_dir_all(&dir)?;
+  let log = dir.join("log");
+  let _ = fs::remove_file(&log);
+  let wrapper = dir.join("wrapper.sh");
+  fs::write(
+    &wrapper,
+    format!("#!/bin/sh\necho \"$@\" >> {}\nexec \"$@\"\n", log.display()),
+  )?;
+  fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755))?;
+
+  let output = run("workspaces/basic", |cmd| {
+    cmd.arg("--rustc-wrapper").env("RUSTC_WRAPPER", &wrapper);
+  })?;
+  assert!(output.contains(r#"There is an item "add" of type "function""#));
+  assert!(fs::read_to_string(&log)?.contains("rustc"));
+  Ok(())
+}
+
+#[test]
+fn plugin_group() -> Result<()> {
+  let output = run_bin("print-all-items-group", "workspaces/basic", true, |_cmd| {})?;
+  assert!(output.contains(r#"There is an item "add" of type "function""#));
+  assert!(output.contains("There are "), "output:\n{output}");
+
+  let output = run_bin("print-all-items-group", "workspaces/basic", true, |cmd| {
+    cmd.args(["--plugins", "count"]);
+  })?;
+  assert!(output.contains("There are "), "output:\n{output}");
+  assert!(!output.contains("There is an item"), "output:\n{output}");
+  Ok(())
+}
+
+#[test]
+fn plugin_group_override_queries() -> Result<()> {
+  // Both plugins override `mir_borrowck`, and neither override replaces the other. The
+  // bodies are `add` and `analyzed`.
+  let output = run_bin("print-all-items-group", "workspaces/basic", true, |cmd| {
+    cmd.arg("--count-borrowck");
+  })?;
+  assert!(
+    output.contains("Borrow-checked bodies seen by items: 2"),
+    "output:\n{output}"
+  );
+  assert!(
+    output.contains("Borrow-checked bodies seen by count: 2"),
+    "output:\n{output}"
+  );
+  Ok(())
+}
+
+#[test]
+fn diagnostics() -> Result<()> {
+  let (code, output, _) =
+    run_status("print-all-items", "workspaces/basic", true, |cmd| {
+      cmd.args(["--warn", "--message-format=json"]);
+    })?;
+  // Diagnostics are findings.
+  assert_eq!(code, Some(1));
+  let message = output
+    .lines()
+    .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+    .find(|msg| {
+      msg["reason"] == "compiler-message"
+        && msg["message"]["message"] == "found item `add`"
+    })
+    .with_context(|| format!("no compiler message in output:\n{output}"))?;
+  assert_eq!(message["message"]["level"], "warning");
+  let span = &message["message"]["spans"][0];
+  assert_eq!(span["file_name"], "src/lib.rs");
+  assert_eq!(span["line_start"], 1);
+  Ok(())
+}
+
+#[test]
+fn toolchain_reexec() -> Result<()> {
+  // The test needs a second toolchain to start from.
+  let stable = Command::new("rustup")
+    .args(["run", "stable", "rustc", "-V"])
+    .output();
+  if !stable.is_ok_and(|output| output.status.success()) {
+    return Ok(());
+  }
+
+  let output = run("workspaces/basic", |cmd| {
+    cmd.env("RUSTUP_TOOLCHAIN", "stable");
+  })?;
+  assert!(output.contains(r#"There is an item "add" of type "function""#));
+  Ok(())
+}
+
+#[test]
+fn standalone_file() -> Result<()> {
+  // There is no Cargo.toml in the directory, so the file is analyzed without Cargo.
+  let output = run("workspaces", |cmd| {
+    cmd.args(["--file", "basic/src/lib.rs"]);
+  })?;
+  assert!(output.contains(r#"There is an item "add" of type "function""#));
+  Ok(())
+}
+
+#[test]
+fn dry_run() -> Result<()> {
+  let output = run("workspaces/multi", |cmd| {
+    cmd.args(["--dry-run", "-p", "b", "--tests"]);
+  })?;
+  let lines = output.lines().collect::<Vec<_>>();
+  assert!(
+    lines.contains(&"plugin b 0.1.0: b (lib, test)"),
+    "output:\n{output}"
+  );
+  assert!(
+    lines.contains(&"rustc  a 0.1.0: a (lib)"),
+    "output:\n{output}"
+  );
+  assert!(!output.contains("There is an item"), "output:\n{output}");
+  Ok(())
+}
+
+#[test]
+fn output_dir() -> Result<()> {
+  run("workspaces/multi", |cmd| {
+    cmd.arg("--save");
+  })?;
+  let plugin_dir = Path::new("tests/workspaces/multi/target/print-all-items-driver");
+  for krate in ["a", "b"] {
+    let saved = fs::read_dir(plugin_dir)?
+      .filter_map(|entry| entry.ok())
+      .filter(|entry| {
+        let name = entry.file_name();
+        name.to_string_lossy().starts_with(&format!("{krate}-"))
+      })
+      .map(|entry| fs::read_to_string(entry.path().join("items.txt")))
+      .collect::<std::io::Result<Vec<_>>>()?;
+    ensure!(saved.len() == 1, "expected one directory for {krate}");
+    ensure!(saved[0].lines().any(|line| line == "add"));
+  }
+  Ok(())
+}
+
+#[test]
+fn plugin_upgrade() -> Result<()> {
+  let analyzed_dirs = || -> Result<usize> {
+    let plugin_dir = Path::new("tests/workspaces/basic/target/print-all-items-driver");
+    let dirs = fs::read_dir(plugin_dir)?
+      .filter_map(|entry| entry.ok())
+      .filter(|entry| entry.path().join("items.txt").exists())
+      .count();
+    Ok(dirs)
+  };
+
+  run("workspaces/basic", |cmd| {
+    cmd.arg("--save");
+  })?;
+  ensure!(analyzed_dirs()? == 1);
+
+  // Reinstalling the driver changes its modification time.
+  let driver = env::temp_dir().join("rustc_plugin/bin/print-all-items-driver");
+  fs::File::options()
+    .append(true)
+    .open(&driver)?
+    .set_modified(std::time::SystemTime::now())?;
+  run_with("workspaces/basic", false, |cmd| {
+    cmd.arg("--save");
+  })?;
+  ensure!(analyzed_dirs()? == 2, "the crate was not analyzed again");
+  Ok(())
+}
+
+#[test]
+fn cargo_metadata() -> Result<()> {
+  let output = run("workspaces/multi", |cmd| {
+    cmd.arg("--packages");
+  })?;
+  assert!(
+    output.contains("Crate `b` uses `a` from package a v0.1.0"),
+    "output:\n{output}"
+  );
+  Ok(())
+}
+
+#[test]
+fn check_first() -> Result<()> {
+  let err = run("workspaces/broken", |cmd| {
+    cmd.arg("--check-first");
+  })
+  .unwrap_err()
+  .to_string();
+  assert!(err.contains("mismatched types"), "error:\n{err}");
+  let target = Path::new("tests/workspaces/broken/target");
+  ensure!(target.join("debug").exists());
+  ensure!(!target
+    .join(format!("plugin-{}", env!("RUSTC_CHANNEL")))
+    .exists());
+
+  let output = run("workspaces/basic", |cmd| {
+    cmd.arg("--check-first");
+  })?;
+  assert!(output.contains(r#"There is an item "add" of type "function""#));
+  Ok(())
+}
+
+#[test]
+fn verbosity() -> Result<()> {
+  let (_, stderr) = run_full("print-all-items", "workspaces/basic", true, |cmd| {
+    cmd.arg("-vv").env_remove("RUST_LOG");
+  })?;
+  assert!(stderr.contains("Running plugin"), "stderr:\n{stderr}");
+  assert!(stderr.contains("Running `"), "stderr:\n{stderr}");
+
+  let (_, stderr) = run_full("print-all-items", "workspaces/basic", true, |cmd| {
+    cmd.arg("-q").env_remove("RUST_LOG");
+  })?;
+  assert!(!stderr.contains("Running plugin"), "stderr:\n{stderr}");
+  Ok(())
+}
+
+#[test]
+fn plugin_jobs() -> Result<()> {
+  let output = run("workspaces/multi", |cmd| {
+    cmd.args(["--plugin-jobs", "1", "--tests"]);
+  })?;
+  assert_eq!(
+    output
+      .matches(r#"There is an item "it_works" of type "function""#)
+      .count(),
+    2,
+    "output:\n{output}"
+  );
+  Ok(())
+}
+
+#[test]
+fn progress() -> Result<()> {
+  let (_, stderr) = run_full("print-all-items", "workspaces/multi", true, |cmd| {
+    cmd.arg("--progress");
+  })?;
+  let lines = stderr.lines().map(str::trim).collect::<Vec<_>>();
+  assert!(
+    lines.contains(&"Analyzing a v0.1.0 (1/2)"),
+    "stderr:\n{stderr}"
+  );
+  assert!(
+    lines.contains(&"Analyzing b v0.1.0 (2/2)"),
+    "stderr:\n{stderr}"
+  );
+  Ok(())
+}
+
+#[test]
+fn matching_filter() -> Result<()> {
+  let output = run("workspaces/multi", |cmd| {
+    cmd.args(["--dry-run", "--only", "a*"]);
+  })?;
+  let lines = output.lines().collect::<Vec<_>>();
+  assert!(
+    lines.contains(&"plugin a 0.1.0: a (lib)"),
+    "output:\n{output}"
+  );
+  assert!(
+    lines.contains(&"rustc  b 0.1.0: b (lib)"),
+    "output:\n{output}"
+  );
+  Ok(())
+}
+
+#[test]
+fn build_mode() -> Result<()> {
+  let output = run("workspaces/basic", |cmd| {
+    cmd.arg("--build");
+  })?;
+  assert!(output.contains(r#"There is an item "add" of type "function""#));
+  ensure!(Path::new("tests/workspaces/basic/target/debug/libbasic.rlib").exists());
+  Ok(())
+}
+
+#[test]
+fn modify_rustc_args() -> Result<()> {
+  let output = run("workspaces/basic", |_cmd| {})?;
+  assert!(
+    output.contains(r#"There is an item "analyzed" of type "function""#),
+    "output:\n{output}"
+  );
+  Ok(())
+}
+
+#[test]
+fn plugin_env() -> Result<()> {
+  let output = run("workspaces/basic", |cmd| {
+    cmd.args(["--prefix", "> "]);
+  })?;
+  assert!(
+    output.contains(r#"> There is an item "add" of type "function""#),
+    "output:\n{output}"
+  );
+
+  // Changing the variables analyzes the crates again instead of replaying the output.
+  let output = run_with("workspaces/basic", false, |_cmd| {})?;
+  assert!(
+    output.contains(r#"There is an item "add" of type "function""#),
+    "output:\n{output}"
+  );
+  assert!(!output.contains("> There is an item"), "output:\n{output}");
+  Ok(())
+}
+
+#[test]
+fn crate_kinds() -> Result<()> {
+  let output = run("workspaces/kinds", |cmd| {
+    cmd.arg("--kinds");
+  })?;
+  for line in [
+    "Crate `kinds` is a workspace",
+    "Crate `kinds_macro` is a proc-macro",
+    "Crate `build_script_build` is a build-script",
+  ] {
+    assert!(output.contains(line), "output:\n{output}");
+  }
+  Ok(())
+}
+
+#[test]
+fn watch() -> Result<()> {
+  use std::{
+    io::{BufRead, BufReader},
+    process::Stdio,
+    sync::mpsc,
+    time::Duration,
+  };
+
+  // The workspace is edited by the test, so it is created outside the repository.
+  let ws = env::temp_dir().join("rustc_plugin_watch");
+  let _ = fs::remove_dir_all(&ws);
+  fs::create_dir_all(ws.join("src"))?;
+  fs::write(
+    ws.join("Cargo.toml"),
+    "[package]\nname = \"watched\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+  )?;
+  fs::write(ws.join("src/lib.rs"), "pub fn first() {}\n")?;
+
+  let mut child = plugin_command("print-all-items", &ws)?
+    .arg("--watch")
+    .stdout(Stdio::piped())
+    .stderr(Stdio::null())
+    .spawn()?;
+  let stdout = BufReader::new(child.stdout.take().unwrap());
+  let (tx, rx) = mpsc::channel();
+  std::thread::spawn(move || {
+    for line in stdout.lines().map_while(Result::ok) {
+      let _ = tx.send(line);
+    }
+  });
+  let wait_for = |item: &str| {
+    let expected = format!(r#"There is an item "{item}" of type "function""#);
+    while let Ok(line) = rx.recv_timeout(Duration::from_secs(60)) {
+      if line.contains(&expected) {
+        return true;
+      }
+    }
+    false
+  };
+
+  let first = wait_for("first");
+  if first {
+    fs::write(ws.join("src/lib.rs"), "pub fn second() {}\n")?;
+  }
+  let second = first && wait_for("second");
+  child.kill()?;
+  child.wait()?;
+  ensure!(first, "the plugin did not run");
+  ensure!(second, "the plugin did not run again after a change");
+  Ok(())
+}
+
+#[test]
+fn rustflags() -> Result<()> {
+  let merged = r#"There is an item "merged" of type "function""#;
+  // Flags from the environment and from the configuration are both kept.
+  for var in ["RUSTFLAGS", "CARGO_BUILD_RUSTFLAGS"] {
+    let output = run("workspaces/basic", |cmd| {
+      cmd
+        .env(var, "--cfg user_flag")
+        .args(["--rustflags", "--cfg plugin_flag"]);
+    })?;
+    assert!(output.contains(merged), "{var}, output:\n{output}");
+  }
+
+  let output = run("workspaces/basic", |cmd| {
+    cmd.args(["--rustflags", "--cfg plugin_flag"]);
+  })?;
+  assert!(!output.contains(merged), "output:\n{output}");
+  Ok(())
+}
+
+#[test]
+fn profile() -> Result<()> {
+  let output = run("workspaces/basic", |cmd| {
+    cmd.args(["--profile", "release"]);
+  })?;
+  assert!(output.contains(r#"There is an item "add" of type "function""#));
+  let target = Path::new("tests/workspaces/basic/target")
+    .join(format!("plugin-{}", env!("RUSTC_CHANNEL")));
+  ensure!(target.join("release").exists());
+  ensure!(!target.join("debug").exists());
+  Ok(())
+}
+
+#[test]
+fn scaffold() -> Result<()> {
+  let dir = env::temp_dir().join("rustc_plugin_scaffold");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir)?;
+  let status = Command::new(env!("CARGO_BIN_EXE_cargo-rustc-plugin"))
+    .args(["rustc-plugin", "new", "demo-lint", "--path"])
+    .arg(Path::new(".").canonicalize()?)
+    .current_dir(&dir)
+    .status()?;
+  ensure!(status.success(), "generating the plugin failed");
+  let plugin = dir.join("demo-lint");
+  for file in ["src/bin/cargo-demo-lint.rs", "src/bin/demo-lint-driver.rs"] {
+    ensure!(plugin.join(file).exists(), "{file} was not generated");
+  }
+  let lib = fs::read_to_string(plugin.join("src/lib.rs"))?;
+  assert!(lib.contains("pub struct DemoLintPlugin;"), "lib.rs:\n{lib}");
+
+  // The generated smoke test installs and runs the plugin. The target directory is
+  // kept between runs of this test, since building rustc_plugin again is slow.
+  let output = Command::new("cargo")
+    .arg("test")
+    .env(
+      "CARGO_TARGET_DIR",
+      env::temp_dir().join("rustc_plugin_scaffold_target"),
+    )
+    .current_dir(&plugin)
+    .output()?;
+  ensure!(
+    output.status.success(),
+    "the generated test failed:\n{}{}",
+    String::from_utf8_lossy(&output.stdout),
+    String::from_utf8_lossy(&output.stderr)
+  );
+  Ok(())
+}
+
+#[test]
+fn topological() -> Result<()> {
+  let (_, output, stderr) =
+    run_status("print-all-items", "workspaces/multi", true, |cmd| {
+      cmd.args(["--summaries", "--topological", "--kinds"]);
+    })?;
+  assert!(
+    output.contains("Crate `b` uses the summary of `a`: add"),
+    "output:\n{output}\nstderr:\n{stderr}"
+  );
+  let a = output.find("Crate `a`").context("a was not analyzed")?;
+  let b = output.find("Crate `b`").context("b was not analyzed")?;
+  assert!(a < b, "output:\n{output}");
+  Ok(())
+}
+
+#[test]
+fn crate_timeout() -> Result<()> {
+  let err = run("workspaces/multi", |cmd| {
+    cmd.args(["--hang-on", "a", "--crate-timeout", "1"]);
+  })
+  .unwrap_err()
+  .to_string();
+  assert!(
+    err.contains("the plugin timed out after 1s on crate `a`"),
+    "error:\n{err}"
+  );
+  Ok(())
+}
+
+#[test]
+fn retry_without_plugin() -> Result<()> {
+  let (code, output, stderr) =
+    run_status("print-all-items", "workspaces/multi", true, |cmd| {
+      cmd.args(["--panic-on", "a", "--on-failure", "retry-without-plugin"]);
+    })?;
+  assert_eq!(code, Some(2));
+  assert_eq!(
+    output
+      .matches(r#"There is an item "add" of type "function""#)
+      .count(),
+    1,
+    "output:\n{output}"
+  );
+  assert!(
+    stderr.contains(
+      "failed    a v0.1.0 (a): the plugin panicked, compiled without the plugin"
+    ),
+    "stderr:\n{stderr}"
+  );
+  assert!(
+    stderr.contains("analyzed  b v0.1.0 (b)"),
+    "stderr:\n{stderr}"
+  );
+  Ok(())
+}
+
+#[test]
+fn panic_report() -> Result<()> {
+  let (code, _, stderr) =
+    run_status("print-all-items", "workspaces/multi", true, |cmd| {
+      cmd.args(["--panic-on", "a"]);
+    })?;
+  assert_eq!(code, Some(2));
+  for line in [
+    "error: the plugin panicked on crate `a`: told to panic on a",
+    "note: while processing add",
+    "failed    a v0.1.0 (a): the plugin panicked",
+  ] {
+    assert!(stderr.contains(line), "stderr:\n{stderr}");
+  }
+  assert!(!stderr.contains("RUST_BACKTRACE"), "stderr:\n{stderr}");
+
+  let reports = Path::new("tests/workspaces/multi/target/print-all-items-driver/reports");
+  let report = fs::read_dir(reports)?
+    .next()
+    .context("no panic report")??
+    .path();
+  let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(report)?)?;
+  assert_eq!(report["crate_name"], "a");
+  assert_eq!(report["processing"][0], "add");
+  assert_eq!(report["message"], "told to panic on a");
+  assert!(report["backtrace"]
+    .as_str()
+    .is_some_and(|bt| !bt.is_empty()));
+  Ok(())
+}
+
+#[test]
+fn guarded_bodies() -> Result<()> {
+  let (output, stderr) = run_full("print-all-items", "workspaces/basic", true, |cmd| {
+    cmd.args(["--guarded-panic-on", "add"]);
+  })?;
+  assert!(
+    output.contains("Analysis of `add` failed: panicked: told to panic on add at "),
+    "output:\n{output}"
+  );
+  assert!(
+    output.contains("Body `analyzed` has 1 blocks"),
+    "output:\n{output}"
+  );
+  // The caught panic is not reported as a failure of the crate.
+  assert!(
+    !stderr.contains("error: the plugin panicked"),
+    "stderr:\n{stderr}"
+  );
+  ensure!(
+    !Path::new("tests/workspaces/basic/target/print-all-items-driver/reports").exists()
+  );
+  Ok(())
+}
+
+#[test]
+fn summary() -> Result<()> {
+  let (_, stderr) = run_full("print-all-items", "workspaces/multi", true, |_cmd| {})?;
+  assert!(
+    stderr.contains("2 analyzed, 0 skipped, 0 failed, 0 timed out; 0 findings"),
+    "stderr:\n{stderr}"
+  );
+
+  let (code, _, stderr) =
+    run_status("print-all-items", "workspaces/basic", true, |cmd| {
+      cmd.arg("--warn");
+    })?;
+  assert_eq!(code, Some(1));
+  assert!(
+    stderr.contains("analyzed  basic v0.1.0 (basic), 4 findings"),
+    "stderr:\n{stderr}"
+  );
+  Ok(())
+}
+
+#[test]
+fn doc() -> Result<()> {
+  let output = run("workspaces/multi", |cmd| {
+    cmd.arg("--doc");
+  })?;
+  assert_eq!(
+    output
+      .matches(r#"There is an item "add" of type "function""#)
+      .count(),
+    2,
+    "output:\n{output}"
+  );
+  let doc_dir = Path::new("tests/workspaces/multi/target")
+    .join(format!("plugin-{}", env!("RUSTC_CHANNEL")))
+    .join("doc");
+  ensure!(doc_dir.join("b").join("index.html").exists());
+  Ok(())
+}
+
+#[test]
+fn aggregate() -> Result<()> {
+  let output = run("workspaces/multi", |cmd| {
+    cmd.arg("--json");
+  })?;
+  assert!(output.contains("a: add"), "output:\n{output}");
+  assert!(output.contains("b: add"), "output:\n{output}");
+  assert!(!output.contains("There is an item"), "output:\n{output}");
+  Ok(())
+}
+
+#[test]
+fn select_tests() -> Result<()> {
+  let output = run("workspaces/multi", |cmd| {
+    cmd.args(["-p", "b", "--tests"]);
+  })?;
+  // Only the test harness of `b` is analyzed, not its dependency `a`.
+  assert!(
+    output.contains(r#"There is an item "tests" of type "module""#),
+    "output:\n{output}"
+  );
+  assert_eq!(
+    output.matches(r#"item "add""#).count(),
+    1,
+    "output:\n{output}"
+  );
+  Ok(())
+}
@@ -17,3 +17,11 @@ mod tests {
     assert_eq!(result, 4);
   }
 }
+
+// Enabled by the example plugin's `modify_rustc_args`.
+#[cfg(print_all_items)]
+pub fn analyzed() {}
+
+// Enabled by the flags of the user and of the plugin together.
+#[cfg(all(user_flag, plugin_flag))]
+pub fn merged() {}
@@ -0,0 +1,7 @@
+pub fn add(left: usize, right: usize) -> usize {
+  left + right
+}
+
+pub fn answer() -> usize {
+  "forty-two"
+}
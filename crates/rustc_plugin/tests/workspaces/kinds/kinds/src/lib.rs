@@ -0,0 +1 @@
+kinds_macro::make_add!();
@@ -0,0 +1,8 @@
+use proc_macro::TokenStream;
+
+#[proc_macro]
+pub fn make_add(_input: TokenStream) -> TokenStream {
+  "pub fn add(left: usize, right: usize) -> usize { left + right }"
+    .parse()
+    .unwrap()
+}
@@ -0,0 +1,5 @@
+#![no_std]
+
+pub fn add(left: usize, right: usize) -> usize {
+  left + right
+}
@@ -0,0 +1,144 @@
+//! A cache that allocates its values in an arena.
+
+use std::{borrow::Borrow, cell::RefCell, hash::Hash};
+
+use rustc_arena::TypedArena;
+use rustc_data_structures::fx::FxHashMap as HashMap;
+
+use super::CycleError;
+
+/// Cache for non-copyable types that allocates values in an arena rather than
+/// boxing each one.
+///
+/// This amortizes allocation for caches holding many small values, e.g. memoized
+/// dataflow states. Values have stable addresses because the arena never moves
+/// them, but they are also never freed before the cache is dropped, so unlike
+/// [`Cache`](super::Cache) this cache cannot evict or remove entries.
+pub struct ArenaCache<In, Out> {
+  arena: TypedArena<Out>,
+  /// `None` while the value is being computed.
+  map: RefCell<HashMap<In, Option<*const Out>>>,
+}
+
+impl<In, Out> ArenaCache<In, Out>
+where
+  In: Hash + Eq + Clone,
+{
+  /// Size of the cache
+  pub fn len(&self) -> usize {
+    self.map.borrow().len()
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn get<Q>(&self, key: &Q, compute: impl FnOnce(&Q) -> Out) -> &Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self
+      .get_checked(key, compute)
+      .unwrap_or_else(|e| panic!("{e}"))
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// Returns `None` if this is a recursive invocation of `get` for key `key`.
+  pub fn get_maybe_recursive<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Option<&Out>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.get_checked(key, compute).ok()
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// Returns a [`CycleError`] if this is a recursive invocation of `get` for key
+  /// `key`.
+  pub fn get_checked<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Result<&Out, CycleError<In>>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let cached = self.map.borrow().get(key).copied();
+    let out = match cached {
+      Some(Some(out)) => out,
+      Some(None) => {
+        return Err(CycleError {
+          key: key.to_owned(),
+        });
+      }
+      None => {
+        self.map.borrow_mut().insert(key.to_owned(), None);
+        let out = self.arena.alloc(compute(key)) as *const Out;
+        *self
+          .map
+          .borrow_mut()
+          .get_mut(key)
+          .expect("invariant broken") = Some(out);
+        out
+      }
+    };
+
+    // SAFETY: the arena never moves or frees its values before it is dropped, and
+    // the returned reference has a lifetime equal to the cache.
+    Ok(unsafe { &*out })
+  }
+}
+
+impl<In, Out> Default for ArenaCache<In, Out> {
+  fn default() -> Self {
+    ArenaCache {
+      arena: TypedArena::default(),
+      map: RefCell::new(HashMap::default()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_arena_cache() {
+    let cache: ArenaCache<usize, Vec<usize>> = ArenaCache::default();
+    let values = (0 .. 1000)
+      .map(|i| cache.get(&i, |i| vec![*i]))
+      .collect::<Vec<_>>();
+    assert_eq!(cache.len(), 1000);
+    for (i, value) in values.into_iter().enumerate() {
+      assert!(std::ptr::eq(value, cache.get(&i, |_| unreachable!())));
+      assert_eq!(value, &vec![i]);
+    }
+
+    fn fib(cache: &ArenaCache<u64, u64>, i: u64) -> u64 {
+      *cache.get(&i, |&i| {
+        if i <= 1 {
+          i
+        } else {
+          fib(cache, i - 1) + fib(cache, i - 2)
+        }
+      })
+    }
+    let cache = ArenaCache::default();
+    assert_eq!(fib(&cache, 50), 12586269025);
+    assert!(cache
+      .get_checked(&51, |_| cache.get_checked(&51, |_| 0).unwrap_err().key)
+      .is_ok());
+  }
+}
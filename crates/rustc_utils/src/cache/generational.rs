@@ -0,0 +1,263 @@
+//! A cache whose entries can be invalidated in bulk.
+
+use std::{borrow::Borrow, cell::RefCell, hash::Hash, pin::Pin};
+
+use rustc_data_structures::fx::FxHashMap as HashMap;
+
+use super::CycleError;
+
+struct Slot<Out> {
+  /// The generation in which the value was last computed.
+  generation: u64,
+  computing: bool,
+  /// `None` if the value has never been computed.
+  value: Option<Pin<Box<Out>>>,
+}
+
+/// Cache for non-copyable types whose entries are invalidated all at once by
+/// [`next_generation`](GenerationalCache::next_generation), e.g. between the phases
+/// of a multi-phase analysis.
+///
+/// Starting a new generation does not touch the entries. Instead, an entry from an
+/// older generation is stale and recomputed on its next access, reusing the
+/// allocation of its old value. With [`get_reusing`](GenerationalCache::get_reusing),
+/// the old value itself can be updated in place, e.g. to reuse the capacity of a
+/// `Vec`.
+pub struct GenerationalCache<In, Out> {
+  generation: u64,
+  map: RefCell<HashMap<In, Slot<Out>>>,
+}
+
+impl<In, Out> GenerationalCache<In, Out>
+where
+  In: Hash + Eq + Clone,
+{
+  /// The current generation, starting at 0.
+  pub fn generation(&self) -> u64 {
+    self.generation
+  }
+
+  /// Invalidates every entry. Requires exclusive access, so no references returned
+  /// by [`get`](GenerationalCache::get) can be alive.
+  pub fn next_generation(&mut self) {
+    self.generation += 1;
+  }
+
+  /// Drops the values of stale entries, reclaiming their memory.
+  pub fn purge(&mut self) {
+    let generation = self.generation;
+    self
+      .map
+      .get_mut()
+      .retain(|_, slot| slot.generation == generation);
+  }
+
+  /// The number of entries computed in the current generation.
+  pub fn len(&self) -> usize {
+    self
+      .map
+      .borrow()
+      .values()
+      .filter(|slot| slot.generation == self.generation && !slot.computing)
+      .count()
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if the value is
+  /// not in cache or is stale.
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn get<Q>(&self, key: &Q, compute: impl FnOnce(&Q) -> Out) -> &Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self
+      .get_checked(key, compute)
+      .unwrap_or_else(|e| panic!("{e}"))
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if the value is
+  /// not in cache or is stale.
+  ///
+  /// Returns `None` if this is a recursive invocation of `get` for key `key`.
+  pub fn get_maybe_recursive<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Option<&Out>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.get_checked(key, compute).ok()
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if the value is
+  /// not in cache or is stale.
+  ///
+  /// Returns a [`CycleError`] if this is a recursive invocation of `get` for key
+  /// `key`.
+  pub fn get_checked<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Result<&Out, CycleError<In>>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    if let Some(out) = self.start(key)? {
+      return Ok(out);
+    }
+    let out = compute(key);
+
+    let mut map = self.map.borrow_mut();
+    let slot = map.get_mut(key).expect("invariant broken");
+    slot.computing = false;
+    match &mut slot.value {
+      Some(value) => value.set(out),
+      None => slot.value = Some(Box::pin(out)),
+    }
+    Ok(self.extend(slot.value.as_ref().unwrap()))
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if the value is not
+  /// in cache. If the value is stale, `update` is called with the old value to
+  /// recompute it in place instead.
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn get_reusing<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+    update: impl FnOnce(&Q, &mut Out),
+  ) -> &Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+    Out: Unpin,
+  {
+    if let Some(out) = self.start(key).unwrap_or_else(|e| panic!("{e}")) {
+      return out;
+    }
+    let stale = self
+      .map
+      .borrow_mut()
+      .get_mut(key)
+      .expect("invariant broken")
+      .value
+      .as_mut()
+      .map(|value| &mut **value as *mut Out);
+    match stale {
+      // SAFETY: the old value belongs to an older generation, so no references to it
+      // can be alive, and it is only accessed here until its entry is done.
+      Some(value) => update(key, unsafe { &mut *value }),
+      None => {
+        let out = compute(key);
+        let mut map = self.map.borrow_mut();
+        map.get_mut(key).expect("invariant broken").value = Some(Box::pin(out));
+      }
+    }
+
+    let mut map = self.map.borrow_mut();
+    let slot = map.get_mut(key).expect("invariant broken");
+    slot.computing = false;
+    self.extend(slot.value.as_ref().unwrap())
+  }
+
+  /// Returns the value of `key` if it is fresh, or otherwise marks it as being
+  /// computed in the current generation.
+  fn start<Q>(&self, key: &Q) -> Result<Option<&Out>, CycleError<In>>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let mut map = self.map.borrow_mut();
+    match map.get_mut(key) {
+      Some(slot) if slot.generation == self.generation => {
+        if slot.computing {
+          return Err(CycleError {
+            key: key.to_owned(),
+          });
+        }
+        Ok(Some(self.extend(slot.value.as_ref().unwrap())))
+      }
+      Some(slot) => {
+        slot.generation = self.generation;
+        slot.computing = true;
+        Ok(None)
+      }
+      None => {
+        map.insert(key.to_owned(), Slot {
+          generation: self.generation,
+          computing: true,
+          value: None,
+        });
+        Ok(None)
+      }
+    }
+  }
+
+  fn extend(&self, out: &Pin<Box<Out>>) -> &Out {
+    // SAFETY: because the value is pinned, it cannot move. It is only replaced or
+    // dropped by recomputing a stale entry or by `purge`, which both require a new
+    // generation and hence exclusive access to the cache. The returned reference
+    // has a lifetime equal to the cache, so neither can happen before this reference
+    // goes out of scope.
+    unsafe { &*(&**out as *const Out) }
+  }
+}
+
+impl<In, Out> Default for GenerationalCache<In, Out> {
+  fn default() -> Self {
+    GenerationalCache {
+      generation: 0,
+      map: RefCell::new(HashMap::default()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_generational_cache() {
+    let mut cache: GenerationalCache<usize, Vec<usize>> = GenerationalCache::default();
+    let computed = RefCell::new(0);
+    let compute = |&i: &usize| {
+      *computed.borrow_mut() += 1;
+      vec![i; 4]
+    };
+
+    let zero = cache.get(&0, compute);
+    assert!(std::ptr::eq(zero, cache.get(&0, compute)));
+    cache.get(&1, compute);
+    assert_eq!((cache.len(), *computed.borrow()), (2, 2));
+
+    let old = zero as *const Vec<usize>;
+    cache.next_generation();
+    assert_eq!(cache.len(), 0);
+    // The stale entry is recomputed into the allocation of its old value.
+    let zero = cache.get_reusing(&0, compute, |&i, value| {
+      value.clear();
+      value.push(i + 10);
+    });
+    assert_eq!(zero, &vec![10]);
+    assert_eq!(zero.capacity(), 4);
+    assert!(std::ptr::eq(zero, old));
+    assert_eq!(*computed.borrow(), 2);
+
+    cache.purge();
+    assert_eq!(cache.len(), 1);
+    assert!(cache
+      .get_checked(&2, |_| vec![
+        cache.get_checked(&2, |_| vec![]).unwrap_err().key
+      ])
+      .is_ok());
+  }
+}
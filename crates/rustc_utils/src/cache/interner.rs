@@ -0,0 +1,242 @@
+//! Hash-consing of values.
+
+use std::{
+  borrow::Borrow,
+  cell::RefCell,
+  fmt,
+  hash::{Hash, Hasher},
+  marker::PhantomData,
+  pin::Pin,
+};
+
+use indexmap::Equivalent;
+use rustc_data_structures::fx::FxIndexSet;
+use rustc_middle::mir::{Place, PlaceElem, ProjectionElem};
+use rustc_target::abi::FieldIdx;
+
+/// A compact handle to a value interned by an [`Interner`].
+///
+/// Two handles from the same interner are equal if and only if their values are
+/// equal, so comparing and hashing handles is cheap regardless of the size of the
+/// value.
+pub struct Interned<T> {
+  index: u32,
+  _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Interned<T> {
+  fn new(index: usize) -> Self {
+    Interned {
+      index: u32::try_from(index).expect("too many interned values"),
+      _marker: PhantomData,
+    }
+  }
+
+  /// The position of the value in the order it was interned.
+  pub fn index(self) -> usize {
+    self.index as usize
+  }
+}
+
+impl<T> Clone for Interned<T> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<T> Copy for Interned<T> {}
+
+impl<T> PartialEq for Interned<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.index == other.index
+  }
+}
+
+impl<T> Eq for Interned<T> {}
+
+impl<T> PartialOrd for Interned<T> {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<T> Ord for Interned<T> {
+  fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    self.index.cmp(&other.index)
+  }
+}
+
+impl<T> Hash for Interned<T> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.index.hash(state);
+  }
+}
+
+impl<T> fmt::Debug for Interned<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Interned({})", self.index)
+  }
+}
+
+/// Interns values of type `T`, i.e. stores a single copy of each distinct value.
+///
+/// [`intern`](Interner::intern) returns a reference to the stored copy, which lives as
+/// long as the interner, and [`intern_id`](Interner::intern_id) returns an
+/// [`Interned`] handle. Values are never removed.
+pub struct Interner<T> {
+  values: RefCell<FxIndexSet<Pin<Box<T>>>>,
+}
+
+/// Looks up an interned value by anything it can be borrowed as, e.g. a `str` for a
+/// `String`, so the value is only cloned when it is first interned.
+struct Lookup<'a, Q: ?Sized>(&'a Q);
+
+impl<Q: Hash + ?Sized> Hash for Lookup<'_, Q> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.0.hash(state);
+  }
+}
+
+impl<T: Borrow<Q>, Q: Eq + ?Sized> Equivalent<Pin<Box<T>>> for Lookup<'_, Q> {
+  fn equivalent(&self, value: &Pin<Box<T>>) -> bool {
+    self.0 == (**value).borrow()
+  }
+}
+
+impl<T> Interner<T>
+where
+  T: Hash + Eq + Clone,
+{
+  /// The number of distinct values interned so far.
+  pub fn len(&self) -> usize {
+    self.values.borrow().len()
+  }
+
+  /// Returns the handle of `value`, interning it if it was not already.
+  pub fn intern_id<Q>(&self, value: &Q) -> Interned<T>
+  where
+    T: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = T> + ?Sized,
+  {
+    let mut values = self.values.borrow_mut();
+    let index = match values.get_index_of(&Lookup(value)) {
+      Some(index) => index,
+      None => values.insert_full(Box::pin(value.to_owned())).0,
+    };
+    Interned::new(index)
+  }
+
+  /// Returns the stored copy of `value`, interning it if it was not already.
+  pub fn intern<Q>(&self, value: &Q) -> &T
+  where
+    T: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = T> + ?Sized,
+  {
+    self.get(self.intern_id(value))
+  }
+
+  /// Returns the value of `id`.
+  ///
+  /// # Panics
+  ///
+  /// If `id` was not created by this interner.
+  pub fn get(&self, id: Interned<T>) -> &T {
+    let values = self.values.borrow();
+    let value = &**values
+      .get_index(id.index())
+      .expect("unknown interned value") as *const T;
+    // SAFETY: values are pinned and never removed, so this pointer will only be
+    // invalidated if the interner is dropped. The returned reference has a lifetime
+    // equal to the interner, so this cannot happen before it goes out of scope.
+    unsafe { &*value }
+  }
+}
+
+impl<T> Default for Interner<T> {
+  fn default() -> Self {
+    Interner {
+      values: RefCell::new(FxIndexSet::default()),
+    }
+  }
+}
+
+/// Interns the projections of places, e.g. to compare or store them independently of
+/// their base local.
+pub type ProjectionInterner<'tcx> = Interner<Vec<PlaceElem<'tcx>>>;
+
+impl<'tcx> ProjectionInterner<'tcx> {
+  /// Returns the handle of the projection of `place`.
+  pub fn intern_place(&self, place: Place<'tcx>) -> Interned<Vec<PlaceElem<'tcx>>> {
+    self.intern_id(place.projection.as_slice())
+  }
+}
+
+/// Interns paths of field projections, e.g. `.0.1` in `x.0.1`.
+pub type FieldPathInterner = Interner<Vec<FieldIdx>>;
+
+impl FieldPathInterner {
+  /// Returns the handle of the fields projected by `place`, or `None` if `place` has
+  /// a projection other than a field.
+  pub fn intern_field_path(&self, place: Place<'_>) -> Option<Interned<Vec<FieldIdx>>> {
+    let path = place
+      .projection
+      .iter()
+      .map(|elem| match elem {
+        ProjectionElem::Field(field, _) => Some(field),
+        _ => None,
+      })
+      .collect::<Option<Vec<_>>>()?;
+    Some(self.intern_id(&path))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test_utils::{self, Placer};
+
+  #[test]
+  fn test_interner() {
+    let interner: Interner<String> = Interner::default();
+    let a = interner.intern_id("a");
+    let b = interner.intern_id("b");
+    assert_ne!(a, b);
+    assert_eq!(interner.intern_id("a"), a);
+    assert_eq!(interner.len(), 2);
+    assert_eq!(interner.get(b), "b");
+    assert!(std::ptr::eq(interner.intern("a"), interner.get(a)));
+  }
+
+  #[test]
+  fn test_place_interners() {
+    let input = r#"
+fn main() {
+  let mut x = ((0, 1), 2);
+  let y = &mut x;
+  (*y).0.1 = 3;
+  x.0.1 = 4;
+}
+"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let placer = Placer::new(tcx, body);
+      let x = placer.local("x").field(0).field(1).mk();
+      let y = placer.local("y").deref().field(0).field(1).mk();
+
+      let projections = ProjectionInterner::default();
+      assert_ne!(projections.intern_place(x), projections.intern_place(y));
+      assert_eq!(
+        projections.intern_place(x),
+        projections.intern_place(placer.local("x").field(0).field(1).mk())
+      );
+
+      let paths = FieldPathInterner::default();
+      let path = paths.intern_field_path(x).unwrap();
+      assert_eq!(paths.get(path), &vec![
+        FieldIdx::from_usize(0),
+        FieldIdx::from_usize(1)
+      ]);
+      assert!(paths.intern_field_path(y).is_none());
+    });
+  }
+}
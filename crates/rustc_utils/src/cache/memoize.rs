@@ -0,0 +1,124 @@
+//! Memoizing analysis methods with a cache field.
+
+/// Defines methods whose results are memoized in a cache field of `self`.
+///
+/// Each method takes `&self`, a context (usually a `TyCtxt`) and a key, and must be
+/// preceded by a `#[memoize(field)]` attribute naming the field to cache results in.
+/// The field can be any cache of this module with a `get(&key, compute)` method, and
+/// the return type of the method is that of `get`, e.g. `&Out` for a
+/// [`Cache`](crate::cache::Cache) and `Out` for a [`CopyCache`](crate::cache::CopyCache).
+/// The body computes the value of a missing key, and may call the method recursively.
+///
+/// ```ignore
+/// struct Analysis {
+///   summaries: Cache<LocalDefId, Summary>,
+/// }
+///
+/// impl Analysis {
+///   memoize! {
+///     #[memoize(summaries)]
+///     /// Returns the summary of the function `def_id`.
+///     pub fn summary(&self, tcx: TyCtxt<'tcx>, def_id: LocalDefId) -> &Summary {
+///       Summary::build(tcx, def_id)
+///     }
+///   }
+/// }
+/// ```
+///
+/// The key is captured by the body rather than cloned, so non-`Copy` keys can only be
+/// used by reference in the body.
+#[macro_export]
+macro_rules! memoize {
+  ($(
+    #[memoize($cache:ident)]
+    $(#[$attr:meta])*
+    $vis:vis fn $name:ident(
+      &$self:ident,
+      $cx:ident: $cx_ty:ty,
+      $key:ident: $key_ty:ty $(,)?
+    ) -> $ret:ty $body:block
+  )*) => {$(
+    $(#[$attr])*
+    $vis fn $name(&$self, $cx: $cx_ty, $key: $key_ty) -> $ret {
+      $self.$cache.get(&$key, |_| $body)
+    }
+  )*};
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::Cell;
+
+  use rustc_hir::def_id::LocalDefId;
+  use rustc_middle::{mir::BasicBlock, ty::TyCtxt};
+
+  use crate::{
+    cache::{Cache, CopyCache},
+    test_utils::{CompileBuilder, CompileResult},
+  };
+
+  struct Analysis {
+    num_blocks: CopyCache<LocalDefId, usize>,
+    dominators: Cache<(LocalDefId, BasicBlock), Vec<BasicBlock>>,
+    computed: Cell<usize>,
+  }
+
+  impl Analysis {
+    memoize! {
+      #[memoize(num_blocks)]
+      fn num_blocks(&self, tcx: TyCtxt<'_>, def_id: LocalDefId) -> usize {
+        self.computed.set(self.computed.get() + 1);
+        tcx.optimized_mir(def_id).basic_blocks.len()
+      }
+
+      #[memoize(dominators)]
+      /// The blocks dominating `key.1`, closest first.
+      fn dominators(
+        &self,
+        tcx: TyCtxt<'_>,
+        key: (LocalDefId, BasicBlock),
+      ) -> &Vec<BasicBlock> {
+        let (def_id, block) = key;
+        let dominators = tcx.optimized_mir(def_id).basic_blocks.dominators();
+        match dominators.immediate_dominator(block) {
+          Some(parent) if parent != block => {
+            let mut out = vec![parent];
+            out.extend(self.dominators(tcx, (def_id, parent)));
+            out
+          }
+          _ => Vec::new(),
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_memoize() {
+    let input = r#"
+fn main() {
+  let x = if true { 1 } else { 2 };
+  let _y = x + 1;
+}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let analysis = Analysis {
+        num_blocks: CopyCache::default(),
+        dominators: Cache::default(),
+        computed: Cell::new(0),
+      };
+
+      let num_blocks = analysis.num_blocks(tcx, def_id);
+      assert_eq!(analysis.num_blocks(tcx, def_id), num_blocks);
+      assert_eq!(analysis.computed.get(), 1);
+
+      let last = BasicBlock::from_usize(num_blocks - 1);
+      let dominators = analysis.dominators(tcx, (def_id, last));
+      assert_eq!(dominators.last(), Some(&BasicBlock::from_usize(0)));
+      assert!(std::ptr::eq(
+        dominators,
+        analysis.dominators(tcx, (def_id, last))
+      ));
+    });
+  }
+}
@@ -0,0 +1,1220 @@
+//! Data structures for memoizing computations.
+//!
+//! Contruct new caches using [`Default::default`], then construct/retrieve
+//! elements with [`get`](Cache::get). `get` should only ever be used with one,
+//! `compute` function[^inconsistent].
+//!
+//! Keys are looked up by reference, like [`HashMap::get`](std::collections::HashMap::get),
+//! so a `Cache<String, _>` can be queried with a `&str` and a `Cache<Vec<T>, _>` with
+//! a `&[T]`. The key is never cloned when its value is cached, and cloned once when it
+//! is computed (once more for a cache created with [`with_capacity`](Cache::with_capacity),
+//! and for the first computation of a key in a cache created with
+//! [`with_stats`](Cache::with_stats)).
+//!
+//! Methods of an analysis that memoize their results in a cache field can be defined
+//! with the [`memoize!`](crate::memoize) macro.
+//!
+//! In terms of choice,
+//! - [`CopyCache`] should be used for expensive computations that create cheap
+//!   (i.e. small) values.
+//! - [`Cache`] should be used for expensive computations that create expensive
+//!   (i.e. large) values.
+//! - [`SyncCache`] should be used instead of [`Cache`] when the cache is shared
+//!   between threads.
+//! - [`ArenaCache`] should be used instead of [`Cache`] for a large number of
+//!   small values that are never removed.
+//! - [`GenerationalCache`] should be used instead of [`Cache`] when all entries are
+//!   invalidated at once, e.g. between the phases of an analysis.
+//! - [`Interner`] should be used to store a single copy of equal values, e.g. the
+//!   projections of places, and refer to them by [`Interned`] handles.
+//!
+//! Both types of caches implement **recursion breaking**. In general because
+//! caches are supposed to be used as simple `&` (no `mut`) the reference may be
+//! freely copied, including into the `compute` closure. What this means is that
+//! a `compute` may call [`get`](Cache::get) on the cache again. This is usually
+//! safe and can be used to compute data structures that recursively depend on
+//! one another, dynamic-programming style. However if a `get` on a key `k`
+//! itself calls `get` again on the same `k` this will either create an infinite
+//! recursion or an inconsistent cache[^inconsistent].
+//!
+//! Consider a simple example where we compute the Fibonacci Series with a
+//! [`CopyCache`]:
+//!
+//! ```rs
+//! struct Fib(CopyCache<u32, u32>);
+//!
+//! impl Fib {
+//!   fn get(&self, i: u32) -> u32 {
+//!     self.0.get(&i, |&this| {
+//!       if this <= 1 {
+//!         return this;
+//!       }
+//!       let fib_1 = self.get(this - 1);
+//!       let fib_2 = self.get(this - 2);
+//!       fib_1 + fib_2
+//!     })
+//!   }
+//! }
+//!
+//! let cache = Fib(Default::default());
+//! let fib_5 = cache.get(5);
+//! ```
+//!
+//! This use of recursive [`get`](CopyCache::get) calls is perfectly legal.
+//! However if we made an error and called `chache.get(this, ...)` (forgetting
+//! the decrement) we would have created an inadvertend infinite recursion.
+//!
+//! To avoid this scenario both caches are implemented to detect when a
+//! recursive call as described is performed and `get` will panic. If your code
+//! uses recursive construction and would like to handle this case gracefully
+//! use [`get_maybe_recursive`](Cache::get_maybe_recursive) instead wich returns
+//! `None` from `get(k)` *iff* `k` this call (potentially transitively)
+//! originates from another `get(k)` call. Similarly,
+//! [`get_checked`](Cache::get_checked) returns a [`CycleError`], and
+//! [`get_or_recover`](Cache::get_or_recover) computes a fallback value for the
+//! recursive call with a user-provided closure.
+//!
+//! By default caches grow without bound. A cache created with
+//! [`with_capacity`](Cache::with_capacity) instead evicts its least-recently-used
+//! entries once it holds more than the given number of entries. Because
+//! [`Cache::get`] returns references that live as long as the cache, an entry
+//! retrieved with `get` is *pinned* and never evicted, until
+//! [`unpin_all`](Cache::unpin_all) is called. To retrieve values from a bounded
+//! cache without pinning them, use [`Cache::with`] instead.
+//!
+//! Entries can be removed with [`invalidate`](Cache::invalidate),
+//! [`retain`](Cache::retain) and [`clear`](Cache::clear), e.g. to re-analyze
+//! a modified function. These only require `&self`, so references returned by
+//! `get` may still point to a removed value. The values of pinned entries are
+//! therefore only dropped once [`unpin_all`](Cache::unpin_all) is called or the
+//! cache is dropped, and until then a removed value still takes up memory.
+//! Entries whose value is being computed are never removed.
+//!
+//! [^inconsistent]: For any given cache value `get` should only ever be used
+//!     with one, referentially transparent `compute` function. Essentially this
+//!     means running `compute(k)` should always return the same value
+//!     *independent of the state of it's environment*. Violation of this rule
+//!     can introduces non-determinism in your program.
+use std::{
+  borrow::Borrow, cell::RefCell, collections::BTreeMap, convert::Infallible, fmt,
+  hash::Hash, pin::Pin, time::Instant,
+};
+
+use rustc_data_structures::fx::FxHashMap as HashMap;
+
+#[cfg(feature = "serde")]
+pub use self::persistent::{default_cache_dir, PersistentCache};
+pub use self::{
+  arena::ArenaCache,
+  generational::GenerationalCache,
+  interner::{FieldPathInterner, Interned, Interner, ProjectionInterner},
+  stats::CacheStats,
+  sync::SyncCache,
+};
+
+mod arena;
+mod generational;
+mod interner;
+mod memoize;
+#[cfg(feature = "serde")]
+mod persistent;
+mod stats;
+mod sync;
+
+/// The error returned when the computation of a cached value (potentially
+/// transitively) tried to retrieve the same value from the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleError<In> {
+  /// The key whose value was requested while it was being computed.
+  pub key: In,
+}
+
+impl<In> fmt::Display for CycleError<In> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Recursion detected! The computation of a value tried to retrieve the same from the cache. Use `get_checked` or `get_or_recover` to handle this case gracefully."
+    )
+  }
+}
+
+impl<In: fmt::Debug> std::error::Error for CycleError<In> {}
+
+/// Why a fallible computation of a cached value did not produce a value.
+enum TryGetError<In, E> {
+  Cycle(CycleError<In>),
+  Failed(E),
+}
+
+impl<In, E> TryGetError<In, E> {
+  fn into_failure(self) -> E {
+    match self {
+      TryGetError::Cycle(e) => panic!("{e}"),
+      TryGetError::Failed(e) => e,
+    }
+  }
+}
+
+impl<In> TryGetError<In, Infallible> {
+  fn into_cycle(self) -> CycleError<In> {
+    match self {
+      TryGetError::Cycle(e) => e,
+      TryGetError::Failed(never) => match never {},
+    }
+  }
+}
+
+struct Entry<V> {
+  /// `None` while the value is being computed.
+  value: Option<V>,
+  /// True if a reference to the value may outlive any access to the cache.
+  pinned: bool,
+  /// The number of scoped accesses currently using the value.
+  in_use: usize,
+  /// The position of the entry in [`Lru::order`].
+  last_used: u64,
+}
+
+/// Least-recently-used order of the computed, unpinned entries of a bounded cache.
+struct Lru<In> {
+  capacity: usize,
+  tick: u64,
+  order: BTreeMap<u64, In>,
+}
+
+/// The entries of a cache, shared by [`Cache`] and [`CopyCache`].
+struct Entries<In, V> {
+  map: HashMap<In, Entry<V>>,
+  lru: Option<Lru<In>>,
+  /// Values computed by `get_or_recover` for recursive calls, which are not cached
+  /// but must live as long as the cache.
+  fallbacks: Vec<V>,
+  /// Values of removed entries that may still be referenced.
+  retired: Vec<V>,
+  stats: Option<CacheStats<In>>,
+}
+
+impl<In, V> Entries<In, V> {
+  fn new(capacity: Option<usize>) -> Self {
+    Entries {
+      map: HashMap::default(),
+      lru: capacity.map(|capacity| Lru {
+        capacity,
+        tick: 0,
+        order: BTreeMap::new(),
+      }),
+      fallbacks: Vec::new(),
+      retired: Vec::new(),
+      stats: None,
+    }
+  }
+}
+
+impl<In: Hash + Eq + Clone, V> Entries<In, V> {
+  /// Marks `key` as being computed, returning when the computation started if the
+  /// cache records statistics.
+  fn start(&mut self, key: In) -> Option<Instant> {
+    self.map.insert(key, Entry {
+      value: None,
+      pinned: false,
+      in_use: 0,
+      last_used: 0,
+    });
+    self.stats.is_some().then(Instant::now)
+  }
+
+  fn record_hit(&mut self) {
+    if let Some(stats) = &mut self.stats {
+      stats.hits += 1;
+    }
+  }
+
+  fn record_miss<Q>(&mut self, key: &Q, start: Option<Instant>)
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    if let (Some(stats), Some(start)) = (&mut self.stats, start) {
+      stats.record_miss(key, start.elapsed());
+    }
+  }
+
+  fn stats(&self) -> Option<CacheStats<In>> {
+    let mut stats = self.stats.clone()?;
+    stats.entries = self.map.len();
+    Some(stats)
+  }
+
+  /// Forgets that `key` was being computed, because its computation failed.
+  fn abort<Q>(&mut self, key: &Q)
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.map.remove(key);
+  }
+
+  /// Removes the entry for `key` unless its value is being computed, returning true
+  /// if it was removed.
+  fn remove<Q>(&mut self, key: &Q) -> bool
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    if self.map.get(key).is_none_or(|entry| entry.value.is_none()) {
+      return false;
+    }
+
+    let entry = self.map.remove(key).unwrap();
+    if let Some(lru) = &mut self.lru {
+      lru.order.remove(&entry.last_used);
+    }
+    if entry.pinned || entry.in_use > 0 {
+      self.retired.push(entry.value.unwrap());
+    }
+    true
+  }
+
+  /// Returns the keys of the entries whose value has been computed.
+  fn computed_keys(&self) -> Vec<In> {
+    self
+      .map
+      .iter()
+      .filter(|(_, entry)| entry.value.is_some())
+      .map(|(key, _)| key.clone())
+      .collect()
+  }
+
+  /// Removes every entry for which `predicate` returns false.
+  fn retain(&mut self, mut predicate: impl FnMut(&In, &V) -> bool) {
+    let keys = self
+      .map
+      .iter()
+      .filter(|(key, entry)| {
+        entry
+          .value
+          .as_ref()
+          .is_some_and(|value| !predicate(key, value))
+      })
+      .map(|(key, _)| key.clone())
+      .collect::<Vec<_>>();
+    for key in keys {
+      self.remove(&key);
+    }
+  }
+
+  /// Stores the computed value for `key`, evicting other entries if necessary.
+  fn finish<Q>(&mut self, key: &Q, value: V)
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.map.get_mut(key).expect("invariant broken").value = Some(value);
+    // Evict before adding the new entry to the order, so it is never evicted right
+    // away.
+    self.evict();
+    self.touch(key);
+  }
+
+  /// Moves `key` to the back of the eviction order if it is not pinned, or removes
+  /// it from the order otherwise.
+  ///
+  /// The key is moved within the order, so it is only cloned when the entry is
+  /// added to the order for the first time.
+  fn touch<Q>(&mut self, key: &Q)
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let Some(lru) = &mut self.lru else { return };
+    let entry = self.map.get_mut(key).expect("invariant broken");
+    let owned = lru.order.remove(&entry.last_used);
+    if entry.value.is_some() && !entry.pinned {
+      lru.tick += 1;
+      entry.last_used = lru.tick;
+      lru
+        .order
+        .insert(lru.tick, owned.unwrap_or_else(|| key.to_owned()));
+    }
+  }
+
+  /// Evicts the least-recently-used entries that are not in use until the cache is
+  /// within its capacity.
+  fn evict(&mut self) {
+    let Some(lru) = &mut self.lru else { return };
+    let mut in_use = Vec::new();
+    while self.map.len() > lru.capacity {
+      let Some((tick, key)) = lru.order.pop_first() else {
+        break;
+      };
+      if self.map[&key].in_use > 0 {
+        in_use.push((tick, key));
+      } else {
+        self.map.remove(&key);
+      }
+    }
+    lru.order.extend(in_use);
+  }
+
+  fn unpin_all(&mut self) {
+    let keys = self
+      .map
+      .iter_mut()
+      .filter(|(_, entry)| entry.pinned)
+      .map(|(key, entry)| {
+        entry.pinned = false;
+        key.clone()
+      })
+      .collect::<Vec<_>>();
+    for key in keys {
+      self.touch(&key);
+    }
+    self.evict();
+    self.fallbacks.clear();
+    self.retired.clear();
+  }
+}
+
+/// Cache for non-copyable types.
+pub struct Cache<In, Out>(RefCell<Entries<In, Pin<Box<Out>>>>);
+
+impl<In, Out> Cache<In, Out> {
+  /// Creates a cache that holds at most `capacity` entries, evicting the
+  /// least-recently-used entries that are not pinned.
+  pub fn with_capacity(capacity: usize) -> Self {
+    Cache(RefCell::new(Entries::new(Some(capacity))))
+  }
+
+  /// Records statistics about the accesses to the cache, which can be retrieved
+  /// with [`stats`](Cache::stats). The statistics keep their own clone of each key
+  /// that misses.
+  pub fn with_stats(mut self) -> Self {
+    self.0.get_mut().stats = Some(CacheStats::default());
+    self
+  }
+}
+
+impl<In, Out> Cache<In, Out>
+where
+  In: Hash + Eq + Clone,
+{
+  /// Size of the cache
+  pub fn len(&self) -> usize {
+    self.0.borrow().map.len()
+  }
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn get<Q>(&self, key: &Q, compute: impl FnOnce(&Q) -> Out) -> &Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self
+      .get_checked(key, compute)
+      .unwrap_or_else(|e| panic!("{e}"))
+  }
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// Returns `None` if this is a recursive invocation of `get` for key `key`.
+  pub fn get_maybe_recursive<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Option<&Out>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.get_checked(key, compute).ok()
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// Returns a [`CycleError`] if this is a recursive invocation of `get` for key
+  /// `key`.
+  pub fn get_checked<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Result<&Out, CycleError<In>>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let out = self
+      .try_get_ptr(key, |key| Ok(compute(key)))
+      .map_err(TryGetError::into_cycle)?;
+    Ok(self.pin(key, out))
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// If `compute` fails, its error is returned and nothing is cached, so the value is
+  /// computed again by the next call. To cache errors as well, use a
+  /// `Cache<In, Result<Out, E>>` with [`get_result`](Cache::get_result).
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn try_get<Q, E>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Result<Out, E>,
+  ) -> Result<&Out, E>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let out = self
+      .try_get_ptr(key, compute)
+      .map_err(TryGetError::into_failure)?;
+    Ok(self.pin(key, out))
+  }
+
+  fn pin<Q>(&self, key: &Q, out: *const Out) -> &Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let mut entries = self.0.borrow_mut();
+    entries.map.get_mut(key).expect("invariant broken").pinned = true;
+    entries.touch(key);
+
+    // SAFETY: because the entry is pinned, it cannot move, and it will not be
+    // evicted. So this pointer will only be invalidated if Cache is dropped or
+    // `unpin_all` is called. The returned reference has a lifetime equal to Cache,
+    // so neither can happen before this reference goes out of scope.
+    unsafe { &*out }
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// If this is a recursive invocation of `get` for key `key`, returns the value of
+  /// `recover` instead. That value is not cached, but is kept alive as long as the
+  /// cache.
+  pub fn get_or_recover<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+    recover: impl FnOnce(&Q) -> Out,
+  ) -> &Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.get_checked(key, compute).unwrap_or_else(|_| {
+      let out = Box::pin(recover(key));
+      let ptr = &*out as *const Out;
+      self.0.borrow_mut().fallbacks.push(out);
+      // SAFETY: fallbacks are pinned and only dropped with the cache.
+      unsafe { &*ptr }
+    })
+  }
+
+  /// Calls `f` with the cached value for the given key, running `compute` if the
+  /// value is not in cache.
+  ///
+  /// Unlike [`get`](Cache::get), this does not pin the entry, so it can later be
+  /// evicted from a cache created with [`with_capacity`](Cache::with_capacity).
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn with<Q, T>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+    f: impl FnOnce(&Out) -> T,
+  ) -> T
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let out = self
+      .try_get_ptr(key, |key| Ok(compute(key)))
+      .unwrap_or_else(|e| panic!("{}", e.into_cycle()));
+    {
+      let mut entries = self.0.borrow_mut();
+      entries.map.get_mut(key).expect("invariant broken").in_use += 1;
+      entries.touch(key);
+    }
+
+    // SAFETY: the entry is pinned in memory and cannot be evicted while it is in use,
+    // even if `f` accesses the cache.
+    let result = f(unsafe { &*out });
+
+    // `f` may have removed the entry, and possibly computed a new one for the key.
+    let mut entries = self.0.borrow_mut();
+    if let Some(entry) = entries.map.get_mut(key)
+      && entry
+        .value
+        .as_ref()
+        .is_some_and(|value| std::ptr::eq(&**value, out))
+    {
+      entry.in_use -= 1;
+    }
+    entries.evict();
+    result
+  }
+
+  /// Removes the cached value for `key`, returning true if there was one.
+  ///
+  /// If the value may still be referenced, it is only dropped by
+  /// [`unpin_all`](Cache::unpin_all), see the [module docs](self).
+  pub fn invalidate<Q>(&self, key: &Q) -> bool
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.0.borrow_mut().remove(key)
+  }
+
+  /// Removes every cached value for which `predicate` returns false.
+  ///
+  /// `predicate` must not access the cache. See [`invalidate`](Cache::invalidate)
+  /// for when removed values are dropped.
+  pub fn retain(&self, mut predicate: impl FnMut(&In, &Out) -> bool) {
+    self
+      .0
+      .borrow_mut()
+      .retain(|key, value| predicate(key, value));
+  }
+
+  /// Removes every cached value. See [`invalidate`](Cache::invalidate) for when
+  /// removed values are dropped.
+  pub fn clear(&self) {
+    self.0.borrow_mut().retain(|_, _| false);
+  }
+
+  /// Returns the keys of all cached values, in no particular order.
+  ///
+  /// The keys are collected up front, so the cache can be accessed while iterating.
+  pub fn keys(&self) -> impl Iterator<Item = In> {
+    self.0.borrow().computed_keys().into_iter()
+  }
+
+  /// Returns all cached values with their keys, in no particular order.
+  ///
+  /// Like [`get`](Cache::get), this pins the returned entries. The entries are
+  /// collected up front, so the cache can be accessed while iterating.
+  pub fn iter(&self) -> impl Iterator<Item = (In, &Out)> {
+    let entries = self
+      .keys()
+      .map(|key| {
+        let out = {
+          let entries = self.0.borrow();
+          let value = entries.map[&key].value.as_ref().expect("invariant broken");
+          &**value as *const Out
+        };
+        let out = self.pin(&key, out);
+        (key, out)
+      })
+      .collect::<Vec<_>>();
+    entries.into_iter()
+  }
+
+  /// Allows every entry to be evicted again, including those pinned by
+  /// [`get`](Cache::get), and drops the values of removed entries. Requires
+  /// exclusive access, so no references returned by `get` can be alive.
+  pub fn unpin_all(&mut self) {
+    self.0.get_mut().unpin_all();
+  }
+
+  fn try_get_ptr<Q, E>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Result<Out, E>,
+  ) -> Result<*const Out, TryGetError<In, E>>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let cached = self.0.borrow().map.contains_key(key);
+    if !cached {
+      // The only clone of the key on a miss, besides the one kept by the statistics.
+      let start = self.0.borrow_mut().start(key.to_owned());
+      let result = compute(key);
+      self.0.borrow_mut().record_miss(key, start);
+      match result {
+        Ok(out) => self.0.borrow_mut().finish(key, Box::pin(out)),
+        Err(e) => {
+          self.0.borrow_mut().abort(key);
+          return Err(TryGetError::Failed(e));
+        }
+      }
+    }
+
+    let mut entries = self.0.borrow_mut();
+    // Important here to first `unwrap` the `Option` created by `get`, then
+    // propagate the potential option stored in the map.
+    let entry = entries.map.get(key).expect("invariant broken");
+    let out = match &entry.value {
+      Some(out) => &**out as *const Out,
+      None => {
+        return Err(TryGetError::Cycle(CycleError {
+          key: key.to_owned(),
+        }));
+      }
+    };
+    if cached {
+      entries.record_hit();
+    }
+    Ok(out)
+  }
+
+  /// Returns the statistics of the cache if it was created
+  /// [`with_stats`](Cache::with_stats).
+  pub fn stats(&self) -> Option<CacheStats<In>> {
+    self.0.borrow().stats()
+  }
+}
+
+impl<In, Out, E> Cache<In, Result<Out, E>>
+where
+  In: Hash + Eq + Clone,
+{
+  /// Returns the cached result for the given key, or runs `compute` if the result
+  /// is not in cache. Unlike [`try_get`](Cache::try_get), errors are cached too.
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn get_result<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Result<Out, E>,
+  ) -> Result<&Out, &E>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.get(key, compute).as_ref()
+  }
+}
+
+impl<In, Out> Default for Cache<In, Out> {
+  fn default() -> Self {
+    Cache(RefCell::new(Entries::new(None)))
+  }
+}
+
+/// Cache for copyable types.
+pub struct CopyCache<In, Out>(RefCell<Entries<In, Out>>);
+
+impl<In, Out> CopyCache<In, Out> {
+  /// Creates a cache that holds at most `capacity` entries, evicting the
+  /// least-recently-used ones.
+  pub fn with_capacity(capacity: usize) -> Self {
+    CopyCache(RefCell::new(Entries::new(Some(capacity))))
+  }
+
+  /// Records statistics about the accesses to the cache, which can be retrieved
+  /// with [`stats`](CopyCache::stats). The statistics keep their own clone of each
+  /// key that misses.
+  pub fn with_stats(mut self) -> Self {
+    self.0.get_mut().stats = Some(CacheStats::default());
+    self
+  }
+}
+
+impl<In, Out> CopyCache<In, Out>
+where
+  In: Hash + Eq + Clone,
+  Out: Copy,
+{
+  /// Size of the cache
+  pub fn len(&self) -> usize {
+    self.0.borrow().map.len()
+  }
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn get<Q>(&self, key: &Q, compute: impl FnOnce(&Q) -> Out) -> Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self
+      .get_checked(key, compute)
+      .unwrap_or_else(|e| panic!("{e}"))
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// Returns `None` if this is a recursive invocation of `get` for key `key`.
+  pub fn get_maybe_recursive<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Option<Out>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.get_checked(key, compute).ok()
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// Returns a [`CycleError`] if this is a recursive invocation of `get` for key
+  /// `key`.
+  pub fn get_checked<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Result<Out, CycleError<In>>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self
+      .try_get_inner(key, |key| Ok(compute(key)))
+      .map_err(TryGetError::into_cycle)
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// If `compute` fails, its error is returned and nothing is cached, so the value is
+  /// computed again by the next call. To cache errors as well, use a
+  /// `CopyCache<In, Result<Out, E>>`.
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn try_get<Q, E>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Result<Out, E>,
+  ) -> Result<Out, E>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self
+      .try_get_inner(key, compute)
+      .map_err(TryGetError::into_failure)
+  }
+
+  fn try_get_inner<Q, E>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Result<Out, E>,
+  ) -> Result<Out, TryGetError<In, E>>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let cached = self.0.borrow().map.get(key).map(|entry| entry.value);
+    if let Some(value) = cached {
+      let value = value.ok_or_else(|| {
+        TryGetError::Cycle(CycleError {
+          key: key.to_owned(),
+        })
+      })?;
+      let mut entries = self.0.borrow_mut();
+      entries.record_hit();
+      entries.touch(key);
+      return Ok(value);
+    }
+
+    // The only clone of the key on a miss, besides the one kept by the statistics.
+    let start = self.0.borrow_mut().start(key.to_owned());
+    let result = compute(key);
+    self.0.borrow_mut().record_miss(key, start);
+    match result {
+      Ok(out) => {
+        self.0.borrow_mut().finish(key, out);
+        Ok(out)
+      }
+      Err(e) => {
+        self.0.borrow_mut().abort(key);
+        Err(TryGetError::Failed(e))
+      }
+    }
+  }
+
+  /// Returns the statistics of the cache if it was created
+  /// [`with_stats`](CopyCache::with_stats).
+  pub fn stats(&self) -> Option<CacheStats<In>> {
+    self.0.borrow().stats()
+  }
+
+  /// Removes the cached value for `key`, returning true if there was one.
+  pub fn invalidate<Q>(&self, key: &Q) -> bool
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.0.borrow_mut().remove(key)
+  }
+
+  /// Removes every cached value for which `predicate` returns false.
+  ///
+  /// `predicate` must not access the cache.
+  pub fn retain(&self, mut predicate: impl FnMut(&In, Out) -> bool) {
+    self
+      .0
+      .borrow_mut()
+      .retain(|key, value| predicate(key, *value));
+  }
+
+  /// Removes every cached value.
+  pub fn clear(&self) {
+    self.0.borrow_mut().retain(|_, _| false);
+  }
+
+  /// Returns the keys of all cached values, in no particular order.
+  ///
+  /// The keys are collected up front, so the cache can be accessed while iterating.
+  pub fn keys(&self) -> impl Iterator<Item = In> {
+    self.0.borrow().computed_keys().into_iter()
+  }
+
+  /// Returns all cached values with their keys, in no particular order.
+  ///
+  /// The entries are collected up front, so the cache can be accessed while
+  /// iterating.
+  pub fn iter(&self) -> impl Iterator<Item = (In, Out)> {
+    let entries = self.0.borrow();
+    let values = entries
+      .map
+      .iter()
+      .filter_map(|(key, entry)| Some((key.clone(), entry.value?)))
+      .collect::<Vec<_>>();
+    values.into_iter()
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// If this is a recursive invocation of `get` for key `key`, returns the value of
+  /// `recover` instead, without caching it.
+  pub fn get_or_recover<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+    recover: impl FnOnce(&Q) -> Out,
+  ) -> Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self
+      .get_checked(key, compute)
+      .unwrap_or_else(|_| recover(key))
+  }
+}
+
+impl<In, Out> Default for CopyCache<In, Out> {
+  fn default() -> Self {
+    CopyCache(RefCell::new(Entries::new(None)))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_cached() {
+    let cache: Cache<usize, usize> = Cache::default();
+    let x = cache.get(&0, |_| 0);
+    let y = cache.get(&1, |_| 1);
+    let z = cache.get(&0, |_| 2);
+    assert_eq!(*x, 0);
+    assert_eq!(*y, 1);
+    assert_eq!(*z, 0);
+    assert!(std::ptr::eq(x, z));
+  }
+
+  #[test]
+  fn test_recursion_breaking() {
+    struct RecursiveUse(Cache<i32, i32>);
+    impl RecursiveUse {
+      fn get_infinite_recursion(&self, i: i32) -> i32 {
+        self
+          .0
+          .get_maybe_recursive(&i, |_| i + self.get_infinite_recursion(i))
+          .copied()
+          .unwrap_or(-18)
+      }
+      fn get_safe_recursion(&self, i: i32) -> i32 {
+        *self.0.get(&i, |_| {
+          if i == 0 {
+            0
+          } else {
+            self.get_safe_recursion(i - 1) + i
+          }
+        })
+      }
+    }
+
+    let cache = RecursiveUse(Default::default());
+
+    assert_eq!(cache.get_infinite_recursion(60), 42);
+    assert_eq!(cache.get_safe_recursion(5), 15);
+  }
+
+  #[test]
+  fn test_cycle_detection() {
+    struct Graph {
+      edges: Vec<Vec<usize>>,
+      reachable: Cache<usize, Vec<usize>>,
+      depth: CopyCache<usize, usize>,
+    }
+    impl Graph {
+      fn reachable(&self, node: usize) -> &Vec<usize> {
+        self.reachable.get_or_recover(
+          &node,
+          |&node| {
+            let mut out = vec![node];
+            for next in &self.edges[node] {
+              out.extend(self.reachable(*next));
+            }
+            out.sort();
+            out.dedup();
+            out
+          },
+          |_| Vec::new(),
+        )
+      }
+      fn depth(&self, node: usize) -> Result<usize, CycleError<usize>> {
+        self.depth.get_checked(&node, |&node| {
+          self.edges[node]
+            .iter()
+            .map(|next| self.depth(*next).map(|depth| depth + 1).unwrap_or(0))
+            .max()
+            .unwrap_or(0)
+        })
+      }
+    }
+
+    let graph = Graph {
+      edges: vec![vec![1], vec![2], vec![0]],
+      reachable: Cache::default(),
+      depth: CopyCache::default(),
+    };
+    assert_eq!(graph.reachable(0), &vec![0, 1, 2]);
+    assert_eq!(graph.depth(0), Ok(2));
+
+    // The inner call fails with the key of the cycle, which the outer call caches.
+    let cache: CopyCache<usize, usize> = CopyCache::default();
+    let outer = cache.get_checked(&7, |_| cache.get_checked(&7, |_| 1).unwrap_err().key);
+    assert_eq!(outer, Ok(7));
+  }
+
+  #[test]
+  fn test_try_get() {
+    let cache: Cache<usize, String> = Cache::default();
+    let attempts = RefCell::new(0);
+    let compute = |&i: &usize| {
+      *attempts.borrow_mut() += 1;
+      if *attempts.borrow() == 1 {
+        Err("first attempt fails")
+      } else {
+        Ok(i.to_string())
+      }
+    };
+    assert_eq!(cache.try_get(&1, compute), Err("first attempt fails"));
+    assert_eq!(cache.len(), 0);
+    assert_eq!(cache.try_get(&1, compute).map(String::as_str), Ok("1"));
+    assert_eq!(cache.try_get(&1, compute).map(String::as_str), Ok("1"));
+    assert_eq!(*attempts.borrow(), 2);
+
+    let copy_cache: CopyCache<usize, usize> = CopyCache::default();
+    assert_eq!(copy_cache.try_get(&1, |_| Err(())), Err(()));
+    assert_eq!(copy_cache.try_get(&1, |i| Ok::<_, ()>(i * 2)), Ok(2));
+
+    let result_cache: Cache<usize, Result<usize, String>> = Cache::default();
+    assert_eq!(
+      result_cache.get_result(&1, |_| Err("error".to_string())),
+      Err(&"error".to_string())
+    );
+    assert_eq!(
+      result_cache.get_result(&1, |_| unreachable!()).unwrap_err(),
+      "error"
+    );
+  }
+
+  #[test]
+  fn test_stats() {
+    let cache: CopyCache<usize, usize> = CopyCache::default().with_stats();
+    for i in [0, 1, 0, 0, 2] {
+      cache.get(&i, |i| i + 1);
+    }
+    let stats = cache.stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (2, 3, 3));
+    assert_eq!(stats.compute_time.len(), 3);
+    assert!((stats.hit_rate() - 0.4).abs() < f64::EPSILON);
+    assert!(stats
+      .to_string()
+      .starts_with("2 hits, 3 misses (40.0% hit rate)"));
+
+    let cache: Cache<usize, usize> = Cache::with_capacity(1).with_stats();
+    cache.with(&0, |&i| i, |_| ());
+    cache.with(&1, |&i| i, |_| ());
+    cache.with(&0, |&i| i, |_| ());
+    cache.get(&0, |&i| i);
+    let stats = cache.stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 3, 1));
+
+    assert!(CopyCache::<usize, usize>::default().stats().is_none());
+  }
+
+  #[test]
+  fn test_invalidation() {
+    let mut cache: Cache<usize, String> = Cache::default();
+    let computed = RefCell::new(0);
+    let compute = |&i: &usize| {
+      *computed.borrow_mut() += 1;
+      i.to_string()
+    };
+
+    let zero = cache.get(&0, compute);
+    cache.get(&1, compute);
+    cache.get(&2, compute);
+    assert!(cache.invalidate(&0));
+    assert!(!cache.invalidate(&0));
+    // The removed value is kept alive for outstanding references.
+    assert_eq!(zero, "0");
+    assert_eq!(cache.get(&0, compute), "0");
+    assert_eq!(*computed.borrow(), 4);
+
+    cache.retain(|key, _| *key != 1);
+    assert_eq!(cache.len(), 2);
+    cache.with(&2, compute, |two| {
+      cache.clear();
+      assert_eq!(two, "2");
+    });
+    assert_eq!(cache.len(), 0);
+    cache.unpin_all();
+
+    let copy_cache: CopyCache<usize, usize> = CopyCache::default();
+    for i in 0 .. 4 {
+      copy_cache.get(&i, |i| i * 2);
+    }
+    copy_cache.retain(|_, value| value > 2);
+    assert_eq!(copy_cache.len(), 2);
+    copy_cache.clear();
+    assert_eq!(copy_cache.len(), 0);
+  }
+
+  #[test]
+  fn test_iter() {
+    let cache: Cache<usize, String> = Cache::default();
+    for i in 0 .. 3 {
+      cache.get(&i, |i| i.to_string());
+    }
+    let mut keys = cache.keys().collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(keys, vec![0, 1, 2]);
+
+    let mut entries = cache.iter().collect::<Vec<_>>();
+    entries.sort();
+    assert_eq!(entries, vec![
+      (0, &"0".to_string()),
+      (1, &"1".to_string()),
+      (2, &"2".to_string())
+    ]);
+    // The cache can be used while iterating.
+    for (key, value) in cache.iter() {
+      assert_eq!(cache.get(&key, |_| unreachable!()), value);
+    }
+
+    let copy_cache: CopyCache<usize, usize> = CopyCache::default();
+    copy_cache.get(&1, |i| i * 10);
+    copy_cache.get(&2, |i| i * 10);
+    let total = copy_cache.iter().map(|(_, value)| value).sum::<usize>();
+    assert_eq!(total, 30);
+    assert_eq!(copy_cache.keys().count(), 2);
+  }
+
+  #[test]
+  fn test_lru_eviction() {
+    let cache: CopyCache<usize, usize> = CopyCache::with_capacity(2);
+    let computed = RefCell::new(Vec::new());
+    let get = |i: usize| {
+      cache.get(&i, |&i| {
+        computed.borrow_mut().push(i);
+        i * 10
+      })
+    };
+    assert_eq!(get(0), 0);
+    assert_eq!(get(1), 10);
+    assert_eq!(get(0), 0);
+    assert_eq!(get(2), 20);
+    assert_eq!(cache.len(), 2);
+    // 1 was the least recently used entry, so it was evicted.
+    assert_eq!(get(1), 10);
+    assert_eq!(*computed.borrow(), vec![0, 1, 2, 1]);
+  }
+
+  #[test]
+  fn test_lru_pinning() {
+    let mut cache: Cache<usize, String> = Cache::with_capacity(1);
+    let computed = RefCell::new(0);
+    let compute = |&i: &usize| {
+      *computed.borrow_mut() += 1;
+      i.to_string()
+    };
+
+    let pinned = cache.get(&0, compute);
+    assert_eq!(cache.with(&1, compute, |s| s.len()), 1);
+    assert_eq!(cache.with(&2, compute, |s| s.len()), 1);
+    // The entry returned by `get` is never evicted, so the others are.
+    assert_eq!(pinned, "0");
+    assert_eq!(cache.len(), 1);
+    cache.with(&0, compute, |_| ());
+    assert_eq!(*computed.borrow(), 3);
+
+    cache.unpin_all();
+    cache.with(&1, compute, |_| ());
+    cache.with(&0, compute, |_| ());
+    assert_eq!(*computed.borrow(), 5);
+  }
+
+  #[test]
+  fn test_borrowed_keys() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(PartialEq, Eq, Hash)]
+    struct Key(usize);
+    impl Clone for Key {
+      fn clone(&self) -> Self {
+        CLONES.fetch_add(1, Ordering::SeqCst);
+        Key(self.0)
+      }
+    }
+
+    let cache: Cache<Key, usize> = Cache::default();
+    let copy_cache: CopyCache<Key, usize> = CopyCache::with_capacity(2);
+    for _ in 0 .. 3 {
+      cache.get(&Key(0), |key| key.0);
+      cache.with(&Key(1), |key| key.0, |_| ());
+      copy_cache.get(&Key(0), |key| key.0);
+    }
+    // Each miss clones its key once, plus once into the order of the bounded cache.
+    assert_eq!(CLONES.load(Ordering::SeqCst), 4);
+
+    let cache: Cache<String, usize> = Cache::default();
+    assert_eq!(*cache.get("hello", |s| s.len()), 5);
+    assert_eq!(*cache.get("hello", |_| unreachable!()), 5);
+    assert!(cache.invalidate("hello"));
+  }
+}
@@ -0,0 +1,218 @@
+//! A cache whose entries persist across compiler runs.
+
+use std::{
+  borrow::Borrow,
+  cell::Cell,
+  fs,
+  hash::Hash,
+  path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::Cache;
+
+/// The contents of a cache file.
+#[derive(Serialize, Deserialize)]
+struct CacheFile<In, Out> {
+  /// The version given by the plugin.
+  version: String,
+  /// The version of rustc that computed the entries.
+  rustc_version: String,
+  entries: Vec<(In, Out)>,
+}
+
+/// A [`Cache`] that is loaded from and saved to a file, so expensive results like
+/// per-function summaries are reused across `cargo` runs.
+///
+/// The file is invalidated when the `version` given to [`PersistentCache::load`] or
+/// the version of rustc changes. Plugins should bump their version whenever the
+/// computation of values changes. Keys must be stable across runs, e.g. a
+/// [`DefPathHash`](rustc_span::def_id::DefPathHash) rather than a
+/// [`DefId`](rustc_span::def_id::DefId).
+///
+/// New entries are written back when the cache is dropped, or explicitly with
+/// [`save`](PersistentCache::save).
+pub struct PersistentCache<In, Out>
+where
+  In: Hash + Eq + Clone + Serialize,
+  Out: Serialize,
+{
+  cache: Cache<In, Out>,
+  path: PathBuf,
+  version: String,
+  dirty: Cell<bool>,
+}
+
+/// Returns `$CARGO_TARGET_DIR/<plugin_name>/cache`, or `target/<plugin_name>/cache` if
+/// `CARGO_TARGET_DIR` is not set.
+pub fn default_cache_dir(plugin_name: &str) -> PathBuf {
+  let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+    .map_or_else(|| PathBuf::from("target"), PathBuf::from);
+  target_dir.join(plugin_name).join("cache")
+}
+
+impl<In, Out> PersistentCache<In, Out>
+where
+  In: Hash + Eq + Clone + Serialize + DeserializeOwned,
+  Out: Serialize + DeserializeOwned,
+{
+  /// Loads the cache named `name` from [`default_cache_dir`] of `plugin_name`.
+  pub fn load(plugin_name: &str, name: &str, version: &str) -> Self {
+    Self::load_from(
+      default_cache_dir(plugin_name).join(format!("{name}.json")),
+      version,
+    )
+  }
+
+  /// Loads the cache from the file at `path`, starting empty if the file does not
+  /// exist, cannot be read, or has a different version.
+  pub fn load_from(path: impl Into<PathBuf>, version: &str) -> Self {
+    let path = path.into();
+    let cache = Cache::default();
+    match read_entries::<In, Out>(&path, version) {
+      Ok(Some(entries)) => {
+        for (key, value) in entries {
+          cache.get(&key, |_| value);
+        }
+      }
+      Ok(None) => {}
+      Err(e) => log::warn!("Ignoring corrupt cache file {}: {e:?}", path.display()),
+    }
+
+    PersistentCache {
+      cache,
+      path,
+      version: version.to_string(),
+      dirty: Cell::new(false),
+    }
+  }
+}
+
+impl<In, Out> PersistentCache<In, Out>
+where
+  In: Hash + Eq + Clone + Serialize,
+  Out: Serialize,
+{
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn get<Q>(&self, key: &Q, compute: impl FnOnce(&Q) -> Out) -> &Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.cache.get(key, |key| {
+      self.dirty.set(true);
+      compute(key)
+    })
+  }
+
+  /// Size of the cache
+  pub fn len(&self) -> usize {
+    self.cache.len()
+  }
+
+  /// The file that the cache is saved to.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Writes the cache to its file, if entries were added since it was loaded.
+  pub fn save(&self) -> Result<()> {
+    if !self.dirty.get() {
+      return Ok(());
+    }
+
+    let entries = self.cache.0.borrow();
+    let file = CacheFile {
+      version: self.version.clone(),
+      rustc_version: rustc_version(),
+      entries: entries
+        .map
+        .iter()
+        .filter_map(|(key, entry)| Some((key, &**entry.value.as_ref()?)))
+        .collect::<Vec<_>>(),
+    };
+    let json = serde_json::to_vec(&file)?;
+
+    let dir = self.path.parent().unwrap();
+    fs::create_dir_all(dir)
+      .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    // Concurrent compilers may write the same file, so write to a unique temporary
+    // file and rename it into place.
+    let tmp = self
+      .path
+      .with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, json)?;
+    fs::rename(&tmp, &self.path)?;
+
+    self.dirty.set(false);
+    Ok(())
+  }
+}
+
+impl<In, Out> Drop for PersistentCache<In, Out>
+where
+  In: Hash + Eq + Clone + Serialize,
+  Out: Serialize,
+{
+  fn drop(&mut self) {
+    if let Err(e) = self.save() {
+      log::warn!("Failed to write cache file {}: {e:?}", self.path.display());
+    }
+  }
+}
+
+fn rustc_version() -> String {
+  rustc_interface::util::rustc_version_str()
+    .unwrap_or("unknown")
+    .to_string()
+}
+
+fn read_entries<In, Out>(path: &Path, version: &str) -> Result<Option<Vec<(In, Out)>>>
+where
+  In: DeserializeOwned,
+  Out: DeserializeOwned,
+{
+  let Ok(bytes) = fs::read(path) else {
+    return Ok(None);
+  };
+  let file: CacheFile<In, Out> = serde_json::from_slice(&bytes)?;
+  let up_to_date = file.version == version && file.rustc_version == rustc_version();
+  Ok(up_to_date.then_some(file.entries))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_persistent_cache() {
+    let dir =
+      std::env::temp_dir().join(format!("rustc_utils_cache_{}", std::process::id()));
+    let path = dir.join("summaries.json");
+
+    {
+      let cache: PersistentCache<String, Vec<u32>> =
+        PersistentCache::load_from(&path, "1");
+      assert_eq!(cache.get("a", |_| vec![1, 2]), &vec![1, 2]);
+    }
+
+    let cache: PersistentCache<String, Vec<u32>> = PersistentCache::load_from(&path, "1");
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.get("a", |_| unreachable!()), &vec![1, 2]);
+    drop(cache);
+
+    // Changing the version invalidates the file.
+    let cache: PersistentCache<String, Vec<u32>> = PersistentCache::load_from(&path, "2");
+    assert_eq!(cache.len(), 0);
+    drop(cache);
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,97 @@
+//! Instrumentation of cache accesses.
+
+use std::{borrow::Borrow, fmt, hash::Hash, time::Duration};
+
+use rustc_data_structures::fx::FxHashMap as HashMap;
+
+/// Statistics about the accesses to a cache, see
+/// [`Cache::with_stats`](super::Cache::with_stats).
+#[derive(Debug, Clone)]
+pub struct CacheStats<In> {
+  /// The number of accesses that found a cached value.
+  pub hits: usize,
+  /// The number of accesses that computed a value.
+  pub misses: usize,
+  /// The number of entries currently in the cache.
+  pub entries: usize,
+  /// The total time spent computing the value of each key. A key may be computed
+  /// more than once, e.g. if it was evicted or its computation failed, and is
+  /// cloned into this map the first time it is computed.
+  pub compute_time: HashMap<In, Duration>,
+}
+
+impl<In> Default for CacheStats<In> {
+  fn default() -> Self {
+    CacheStats {
+      hits: 0,
+      misses: 0,
+      entries: 0,
+      compute_time: HashMap::default(),
+    }
+  }
+}
+
+impl<In: Hash + Eq + Clone> CacheStats<In> {
+  pub(super) fn record_miss<Q>(&mut self, key: &Q, elapsed: Duration)
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.misses += 1;
+    match self.compute_time.get_mut(key) {
+      Some(time) => *time += elapsed,
+      None => {
+        self.compute_time.insert(key.to_owned(), elapsed);
+      }
+    }
+  }
+}
+
+impl<In> CacheStats<In> {
+  /// The fraction of accesses that found a cached value.
+  pub fn hit_rate(&self) -> f64 {
+    let accesses = self.hits + self.misses;
+    if accesses == 0 {
+      0.
+    } else {
+      self.hits as f64 / accesses as f64
+    }
+  }
+
+  pub fn total_compute_time(&self) -> Duration {
+    self.compute_time.values().sum()
+  }
+
+  /// Returns the keys sorted by their compute time, slowest first.
+  pub fn slowest(&self) -> Vec<(&In, Duration)> {
+    let mut keys = self
+      .compute_time
+      .iter()
+      .map(|(key, time)| (key, *time))
+      .collect::<Vec<_>>();
+    keys.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+    keys
+  }
+}
+
+/// The number of keys shown in the summary table.
+const NUM_SLOWEST: usize = 10;
+
+impl<In: fmt::Debug> fmt::Display for CacheStats<In> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(
+      f,
+      "{} hits, {} misses ({:.1}% hit rate), {} entries, {:?} computing",
+      self.hits,
+      self.misses,
+      self.hit_rate() * 100.,
+      self.entries,
+      self.total_compute_time()
+    )?;
+    writeln!(f, "{:>12}  key", "time")?;
+    for (key, time) in self.slowest().into_iter().take(NUM_SLOWEST) {
+      writeln!(f, "{:>12}  {key:?}", format!("{time:.2?}"))?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,203 @@
+//! A cache that can be shared between threads.
+
+use std::{
+  borrow::Borrow,
+  hash::{Hash, Hasher},
+  pin::Pin,
+  sync::RwLock,
+  thread::{self, ThreadId},
+};
+
+use rustc_data_structures::fx::{FxHashMap as HashMap, FxHasher};
+
+use super::CycleError;
+
+enum Slot<Out> {
+  /// The threads currently computing the value.
+  InProgress(Vec<ThreadId>),
+  Done(Pin<Box<Out>>),
+}
+
+const NUM_SHARDS: usize = 32;
+
+/// Cache for non-copyable types that can be shared between threads, e.g. by analyses
+/// running on rustc's thread pool.
+///
+/// The cache is split into shards that are each guarded by their own [`RwLock`], so
+/// threads accessing different keys rarely contend. Locks are not held while a value
+/// is computed, so if several threads request the same missing key at once, each
+/// computes the value and the first to finish wins.
+pub struct SyncCache<In, Out> {
+  shards: [RwLock<HashMap<In, Slot<Out>>>; NUM_SHARDS],
+}
+
+impl<In, Out> SyncCache<In, Out>
+where
+  In: Hash + Eq + Clone,
+{
+  fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &RwLock<HashMap<In, Slot<Out>>> {
+    let mut hasher = FxHasher::default();
+    key.hash(&mut hasher);
+    &self.shards[hasher.finish() as usize % NUM_SHARDS]
+  }
+
+  /// Size of the cache
+  pub fn len(&self) -> usize {
+    self
+      .shards
+      .iter()
+      .map(|shard| shard.read().unwrap().len())
+      .sum()
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// # Panics
+  ///
+  /// If this is a recursive invocation for this key.
+  pub fn get<Q>(&self, key: &Q, compute: impl FnOnce(&Q) -> Out) -> &Out
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self
+      .get_checked(key, compute)
+      .unwrap_or_else(|e| panic!("{e}"))
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// Returns `None` if this is a recursive invocation of `get` for key `key` on the
+  /// same thread.
+  pub fn get_maybe_recursive<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Option<&Out>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    self.get_checked(key, compute).ok()
+  }
+
+  /// Returns the cached value for the given key, or runs `compute` if
+  /// the value is not in cache.
+  ///
+  /// Returns a [`CycleError`] if this is a recursive invocation of `get` for key
+  /// `key` on the same thread.
+  pub fn get_checked<Q>(
+    &self,
+    key: &Q,
+    compute: impl FnOnce(&Q) -> Out,
+  ) -> Result<&Out, CycleError<In>>
+  where
+    In: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = In> + ?Sized,
+  {
+    let shard = self.shard(key);
+    if let Some(Slot::Done(out)) = shard.read().unwrap().get(key) {
+      return Ok(self.extend(out));
+    }
+
+    let this_thread = thread::current().id();
+    {
+      let mut shard = shard.write().unwrap();
+      match shard.get_mut(key) {
+        Some(Slot::Done(out)) => return Ok(self.extend(out)),
+        Some(Slot::InProgress(threads)) if threads.contains(&this_thread) => {
+          return Err(CycleError {
+            key: key.to_owned(),
+          });
+        }
+        Some(Slot::InProgress(threads)) => threads.push(this_thread),
+        None => {
+          shard.insert(key.to_owned(), Slot::InProgress(vec![this_thread]));
+        }
+      }
+    }
+
+    let out = Box::pin(compute(key));
+
+    let mut shard = shard.write().unwrap();
+    let slot = shard.get_mut(key).expect("invariant broken");
+    if let Slot::InProgress(_) = slot {
+      *slot = Slot::Done(out);
+    }
+    let Slot::Done(out) = slot else {
+      unreachable!()
+    };
+    Ok(self.extend(out))
+  }
+
+  fn extend(&self, out: &Pin<Box<Out>>) -> &Out {
+    // SAFETY: because the entry is pinned, it cannot move and this pointer will
+    // only be invalidated if the cache is dropped. Entries are never replaced once
+    // done. The returned reference has a lifetime equal to the cache, so the cache
+    // cannot be dropped before this reference goes out of scope.
+    unsafe { &*(&**out as *const Out) }
+  }
+}
+
+impl<In, Out> Default for SyncCache<In, Out> {
+  fn default() -> Self {
+    SyncCache {
+      shards: std::array::from_fn(|_| RwLock::new(HashMap::default())),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use super::*;
+
+  #[test]
+  fn test_sync_cache() {
+    let cache: SyncCache<usize, String> = SyncCache::default();
+    let computed = AtomicUsize::new(0);
+    let results = thread::scope(|s| {
+      let handles = (0 .. 4)
+        .map(|_| {
+          s.spawn(|| {
+            (0 .. 100)
+              .map(|i| {
+                cache.get(&i, |i| {
+                  computed.fetch_add(1, Ordering::SeqCst);
+                  i.to_string()
+                })
+              })
+              .collect::<Vec<_>>()
+          })
+        })
+        .collect::<Vec<_>>();
+      handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect::<Vec<_>>()
+    });
+
+    assert_eq!(cache.len(), 100);
+    assert!(computed.load(Ordering::SeqCst) >= 100);
+    // Every thread sees the same stored value.
+    assert!(results
+      .windows(2)
+      .all(|w| w[0].iter().zip(&w[1]).all(|(a, b)| std::ptr::eq(*a, *b))));
+    assert_eq!(cache.get(&42, |_| unreachable!()), "42");
+  }
+
+  #[test]
+  fn test_sync_recursion_breaking() {
+    let cache: SyncCache<i32, i32> = SyncCache::default();
+    fn get(cache: &SyncCache<i32, i32>, i: i32) -> i32 {
+      cache
+        .get_maybe_recursive(&i, |_| i + get(cache, i))
+        .copied()
+        .unwrap_or(-18)
+    }
+    assert_eq!(get(&cache, 60), 42);
+  }
+}
@@ -1,3 +1,4 @@
 //! Utilities for HIR-level data structures.
 
 pub mod ty;
+pub mod unsafety;
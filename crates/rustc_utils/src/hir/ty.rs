@@ -28,8 +28,10 @@ pub trait TyExt<'tcx> {
 }
 
 impl<'tcx> TyExt<'tcx> for Ty<'tcx> {
-  type AllRegionsIter<'a> = impl Iterator<Item = Region<'tcx>> + Captures<'tcx> + 'a
-    where Self: 'a;
+  type AllRegionsIter<'a>
+    = impl Iterator<Item = Region<'tcx>> + Captures<'tcx> + 'a
+  where
+    Self: 'a;
 
   fn inner_regions(&self) -> Self::AllRegionsIter<'_> {
     self.walk().filter_map(|part| match part.unpack() {
@@ -0,0 +1,232 @@
+//! An inventory of the uses of `unsafe` in a crate, e.g. for auditing plugins.
+//!
+//! [`UnsafetyInventory`] finds the unsafe blocks, unsafe functions and unsafe trait
+//! impls written in the crate, along with the operations on raw pointers and unions
+//! that need `unsafe`. Each is recorded with its span and the item or body that
+//! contains it.
+
+use rustc_hir::{
+  def_id::LocalDefId,
+  intravisit::{self, Visitor},
+  BlockCheckMode, Expr, ExprKind, ItemKind, Safety, UnOp, UnsafeSource,
+};
+use rustc_middle::ty::{TyCtxt, TypeckResults};
+use rustc_span::Span;
+
+/// The kind of an [`UnsafeUse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnsafeKind {
+  /// An `unsafe { .. }` block. Blocks generated by the compiler aren't included.
+  Block,
+  /// An `unsafe fn`, including those declared in traits and `extern` blocks.
+  Fn,
+  /// An `unsafe impl` of a trait.
+  TraitImpl,
+  /// An access of a union field, e.g. `u.f`. Writing to a field that doesn't need to
+  /// be dropped is safe, but is included too.
+  UnionField,
+  /// A dereference of a raw pointer, e.g. `*p` or `(*p).f`.
+  RawPtrDeref,
+}
+
+/// A use of `unsafe` found by [`UnsafetyInventory::build`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsafeUse {
+  pub kind: UnsafeKind,
+  /// The span of the block, operation, or signature of the function or impl.
+  pub span: Span,
+  /// The function or impl that is unsafe, or the body that contains the block or
+  /// operation. Closures have their own bodies.
+  pub def_id: LocalDefId,
+}
+
+/// The uses of `unsafe` in the local crate, see the [module documentation](self).
+pub struct UnsafetyInventory {
+  uses: Vec<UnsafeUse>,
+}
+
+impl UnsafetyInventory {
+  /// Finds the uses of `unsafe` in every item and body of the local crate.
+  pub fn build(tcx: TyCtxt<'_>) -> Self {
+    let mut uses = Vec::new();
+
+    let hir = tcx.hir();
+    for def_id in tcx.hir_crate_items(()).definitions() {
+      let is_unsafe_fn = tcx
+        .hir_node_by_def_id(def_id)
+        .fn_sig()
+        .is_some_and(|sig| sig.header.safety == Safety::Unsafe);
+      if is_unsafe_fn {
+        uses.push(UnsafeUse {
+          kind: UnsafeKind::Fn,
+          span: tcx.def_span(def_id),
+          def_id,
+        });
+      }
+    }
+
+    for item_id in hir.items() {
+      let item = hir.item(item_id);
+      if let ItemKind::Impl(impl_) = item.kind
+        && impl_.of_trait.is_some()
+        && impl_.safety == Safety::Unsafe
+      {
+        uses.push(UnsafeUse {
+          kind: UnsafeKind::TraitImpl,
+          span: tcx.def_span(item.owner_id.def_id),
+          def_id: item.owner_id.def_id,
+        });
+      }
+    }
+
+    for def_id in hir.body_owners() {
+      let mut finder = UnsafeFinder {
+        typeck_results: tcx.typeck(def_id),
+        def_id,
+        uses: &mut uses,
+      };
+      finder.visit_expr(hir.body_owned_by(def_id).value);
+    }
+
+    uses.sort_by_key(|unsafe_use| (unsafe_use.span.lo(), unsafe_use.span.hi()));
+    UnsafetyInventory { uses }
+  }
+
+  /// Returns every use of `unsafe`, in order of position in the source.
+  pub fn uses(&self) -> &[UnsafeUse] {
+    &self.uses
+  }
+
+  /// Returns the uses of `unsafe` of the given kind.
+  pub fn of_kind(&self, kind: UnsafeKind) -> impl Iterator<Item = &UnsafeUse> + '_ {
+    self
+      .uses
+      .iter()
+      .filter(move |unsafe_use| unsafe_use.kind == kind)
+  }
+
+  /// Returns the uses of `unsafe` that belong to `def_id`, see [`UnsafeUse::def_id`].
+  pub fn in_def(&self, def_id: LocalDefId) -> impl Iterator<Item = &UnsafeUse> + '_ {
+    self
+      .uses
+      .iter()
+      .filter(move |unsafe_use| unsafe_use.def_id == def_id)
+  }
+}
+
+struct UnsafeFinder<'a, 'tcx> {
+  typeck_results: &'tcx TypeckResults<'tcx>,
+  def_id: LocalDefId,
+  uses: &'a mut Vec<UnsafeUse>,
+}
+
+impl UnsafeFinder<'_, '_> {
+  fn add(&mut self, kind: UnsafeKind, span: Span) {
+    self.uses.push(UnsafeUse {
+      kind,
+      span,
+      def_id: self.def_id,
+    });
+  }
+}
+
+impl<'tcx> Visitor<'tcx> for UnsafeFinder<'_, 'tcx> {
+  // Nested bodies aren't visited, since they're visited as body owners.
+  fn visit_expr(&mut self, expr: &'tcx Expr<'tcx>) {
+    match expr.kind {
+      ExprKind::Block(block, _)
+        if block.rules == BlockCheckMode::UnsafeBlock(UnsafeSource::UserProvided) =>
+      {
+        self.add(UnsafeKind::Block, block.span);
+      }
+      ExprKind::Unary(UnOp::Deref, inner)
+        if self.typeck_results.expr_ty_adjusted(inner).is_unsafe_ptr() =>
+      {
+        self.add(UnsafeKind::RawPtrDeref, expr.span);
+      }
+      ExprKind::Field(base, _)
+        if self.typeck_results.expr_ty_adjusted(base).is_union() =>
+      {
+        self.add(UnsafeKind::UnionField, expr.span);
+      }
+      _ => {}
+    }
+    intravisit::walk_expr(self, expr);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test_utils::CompileBuilder;
+
+  #[test]
+  fn test_unsafety_inventory() {
+    let input = r#"
+union IntOrFloat { i: u32, f: f32 }
+unsafe trait Zeroable {}
+unsafe impl Zeroable for IntOrFloat {}
+impl Clone for IntOrFloat { fn clone(&self) -> Self { *self } }
+impl Copy for IntOrFloat {}
+
+extern "C" { fn abs(x: i32) -> i32; }
+
+unsafe fn read(p: *const u32) -> u32 { *p }
+
+fn safe(x: &mut u32) -> u32 {
+  let p = x as *mut u32;
+  let mut u = IntOrFloat { i: 1 };
+  u.i = 2;
+  let f = || unsafe { *p };
+  unsafe { read(p) + u.i + f() }
+}
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let tcx = result.tcx;
+      let inventory = UnsafetyInventory::build(tcx);
+      let snippet = |span: Span| tcx.sess.source_map().span_to_snippet(span).unwrap();
+      let uses = inventory
+        .uses()
+        .iter()
+        .map(|unsafe_use| {
+          (
+            unsafe_use.kind,
+            snippet(unsafe_use.span),
+            tcx.def_path_str(unsafe_use.def_id),
+          )
+        })
+        .collect::<Vec<_>>();
+      let expected = [
+        (
+          UnsafeKind::TraitImpl,
+          "unsafe impl Zeroable for IntOrFloat",
+          "<IntOrFloat as Zeroable>",
+        ),
+        (UnsafeKind::Fn, "fn abs(x: i32) -> i32;", "abs"),
+        (
+          UnsafeKind::Fn,
+          "unsafe fn read(p: *const u32) -> u32",
+          "read",
+        ),
+        (UnsafeKind::RawPtrDeref, "*p", "read"),
+        (UnsafeKind::UnionField, "u.i", "safe"),
+        (UnsafeKind::Block, "unsafe { *p }", "safe::{closure#0}"),
+        (UnsafeKind::RawPtrDeref, "*p", "safe::{closure#0}"),
+        (UnsafeKind::Block, "unsafe { read(p) + u.i + f() }", "safe"),
+        (UnsafeKind::UnionField, "u.i", "safe"),
+      ];
+      let expected = expected
+        .into_iter()
+        .map(|(kind, snippet, path)| (kind, snippet.to_string(), path.to_string()))
+        .collect::<Vec<_>>();
+      assert_eq!(uses, expected);
+
+      assert_eq!(inventory.of_kind(UnsafeKind::UnionField).count(), 2);
+      let read = inventory
+        .of_kind(UnsafeKind::Fn)
+        .find(|unsafe_use| tcx.def_path_str(unsafe_use.def_id) == "read")
+        .unwrap();
+      assert_eq!(inventory.in_def(read.def_id).count(), 2);
+    });
+  }
+}
@@ -20,11 +20,17 @@
   exact_size_is_empty,   // for graphviz module
   impl_trait_in_assoc_type,
   doc_auto_cfg,          // for feature gates in documentation
+  never_type,            // for const_eval machines
+  yeet_expr,             // for throw_* macros in const_eval machines
 )]
 #![allow(clippy::len_zero, clippy::len_without_is_empty)]
 
 extern crate either;
+extern crate indexmap;
+extern crate polonius_engine;
+extern crate rustc_arena;
 extern crate rustc_borrowck;
+extern crate rustc_const_eval;
 extern crate rustc_data_structures;
 extern crate rustc_driver;
 extern crate rustc_errors;
@@ -33,6 +39,7 @@ extern crate rustc_hir;
 extern crate rustc_index;
 extern crate rustc_infer;
 extern crate rustc_interface;
+extern crate rustc_lexer;
 extern crate rustc_macros;
 extern crate rustc_middle;
 extern crate rustc_mir_dataflow;
@@ -56,7 +63,11 @@ pub mod timer;
 pub use crate::{
   hir::ty::TyExt,
   mir::{
-    adt_def::AdtDefExt, body::BodyExt, mutability::MutabilityExt, operand::OperandExt,
+    adt_def::AdtDefExt,
+    body::BodyExt,
+    borrowck_facts::{BodyFactsExt, LocationTableExt},
+    mutability::MutabilityExt,
+    operand::OperandExt,
     place::PlaceExt,
   },
   source_map::span::{SpanDataExt, SpanExt},
@@ -0,0 +1,243 @@
+//! A conservative, intraprocedural points-to analysis for MIR bodies.
+//!
+//! The analysis is field-sensitive and flow-insensitive, in the style of Andersen's
+//! analysis, and uses the borrow checker's facts as its constraints. Each region
+//! contains the places that the references with that region may point to:
+//!
+//! 1. A region contains the places borrowed by the loans of the region, e.g. `x` for
+//!    `&'1 x`. The region of a reference in an argument contains the dereference of the
+//!    reference, e.g. `*p` for `p: &'a i32`, since it points to an unknown place.
+//! 2. If `'a: 'b`, then `'b` contains every place in `'a`, since a reference of region
+//!    `'a` may flow into one of region `'b`.
+//!
+//! The aliases of a place that dereferences a reference are the places of the
+//! reference's region. Arguments are assumed not to alias each other unless their
+//! regions are related, and dereferences of raw pointers alias only themselves.
+
+use rustc_borrowck::consumers::{
+  places_conflict, BodyWithBorrowckFacts, PlaceConflictBias,
+};
+use rustc_data_structures::fx::{FxHashMap as HashMap, FxHashSet as HashSet};
+use rustc_hir::def_id::DefId;
+use rustc_middle::{
+  mir::{Body, Place},
+  ty::{RegionKind, RegionVid, TyCtxt, TyKind},
+};
+
+use super::borrowck_facts::BodyFactsExt;
+use crate::{
+  cache::Cache,
+  mir::place::{PlaceExt, UNKNOWN_REGION},
+};
+
+/// A set of places, e.g. the aliases of a place.
+pub type PlaceSet<'tcx> = HashSet<Place<'tcx>>;
+
+/// The points-to information of a body, see the [module documentation](self).
+pub struct Aliases<'a, 'tcx> {
+  tcx: TyCtxt<'tcx>,
+  body: &'a Body<'tcx>,
+  contents: HashMap<RegionVid, PlaceSet<'tcx>>,
+  empty: PlaceSet<'tcx>,
+  aliases: Cache<Place<'tcx>, PlaceSet<'tcx>>,
+}
+
+impl<'a, 'tcx> Aliases<'a, 'tcx> {
+  /// Computes the points-to information of the body of `def_id`.
+  pub fn build(
+    tcx: TyCtxt<'tcx>,
+    def_id: DefId,
+    body_with_facts: &'a BodyWithBorrowckFacts<'tcx>,
+  ) -> Self {
+    let body = &body_with_facts.body;
+    let mut initial: HashMap<RegionVid, PlaceSet<'tcx>> = HashMap::default();
+    for loan in body_with_facts.loans() {
+      initial
+        .entry(loan.region)
+        .or_default()
+        .insert(loan.borrowed_place);
+    }
+    for arg in body.args_iter() {
+      let pointers = Place::from_local(arg, tcx).interior_pointers(tcx, body, def_id);
+      for (region, places) in pointers {
+        if region == UNKNOWN_REGION {
+          continue;
+        }
+        let targets = places.into_iter().map(|(ptr, _)| tcx.mk_place_deref(ptr));
+        initial.entry(region).or_default().extend(targets);
+      }
+    }
+
+    // Constraints with 'static would make every reference point to every place that
+    // flows into a static reference, so they are ignored.
+    let static_region = RegionVid::from_usize(0);
+    let graph = body_with_facts.outlives_graph();
+    let mut contents: HashMap<RegionVid, PlaceSet<'tcx>> = HashMap::default();
+    for (region, places) in initial {
+      let mut visited = HashSet::default();
+      let mut stack = vec![region];
+      while let Some(region) = stack.pop() {
+        if visited.insert(region) {
+          contents
+            .entry(region)
+            .or_default()
+            .extend(places.iter().copied());
+          stack.extend(graph.successors(region).filter(|sub| *sub != static_region));
+        }
+      }
+    }
+
+    Aliases {
+      tcx,
+      body,
+      contents,
+      empty: PlaceSet::default(),
+      aliases: Cache::default(),
+    }
+  }
+
+  /// Returns the places that references of `region` may point to.
+  pub fn region_contents(&self, region: RegionVid) -> &PlaceSet<'tcx> {
+    self.contents.get(&region).unwrap_or(&self.empty)
+  }
+
+  /// Returns the places that `place` may refer to and that don't dereference a
+  /// reference, e.g. `{x, y}` for `*r` if `r` may point to either. A place without such
+  /// a dereference is its only alias.
+  pub fn aliases_of(&self, place: Place<'tcx>) -> &PlaceSet<'tcx> {
+    self.aliases.get(&place, |place| {
+      let mut aliases = PlaceSet::default();
+      self.resolve(*place, &mut HashSet::default(), &mut aliases);
+      aliases
+    })
+  }
+
+  /// Returns true if `a` and `b` may refer to overlapping memory.
+  pub fn may_alias(&self, a: Place<'tcx>, b: Place<'tcx>) -> bool {
+    let b_aliases = self.aliases_of(b);
+    self.aliases_of(a).iter().any(|a| {
+      b_aliases
+        .iter()
+        .any(|b| places_conflict(self.tcx, self.body, *a, *b, PlaceConflictBias::Overlap))
+    })
+  }
+
+  fn resolve(
+    &self,
+    place: Place<'tcx>,
+    visited: &mut PlaceSet<'tcx>,
+    aliases: &mut PlaceSet<'tcx>,
+  ) {
+    if !visited.insert(place) {
+      return;
+    }
+
+    // The region of the last dereferenced reference determines the targets, even if
+    // the reference is itself behind another reference.
+    let Some((ptr, after)) = place.refs_in_projection(self.body, self.tcx).last() else {
+      aliases.insert(place);
+      return;
+    };
+    let ptr_ty = ptr.ty(self.body, self.tcx).ty;
+    let region = match ptr_ty.kind() {
+      TyKind::Ref(region, ..) => match region.kind() {
+        RegionKind::ReVar(region) => Some(region),
+        _ => None,
+      },
+      _ => None,
+    };
+    let targets = region.map(|region| self.region_contents(region));
+    match targets {
+      Some(targets) if !targets.is_empty() => {
+        for target in targets {
+          let alias = target.project_deeper(after, self.tcx);
+          // The reference of an argument points to an unknown place, i.e. itself.
+          if alias == place {
+            aliases.insert(alias);
+          } else {
+            self.resolve(alias, visited, aliases);
+          }
+        }
+      }
+      _ => {
+        aliases.insert(place);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test_utils::{self, compare_sets, Placer};
+
+  #[test]
+  fn test_aliases() {
+    let input = r#"
+fn main() {
+  let mut x = 1;
+  let y = 2;
+  let a = &mut x;
+  let b = &*a;
+  let c = if true { &y } else { b };
+  let d = &y;
+  let t = (0, 1);
+  let e = &t.1;
+  let f = &e;
+}
+"#;
+    test_utils::compile_body(input, |tcx, body_id, body_with_facts| {
+      let body = &body_with_facts.body;
+      let def_id = tcx.hir().body_owner_def_id(body_id).to_def_id();
+      let aliases = Aliases::build(tcx, def_id, body_with_facts);
+      let p = Placer::new(tcx, body);
+      let deref = |name| p.local(name).deref().mk();
+
+      let x = p.local("x").mk();
+      let y = p.local("y").mk();
+      compare_sets(aliases.aliases_of(deref("a")).clone(), [x]);
+      compare_sets(aliases.aliases_of(deref("b")).clone(), [x]);
+      compare_sets(aliases.aliases_of(deref("c")).clone(), [x, y]);
+      compare_sets(aliases.aliases_of(deref("d")).clone(), [y]);
+      compare_sets(aliases.aliases_of(x).clone(), [x]);
+
+      let t_1 = p.local("t").field(1).mk();
+      compare_sets(aliases.aliases_of(deref("e")).clone(), [t_1]);
+      compare_sets(
+        aliases
+          .aliases_of(p.local("f").deref().deref().mk())
+          .clone(),
+        [t_1],
+      );
+
+      assert!(aliases.may_alias(deref("c"), deref("a")));
+      assert!(aliases.may_alias(deref("c"), deref("d")));
+      assert!(!aliases.may_alias(deref("a"), deref("d")));
+      assert!(aliases.may_alias(deref("e"), p.local("t").mk()));
+      assert!(!aliases.may_alias(deref("e"), p.local("t").field(0).mk()));
+    });
+  }
+
+  #[test]
+  fn test_aliases_args() {
+    let input = r#"
+fn foo<'a, 'b>(p: &'a i32, q: &'b i32, r: &'a i32) {
+  let s = if true { p } else { r };
+  let t = q;
+}
+"#;
+    test_utils::compile_body(input, |tcx, body_id, body_with_facts| {
+      let body = &body_with_facts.body;
+      let def_id = tcx.hir().body_owner_def_id(body_id).to_def_id();
+      let aliases = Aliases::build(tcx, def_id, body_with_facts);
+      let p = Placer::new(tcx, body);
+      let deref = |name| p.local(name).deref().mk();
+
+      compare_sets(aliases.aliases_of(deref("t")).clone(), [deref("q")]);
+      assert!(aliases.may_alias(deref("s"), deref("p")));
+      assert!(aliases.may_alias(deref("s"), deref("r")));
+      assert!(!aliases.may_alias(deref("s"), deref("q")));
+      assert!(!aliases.may_alias(deref("p"), deref("q")));
+    });
+  }
+}
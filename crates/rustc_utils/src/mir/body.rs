@@ -8,7 +8,9 @@ use std::{
 
 use anyhow::{ensure, Result};
 use rustc_data_structures::{captures::Captures, fx::FxHashMap as HashMap};
-use rustc_hir::{def_id::DefId, CoroutineDesugaring, CoroutineKind, HirId};
+use rustc_hir::{
+  def_id::DefId, CoroutineDesugaring, CoroutineKind, ExprKind, HirId, Node,
+};
 use rustc_middle::{
   mir::{
     pretty::{write_mir_fn, PrettyPrintMirOptions},
@@ -16,10 +18,15 @@ use rustc_middle::{
   },
   ty::{Region, Ty, TyCtxt},
 };
+use rustc_span::{Span, Symbol};
 use smallvec::SmallVec;
 
-use super::control_dependencies::ControlDependencies;
-use crate::{PlaceExt, TyExt};
+use super::{
+  control_dependencies::{BodyCfg, CfgOptions, ControlDependencies, PostDominators},
+  dot::{DotBody, DotOptions},
+  loops::Loops,
+};
+use crate::{mir::place::InteriorPlaces, PlaceExt, TyExt};
 
 /// Extension trait for [`Body`].
 pub trait BodyExt<'tcx> {
@@ -45,9 +52,29 @@ pub trait BodyExt<'tcx> {
   /// Returns a mapping from source-level variable names to [`Local`]s.
   fn debug_info_name_map(&self) -> HashMap<String, Local>;
 
+  /// Returns the locals that hold a variable of the source code, in order, excluding
+  /// compiler temporaries, variables introduced by desugaring or macros, and the
+  /// references to match bindings used by guards.
+  fn user_locals(&self) -> Vec<UserLocal>;
+
   /// Converts a Body to a debug representation.
   fn to_string(&self, tcx: TyCtxt<'tcx>) -> Result<String>;
 
+  /// Converts a Body to a debug representation where each statement and terminator
+  /// is annotated with its source code and the kind of its HIR node, e.g.
+  /// `_2 = Add(copy _1, const 1_i32)  // x + 1 [Expr(Binary)]`.
+  ///
+  /// Unlike [`BodyExt::to_string`], the output doesn't contain spans or other
+  /// positions, so it is stable under edits to unrelated code. The HIR nodes are only
+  /// precise if the crate is compiled with `-Zmaximal-hir-to-mir-coverage`, and are
+  /// otherwise the nodes of the enclosing items, see [`BodyExt::location_to_hir_id`].
+  fn to_annotated_string(&self, tcx: TyCtxt<'tcx>) -> String;
+
+  /// Renders the control-flow graph to Graphviz DOT, with the statements of each block
+  /// and the annotations selected by `options`. The output can be converted to a PDF
+  /// with [`run_dot`].
+  fn to_dot(&self, options: &DotOptions<'_>) -> String;
+
   /// Returns the [`HirId`] corresponding to a MIR [`Location`].
   ///
   /// You **MUST** use the `-Zmaximize-hir-to-mir-mapping` flag for this
@@ -62,6 +89,21 @@ pub trait BodyExt<'tcx> {
   /// for details.
   fn control_dependencies(&self) -> ControlDependencies<BasicBlock>;
 
+  /// Returns all the control dependencies within the CFG selected by `options`, e.g.
+  /// including unwind edges.
+  fn control_dependencies_with(
+    &self,
+    options: CfgOptions,
+  ) -> ControlDependencies<BasicBlock>;
+
+  /// Returns the post-dominator tree of the CFG selected by `options`, whose roots are
+  /// the exits of the body.
+  fn post_dominators(&self, options: CfgOptions) -> PostDominators<BasicBlock>;
+
+  /// Returns the natural loops of the CFG, see the [`loops`][super::loops] module
+  /// documentation for details.
+  fn loops(&self) -> Loops;
+
   /// If this body is an async function, then return the type of the context that holds
   /// locals across await calls.
   fn async_context(&self, tcx: TyCtxt<'tcx>, def_id: DefId) -> Option<Ty<'tcx>>;
@@ -140,6 +182,44 @@ impl<'tcx> BodyExt<'tcx> for Body<'tcx> {
       .collect()
   }
 
+  fn user_locals(&self) -> Vec<UserLocal> {
+    let mut locals: Vec<UserLocal> = Vec::new();
+    for local in self.local_decls.indices() {
+      let Some(info) = user_debug_info(self, local) else {
+        continue;
+      };
+      locals.push(UserLocal {
+        local,
+        name: info.name,
+        span: self.local_decls[local].source_info.span,
+        scope: info.source_info.scope,
+        shadows: None,
+      });
+    }
+
+    // A variable shadows the last variable with the same name declared before it
+    // whose scope contains its own. Variables of different match arms are in sibling
+    // scopes, so they don't shadow each other.
+    for index in 0 .. locals.len() {
+      let user_local = locals[index];
+      let scopes = std::iter::successors(Some(user_local.scope), |scope| {
+        self.source_scopes[*scope].parent_scope
+      })
+      .collect::<Vec<_>>();
+      locals[index].shadows = locals
+        .iter()
+        .filter(|other| {
+          other.name == user_local.name
+            && other.span.lo() < user_local.span.lo()
+            && scopes.contains(&other.scope)
+        })
+        .max_by_key(|other| other.span.lo())
+        .map(|other| other.local);
+    }
+
+    locals
+  }
+
   fn to_string(&self, tcx: TyCtxt<'tcx>) -> Result<String> {
     let mut buffer = Vec::new();
 
@@ -153,6 +233,68 @@ impl<'tcx> BodyExt<'tcx> for Body<'tcx> {
     Ok(String::from_utf8(buffer)?)
   }
 
+  fn to_annotated_string(&self, tcx: TyCtxt<'tcx>) -> String {
+    let source_map = tcx.sess.source_map();
+    let annotation = |source_info: &SourceInfo| {
+      let span = source_info.span.source_callsite();
+      let node = tcx.hir_node(self.source_info_to_hir_id(source_info));
+      let kind = hir_node_kind(node);
+      let snippet = match source_map.span_to_snippet(span) {
+        Ok(snippet) if !span.is_dummy() => {
+          snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+        }
+        _ => String::new(),
+      };
+      if snippet.is_empty() {
+        format!("[{kind}]")
+      } else if snippet.chars().count() > 40 {
+        let prefix = snippet.chars().take(40).collect::<String>();
+        format!("{prefix}... [{kind}]")
+      } else {
+        format!("{snippet} [{kind}]")
+      }
+    };
+    let line = |text: String, source_info: &SourceInfo| {
+      format!("    {text:<40} // {}\n", annotation(source_info))
+    };
+
+    let mut output = format!("fn {} {{\n", tcx.def_path_str(self.source.def_id()));
+    for (local, decl) in self.local_decls.iter_enumerated() {
+      output.push_str(&line(
+        format!("let {local:?}: {};", decl.ty),
+        &decl.source_info,
+      ));
+    }
+    for (block, data) in self.basic_blocks.iter_enumerated() {
+      let cleanup = if data.is_cleanup { " (cleanup)" } else { "" };
+      output.push_str(&format!("\n  {block:?}{cleanup}: {{\n"));
+      for statement in &data.statements {
+        output.push_str(&line(format!("{statement:?};"), &statement.source_info));
+      }
+      let terminator = data.terminator();
+      output.push_str(&line(
+        format!("{:?};", terminator.kind),
+        &terminator.source_info,
+      ));
+      output.push_str("  }\n");
+    }
+    output.push_str("}\n");
+    output
+  }
+
+  fn to_dot(&self, options: &DotOptions<'_>) -> String {
+    let mut buffer = Vec::new();
+    rustc_graphviz::render(
+      &DotBody {
+        body: self,
+        options,
+      },
+      &mut buffer,
+    )
+    .unwrap();
+    String::from_utf8(buffer).unwrap()
+  }
+
   fn location_to_hir_id(&self, location: Location) -> HirId {
     let source_info = self.source_info(location);
     self.source_info_to_hir_id(source_info)
@@ -165,10 +307,24 @@ impl<'tcx> BodyExt<'tcx> for Body<'tcx> {
   }
 
   fn control_dependencies(&self) -> ControlDependencies<BasicBlock> {
-    ControlDependencies::build_many(
-      &self.basic_blocks,
-      self.all_returns().map(|loc| loc.block),
-    )
+    self.control_dependencies_with(CfgOptions::default())
+  }
+
+  fn control_dependencies_with(
+    &self,
+    options: CfgOptions,
+  ) -> ControlDependencies<BasicBlock> {
+    let cfg = BodyCfg::new(self, options);
+    ControlDependencies::build_many(&cfg, cfg.exits())
+  }
+
+  fn post_dominators(&self, options: CfgOptions) -> PostDominators<BasicBlock> {
+    let cfg = BodyCfg::new(self, options);
+    PostDominators::build_many(&cfg, cfg.exits())
+  }
+
+  fn loops(&self) -> Loops {
+    Loops::build(self)
   }
 
   fn async_context(&self, tcx: TyCtxt<'tcx>, def_id: DefId) -> Option<Ty<'tcx>> {
@@ -209,12 +365,115 @@ impl<'tcx> BodyExt<'tcx> for Body<'tcx> {
   }
 
   fn all_places(&self, tcx: TyCtxt<'tcx>, def_id: DefId) -> Self::PlacesIter<'_> {
-    self.local_decls.indices().flat_map(move |local| {
-      Place::from_local(local, tcx).interior_paths(tcx, self, def_id)
-    })
+    let interior = InteriorPlaces::new(tcx, def_id, None);
+    self
+      .local_decls
+      .indices()
+      .flat_map(move |local| interior.paths(Place::from_local(local, tcx), self))
   }
 }
 
+/// Returns the name of the kind of `node`, e.g. `Expr(Call)` or `LetStmt`.
+fn hir_node_kind(node: Node<'_>) -> String {
+  let kind = match node {
+    Node::Expr(expr) => {
+      let expr_kind = match expr.kind {
+        ExprKind::ConstBlock(..) => "ConstBlock",
+        ExprKind::Array(..) => "Array",
+        ExprKind::Call(..) => "Call",
+        ExprKind::MethodCall(..) => "MethodCall",
+        ExprKind::Tup(..) => "Tup",
+        ExprKind::Binary(..) => "Binary",
+        ExprKind::Unary(..) => "Unary",
+        ExprKind::Lit(..) => "Lit",
+        ExprKind::Cast(..) => "Cast",
+        ExprKind::Type(..) => "Type",
+        ExprKind::DropTemps(..) => "DropTemps",
+        ExprKind::Let(..) => "Let",
+        ExprKind::If(..) => "If",
+        ExprKind::Loop(..) => "Loop",
+        ExprKind::Match(..) => "Match",
+        ExprKind::Closure(..) => "Closure",
+        ExprKind::Block(..) => "Block",
+        ExprKind::Assign(..) => "Assign",
+        ExprKind::AssignOp(..) => "AssignOp",
+        ExprKind::Field(..) => "Field",
+        ExprKind::Index(..) => "Index",
+        ExprKind::Path(..) => "Path",
+        ExprKind::AddrOf(..) => "AddrOf",
+        ExprKind::Break(..) => "Break",
+        ExprKind::Continue(..) => "Continue",
+        ExprKind::Ret(..) => "Ret",
+        ExprKind::Become(..) => "Become",
+        ExprKind::InlineAsm(..) => "InlineAsm",
+        ExprKind::OffsetOf(..) => "OffsetOf",
+        ExprKind::Struct(..) => "Struct",
+        ExprKind::Repeat(..) => "Repeat",
+        ExprKind::Yield(..) => "Yield",
+        ExprKind::Err(..) => "Err",
+      };
+      return format!("Expr({expr_kind})");
+    }
+    Node::Param(..) => "Param",
+    Node::Item(..) => "Item",
+    Node::ForeignItem(..) => "ForeignItem",
+    Node::TraitItem(..) => "TraitItem",
+    Node::ImplItem(..) => "ImplItem",
+    Node::Variant(..) => "Variant",
+    Node::Field(..) => "Field",
+    Node::AnonConst(..) => "AnonConst",
+    Node::ConstBlock(..) => "ConstBlock",
+    Node::Stmt(..) => "Stmt",
+    Node::Ty(..) => "Ty",
+    Node::Pat(..) => "Pat",
+    Node::PatField(..) => "PatField",
+    Node::Arm(..) => "Arm",
+    Node::Block(..) => "Block",
+    Node::LetStmt(..) => "LetStmt",
+    Node::Ctor(..) => "Ctor",
+    Node::Synthetic => "Synthetic",
+    _ => "Other",
+  };
+  kind.to_string()
+}
+
+/// A local that holds a variable of the source code, see [`BodyExt::user_locals`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserLocal {
+  pub local: Local,
+  /// The name of the variable.
+  pub name: Symbol,
+  /// The span of the variable's binding.
+  pub span: Span,
+  /// The scope in which the variable is visible.
+  pub scope: SourceScope,
+  /// The variable with the same name that this variable shadows.
+  pub shadows: Option<Local>,
+}
+
+/// Returns the debug info of `local` if it is a variable of the source code.
+pub(crate) fn user_debug_info<'a, 'tcx>(
+  body: &'a Body<'tcx>,
+  local: Local,
+) -> Option<&'a VarDebugInfo<'tcx>> {
+  let decl = &body.local_decls[local];
+  if local == RETURN_PLACE
+    || !decl.is_user_variable()
+    || decl.is_ref_for_guard()
+    || decl.source_info.span.from_expansion()
+  {
+    return None;
+  }
+  body.var_debug_info.iter().find(|info| {
+    matches!(
+      info.value,
+      VarDebugInfoContents::Place(place)
+        if place.local == local && place.projection.is_empty()
+    )
+  })
+}
+
+/// Converts the DOT graph in `buf` to a PDF at `path` with the `dot` command.
 pub fn run_dot(path: &Path, buf: Vec<u8>) -> Result<()> {
   let mut p = Command::new("dot")
     .args(["-Tpdf", "-o", &path.display().to_string()])
@@ -231,8 +490,10 @@ pub fn run_dot(path: &Path, buf: Vec<u8>) -> Result<()> {
 
 #[cfg(test)]
 mod test {
+  use rustc_middle::mir::{Place, PlaceElem};
+
   use super::BodyExt;
-  use crate::test_utils;
+  use crate::{mir::dot::DotOptions, test_utils, PlaceExt};
 
   #[test]
   fn test_body_ext() {
@@ -251,4 +512,115 @@ mod test {
       assert_eq!(body.regions_in_return().count(), 1);
     });
   }
+
+  #[test]
+  fn test_user_locals() {
+    let input = r#"
+    fn main() {
+      let x = 1;
+      let x = x + 1;
+      match Some(x) {
+        Some(n) if n > 2 => { let m = n; }
+        Some(n) => {}
+        None => {}
+      }
+      for i in 0 .. x {}
+      println!("{x}");
+    }"#;
+
+    test_utils::compile_body(input, |tcx, _, body| {
+      let body = &body.body;
+      let user_locals = body.user_locals();
+      let names = user_locals
+        .iter()
+        .map(|user_local| user_local.name.as_str())
+        .collect::<Vec<_>>();
+      assert_eq!(names, ["x", "x", "n", "m", "n", "i"]);
+
+      let shadows = user_locals
+        .iter()
+        .map(|user_local| user_local.shadows)
+        .collect::<Vec<_>>();
+      assert_eq!(shadows, [
+        None,
+        Some(user_locals[0].local),
+        None,
+        None,
+        None,
+        None
+      ]);
+
+      for local in body.local_decls.indices() {
+        let is_user = user_locals
+          .iter()
+          .any(|user_local| user_local.local == local);
+        assert_eq!(Place::from_local(local, tcx).is_user_visible(body), is_user);
+      }
+
+      // The guard reads `n` through a reference.
+      let guard_ref = body
+        .local_decls
+        .indices()
+        .find(|local| body.local_decls[*local].is_ref_for_guard())
+        .unwrap();
+      let guard_n = Place::make(guard_ref, &[PlaceElem::Deref], tcx);
+      assert!(guard_n.is_user_visible(body));
+    });
+  }
+
+  #[test]
+  fn test_to_annotated_string() {
+    let input = r#"
+    fn main() {
+      let x = 1;
+      let y = x + 2;
+    }"#;
+
+    test_utils::compile_body(input, |tcx, _, body| {
+      let body = &body.body;
+      let text = body.to_annotated_string(tcx);
+      let lines = text.lines().collect::<Vec<_>>();
+      assert_eq!(lines[0], "fn main {");
+      let annotated = |code: &str| {
+        lines
+          .iter()
+          .find(|line| line.trim_start().starts_with(code))
+          .unwrap_or_else(|| panic!("{code}"))
+          .split(" // ")
+          .nth(1)
+          .unwrap()
+      };
+      assert_eq!(annotated("_1 = const 1_i32;"), "1 [Expr(Lit)]");
+      assert_eq!(annotated("let _2: i32;"), "y [LetStmt]");
+      assert_eq!(annotated("_4 = AddWithOverflow"), "x + 2 [Expr(Binary)]");
+      assert_eq!(annotated("return;"), "[Item]");
+      assert!(!text.contains("dummy.rs"));
+    });
+  }
+
+  #[test]
+  fn test_to_dot() {
+    let input = r#"
+    fn main() {
+      let x = 1;
+      let y = if x > 0 { &x } else { &0 };
+    }"#;
+
+    test_utils::compile_body(input, |_, _, body| {
+      let body = &body.body;
+      let options = DotOptions {
+        spans: true,
+        annotate: Some(Box::new(|location| Some(format!("state at {location:?}")))),
+        ..Default::default()
+      };
+      let dot = body.to_dot(&options);
+      assert!(dot.starts_with("digraph mir {"));
+      assert!(dot.contains("bb0 -> bb1"));
+      assert!(dot.contains("state at bb0[0]"));
+      assert!(dot.contains("dummy.rs:"));
+      // Statements are escaped for the HTML labels.
+      assert!(dot.contains("&amp;"));
+      assert!(!dot.contains("(cleanup)"));
+    });
+  }
 }
@@ -0,0 +1,458 @@
+//! On-disk cache of Polonius outputs, so repeated runs on an unchanged crate skip
+//! recomputing them.
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fs,
+  hash::Hash,
+  path::{Path, PathBuf},
+  sync::RwLock,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use polonius_engine::{Algorithm, Atom, FactTypes};
+use rustc_borrowck::consumers::{PoloniusInput, PoloniusOutput, RustcFacts};
+use rustc_data_structures::{
+  fingerprint::Fingerprint, fx::FxHashMap, stable_hasher::StableHasher,
+};
+
+static CACHE_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Enables caching the results of [`compute_output`](super::compute_output) in `dir`.
+///
+/// Outputs are keyed by a hash of the input facts that Polonius runs on, along with
+/// the algorithm, whether the body is location-insensitive and the compiler version,
+/// so they are reused across compiler runs as long as the facts are unchanged.
+///
+/// Only the outputs are cached. The input facts are computed by the same `mir_borrowck`
+/// call that produces the body, which has to run anyway, so restoring them would not
+/// save any work. For the same reason the key is not the HIR hash of the body: the
+/// facts also depend on items outside the body, such as the signatures it calls, and
+/// hashing the facts is cheap next to running Polonius.
+///
+/// A typical location is a subdirectory of `target/`, see [`default_disk_cache_dir`].
+pub fn enable_disk_cache(dir: impl Into<PathBuf>) {
+  *CACHE_DIR.write().unwrap() = Some(dir.into());
+}
+
+/// Disables the cache enabled by [`enable_disk_cache`]. Files already written are kept.
+pub fn disable_disk_cache() {
+  *CACHE_DIR.write().unwrap() = None;
+}
+
+/// Returns `$CARGO_TARGET_DIR/rustc_utils/polonius`, or `target/rustc_utils/polonius`
+/// if `CARGO_TARGET_DIR` is not set.
+pub fn default_disk_cache_dir() -> PathBuf {
+  let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+    .map_or_else(|| PathBuf::from("target"), PathBuf::from);
+  target_dir.join("rustc_utils").join("polonius")
+}
+
+fn cache_path(
+  input_facts: &PoloniusInput,
+  algorithm: Algorithm,
+  location_insensitive: bool,
+) -> Option<PathBuf> {
+  let dir = CACHE_DIR.read().unwrap().clone()?;
+  let key = cache_key(input_facts, algorithm, location_insensitive);
+  Some(dir.join(format!("{}.polonius", key.to_hex())))
+}
+
+fn cache_key(
+  input_facts: &PoloniusInput,
+  algorithm: Algorithm,
+  location_insensitive: bool,
+) -> Fingerprint {
+  let mut hasher = StableHasher::new();
+  hash_input(input_facts, &mut hasher);
+  format!("{algorithm:?}").hash(&mut hasher);
+  location_insensitive.hash(&mut hasher);
+  rustc_interface::util::rustc_version_str().hash(&mut hasher);
+  hasher.finish()
+}
+
+/// Hashes every fact table, since the output depends on all of them.
+fn hash_input(facts: &PoloniusInput, hasher: &mut StableHasher) {
+  let PoloniusInput {
+    loan_issued_at,
+    universal_region,
+    cfg_edge,
+    loan_killed_at,
+    subset_base,
+    loan_invalidated_at,
+    var_used_at,
+    var_defined_at,
+    var_dropped_at,
+    use_of_var_derefs_origin,
+    drop_of_var_derefs_origin,
+    child_path,
+    path_is_var,
+    path_assigned_at_base,
+    path_moved_at_base,
+    path_accessed_at_base,
+    known_placeholder_subset,
+    placeholder,
+  } = facts;
+  macro_rules! hash {
+    ($($field:ident),*) => {$(
+      $field.hash(hasher);
+    )*};
+  }
+  hash!(
+    loan_issued_at,
+    universal_region,
+    cfg_edge,
+    loan_killed_at,
+    subset_base,
+    loan_invalidated_at,
+    var_used_at,
+    var_defined_at,
+    var_dropped_at,
+    use_of_var_derefs_origin,
+    drop_of_var_derefs_origin,
+    child_path,
+    path_is_var,
+    path_assigned_at_base,
+    path_moved_at_base,
+    path_accessed_at_base,
+    known_placeholder_subset,
+    placeholder
+  );
+}
+
+/// Reads the output for `input_facts` from the cache, if the cache is enabled and has
+/// one.
+pub(super) fn load(
+  input_facts: &PoloniusInput,
+  algorithm: Algorithm,
+  location_insensitive: bool,
+) -> Option<PoloniusOutput> {
+  let path = cache_path(input_facts, algorithm, location_insensitive)?;
+  let bytes = fs::read(&path).ok()?;
+  match decode_output(&bytes) {
+    Ok(output) => Some(output),
+    Err(e) => {
+      log::warn!(
+        "Ignoring corrupt Polonius cache file {}: {e:?}",
+        path.display()
+      );
+      None
+    }
+  }
+}
+
+/// Writes the output for `input_facts` to the cache, if the cache is enabled.
+pub(super) fn save(
+  input_facts: &PoloniusInput,
+  algorithm: Algorithm,
+  location_insensitive: bool,
+  output: &PoloniusOutput,
+) {
+  let Some(path) = cache_path(input_facts, algorithm, location_insensitive) else {
+    return;
+  };
+  if let Err(e) = write_atomic(&path, &encode_output(output)) {
+    log::warn!(
+      "Failed to write Polonius cache file {}: {e:?}",
+      path.display()
+    );
+  }
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+  let dir = path.parent().unwrap();
+  fs::create_dir_all(dir)
+    .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+
+  // Concurrent compilers may write the same entry, so write to a unique temporary
+  // file and rename it into place.
+  let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+  fs::write(&tmp, bytes)?;
+  fs::rename(&tmp, path)?;
+  Ok(())
+}
+
+const MAGIC: &[u8; 8] = b"POLONIUS";
+const FORMAT_VERSION: u32 = 1;
+
+/// Values that can be written to the cache.
+trait Persist: Sized {
+  fn encode(&self, buf: &mut Vec<u8>);
+  fn decode(decoder: &mut Decoder<'_>) -> Result<Self>;
+}
+
+struct Decoder<'a> {
+  bytes: &'a [u8],
+}
+
+impl Decoder<'_> {
+  fn read_u32(&mut self) -> Result<u32> {
+    ensure!(self.bytes.len() >= 4, "unexpected end of file");
+    let (head, tail) = self.bytes.split_at(4);
+    self.bytes = tail;
+    Ok(u32::from_le_bytes(head.try_into().unwrap()))
+  }
+
+  fn read_len(&mut self) -> Result<usize> {
+    let len = self.read_u32()? as usize;
+    // Every element takes at least 4 bytes, so this bounds allocations on bad input.
+    ensure!(len <= self.bytes.len() / 4, "invalid length {len}");
+    Ok(len)
+  }
+
+  fn read_atom<T: Atom>(&mut self) -> Result<T> {
+    Ok(T::from(self.read_u32()? as usize))
+  }
+}
+
+fn write_u32(n: usize, buf: &mut Vec<u8>) {
+  buf.extend_from_slice(&u32::try_from(n).unwrap().to_le_bytes());
+}
+
+// Polonius relations only contain atoms at their leaves, so containers are only
+// implemented for atom elements. This avoids overlapping with upstream crates that
+// could implement `Atom` for containers.
+
+impl<T: Atom> Persist for Vec<T> {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    write_u32(self.len(), buf);
+    for x in self {
+      write_u32(x.index(), buf);
+    }
+  }
+
+  fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+    let len = decoder.read_len()?;
+    (0 .. len).map(|_| decoder.read_atom()).collect()
+  }
+}
+
+impl<T: Atom> Persist for BTreeSet<T> {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    write_u32(self.len(), buf);
+    for x in self {
+      write_u32(x.index(), buf);
+    }
+  }
+
+  fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+    let len = decoder.read_len()?;
+    (0 .. len).map(|_| decoder.read_atom()).collect()
+  }
+}
+
+impl<K: Atom, V: Persist> Persist for BTreeMap<K, V> {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    write_u32(self.len(), buf);
+    for (k, v) in self {
+      write_u32(k.index(), buf);
+      v.encode(buf);
+    }
+  }
+
+  fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+    let len = decoder.read_len()?;
+    (0 .. len)
+      .map(|_| Ok((decoder.read_atom()?, V::decode(decoder)?)))
+      .collect()
+  }
+}
+
+impl<K: Atom, V: Persist> Persist for FxHashMap<K, V> {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    write_u32(self.len(), buf);
+    for (k, v) in self {
+      write_u32(k.index(), buf);
+      v.encode(buf);
+    }
+  }
+
+  fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+    let len = decoder.read_len()?;
+    (0 .. len)
+      .map(|_| Ok((decoder.read_atom()?, V::decode(decoder)?)))
+      .collect()
+  }
+}
+
+impl Persist for bool {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    write_u32(*self as usize, buf);
+  }
+
+  fn decode(decoder: &mut Decoder<'_>) -> Result<Self> {
+    match decoder.read_u32()? {
+      0 => Ok(false),
+      1 => Ok(true),
+      n => bail!("invalid bool {n}"),
+    }
+  }
+}
+
+type Origin = <RustcFacts as FactTypes>::Origin;
+type SubsetErrors =
+  FxHashMap<<RustcFacts as FactTypes>::Point, BTreeSet<(Origin, Origin)>>;
+
+/// `subset_errors` is the only relation with tuples, so it is stored as a map from
+/// each origin to the origins it must not outlive.
+fn encode_subset_errors(subset_errors: &SubsetErrors, buf: &mut Vec<u8>) {
+  let grouped = subset_errors
+    .iter()
+    .map(|(point, pairs)| {
+      let mut by_origin = BTreeMap::<Origin, BTreeSet<Origin>>::new();
+      for (sup, sub) in pairs {
+        by_origin.entry(*sup).or_default().insert(*sub);
+      }
+      (*point, by_origin)
+    })
+    .collect::<FxHashMap<_, _>>();
+  grouped.encode(buf);
+}
+
+fn decode_subset_errors(decoder: &mut Decoder<'_>) -> Result<SubsetErrors> {
+  let grouped = FxHashMap::<_, BTreeMap<Origin, BTreeSet<Origin>>>::decode(decoder)?;
+  Ok(
+    grouped
+      .into_iter()
+      .map(|(point, by_origin)| {
+        let pairs = by_origin
+          .into_iter()
+          .flat_map(|(sup, subs)| subs.into_iter().map(move |sub| (sup, sub)))
+          .collect();
+        (point, pairs)
+      })
+      .collect(),
+  )
+}
+
+macro_rules! output_fields {
+  ($mac:ident) => {
+    $mac!(
+      errors,
+      move_errors,
+      dump_enabled,
+      loan_live_at,
+      origin_contains_loan_at,
+      origin_contains_loan_anywhere,
+      origin_live_on_entry,
+      loan_invalidated_at,
+      subset,
+      subset_anywhere,
+      var_live_on_entry,
+      var_drop_live_on_entry,
+      path_maybe_initialized_on_exit,
+      path_maybe_uninitialized_on_exit,
+      known_contains,
+      var_maybe_partly_initialized_on_exit
+    )
+  };
+}
+
+fn encode_output(output: &PoloniusOutput) -> Vec<u8> {
+  let mut buf = MAGIC.to_vec();
+  write_u32(FORMAT_VERSION as usize, &mut buf);
+  macro_rules! encode_fields {
+    ($($field:ident),*) => {
+      // Destructure so that new fields in Polonius are a compile error here.
+      let PoloniusOutput { subset_errors, $($field),* } = output;
+      encode_subset_errors(subset_errors, &mut buf);
+      $($field.encode(&mut buf);)*
+    };
+  }
+  output_fields!(encode_fields);
+  buf
+}
+
+fn decode_output(bytes: &[u8]) -> Result<PoloniusOutput> {
+  ensure!(bytes.starts_with(MAGIC), "missing header");
+  let mut decoder = Decoder {
+    bytes: &bytes[MAGIC.len() ..],
+  };
+  let version = decoder.read_u32()?;
+  ensure!(
+    version == FORMAT_VERSION,
+    "unsupported format version {version}"
+  );
+  macro_rules! decode_fields {
+    ($($field:ident),*) => {
+      PoloniusOutput {
+        subset_errors: decode_subset_errors(&mut decoder)?,
+        $($field: Persist::decode(&mut decoder)?),*
+      }
+    };
+  }
+  let output = output_fields!(decode_fields);
+  ensure!(decoder.bytes.is_empty(), "trailing data");
+  Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    mir::borrowck_facts::get_body_with_borrowck_facts,
+    test_utils::{self, CompileResult},
+  };
+
+  #[test]
+  fn test_output_roundtrip() {
+    let input = r#"
+fn main() {
+  let mut x = 1;
+  let y = &mut x;
+  *y += 1;
+  println!("{x}");
+}
+"#;
+    test_utils::CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+      let input_facts = body_with_facts.input_facts.as_ref().unwrap();
+      let output = PoloniusOutput::compute(input_facts, Algorithm::Naive, true);
+      assert!(!output.loan_live_at.is_empty());
+
+      let bytes = encode_output(&output);
+      let decoded = decode_output(&bytes).unwrap();
+      assert_eq!(output.errors, decoded.errors);
+      assert_eq!(output.loan_live_at, decoded.loan_live_at);
+      assert_eq!(
+        output.origin_contains_loan_at,
+        decoded.origin_contains_loan_at
+      );
+      assert_eq!(output.subset, decoded.subset);
+      assert_eq!(output.dump_enabled, decoded.dump_enabled);
+      assert_eq!(encode_output(&decoded).len(), bytes.len());
+
+      assert!(decode_output(&bytes[.. bytes.len() - 1]).is_err());
+    });
+  }
+
+  #[test]
+  fn test_cache_key() {
+    let input = r#"
+fn main() {
+  let mut x = 1;
+  let y = &mut x;
+  *y += 1;
+}
+"#;
+    test_utils::CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+      let input_facts = body_with_facts.input_facts.as_deref().unwrap();
+      let key = cache_key(input_facts, Algorithm::Naive, false);
+      assert_eq!(
+        key,
+        cache_key(&input_facts.clone(), Algorithm::Naive, false)
+      );
+      assert_ne!(key, cache_key(input_facts, Algorithm::Hybrid, false));
+      assert_ne!(key, cache_key(input_facts, Algorithm::Naive, true));
+
+      // Facts that change without the body changing, e.g. because of the signature of
+      // a callee, must change the key.
+      let mut changed = input_facts.clone();
+      changed.loan_killed_at.clear();
+      assert_ne!(key, cache_key(&changed, Algorithm::Naive, false));
+    });
+  }
+}
@@ -0,0 +1,149 @@
+//! Writing Polonius input facts in the `-Znll-facts` directory layout.
+
+use std::{
+  fmt::Debug,
+  fs::{self, File},
+  io::{BufWriter, Write},
+  path::Path,
+};
+
+use anyhow::{Context, Result};
+use rustc_borrowck::consumers::{BodyWithBorrowckFacts, LocationTable, PoloniusInput};
+use rustc_hir::def_id::LocalDefId;
+use rustc_middle::ty::TyCtxt;
+
+use super::{get_body_with_borrowck_facts, LocationTableExt, PointIndex};
+
+fn write_facts_file(
+  dir: &Path,
+  name: &str,
+  rows: impl Iterator<Item = Vec<String>>,
+) -> Result<()> {
+  let path = dir.join(format!("{name}.facts"));
+  let file = File::create(&path)
+    .with_context(|| format!("Could not create {}", path.display()))?;
+  let mut out = BufWriter::new(file);
+  for row in rows {
+    // Cells are quoted and escaped with `Debug`, matching rustc's own output.
+    let cells = row
+      .into_iter()
+      .map(|cell| format!("{cell:?}"))
+      .collect::<Vec<_>>();
+    writeln!(out, "{}", cells.join("\t"))?;
+  }
+  out.flush()?;
+  Ok(())
+}
+
+fn cell(value: impl Debug) -> String {
+  format!("{value:?}")
+}
+
+/// Writes `facts` to `dir` as one tab-separated `<relation>.facts` file per relation.
+///
+/// The output has the same layout as the `nll-facts/<item>/` directories generated
+/// by `-Znll-facts`, so it can be given directly to the
+/// [Polonius CLI](https://github.com/rust-lang/polonius) or to Soufflé.
+pub fn write_facts_to_dir(
+  facts: &PoloniusInput,
+  location_table: &LocationTable,
+  dir: &Path,
+) -> Result<()> {
+  fs::create_dir_all(dir)
+    .with_context(|| format!("Could not create directory {}", dir.display()))?;
+
+  let point = |point: PointIndex| location_table.point_to_string(point);
+
+  macro_rules! write_relation {
+    ($field:ident, |$row:pat_param| $cells:expr) => {
+      write_facts_file(
+        dir,
+        stringify!($field),
+        facts.$field.iter().map(|$row| $cells),
+      )?;
+    };
+  }
+
+  write_relation!(loan_issued_at, |&(o, l, p)| vec![
+    cell(o),
+    cell(l),
+    point(p)
+  ]);
+  write_relation!(universal_region, |&o| vec![cell(o)]);
+  write_relation!(cfg_edge, |&(p, q)| vec![point(p), point(q)]);
+  write_relation!(loan_killed_at, |&(l, p)| vec![cell(l), point(p)]);
+  write_relation!(subset_base, |&(o1, o2, p)| vec![
+    cell(o1),
+    cell(o2),
+    point(p)
+  ]);
+  write_relation!(loan_invalidated_at, |&(p, l)| vec![point(p), cell(l)]);
+  write_relation!(var_used_at, |&(v, p)| vec![cell(v), point(p)]);
+  write_relation!(var_defined_at, |&(v, p)| vec![cell(v), point(p)]);
+  write_relation!(var_dropped_at, |&(v, p)| vec![cell(v), point(p)]);
+  write_relation!(use_of_var_derefs_origin, |&(v, o)| vec![cell(v), cell(o)]);
+  write_relation!(drop_of_var_derefs_origin, |&(v, o)| vec![cell(v), cell(o)]);
+  write_relation!(child_path, |&(m1, m2)| vec![cell(m1), cell(m2)]);
+  write_relation!(path_is_var, |&(m, v)| vec![cell(m), cell(v)]);
+  write_relation!(path_assigned_at_base, |&(m, p)| vec![cell(m), point(p)]);
+  write_relation!(path_moved_at_base, |&(m, p)| vec![cell(m), point(p)]);
+  write_relation!(path_accessed_at_base, |&(m, p)| vec![cell(m), point(p)]);
+  write_relation!(known_placeholder_subset, |&(o1, o2)| vec![
+    cell(o1),
+    cell(o2)
+  ]);
+  write_relation!(placeholder, |&(o, l)| vec![cell(o), cell(l)]);
+
+  Ok(())
+}
+
+/// Writes the input facts collected for `def_id` to `dir`.
+///
+/// See [`write_facts_to_dir`] for the output format. Fails if facts were not collected
+/// for the body, e.g. because of [`set_consumer_options`](super::set_consumer_options).
+pub fn dump_facts_to_dir(tcx: TyCtxt<'_>, def_id: LocalDefId, dir: &Path) -> Result<()> {
+  let body_with_facts: &BodyWithBorrowckFacts = get_body_with_borrowck_facts(tcx, def_id);
+  let facts = body_with_facts
+    .input_facts
+    .as_ref()
+    .context("Input facts were not collected for this body")?;
+  let location_table = body_with_facts
+    .location_table
+    .as_ref()
+    .context("Location table was not collected for this body")?;
+  write_facts_to_dir(facts, location_table, dir)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test_utils;
+
+  #[test]
+  fn test_dump_facts_to_dir() {
+    let input = r#"
+fn main() {
+  let mut x = 1;
+  let y = &mut x;
+  *y += 1;
+}
+"#;
+    test_utils::compile_body(input, |tcx, body_id, _| {
+      let def_id = tcx.hir().body_owner_def_id(body_id);
+      let dir = std::env::temp_dir()
+        .join("rustc_utils_nll_facts")
+        .join(format!("{}", std::process::id()));
+      dump_facts_to_dir(tcx, def_id, &dir).unwrap();
+
+      let cfg_edge = fs::read_to_string(dir.join("cfg_edge.facts")).unwrap();
+      let first = cfg_edge.lines().next().unwrap();
+      assert_eq!(first, "\"Start(bb0[0])\"\t\"Mid(bb0[0])\"");
+
+      let loans = fs::read_to_string(dir.join("loan_issued_at.facts")).unwrap();
+      assert!(loans.lines().count() > 0);
+      assert!(dir.join("placeholder.facts").exists());
+
+      fs::remove_dir_all(&dir).unwrap();
+    });
+  }
+}
@@ -0,0 +1,71 @@
+//! Errors from retrieving borrowck facts.
+
+use std::fmt;
+
+use rustc_hir::def_id::{DefId, LocalDefId};
+
+/// Reasons why [`try_get_body_with_borrowck_facts`](super::try_get_body_with_borrowck_facts)
+/// could not return a body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FactsError {
+  /// [`override_queries`](super::override_queries) was not registered for the
+  /// compiler session.
+  OverrideNotInstalled,
+  /// The item is defined in another crate.
+  NotLocal(DefId),
+  /// The item is defined in the local crate, so its facts should be retrieved with
+  /// [`get_body_with_borrowck_facts`](super::get_body_with_borrowck_facts).
+  NotExtern(LocalDefId),
+  /// The MIR of an extern item was not encoded in its crate's metadata, e.g. because
+  /// the crate was compiled without `-Zalways-encode-mir`.
+  MirNotEncoded(DefId),
+  /// The item does not have a body, e.g. because it is a struct.
+  NoBody(LocalDefId),
+  /// Type-checking the body produced errors, so it cannot be borrow-checked reliably.
+  TaintedByErrors(LocalDefId),
+  /// The item is excluded by [`set_filter`](super::set_filter).
+  Filtered(LocalDefId),
+  /// The body was removed from the cache, e.g. by
+  /// [`take_body_with_borrowck_facts`](super::take_body_with_borrowck_facts).
+  Removed(LocalDefId),
+  /// The body was computed on another thread, e.g. by the parallel compiler, and can
+  /// only be used on that thread.
+  OtherThread(LocalDefId),
+}
+
+impl fmt::Display for FactsError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FactsError::OverrideNotInstalled => write!(
+        f,
+        "borrowck_facts::override_queries was not registered in rustc_driver::Callbacks::config"
+      ),
+      FactsError::NotLocal(def_id) => {
+        write!(f, "item {def_id:?} is not defined in the local crate")
+      }
+      FactsError::NotExtern(def_id) => {
+        write!(f, "item {def_id:?} is defined in the local crate")
+      }
+      FactsError::MirNotEncoded(def_id) => write!(
+        f,
+        "MIR for item {def_id:?} is not available, was its crate compiled with -Zalways-encode-mir?"
+      ),
+      FactsError::NoBody(def_id) => write!(f, "item {def_id:?} does not have a body"),
+      FactsError::TaintedByErrors(def_id) => {
+        write!(f, "body of item {def_id:?} has type errors")
+      }
+      FactsError::Filtered(def_id) => {
+        write!(f, "item {def_id:?} is excluded by borrowck_facts::set_filter")
+      }
+      FactsError::Removed(def_id) => {
+        write!(f, "body of item {def_id:?} was removed from the borrowck facts cache")
+      }
+      FactsError::OtherThread(def_id) => write!(
+        f,
+        "body of item {def_id:?} was computed on another thread and can only be used there"
+      ),
+    }
+  }
+}
+
+impl std::error::Error for FactsError {}
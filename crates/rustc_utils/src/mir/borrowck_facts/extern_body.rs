@@ -0,0 +1,226 @@
+//! Bodies of items from dependency crates.
+
+use either::Either;
+use rustc_data_structures::fx::FxHashSet as HashSet;
+use rustc_hir::def_id::DefId;
+use rustc_index::IndexVec;
+use rustc_middle::{
+  mir::{
+    visit::{PlaceContext, Visitor},
+    Body, BorrowKind, Local, Location, Place, Rvalue, StatementKind, TerminatorKind,
+  },
+  ty::{TyCtxt, TypeVisitableExt},
+};
+
+use super::FactsError;
+use crate::BodyExt;
+
+/// A borrow in an [`ExternBody`], the counterpart of
+/// [`LoanInfo`](super::LoanInfo) for bodies that were not borrow-checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExternBorrow<'tcx> {
+  /// The place being borrowed, e.g. `x` in `y = &mut x`.
+  pub borrowed_place: Place<'tcx>,
+  /// The place the reference is assigned to, e.g. `y` in `y = &mut x`.
+  pub assigned_place: Place<'tcx>,
+  pub kind: BorrowKind,
+  pub location: Location,
+}
+
+/// The MIR body of an item from another crate, with the borrows and their regions
+/// reconstructed from its statements.
+///
+/// Extern bodies are only available as optimized MIR, which has been through the MIR
+/// pipeline and has its regions erased. So unlike a local
+/// [`BodyWithBorrowckFacts`](rustc_borrowck::consumers::BodyWithBorrowckFacts), there
+/// are no Polonius facts or region inference context. Instead each local stands for
+/// the regions in its type, and [`ExternBody::loans_in`] approximates which borrows
+/// each region may contain, like `origin_contains_loan_anywhere` in the Polonius
+/// output.
+pub struct ExternBody<'tcx> {
+  pub def_id: DefId,
+  pub body: &'tcx Body<'tcx>,
+  /// The borrows of the body, in the order of [`BodyExt::all_locations`].
+  pub borrows: Vec<ExternBorrow<'tcx>>,
+  /// The indices in `borrows` of the borrows that may be held by each local.
+  loans: IndexVec<Local, HashSet<usize>>,
+}
+
+impl<'tcx> ExternBody<'tcx> {
+  fn new(tcx: TyCtxt<'tcx>, def_id: DefId, body: &'tcx Body<'tcx>) -> Self {
+    let mut borrows = Vec::new();
+    let mut loans = IndexVec::from_elem_n(HashSet::default(), body.local_decls.len());
+    // Edges `(from, to)` along which the references held by `from` flow into `to`.
+    let mut edges = Vec::new();
+    let has_regions = |place: &Place<'tcx>| place.ty(body, tcx).ty.has_erased_regions();
+
+    for location in body.all_locations() {
+      match body.stmt_at(location) {
+        Either::Left(statement) => {
+          let StatementKind::Assign(box (assigned_place, rvalue)) = &statement.kind
+          else {
+            continue;
+          };
+          if let Rvalue::Ref(_, kind, borrowed_place) = rvalue {
+            loans[assigned_place.local].insert(borrows.len());
+            borrows.push(ExternBorrow {
+              borrowed_place: *borrowed_place,
+              assigned_place: *assigned_place,
+              kind: *kind,
+              location,
+            });
+          } else if has_regions(assigned_place) {
+            let mut collector = LocalCollector::default();
+            collector.visit_rvalue(rvalue, location);
+            edges.extend(
+              collector
+                .locals
+                .into_iter()
+                .map(|local| (local, assigned_place.local)),
+            );
+          }
+        }
+        // Callees are assumed to return references derived from their arguments.
+        Either::Right(terminator) => {
+          if let TerminatorKind::Call {
+            args, destination, ..
+          } = &terminator.kind
+            && has_regions(destination)
+          {
+            edges.extend(
+              args
+                .iter()
+                .filter_map(|arg| arg.node.place())
+                .map(|place| (place.local, destination.local)),
+            );
+          }
+        }
+      }
+    }
+
+    let mut changed = true;
+    while changed {
+      changed = false;
+      for (from, to) in &edges {
+        if from == to {
+          continue;
+        }
+        let new = loans[*from]
+          .difference(&loans[*to])
+          .copied()
+          .collect::<Vec<_>>();
+        changed |= !new.is_empty();
+        loans[*to].extend(new);
+      }
+    }
+
+    ExternBody {
+      def_id,
+      body,
+      borrows,
+      loans,
+    }
+  }
+
+  /// Returns the borrows whose references may be held by `local`, either directly or
+  /// within its fields or pointees.
+  ///
+  /// This is flow-insensitive: a borrow is included if it may be held by `local` at any
+  /// point in the body.
+  pub fn loans_in(&self, local: Local) -> impl Iterator<Item = &ExternBorrow<'tcx>> + '_ {
+    let mut indices = self.loans[local].iter().copied().collect::<Vec<_>>();
+    indices.sort_unstable();
+    indices.into_iter().map(|index| &self.borrows[index])
+  }
+}
+
+/// Collects the locals of the places used by an rvalue.
+#[derive(Default)]
+struct LocalCollector {
+  locals: Vec<Local>,
+}
+
+impl<'tcx> Visitor<'tcx> for LocalCollector {
+  fn visit_place(&mut self, place: &Place<'tcx>, _context: PlaceContext, _: Location) {
+    self.locals.push(place.local);
+  }
+}
+
+/// Gets the MIR body of `def_id` from the metadata of the crate that defines it.
+///
+/// Crates only encode the MIR of items that may be instantiated or inlined by other
+/// crates, e.g. generic or `#[inline]` functions. To make the MIR of every item
+/// available, dependencies must be compiled with `-Zalways-encode-mir`. Returns an error
+/// if `def_id` is local, or if its MIR was not encoded.
+pub fn get_extern_body(
+  tcx: TyCtxt<'_>,
+  def_id: DefId,
+) -> Result<ExternBody<'_>, FactsError> {
+  if let Some(local_def_id) = def_id.as_local() {
+    return Err(FactsError::NotExtern(local_def_id));
+  }
+
+  let body = if tcx.is_mir_available(def_id) {
+    tcx.optimized_mir(def_id)
+  } else if tcx.is_ctfe_mir_available(def_id) {
+    tcx.mir_for_ctfe(def_id)
+  } else {
+    return Err(FactsError::MirNotEncoded(def_id));
+  };
+
+  Ok(ExternBody::new(tcx, def_id, body))
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::mir::RETURN_PLACE;
+
+  use super::*;
+  use crate::{
+    mir::borrowck_facts::get_body_with_borrowck_facts,
+    test_utils::{CompileBuilder, CompileResult},
+    PlaceExt,
+  };
+
+  #[test]
+  fn test_get_extern_body() {
+    let input = r#"
+fn main() {
+  let x = Some(1);
+  let y = x.as_ref();
+}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let local_def_id = tcx.hir().body_owners().next().unwrap();
+      assert_eq!(
+        get_extern_body(tcx, local_def_id.to_def_id()).err(),
+        Some(FactsError::NotExtern(local_def_id))
+      );
+
+      let body = &get_body_with_borrowck_facts(tcx, local_def_id).body;
+      let as_ref = body
+        .basic_blocks
+        .iter()
+        .find_map(|data| match &data.terminator().kind {
+          TerminatorKind::Call { func, .. } => Some(func.const_fn_def()?.0),
+          _ => None,
+        })
+        .unwrap();
+      assert_eq!(tcx.item_name(as_ref).as_str(), "as_ref");
+
+      let extern_body = get_extern_body(tcx, as_ref).unwrap();
+      assert_eq!(extern_body.def_id, as_ref);
+      let borrow = extern_body.borrows.first().unwrap();
+      assert!(matches!(borrow.kind, BorrowKind::Shared));
+      // `as_ref` borrows the contents of `*self`.
+      assert!(
+        borrow.borrowed_place.is_indirect()
+          && borrow.borrowed_place.is_arg(extern_body.body)
+      );
+      // The reference flows into the `Option` that is returned.
+      assert!(extern_body
+        .loans_in(RETURN_PLACE)
+        .any(|loan| loan.location == borrow.location));
+    });
+  }
+}
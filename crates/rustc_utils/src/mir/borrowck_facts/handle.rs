@@ -0,0 +1,87 @@
+//! Reference-counted access to stored bodies.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use rustc_borrowck::consumers::BodyWithBorrowckFacts;
+use rustc_hir::def_id::LocalDefId;
+use rustc_middle::ty::TyCtxt;
+
+use super::{store::StoredBody, stored_body};
+
+/// A handle to the MIR body and borrowck facts for a [`LocalDefId`].
+///
+/// Unlike the reference returned by
+/// [`get_body_with_borrowck_facts`](super::get_body_with_borrowck_facts), a handle keeps
+/// the body alive even if it is released from the cache by
+/// [`release_body`](super::release_body) or [`clear_all`](super::clear_all), so it can
+/// be used without any `unsafe` code. The body is only accessible inside
+/// [`BodyFactsHandle::with`], so references to it cannot outlive the handle.
+#[derive(Clone)]
+pub struct BodyFactsHandle<'tcx> {
+  def_id: LocalDefId,
+  stored: Arc<StoredBody>,
+  _tcx: PhantomData<TyCtxt<'tcx>>,
+}
+
+impl<'tcx> BodyFactsHandle<'tcx> {
+  /// The item whose body this handle refers to.
+  pub fn def_id(&self) -> LocalDefId {
+    self.def_id
+  }
+
+  /// Calls `f` with the body and its borrowck facts.
+  pub fn with<T>(&self, f: impl FnOnce(&BodyWithBorrowckFacts<'tcx>) -> T) -> T {
+    // SAFETY: `body_facts_handle` ties `'tcx` to the `TyCtxt` that produced the body,
+    // and the handle keeps the body alive for the duration of `f`.
+    let body: &BodyWithBorrowckFacts<'tcx> = unsafe { self.stored.borrow() };
+    f(body)
+  }
+
+  /// Returns true if the Polonius facts for the body were dropped because they
+  /// exceeded the time budget, see [`set_time_budget`](super::set_time_budget).
+  pub fn timed_out(&self) -> bool {
+    self.stored.timed_out
+  }
+}
+
+/// Gets a [`BodyFactsHandle`] to the MIR body and borrowck facts for `def_id`.
+///
+/// The same requirements as
+/// [`get_body_with_borrowck_facts`](super::get_body_with_borrowck_facts) apply. While a
+/// handle is alive, the body cannot be taken by
+/// [`take_body_with_borrowck_facts`](super::take_body_with_borrowck_facts).
+pub fn body_facts_handle(tcx: TyCtxt<'_>, def_id: LocalDefId) -> BodyFactsHandle<'_> {
+  BodyFactsHandle {
+    def_id,
+    stored: stored_body(tcx, def_id),
+    _tcx: PhantomData,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    mir::borrowck_facts::release_body,
+    test_utils::{CompileBuilder, CompileResult},
+  };
+
+  #[test]
+  fn test_handle_outlives_release() {
+    let input = r#"
+fn main() { let x = 1; let y = &x; }
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let handle = body_facts_handle(tcx, def_id);
+      assert!(unsafe { release_body(tcx, def_id) });
+
+      let num_borrows = handle.with(|body_with_facts| {
+        assert_eq!(body_with_facts.body.source.def_id(), def_id.to_def_id());
+        body_with_facts.borrow_set.len()
+      });
+      assert_eq!(num_borrows, 1);
+      assert_eq!(handle.def_id(), def_id);
+    });
+  }
+}
@@ -0,0 +1,147 @@
+//! Which loans are live at each location, according to Polonius.
+
+use rustc_borrowck::consumers::{
+  BorrowIndex, LocationTable, PoloniusOutput, RichLocation,
+};
+use rustc_data_structures::fx::FxHashMap as HashMap;
+use rustc_hir::def_id::LocalDefId;
+use rustc_index::bit_set::BitSet;
+use rustc_middle::{mir::Location, ty::TyCtxt};
+
+use super::{compute_output, get_body_with_borrowck_facts, Algorithm};
+
+/// The loans live at each location of a body, computed from the `loan_live_at`
+/// relation of the Polonius output.
+///
+/// A loan is live at a point if the reference it created (or one derived from it) may
+/// still be used later. Polonius distinguishes the start of a location, before the
+/// statement takes effect, from its mid-point, where it takes effect.
+pub struct LoanLiveness {
+  num_loans: usize,
+  on_entry: HashMap<Location, BitSet<BorrowIndex>>,
+  at_mid: HashMap<Location, BitSet<BorrowIndex>>,
+}
+
+impl LoanLiveness {
+  /// Builds the liveness from a Polonius `output` whose `loan_live_at` relation was
+  /// computed, i.e. with dumping enabled and an algorithm other than
+  /// [`Algorithm::LocationInsensitive`].
+  pub fn new(
+    output: &PoloniusOutput,
+    location_table: &LocationTable,
+    num_loans: usize,
+  ) -> Self {
+    let mut on_entry = HashMap::default();
+    let mut at_mid = HashMap::default();
+    for (point, loans) in &output.loan_live_at {
+      let (map, location) = match location_table.to_location(*point) {
+        RichLocation::Start(location) => (&mut on_entry, location),
+        RichLocation::Mid(location) => (&mut at_mid, location),
+      };
+      let set = map
+        .entry(location)
+        .or_insert_with(|| BitSet::new_empty(num_loans));
+      for loan in loans {
+        set.insert(*loan);
+      }
+    }
+
+    LoanLiveness {
+      num_loans,
+      on_entry,
+      at_mid,
+    }
+  }
+
+  /// Runs Polonius on the body for `def_id` and builds its liveness.
+  ///
+  /// # Panics
+  ///
+  /// If input facts were not collected for the body.
+  pub fn compute(tcx: TyCtxt<'_>, def_id: LocalDefId) -> Self {
+    let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+    let location_table = body_with_facts
+      .location_table
+      .as_ref()
+      .unwrap_or_else(|| panic!("input facts were not collected for item: {def_id:?}"));
+    // The hybrid algorithm skips computing liveness for bodies without errors.
+    let output = compute_output(tcx, def_id, Algorithm::DatafrogOpt);
+    LoanLiveness::new(output, location_table, body_with_facts.borrow_set.len())
+  }
+
+  /// Returns the loans live on entry to or during `location`.
+  pub fn loans_live_at(&self, location: Location) -> BitSet<BorrowIndex> {
+    let mut loans = BitSet::new_empty(self.num_loans);
+    for map in [&self.on_entry, &self.at_mid] {
+      if let Some(set) = map.get(&location) {
+        loans.union(set);
+      }
+    }
+    loans
+  }
+
+  /// Returns true if `borrow` is live before `location` takes effect.
+  pub fn is_loan_live_on_entry(&self, borrow: BorrowIndex, location: Location) -> bool {
+    self
+      .on_entry
+      .get(&location)
+      .is_some_and(|set| set.contains(borrow))
+  }
+
+  /// Returns true if `borrow` is live at the mid-point of `location`.
+  pub fn is_loan_live_at_mid(&self, borrow: BorrowIndex, location: Location) -> bool {
+    self
+      .at_mid
+      .get(&location)
+      .is_some_and(|set| set.contains(borrow))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    mir::borrowck_facts::BodyFactsExt,
+    test_utils::{CompileBuilder, CompileResult},
+    BodyExt,
+  };
+
+  #[test]
+  fn test_loan_liveness() {
+    let input = r#"
+fn main() {
+  let mut x = 1;
+  let y = &mut x;
+  *y += 1;
+  x += 1;
+}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+      let body = &body_with_facts.body;
+      let liveness = LoanLiveness::compute(tcx, def_id);
+
+      let loan = body_with_facts.loans().next().unwrap();
+      let live_locations = body
+        .all_locations()
+        .filter(|location| liveness.is_loan_live_on_entry(loan.index, *location))
+        .collect::<Vec<_>>();
+      assert!(!live_locations.is_empty());
+      assert!(live_locations
+        .iter()
+        .all(|location| liveness.loans_live_at(*location).contains(loan.index)));
+
+      // The loan is dead once `x` is used again.
+      let source_map = tcx.sess.source_map();
+      let assign_x = body
+        .all_locations()
+        .find(|location| {
+          let span = body.source_info(*location).span;
+          source_map.span_to_snippet(span).unwrap() == "x += 1"
+        })
+        .unwrap();
+      assert!(!liveness.is_loan_live_on_entry(loan.index, assign_x));
+    });
+  }
+}
@@ -0,0 +1,181 @@
+//! Describing loans from the borrowck facts in terms of the source program.
+
+use rustc_borrowck::consumers::{BodyWithBorrowckFacts, BorrowIndex};
+use rustc_middle::{
+  mir::{
+    Body, BorrowKind, Location, MutBorrowKind, Place, Rvalue, Statement, StatementKind,
+  },
+  ty::{RegionVid, TyCtxt},
+};
+use rustc_span::Span;
+
+use crate::{PlaceExt, SpanExt};
+
+/// A loan (borrow) from the [`BorrowSet`](rustc_borrowck::borrow_set::BorrowSet) of a
+/// body, i.e. the `Loan` atoms of the Polonius facts.
+#[derive(Debug, Clone, Copy)]
+pub struct LoanInfo<'a, 'tcx> {
+  pub index: BorrowIndex,
+  /// The place being borrowed, e.g. `x` in `y = &mut x`.
+  pub borrowed_place: Place<'tcx>,
+  /// The place the reference is assigned to, e.g. `y` in `y = &mut x`.
+  pub assigned_place: Place<'tcx>,
+  pub kind: BorrowKind,
+  /// The region of the reference created by the borrow.
+  pub region: RegionVid,
+  /// The location of the statement that introduces the loan in the stored body, which
+  /// differs from the `reserve_location` of the borrow if the body was simplified.
+  pub location: Location,
+  pub statement: &'a Statement<'tcx>,
+  /// The span of the borrow expression, moved out of macro expansions into the body
+  /// when possible.
+  pub span: Span,
+}
+
+impl<'a, 'tcx> LoanInfo<'a, 'tcx> {
+  /// Returns `None` if the statement of the loan is no longer in the stored body.
+  ///
+  /// The borrow set was computed before the stored body was simplified or transformed,
+  /// so its locations may be out of date. Removing statements only moves the borrow to
+  /// an earlier statement of its block, so the borrow is searched for from its original
+  /// location backwards.
+  pub(super) fn new(
+    body_with_facts: &'a BodyWithBorrowckFacts<'tcx>,
+    index: BorrowIndex,
+  ) -> Option<Self> {
+    let body = &body_with_facts.body;
+    let borrow = &body_with_facts.borrow_set[index];
+    let reserve_location = borrow.reserve_location;
+    let statements = &body.basic_blocks.get(reserve_location.block)?.statements;
+    let last = reserve_location
+      .statement_index
+      .min(statements.len().checked_sub(1)?);
+    let statement_index = (0 ..= last).rev().find(|i| {
+      matches!(
+        &statements[*i].kind,
+        StatementKind::Assign(box (assigned_place, Rvalue::Ref(_, _, borrowed_place)))
+          if *assigned_place == borrow.assigned_place
+            && *borrowed_place == borrow.borrowed_place
+      )
+    })?;
+    let location = Location {
+      block: reserve_location.block,
+      statement_index,
+    };
+    let statement = &statements[statement_index];
+    let span = statement.source_info.span;
+    Some(LoanInfo {
+      index,
+      borrowed_place: borrow.borrowed_place,
+      assigned_place: borrow.assigned_place,
+      kind: borrow.kind,
+      region: borrow.region,
+      location,
+      statement,
+      span: span.as_local(body.span).unwrap_or(span),
+    })
+  }
+
+  /// Describes the borrow as source code, e.g. `&mut x.0`, or returns `None` if the
+  /// borrowed place has no name in the source.
+  pub fn describe(&self, tcx: TyCtxt<'tcx>, body: &Body<'tcx>) -> Option<String> {
+    let place = self.borrowed_place.to_string(tcx, body)?;
+    let prefix = match self.kind {
+      BorrowKind::Shared => "&",
+      BorrowKind::Fake(_) => "&fake ",
+      BorrowKind::Mut {
+        kind: MutBorrowKind::ClosureCapture,
+      } => "&uniq ",
+      BorrowKind::Mut { .. } => "&mut ",
+    };
+    Some(format!("{prefix}{place}"))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::mir::{Rvalue, StatementKind};
+
+  use crate::{
+    mir::borrowck_facts::{
+      self, get_body_with_borrowck_facts, take_body_with_borrowck_facts, BodyFactsExt,
+    },
+    test_utils::{CompileBuilder, CompileResult},
+  };
+
+  #[test]
+  fn test_loan_info() {
+    let input = r#"
+fn main() {
+  let mut x = (1, 2);
+  let y = &mut x.0;
+  *y += 1;
+  let z = &x;
+}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+      let body = &body_with_facts.body;
+      let source_map = tcx.sess.source_map();
+
+      let loans = body_with_facts
+        .loans()
+        .map(|loan| {
+          assert_eq!(
+            body_with_facts.loan_info(loan.index).unwrap().location,
+            loan.location
+          );
+          (
+            loan.describe(tcx, body).unwrap(),
+            source_map.span_to_snippet(loan.span).unwrap(),
+          )
+        })
+        .collect::<Vec<_>>();
+      assert_eq!(loans, vec![
+        ("&mut x.0".to_string(), "&mut x.0".to_string()),
+        ("&x".to_string(), "&x".to_string()),
+      ]);
+    });
+  }
+
+  #[test]
+  fn test_loan_info_simplified() {
+    let input = r#"
+fn main() {
+  let mut x = (1, 2);
+  let y = &mut x.0;
+  *y += 1;
+  let z = &x;
+}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      // Simplification is global, so apply it to an owned body rather than through
+      // `enable_mir_simplification`. It removes the `StorageLive`s before the borrows,
+      // so their statement indices shift.
+      let mut body_with_facts = take_body_with_borrowck_facts(tcx, def_id);
+      borrowck_facts::simplify_mir(&mut body_with_facts.body);
+      let body = &body_with_facts.body;
+      assert!(body.basic_blocks.iter().all(|data| {
+        data
+          .statements
+          .iter()
+          .all(|statement| !matches!(statement.kind, StatementKind::StorageLive(_)))
+      }));
+
+      let loans = body_with_facts.loans().collect::<Vec<_>>();
+      assert_eq!(loans.len(), 2);
+      assert!(loans.iter().any(|loan| {
+        loan.location != body_with_facts.borrow_set[loan.index].reserve_location
+      }));
+      for loan in loans {
+        let statement = body.stmt_at(loan.location).left().unwrap();
+        assert!(matches!(
+          &statement.kind,
+          StatementKind::Assign(box (place, Rvalue::Ref(..))) if *place == loan.assigned_place
+        ));
+      }
+    });
+  }
+}
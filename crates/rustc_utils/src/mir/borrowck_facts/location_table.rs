@@ -0,0 +1,114 @@
+//! Utilities for [`LocationTable`].
+
+use polonius_engine::FactTypes;
+use rustc_borrowck::consumers::{LocationTable, RichLocation, RustcFacts};
+use rustc_middle::mir::{BasicBlock, Body, Location};
+
+/// The index of a point in the Polonius facts, as used by [`LocationTable`].
+pub type PointIndex = <RustcFacts as FactTypes>::Point;
+
+/// Extension trait for [`LocationTable`].
+pub trait LocationTableExt {
+  /// Returns the point for a start or mid location.
+  fn to_point(&self, location: RichLocation) -> PointIndex;
+
+  /// Returns the start and mid points of `location`, in that order.
+  fn points(&self, location: Location) -> [PointIndex; 2];
+
+  /// Returns the MIR location of `point`, ignoring whether it is a start or mid point.
+  fn to_mir_location(&self, point: PointIndex) -> Location;
+
+  /// Returns true if `point` is the start point of its location.
+  fn is_start(&self, point: PointIndex) -> bool;
+
+  type BlockPointsIter: Iterator<Item = PointIndex>;
+
+  /// Returns the points of `block` in control-flow order, i.e. the start and mid
+  /// point of each statement followed by those of the terminator.
+  fn points_in_block(&self, body: &Body<'_>, block: BasicBlock) -> Self::BlockPointsIter;
+
+  /// Formats `point` as it appears in `-Znll-facts` dumps, e.g. `Mid(bb0[1])`.
+  fn point_to_string(&self, point: PointIndex) -> String;
+}
+
+impl LocationTableExt for LocationTable {
+  fn to_point(&self, location: RichLocation) -> PointIndex {
+    match location {
+      RichLocation::Start(location) => self.start_index(location),
+      RichLocation::Mid(location) => self.mid_index(location),
+    }
+  }
+
+  fn points(&self, location: Location) -> [PointIndex; 2] {
+    [self.start_index(location), self.mid_index(location)]
+  }
+
+  fn to_mir_location(&self, point: PointIndex) -> Location {
+    match self.to_location(point) {
+      RichLocation::Start(location) | RichLocation::Mid(location) => location,
+    }
+  }
+
+  fn is_start(&self, point: PointIndex) -> bool {
+    matches!(self.to_location(point), RichLocation::Start(_))
+  }
+
+  type BlockPointsIter = impl Iterator<Item = PointIndex>;
+  fn points_in_block(&self, body: &Body<'_>, block: BasicBlock) -> Self::BlockPointsIter {
+    // Points are allocated contiguously for each block.
+    let start = self.start_index(block.start_location());
+    let num_points = (body.basic_blocks[block].statements.len() + 1) * 2;
+    (start.as_usize() .. start.as_usize() + num_points).map(PointIndex::from_usize)
+  }
+
+  fn point_to_string(&self, point: PointIndex) -> String {
+    format!("{:?}", self.to_location(point))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    mir::borrowck_facts::get_body_with_borrowck_facts,
+    test_utils::{CompileBuilder, CompileResult},
+    BodyExt,
+  };
+
+  #[test]
+  fn test_location_table_ext() {
+    let input = r#"
+fn main() {
+  let mut x = 1;
+  if x > 0 { x += 1; }
+}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+      let body = &body_with_facts.body;
+      let table = body_with_facts.location_table.as_ref().unwrap();
+
+      for location in body.all_locations() {
+        let [start, mid] = table.points(location);
+        assert!(table.is_start(start) && !table.is_start(mid));
+        assert_eq!(table.to_mir_location(start), location);
+        assert_eq!(table.to_mir_location(mid), location);
+        assert_eq!(table.to_point(RichLocation::Mid(location)), mid);
+      }
+
+      let block_points = body
+        .basic_blocks
+        .indices()
+        .flat_map(|block| table.points_in_block(body, block))
+        .collect::<Vec<_>>();
+      assert_eq!(block_points, table.all_points().collect::<Vec<_>>());
+
+      let point = table.mid_index(Location {
+        block: BasicBlock::from_usize(0),
+        statement_index: 1,
+      });
+      assert_eq!(table.point_to_string(point), "Mid(bb0[1])");
+    });
+  }
+}
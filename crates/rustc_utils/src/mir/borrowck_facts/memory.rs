@@ -0,0 +1,352 @@
+//! Estimating the memory retained by cached bodies.
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fmt,
+  mem::size_of,
+};
+
+use rustc_borrowck::consumers::{BodyWithBorrowckFacts, PoloniusInput, PoloniusOutput};
+use rustc_data_structures::fx::FxHashMap as HashMap;
+use rustc_hir::def_id::LocalDefId;
+use rustc_middle::{
+  mir::{BasicBlockData, Body, LocalDecl, Statement, StatementKind, VarDebugInfo},
+  ty::TyCtxt,
+};
+use rustc_mir_dataflow::move_paths::{InitIndex, MoveData, MoveOut, MovePath};
+
+use super::store::{SessionId, MIR_BODIES, POLONIUS_OUTPUTS};
+
+/// Estimated memory retained by one cached body.
+///
+/// Sizes are in bytes and only approximate: they count the elements of each table,
+/// but not allocator overhead or data interned in the `TyCtxt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyMemoryUsage {
+  pub def_id: LocalDefId,
+  /// The MIR body and its promoted constants.
+  pub mir_bytes: usize,
+  /// The number of rows across all Polonius input fact tables.
+  pub input_fact_rows: usize,
+  pub input_facts_bytes: usize,
+  /// The Polonius output stored in the body and any outputs cached by
+  /// [`compute_output`](super::compute_output).
+  pub output_facts_bytes: usize,
+  /// See [`enable_move_data`](super::enable_move_data).
+  pub move_data_bytes: usize,
+}
+
+impl BodyMemoryUsage {
+  pub fn total_bytes(&self) -> usize {
+    self.mir_bytes
+      + self.input_facts_bytes
+      + self.output_facts_bytes
+      + self.move_data_bytes
+  }
+}
+
+/// The memory retained by every cached body of a compiler session, see
+/// [`memory_report`].
+#[derive(Debug, Clone, Default)]
+pub struct MemoryReport {
+  pub bodies: Vec<BodyMemoryUsage>,
+}
+
+impl MemoryReport {
+  pub fn total_bytes(&self) -> usize {
+    self.bodies.iter().map(BodyMemoryUsage::total_bytes).sum()
+  }
+
+  /// Sorts the bodies by their total size, largest first.
+  pub fn sort_by_size(&mut self) {
+    self
+      .bodies
+      .sort_by_key(|usage| std::cmp::Reverse(usage.total_bytes()));
+  }
+}
+
+impl fmt::Display for MemoryReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(
+      f,
+      "{:>12} {:>12} {:>12} {:>12} {:>12} {:>10}  item",
+      "total", "mir", "input", "output", "moves", "rows"
+    )?;
+    for usage in &self.bodies {
+      writeln!(
+        f,
+        "{:>12} {:>12} {:>12} {:>12} {:>12} {:>10}  {:?}",
+        usage.total_bytes(),
+        usage.mir_bytes,
+        usage.input_facts_bytes,
+        usage.output_facts_bytes,
+        usage.move_data_bytes,
+        usage.input_fact_rows,
+        usage.def_id
+      )?;
+    }
+    write!(
+      f,
+      "{:>12} total for {} bodies",
+      self.total_bytes(),
+      self.bodies.len()
+    )
+  }
+}
+
+/// Estimates the memory retained by each body cached for the session of `tcx`,
+/// sorted by size with the largest body first.
+///
+/// This is useful to find the bodies responsible for running out of memory on large
+/// crates. Bodies computed on other threads of the parallel compiler are not included.
+pub fn memory_report(tcx: TyCtxt<'_>) -> MemoryReport {
+  let session = SessionId::of_tcx(tcx);
+
+  let mut cached_outputs: HashMap<LocalDefId, usize> = HashMap::default();
+  for (def_id, output) in POLONIUS_OUTPUTS.session_values(session) {
+    *cached_outputs.entry(def_id).or_default() += output_bytes(&output);
+  }
+
+  let bodies = MIR_BODIES
+    .session_values(session)
+    .into_iter()
+    .filter(|(_, stored)| stored.is_on_current_thread())
+    .map(|(def_id, stored)| {
+      // SAFETY: the body belongs to the session of `tcx`, and `stored` keeps it alive
+      // while it is borrowed.
+      let body_with_facts: &BodyWithBorrowckFacts<'_> = unsafe { stored.borrow() };
+      let move_data: Option<&MoveData<'_>> = unsafe { stored.lend_move_data() };
+      let (input_fact_rows, input_facts_bytes) = body_with_facts
+        .input_facts
+        .as_deref()
+        .map_or((0, 0), input_size);
+      BodyMemoryUsage {
+        def_id,
+        mir_bytes: mir_bytes(&body_with_facts.body)
+          + body_with_facts
+            .promoted
+            .iter()
+            .map(mir_bytes)
+            .sum::<usize>(),
+        input_fact_rows,
+        input_facts_bytes,
+        output_facts_bytes: body_with_facts
+          .output_facts
+          .as_deref()
+          .map_or(0, output_bytes)
+          + cached_outputs.get(&def_id).copied().unwrap_or(0),
+        move_data_bytes: move_data.map_or(0, move_data_bytes),
+      }
+    })
+    .collect();
+
+  let mut report = MemoryReport { bodies };
+  report.sort_by_size();
+  report
+}
+
+fn mir_bytes(body: &Body<'_>) -> usize {
+  let statement_bytes = body
+    .basic_blocks
+    .iter()
+    .flat_map(|data| &data.statements)
+    .map(|statement| {
+      size_of::<Statement>()
+        + match &statement.kind {
+          StatementKind::Assign(assign) => size_of_val(&**assign),
+          _ => 0,
+        }
+    })
+    .sum::<usize>();
+  size_of::<Body>()
+    + body.basic_blocks.len() * size_of::<BasicBlockData>()
+    + statement_bytes
+    + body.local_decls.len() * size_of::<LocalDecl>()
+    + body.var_debug_info.len() * size_of::<VarDebugInfo>()
+}
+
+fn input_size(facts: &PoloniusInput) -> (usize, usize) {
+  let mut rows = 0;
+  let mut bytes = 0;
+  // Destructured so that a new fact table is a compile error rather than left out.
+  let PoloniusInput {
+    loan_issued_at,
+    universal_region,
+    cfg_edge,
+    loan_killed_at,
+    subset_base,
+    loan_invalidated_at,
+    var_used_at,
+    var_defined_at,
+    var_dropped_at,
+    use_of_var_derefs_origin,
+    drop_of_var_derefs_origin,
+    child_path,
+    path_is_var,
+    path_assigned_at_base,
+    path_moved_at_base,
+    path_accessed_at_base,
+    known_placeholder_subset,
+    placeholder,
+  } = facts;
+  macro_rules! count {
+    ($($field:ident),*) => {$(
+      rows += $field.len();
+      bytes += $field.heap_size();
+    )*};
+  }
+  count!(
+    loan_issued_at,
+    universal_region,
+    cfg_edge,
+    loan_killed_at,
+    subset_base,
+    loan_invalidated_at,
+    var_used_at,
+    var_defined_at,
+    var_dropped_at,
+    use_of_var_derefs_origin,
+    drop_of_var_derefs_origin,
+    child_path,
+    path_is_var,
+    path_assigned_at_base,
+    path_moved_at_base,
+    path_accessed_at_base,
+    known_placeholder_subset,
+    placeholder
+  );
+  (rows, bytes)
+}
+
+fn output_bytes(output: &PoloniusOutput) -> usize {
+  let mut bytes = size_of::<PoloniusOutput>();
+  let PoloniusOutput {
+    errors,
+    subset_errors,
+    move_errors,
+    dump_enabled: _,
+    loan_live_at,
+    origin_contains_loan_at,
+    origin_contains_loan_anywhere,
+    origin_live_on_entry,
+    loan_invalidated_at,
+    subset,
+    subset_anywhere,
+    var_live_on_entry,
+    var_drop_live_on_entry,
+    path_maybe_initialized_on_exit,
+    path_maybe_uninitialized_on_exit,
+    known_contains,
+    var_maybe_partly_initialized_on_exit,
+  } = output;
+  macro_rules! count {
+    ($($field:ident),*) => {$(
+      bytes += $field.heap_size();
+    )*};
+  }
+  count!(
+    errors,
+    subset_errors,
+    move_errors,
+    loan_live_at,
+    origin_contains_loan_at,
+    origin_contains_loan_anywhere,
+    origin_live_on_entry,
+    loan_invalidated_at,
+    subset,
+    subset_anywhere,
+    var_live_on_entry,
+    var_drop_live_on_entry,
+    path_maybe_initialized_on_exit,
+    path_maybe_uninitialized_on_exit,
+    known_contains,
+    var_maybe_partly_initialized_on_exit
+  );
+  bytes
+}
+
+fn move_data_bytes(move_data: &MoveData<'_>) -> usize {
+  size_of::<MoveData>()
+    + move_data.move_paths.len() * size_of::<MovePath>()
+    + move_data.moves.len() * size_of::<MoveOut>()
+    + move_data.inits.len() * size_of::<InitIndex>()
+    + size_of_val(&move_data.path_map.raw[..])
+}
+
+/// The heap memory owned by a fact table.
+///
+/// Elements of `Vec` and `BTreeSet` are always atoms or tuples of atoms in the
+/// Polonius facts, so they own no memory of their own.
+trait HeapSize {
+  fn heap_size(&self) -> usize;
+}
+
+impl<T> HeapSize for Vec<T> {
+  fn heap_size(&self) -> usize {
+    self.capacity() * size_of::<T>()
+  }
+}
+
+impl<T> HeapSize for BTreeSet<T> {
+  fn heap_size(&self) -> usize {
+    self.len() * size_of::<T>()
+  }
+}
+
+impl<K, V: HeapSize> HeapSize for BTreeMap<K, V> {
+  fn heap_size(&self) -> usize {
+    self.len() * size_of::<(K, V)>() + self.values().map(V::heap_size).sum::<usize>()
+  }
+}
+
+impl<K, V: HeapSize> HeapSize for HashMap<K, V> {
+  fn heap_size(&self) -> usize {
+    self.capacity() * (size_of::<(K, V)>() + 1)
+      + self.values().map(V::heap_size).sum::<usize>()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    mir::borrowck_facts::get_body_with_borrowck_facts,
+    test_utils::{CompileBuilder, CompileResult},
+  };
+
+  #[test]
+  fn test_memory_report() {
+    let input = r#"
+fn small() {}
+fn large(v: &mut Vec<i32>) {
+  for x in v.iter_mut() {
+    let y = &mut *x;
+    if *y > 0 { *y += 1; } else { *y -= 1; }
+  }
+}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_ids = tcx.hir().body_owners().collect::<Vec<_>>();
+      for def_id in &def_ids {
+        get_body_with_borrowck_facts(tcx, *def_id);
+      }
+
+      let report = memory_report(tcx);
+      assert_eq!(report.bodies.len(), def_ids.len());
+      assert!(report
+        .bodies
+        .windows(2)
+        .all(|w| w[0].total_bytes() >= w[1].total_bytes()));
+      assert_eq!(
+        report.total_bytes(),
+        report.bodies.iter().map(|u| u.total_bytes()).sum::<usize>()
+      );
+
+      let name = |usage: &BodyMemoryUsage| tcx.item_name(usage.def_id.to_def_id());
+      let largest = &report.bodies[0];
+      assert_eq!(name(largest).as_str(), "large");
+      assert!(largest.input_fact_rows > 0 && largest.input_facts_bytes > 0);
+      assert_eq!(report.to_string().lines().count(), report.bodies.len() + 2);
+    });
+  }
+}
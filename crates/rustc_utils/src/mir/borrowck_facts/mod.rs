@@ -0,0 +1,991 @@
+//! Polonius integration to extract borrowck facts from rustc.
+
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, LazyLock, Mutex, RwLock,
+  },
+  time::{Duration, Instant},
+};
+
+use either::Either;
+use rustc_borrowck::consumers::{BodyWithBorrowckFacts, ConsumerOptions, PoloniusOutput};
+use rustc_data_structures::fx::FxHashMap as HashMap;
+use rustc_hir::def_id::{DefId, LocalDefId};
+use rustc_middle::{
+  mir::{
+    Body, BorrowCheckResult, Location, Place, Promoted, Rvalue, Statement, StatementKind,
+    TerminatorKind,
+  },
+  ty::{Region, TyCtxt},
+  util::Providers,
+};
+use rustc_mir_dataflow::move_paths::MoveData;
+
+pub(crate) use self::store::SessionId;
+use self::store::{StoredBody, MIR_BODIES};
+pub use self::{
+  disk_cache::{default_disk_cache_dir, disable_disk_cache, enable_disk_cache},
+  dump::{dump_facts_to_dir, write_facts_to_dir},
+  error::FactsError,
+  extern_body::{get_extern_body, ExternBody, ExternBorrow},
+  handle::{body_facts_handle, BodyFactsHandle},
+  liveness::LoanLiveness,
+  loans::LoanInfo,
+  location_table::{LocationTableExt, PointIndex},
+  memory::{memory_report, BodyMemoryUsage, MemoryReport},
+  output::{compute_output, Algorithm},
+  regions::{BodyFactsExt, OutlivesGraph},
+};
+use crate::{block_timer, BodyExt};
+
+mod disk_cache;
+mod dump;
+mod error;
+mod extern_body;
+mod handle;
+mod liveness;
+mod loans;
+mod location_table;
+mod memory;
+mod output;
+mod regions;
+mod store;
+
+static CONSUMER_OPTIONS: Mutex<ConsumerOptions> =
+  Mutex::new(ConsumerOptions::PoloniusInputFacts);
+
+/// Selects which borrowck outputs are computed for every body stored by
+/// [`override_queries`].
+///
+/// The default is [`ConsumerOptions::PoloniusInputFacts`]. Analyses that only need the
+/// region inference context can pass [`ConsumerOptions::RegionInferenceContext`] to skip
+/// generating the Polonius fact tables, in which case `input_facts` and `location_table`
+/// will be `None`. Passing [`ConsumerOptions::PoloniusOutputFacts`] additionally runs
+/// Polonius and populates `output_facts`.
+///
+/// This must be called before any body is borrow-checked, i.e. before the first call to
+/// [`get_body_with_borrowck_facts`].
+pub fn set_consumer_options(options: ConsumerOptions) {
+  *CONSUMER_OPTIONS.lock().unwrap() = options;
+}
+
+/// Returns the [`ConsumerOptions`] currently used by the `mir_borrowck` override.
+pub fn consumer_options() -> ConsumerOptions {
+  *CONSUMER_OPTIONS.lock().unwrap()
+}
+
+static TIME_BUDGET: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Limits how long Polonius may spend on a single body stored by [`override_queries`].
+///
+/// If generating the Polonius facts for a body (and running Polonius, with
+/// [`ConsumerOptions::PoloniusOutputFacts`]) takes longer than `budget`, the body is
+/// stored as if [`ConsumerOptions::RegionInferenceContext`] were selected, i.e. with
+/// `location_table`, `input_facts` and `output_facts` set to `None`, and
+/// [`timed_out`] returns true for it.
+///
+/// Fact generation happens inside rustc and cannot be interrupted, so the budget is
+/// only checked once it completes. Polonius itself runs on a separate thread, which is
+/// abandoned when the budget runs out. At most 4 abandoned threads run at once: while
+/// that many are still running, further bodies are treated as over budget without
+/// running Polonius. `None` (the default) disables the limit.
+pub fn set_time_budget(budget: Option<Duration>) {
+  *TIME_BUDGET.lock().unwrap() = budget;
+}
+
+/// Returns the per-body budget registered by [`set_time_budget`].
+pub fn time_budget() -> Option<Duration> {
+  *TIME_BUDGET.lock().unwrap()
+}
+
+/// Returns true if the Polonius facts for `def_id` were dropped because they exceeded
+/// the budget set by [`set_time_budget`].
+pub fn timed_out(tcx: TyCtxt<'_>, def_id: LocalDefId) -> bool {
+  stored_body(tcx, def_id).timed_out
+}
+
+static COLLECT_MOVE_DATA: AtomicBool = AtomicBool::new(false);
+
+/// Also computes the [`MoveData`] of every body stored by [`override_queries`], which
+/// can then be retrieved with [`get_move_data`].
+///
+/// The move data is gathered after simplification and transforms, so it matches the
+/// stored body. This must be called before any body is borrow-checked.
+pub fn enable_move_data() {
+  COLLECT_MOVE_DATA.store(true, Ordering::SeqCst);
+}
+
+type BodyFilter = Box<dyn Fn(TyCtxt<'_>, LocalDefId) -> bool + Send + Sync>;
+
+static FILTER: RwLock<Option<BodyFilter>> = RwLock::new(None);
+
+/// Restricts which bodies have their borrowck facts stored by [`override_queries`].
+///
+/// Bodies for which `filter` returns false are borrow-checked by the original
+/// `mir_borrowck` provider, so they do not pay the cost of generating facts, but they
+/// also cannot be retrieved with [`get_body_with_borrowck_facts`].
+///
+/// By default, facts are stored for every body.
+pub fn set_filter(
+  filter: impl Fn(TyCtxt<'_>, LocalDefId) -> bool + Send + Sync + 'static,
+) {
+  *FILTER.write().unwrap() = Some(Box::new(filter));
+}
+
+/// Removes the filter registered by [`set_filter`], so facts are stored for every body.
+pub fn clear_filter() {
+  *FILTER.write().unwrap() = None;
+}
+
+fn should_store_facts(tcx: TyCtxt<'_>, def_id: LocalDefId) -> bool {
+  match &*FILTER.read().unwrap() {
+    Some(filter) => filter(tcx, def_id),
+    None => true,
+  }
+}
+
+static LOCATION_INSENSITIVE_FILTER: RwLock<Option<BodyFilter>> = RwLock::new(None);
+
+/// Collects only the facts needed by [`Algorithm::LocationInsensitive`] for the bodies
+/// stored by [`override_queries`] for which `filter` returns true.
+///
+/// The location-insensitive approximation is much faster than the full analysis, and
+/// is enough for conservative may-alias results on very large functions. For these
+/// bodies, `loan_killed_at` is left empty, and with
+/// [`ConsumerOptions::PoloniusOutputFacts`] the `output_facts` are computed with
+/// [`Algorithm::LocationInsensitive`]. Running a location-sensitive algorithm on them
+/// with [`compute_output`] treats every loan as live until the end of its region, so
+/// its results are also conservative. Use [`is_location_insensitive`] to check which
+/// mode was used for a body.
+///
+/// This has no effect with [`ConsumerOptions::RegionInferenceContext`], and must be
+/// called before any body is borrow-checked.
+pub fn set_location_insensitive_filter(
+  filter: impl Fn(TyCtxt<'_>, LocalDefId) -> bool + Send + Sync + 'static,
+) {
+  *LOCATION_INSENSITIVE_FILTER.write().unwrap() = Some(Box::new(filter));
+}
+
+/// Removes the filter registered by [`set_location_insensitive_filter`], so all facts
+/// are collected for every body.
+pub fn clear_location_insensitive_filter() {
+  *LOCATION_INSENSITIVE_FILTER.write().unwrap() = None;
+}
+
+fn should_be_location_insensitive(tcx: TyCtxt<'_>, def_id: LocalDefId) -> bool {
+  match &*LOCATION_INSENSITIVE_FILTER.read().unwrap() {
+    Some(filter) => filter(tcx, def_id),
+    None => false,
+  }
+}
+
+/// Returns true if only the location-insensitive facts were collected for `def_id`,
+/// see [`set_location_insensitive_filter`].
+pub fn is_location_insensitive(tcx: TyCtxt<'_>, def_id: LocalDefId) -> bool {
+  stored_body(tcx, def_id).location_insensitive
+}
+
+static SIMPLIFY_FILTER: RwLock<Option<BodyFilter>> = RwLock::new(None);
+
+/// Applies [`simplify_mir`] to every body stored by [`override_queries`].
+pub fn enable_mir_simplification() {
+  set_simplification_filter(|_, _| true);
+}
+
+/// Applies [`simplify_mir`] only to the bodies stored by [`override_queries`] for which
+/// `filter` returns true.
+///
+/// For example, a plugin can simplify huge generated functions while keeping the full
+/// MIR for the functions it reports on.
+pub fn set_simplification_filter(
+  filter: impl Fn(TyCtxt<'_>, LocalDefId) -> bool + Send + Sync + 'static,
+) {
+  *SIMPLIFY_FILTER.write().unwrap() = Some(Box::new(filter));
+}
+
+/// Stops simplifying stored bodies, undoing [`enable_mir_simplification`] and
+/// [`set_simplification_filter`].
+pub fn disable_mir_simplification() {
+  *SIMPLIFY_FILTER.write().unwrap() = None;
+}
+
+fn should_simplify(tcx: TyCtxt<'_>, def_id: LocalDefId) -> bool {
+  match &*SIMPLIFY_FILTER.read().unwrap() {
+    Some(filter) => filter(tcx, def_id),
+    None => false,
+  }
+}
+
+static BODY_TRANSFORMS: RwLock<Vec<fn(&mut Body<'_>)>> = RwLock::new(Vec::new());
+
+/// Registers a transformation applied to every body (and its promoteds) stored by
+/// [`override_queries`], so that all consumers see the transformed MIR.
+///
+/// Transforms run in registration order, after [`simplify_mir`] if it is enabled. Like
+/// [`simplify_mir`], they run after the borrowck facts were generated, so a transform
+/// that adds or removes statements invalidates the `location_table` and the
+/// locations referenced by the Polonius facts.
+pub fn register_body_transform(transform: fn(&mut Body<'_>)) {
+  BODY_TRANSFORMS.write().unwrap().push(transform);
+}
+
+/// MIR pass to remove instructions not important for Flowistry.
+///
+/// This pass is applied before running the analysis, so it e.g. removes
+/// `StorageLive`, `StorageDead` and other no-ops.
+pub fn simplify_mir(body: &mut Body<'_>) {
+  let return_blocks = body
+    .all_returns()
+    .filter_map(|loc| {
+      let bb = &body.basic_blocks[loc.block];
+      (bb.statements.len() == 0).then_some(loc.block)
+    })
+    .collect::<Vec<_>>();
+
+  for block in body.basic_blocks_mut() {
+    block.statements.retain(|stmt| {
+      !matches!(
+        stmt.kind,
+        StatementKind::StorageLive(..) | StatementKind::StorageDead(..)
+      )
+    });
+
+    let terminator = block.terminator_mut();
+    terminator.kind = match terminator.kind {
+      TerminatorKind::FalseEdge { real_target, .. } => TerminatorKind::Goto {
+        target: real_target,
+      },
+      TerminatorKind::FalseUnwind { real_target, .. } => TerminatorKind::Goto {
+        target: real_target,
+      },
+      // Ensures that control dependencies can determine the independence of different
+      // return paths
+      TerminatorKind::Goto { target } if return_blocks.contains(&target) => {
+        TerminatorKind::Return
+      }
+      _ => continue,
+    }
+  }
+}
+
+/// You must use this function in [`rustc_driver::Callbacks::config`] to call [`get_body_with_borrowck_facts`].
+///
+/// For why we need to do override mir_borrowck, see:
+/// <https://github.com/rust-lang/rust/blob/485ced56b8753ec86936903f2a8c95e9be8996a1/src/test/run-make-fulldeps/obtain-borrowck/driver.rs>
+///
+/// Bodies are stored in a process-wide cache shared by all threads, so under the
+/// parallel compiler (`-Zthreads` greater than 1) a body computed on one thread is not
+/// mistaken for a missing body on another. However a body holds `Rc`s, so it can only
+/// be used on the thread whose `mir_borrowck` call computed it: on other threads,
+/// [`try_get_body_with_borrowck_facts`] returns [`FactsError::OtherThread`] and
+/// [`get_body_with_borrowck_facts`] panics.
+///
+/// The `mir_borrowck` provider already in `local` is wrapped rather than replaced, so
+/// overrides installed before this function are still called after the facts are
+/// stored. See [`override_queries_with`] to combine this with another override.
+pub fn override_queries(session: &rustc_session::Session, local: &mut Providers) {
+  // A new session may be allocated at the address of an old one, so discard any
+  // bodies left over from a previous session.
+  let session_id = SessionId::of(session);
+  store::clear_session(session_id);
+
+  // Overriding twice must not make `mir_borrowck` call itself.
+  let prev = local.mir_borrowck;
+  if prev as usize != mir_borrowck as usize {
+    PREV_MIR_BORROWCK.write().unwrap().insert(session_id, prev);
+  }
+  local.mir_borrowck = mir_borrowck;
+}
+
+/// Runs `prev_override`, then [`override_queries`] on top of the providers it installed.
+///
+/// For example, a plugin with its own query overrides can use:
+///
+/// ```ignore
+/// config.override_queries = Some(|session, local| {
+///   borrowck_facts::override_queries_with(session, local, my_override_queries)
+/// });
+/// ```
+pub fn override_queries_with(
+  session: &rustc_session::Session,
+  local: &mut Providers,
+  prev_override: fn(&rustc_session::Session, &mut Providers),
+) {
+  prev_override(session, local);
+  override_queries(session, local);
+}
+
+type MirBorrowck =
+  for<'tcx> fn(TyCtxt<'tcx>, LocalDefId) -> &'tcx BorrowCheckResult<'tcx>;
+
+/// The `mir_borrowck` provider replaced by [`override_queries`] in each session.
+static PREV_MIR_BORROWCK: LazyLock<RwLock<HashMap<SessionId, MirBorrowck>>> =
+  LazyLock::new(Default::default);
+
+fn mir_borrowck(tcx: TyCtxt<'_>, def_id: LocalDefId) -> &BorrowCheckResult<'_> {
+  if should_store_facts(tcx, def_id) {
+    store_body_with_facts(tcx, def_id);
+  }
+
+  let prev = PREV_MIR_BORROWCK
+    .read()
+    .unwrap()
+    .get(&SessionId::of_tcx(tcx))
+    .copied();
+  let prev = prev.unwrap_or_else(|| {
+    let mut providers = Providers::default();
+    rustc_borrowck::provide(&mut providers);
+    providers.mir_borrowck
+  });
+  prev(tcx, def_id)
+}
+
+fn store_body_with_facts(tcx: TyCtxt<'_>, def_id: LocalDefId) {
+  block_timer!(&format!(
+    "get_body_with_borrowck_facts for {}",
+    tcx.def_path_debug_str(def_id.to_def_id())
+  ));
+
+  let options = consumer_options();
+  let collects_facts = !matches!(options, ConsumerOptions::RegionInferenceContext);
+  let location_insensitive =
+    collects_facts && should_be_location_insensitive(tcx, def_id);
+  let budget = time_budget();
+  let (mut body_with_facts, timed_out) =
+    if collects_facts && (budget.is_some() || location_insensitive) {
+      get_body_with_polonius(tcx, def_id, options, location_insensitive, budget)
+    } else {
+      (
+        rustc_borrowck::consumers::get_body_with_borrowck_facts(tcx, def_id, options),
+        false,
+      )
+    };
+
+  if should_simplify(tcx, def_id) {
+    simplify_mir(&mut body_with_facts.body);
+    for promoted in body_with_facts.promoted.iter_mut() {
+      simplify_mir(promoted);
+    }
+  }
+
+  for transform in BODY_TRANSFORMS.read().unwrap().iter() {
+    transform(&mut body_with_facts.body);
+    for promoted in body_with_facts.promoted.iter_mut() {
+      transform(promoted);
+    }
+  }
+
+  let move_data = COLLECT_MOVE_DATA.load(Ordering::SeqCst).then(|| {
+    let param_env = tcx.param_env(def_id);
+    MoveData::gather_moves(&body_with_facts.body, tcx, param_env, |_| true)
+  });
+
+  MIR_BODIES.insert(
+    (SessionId::of_tcx(tcx), def_id),
+    StoredBody::new(body_with_facts, move_data, timed_out, location_insensitive),
+  );
+}
+
+/// Gets the facts for `def_id`, running Polonius ourselves rather than in rustc so that
+/// it can use the location-insensitive algorithm or be abandoned once `budget` runs
+/// out. Returns true if the facts were dropped because the budget ran out.
+fn get_body_with_polonius(
+  tcx: TyCtxt<'_>,
+  def_id: LocalDefId,
+  options: ConsumerOptions,
+  location_insensitive: bool,
+  budget: Option<Duration>,
+) -> (BodyWithBorrowckFacts<'_>, bool) {
+  let start = Instant::now();
+
+  let mut body_with_facts = rustc_borrowck::consumers::get_body_with_borrowck_facts(
+    tcx,
+    def_id,
+    ConsumerOptions::PoloniusInputFacts,
+  );
+
+  let algorithm = if location_insensitive {
+    // Kills are the only facts that the location-insensitive algorithm ignores.
+    body_with_facts.input_facts.as_mut().unwrap().loan_killed_at = Vec::new();
+    Algorithm::LocationInsensitive
+  } else {
+    Algorithm::Hybrid
+  };
+
+  let mut timed_out = budget.is_some_and(|budget| start.elapsed() > budget);
+  if !timed_out && matches!(options, ConsumerOptions::PoloniusOutputFacts) {
+    let input_facts = body_with_facts.input_facts.as_deref().unwrap();
+    let output = match budget {
+      // The budget may have run out since it was checked above.
+      Some(budget) => budget.checked_sub(start.elapsed()).and_then(|remaining| {
+        output::compute_output_within(input_facts, algorithm, remaining)
+      }),
+      None => Some(PoloniusOutput::compute(input_facts, algorithm, false)),
+    };
+    match output {
+      Some(output) => body_with_facts.output_facts = Some(Box::new(output)),
+      None => timed_out = true,
+    }
+  }
+
+  if timed_out {
+    log::warn!(
+      "Polonius exceeded its time budget of {:?} for {}, storing only the region inference context",
+      budget.unwrap(),
+      tcx.def_path_debug_str(def_id.to_def_id())
+    );
+    body_with_facts.location_table = None;
+    body_with_facts.input_facts = None;
+    body_with_facts.output_facts = None;
+  }
+
+  (body_with_facts, timed_out)
+}
+
+/// Gets the MIR body and [Polonius](https://github.com/rust-lang/polonius)-generated
+/// [borrowck facts](https://doc.rust-lang.org/nightly/nightly-rustc/rustc_borrowck/struct.BodyWithBorrowckFacts.html)
+/// for a given [`LocalDefId`].
+///
+/// For this function to work, you MUST add [`override_queries`] to the
+/// [`rustc_interface::Config`](https://doc.rust-lang.org/nightly/nightly-rustc/rustc_interface/interface/struct.Config.html)
+/// inside of your [`rustc_driver::Callbacks`]. For example, see
+/// [example.rs](https://github.com/willcrichton/flowistry/tree/master/crates/flowistry/examples/example.rs).
+///
+/// Which facts are populated is controlled by [`set_consumer_options`].
+///
+/// Note that as of May 2022, Polonius can be *very* slow for large functions.
+/// It may take up to 30 seconds to analyze a single body with a large CFG.
+#[allow(clippy::needless_lifetimes)]
+pub fn get_body_with_borrowck_facts<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  def_id: LocalDefId,
+) -> &'tcx BodyWithBorrowckFacts<'tcx> {
+  let stored = stored_body(tcx, def_id);
+  // SAFETY: the body was produced by `tcx`, and is kept alive by the store until it is
+  // removed by `release_body` or `clear_all`, whose callers promise not to use this
+  // reference.
+  unsafe { stored.lend() }
+}
+
+/// Gets the MIR body and borrowck facts for `def_id` like
+/// [`get_body_with_borrowck_facts`], but returns an error instead of panicking if the
+/// body is not available.
+#[allow(clippy::needless_lifetimes)]
+pub fn try_get_body_with_borrowck_facts<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  def_id: DefId,
+) -> Result<&'tcx BodyWithBorrowckFacts<'tcx>, FactsError> {
+  let def_id = def_id.as_local().ok_or(FactsError::NotLocal(def_id))?;
+  let stored = try_stored_body(tcx, def_id)?;
+  // SAFETY: see `get_body_with_borrowck_facts`.
+  Ok(unsafe { stored.lend() })
+}
+
+fn try_stored_body(
+  tcx: TyCtxt<'_>,
+  def_id: LocalDefId,
+) -> Result<Arc<StoredBody>, FactsError> {
+  let session = SessionId::of_tcx(tcx);
+  if !PREV_MIR_BORROWCK.read().unwrap().contains_key(&session) {
+    return Err(FactsError::OverrideNotInstalled);
+  }
+  if tcx.hir().maybe_body_owned_by(def_id).is_none() {
+    return Err(FactsError::NoBody(def_id));
+  }
+  if tcx.typeck(def_id).tainted_by_errors.is_some() {
+    return Err(FactsError::TaintedByErrors(def_id));
+  }
+
+  let _ = tcx.mir_borrowck(def_id);
+  let stored = MIR_BODIES.get(&(session, def_id)).ok_or_else(|| {
+    if should_store_facts(tcx, def_id) {
+      FactsError::Removed(def_id)
+    } else {
+      FactsError::Filtered(def_id)
+    }
+  })?;
+  if !stored.is_on_current_thread() {
+    return Err(FactsError::OtherThread(def_id));
+  }
+  Ok(stored)
+}
+
+fn stored_body(tcx: TyCtxt<'_>, def_id: LocalDefId) -> Arc<StoredBody> {
+  try_stored_body(tcx, def_id).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Gets the [`MoveData`] for the body of `def_id`, or `None` if [`enable_move_data`]
+/// was not called before the body was stored.
+///
+/// The same requirements as [`get_body_with_borrowck_facts`] apply.
+#[allow(clippy::needless_lifetimes)]
+pub fn get_move_data<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  def_id: LocalDefId,
+) -> Option<&'tcx MoveData<'tcx>> {
+  let stored = stored_body(tcx, def_id);
+  // SAFETY: see `get_body_with_borrowck_facts`.
+  unsafe { stored.lend_move_data() }
+}
+
+/// Returns the MIR body and borrowck facts for every local body in the crate,
+/// computing them if necessary.
+///
+/// This includes nested bodies such as closures, async blocks and anonymous constants,
+/// but skips bodies excluded by [`set_filter`]. The same requirements as
+/// [`get_body_with_borrowck_facts`] apply.
+#[allow(clippy::needless_lifetimes)]
+pub fn bodies_with_facts<'tcx>(
+  tcx: TyCtxt<'tcx>,
+) -> impl Iterator<Item = (LocalDefId, &'tcx BodyWithBorrowckFacts<'tcx>)> {
+  tcx
+    .hir()
+    .body_owners()
+    .filter(move |def_id| should_store_facts(tcx, *def_id))
+    .map(move |def_id| (def_id, get_body_with_borrowck_facts(tcx, def_id)))
+}
+
+/// A promoted constant of a body, along with the borrowck facts of its parent.
+///
+/// Promoteds are borrow-checked together with their parent, so they share its region
+/// inference context and Polonius facts. Borrows inside a promoted are not part of the
+/// parent's [`BorrowSet`](rustc_borrowck::borrow_set::BorrowSet), so they can be
+/// enumerated with [`PromotedWithFacts::borrows`] instead.
+pub struct PromotedWithFacts<'a, 'tcx> {
+  pub promoted: Promoted,
+  pub body: &'a Body<'tcx>,
+  pub parent: &'a BodyWithBorrowckFacts<'tcx>,
+}
+
+impl<'tcx> PromotedWithFacts<'_, 'tcx> {
+  /// Returns every borrow in the promoted body, as its location, the borrowed place,
+  /// and the region of the resulting reference.
+  pub fn borrows(&self) -> Vec<(Location, Place<'tcx>, Region<'tcx>)> {
+    self
+      .body
+      .all_locations()
+      .filter_map(|location| match self.body.stmt_at(location) {
+        Either::Left(Statement {
+          kind: StatementKind::Assign(box (_, Rvalue::Ref(region, _, place))),
+          ..
+        }) => Some((location, *place, *region)),
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+/// Gets the promoted constant `promoted` of the body for `def_id`, along with the
+/// borrowck facts of that body.
+///
+/// The same requirements as [`get_body_with_borrowck_facts`] apply.
+///
+/// # Panics
+///
+/// If the body has no promoted with index `promoted`.
+#[allow(clippy::needless_lifetimes)]
+pub fn get_promoted_with_facts<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  def_id: LocalDefId,
+  promoted: Promoted,
+) -> PromotedWithFacts<'tcx, 'tcx> {
+  let parent = get_body_with_borrowck_facts(tcx, def_id);
+  let body = parent
+    .promoted
+    .get(promoted)
+    .unwrap_or_else(|| panic!("item {def_id:?} has no promoted {promoted:?}"));
+  PromotedWithFacts {
+    promoted,
+    body,
+    parent,
+  }
+}
+
+/// Gets the MIR body and borrowck facts for a given [`LocalDefId`], transferring
+/// ownership of the body to the caller rather than keeping it in the cache.
+///
+/// This is useful for analyses that consume each body exactly once, since the body's
+/// memory is freed as soon as the caller drops it. Because `mir_borrowck` is memoized
+/// by rustc, the body can only be taken once: a later call to this function or to
+/// [`get_body_with_borrowck_facts`] for the same `def_id` will panic.
+///
+/// # Panics
+///
+/// If the body was already taken, if a reference to it was previously handed out by
+/// [`get_body_with_borrowck_facts`], or if a [`BodyFactsHandle`] to it is alive.
+#[allow(clippy::needless_lifetimes)]
+pub fn take_body_with_borrowck_facts<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  def_id: LocalDefId,
+) -> BodyWithBorrowckFacts<'tcx> {
+  let stored = stored_body(tcx, def_id);
+  assert!(
+    !stored.is_lent(),
+    "body for item {def_id:?} is borrowed by get_body_with_borrowck_facts and cannot be taken"
+  );
+  drop(stored);
+
+  let session = SessionId::of_tcx(tcx);
+  let stored = MIR_BODIES.remove(&(session, def_id)).unwrap();
+  store::remove_def_id(session, def_id);
+  let stored = Arc::into_inner(stored).unwrap_or_else(|| {
+    panic!("body for item {def_id:?} is held by a BodyFactsHandle and cannot be taken")
+  });
+  // SAFETY: the body was produced by `tcx`.
+  unsafe { stored.into_body() }
+}
+
+/// Drops the cached body for `def_id`, returning true if a body was stored.
+///
+/// # Safety
+///
+/// Any reference previously returned by [`get_body_with_borrowck_facts`] for `def_id`
+/// becomes dangling, and must not be used after this call. A [`BodyFactsHandle`]
+/// remains valid, and the body is freed once the last handle is dropped.
+pub unsafe fn release_body(tcx: TyCtxt<'_>, def_id: LocalDefId) -> bool {
+  store::remove_def_id(SessionId::of_tcx(tcx), def_id)
+}
+
+/// Drops every cached body, across all compiler sessions.
+///
+/// # Safety
+///
+/// All references previously returned by [`get_body_with_borrowck_facts`] become
+/// dangling, and must not be used after this call. [`BodyFactsHandle`]s remain valid.
+pub unsafe fn clear_all() {
+  store::clear();
+}
+
+#[cfg(test)]
+mod test {
+  use std::sync::atomic::AtomicUsize;
+
+  use rustc_hir::{def::DefKind, ItemKind};
+  use rustc_middle::mir::{Local, START_BLOCK};
+  use rustc_span::Symbol;
+
+  use super::*;
+  use crate::test_utils::{CompileBuilder, CompileResult};
+
+  #[test]
+  fn test_take_and_release_body() {
+    let input = r#"
+fn foo() -> i32 { 1 }
+fn bar() -> i32 { 2 }
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let hir = tcx.hir();
+      let def_ids = hir
+        .items()
+        .filter(|id| matches!(hir.item(*id).kind, ItemKind::Fn(..)))
+        .map(|id| id.owner_id.def_id)
+        .collect::<Vec<_>>();
+      let (foo, bar) = (def_ids[0], def_ids[1]);
+
+      let body = take_body_with_borrowck_facts(tcx, foo);
+      assert!(body.input_facts.is_some());
+      assert!(!unsafe { release_body(tcx, foo) });
+
+      let _ = get_body_with_borrowck_facts(tcx, bar);
+      assert!(unsafe { release_body(tcx, bar) });
+    });
+  }
+
+  #[test]
+  fn test_body_on_other_thread() {
+    let input = r#"
+fn foo() -> i32 { 1 }
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let body = take_body_with_borrowck_facts(tcx, def_id);
+      let stored = Arc::new(StoredBody::new(body, None, false, false));
+      assert!(stored.is_on_current_thread());
+
+      // Releasing a reference on another thread leaves the body usable on its own.
+      let other = Arc::clone(&stored);
+      std::thread::scope(|s| {
+        s.spawn(move || {
+          assert!(!other.is_on_current_thread());
+          drop(other);
+        });
+      });
+      assert_eq!(unsafe { stored.borrow() }.body.arg_count, 0);
+    });
+  }
+
+  #[test]
+  fn test_time_budget() {
+    // The budget is global, so exercise the budgeted path directly instead of
+    // through `set_time_budget`.
+    let input = r#"
+fn within_budget(x: &mut i32) -> &i32 { x }
+fn over_budget(x: &mut i32) -> &i32 { x }
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let hir = tcx.hir();
+      for id in hir.items() {
+        if !matches!(hir.item(id).kind, ItemKind::Fn(..)) {
+          continue;
+        }
+        let def_id = id.owner_id.def_id;
+        let options = ConsumerOptions::PoloniusOutputFacts;
+        match tcx.item_name(def_id.to_def_id()).as_str() {
+          "within_budget" => {
+            let (body, timed_out) = get_body_with_polonius(
+              tcx,
+              def_id,
+              options,
+              false,
+              Some(Duration::from_secs(600)),
+            );
+            assert!(!timed_out);
+            assert!(body.input_facts.is_some() && body.output_facts.is_some());
+          }
+          "over_budget" => {
+            let (body, timed_out) =
+              get_body_with_polonius(tcx, def_id, options, false, Some(Duration::ZERO));
+            assert!(timed_out);
+            assert!(body.location_table.is_none() && body.input_facts.is_none());
+            assert!(body.output_facts.is_none());
+          }
+          _ => {}
+        }
+      }
+    });
+  }
+
+  #[test]
+  fn test_location_insensitive_filter() {
+    let input = r#"
+fn location_insensitive_body() {
+  let (mut a, mut b) = (1, 2);
+  let mut p = &mut a;
+  let q = &mut *p;
+  *q += 1;
+  p = &mut b;
+  *p += 1;
+}
+fn location_sensitive_body() {
+  let (mut a, mut b) = (1, 2);
+  let mut p = &mut a;
+  let q = &mut *p;
+  *q += 1;
+  p = &mut b;
+  *p += 1;
+}
+"#;
+    set_location_insensitive_filter(|tcx, def_id| {
+      tcx
+        .opt_item_name(def_id.to_def_id())
+        .is_some_and(|name| name.as_str() == "location_insensitive_body")
+    });
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      for def_id in tcx.hir().body_owners() {
+        let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+        let kills = &body_with_facts.input_facts.as_ref().unwrap().loan_killed_at;
+        let insensitive = is_location_insensitive(tcx, def_id);
+        match tcx.item_name(def_id.to_def_id()).as_str() {
+          "location_insensitive_body" => assert!(insensitive && kills.is_empty()),
+          "location_sensitive_body" => assert!(!insensitive && !kills.is_empty()),
+          _ => unreachable!(),
+        }
+      }
+    });
+  }
+
+  #[test]
+  fn test_promoted_with_facts() {
+    let input = r#"
+fn promotes() -> &'static i32 { &(1 + 2) }
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let hir = tcx.hir();
+      let def_id = hir
+        .items()
+        .find(|id| matches!(hir.item(*id).kind, ItemKind::Fn(..)))
+        .unwrap()
+        .owner_id
+        .def_id;
+      let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+      assert_eq!(body_with_facts.promoted.len(), 1);
+
+      let promoted = get_promoted_with_facts(tcx, def_id, Promoted::from_usize(0));
+      assert!(std::ptr::eq(promoted.parent, body_with_facts));
+      let borrows = promoted.borrows();
+      assert_eq!(borrows.len(), 1);
+      assert!(borrows[0].2.is_var());
+    });
+  }
+
+  #[test]
+  fn test_bodies_with_facts() {
+    let input = r#"
+fn outer() {
+  let f = |x: i32| x + 1;
+  f(0);
+}
+async fn asynchronous() {}
+const N: usize = 1;
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let bodies = bodies_with_facts(tcx).collect::<Vec<_>>();
+      let kinds = bodies
+        .iter()
+        .map(|(def_id, _)| tcx.def_kind(*def_id))
+        .collect::<Vec<_>>();
+      assert!(kinds.contains(&DefKind::Fn));
+      assert!(kinds.contains(&DefKind::Const));
+      // The closure in `outer` and the coroutine of `asynchronous`.
+      assert_eq!(kinds.iter().filter(|k| **k == DefKind::Closure).count(), 2);
+
+      for (def_id, body_with_facts) in bodies {
+        assert_eq!(body_with_facts.body.source.def_id(), def_id.to_def_id());
+      }
+    });
+  }
+
+  #[test]
+  fn test_body_transform() {
+    // Transforms are global, so only modify bodies that no other test defines.
+    fn mark_body(body: &mut Body<'_>) {
+      let marked = body
+        .var_debug_info
+        .iter()
+        .any(|info| info.name.as_str() == "transform_marker");
+      if marked {
+        let bb0 = &mut body.basic_blocks_mut()[START_BLOCK];
+        let source_info = bb0.terminator().source_info;
+        bb0.statements.push(Statement {
+          source_info,
+          kind: StatementKind::Nop,
+        });
+      }
+    }
+    register_body_transform(mark_body);
+
+    let input = r#"
+fn transformed() { let transform_marker = 0; }
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let body = &get_body_with_borrowck_facts(tcx, def_id).body;
+      let last = body.basic_blocks[START_BLOCK].statements.last().unwrap();
+      assert!(matches!(last.kind, StatementKind::Nop));
+    });
+  }
+
+  #[test]
+  fn test_move_data() {
+    // Collecting move data is additive, so enabling it does not affect other tests.
+    enable_move_data();
+    let input = r#"
+fn moves(s: String) -> String { let t = s; t }
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+      let body = &get_body_with_borrowck_facts(tcx, def_id).body;
+      let move_data = get_move_data(tcx, def_id).unwrap();
+      let moved_locals = move_data
+        .moves
+        .iter()
+        .filter_map(|mo| move_data.move_paths[mo.path].place.as_local())
+        .collect::<Vec<_>>();
+      assert!(moved_locals.contains(&Local::from_usize(1)));
+      assert!(moved_locals.len() >= 2 && moved_locals.len() <= body.local_decls.len());
+    });
+  }
+
+  #[test]
+  fn test_override_queries_with() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn counting_mir_borrowck(
+      tcx: TyCtxt<'_>,
+      def_id: LocalDefId,
+    ) -> &BorrowCheckResult<'_> {
+      CALLS.fetch_add(1, Ordering::SeqCst);
+      let mut providers = Providers::default();
+      rustc_borrowck::provide(&mut providers);
+      (providers.mir_borrowck)(tcx, def_id)
+    }
+    fn counting_override(_session: &rustc_session::Session, local: &mut Providers) {
+      local.mir_borrowck = counting_mir_borrowck;
+    }
+
+    let input = r#"
+fn chained() {}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let def_id = tcx.hir().body_owners().next().unwrap();
+
+      // Install the chained providers for this session and call them directly, since
+      // the session's real providers were already fixed by `CompileBuilder`.
+      let mut providers = Providers::default();
+      override_queries_with(tcx.sess, &mut providers, counting_override);
+      override_queries(tcx.sess, &mut providers);
+      let _ = (providers.mir_borrowck)(tcx, def_id);
+
+      assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+      assert!(MIR_BODIES.get(&(SessionId::of_tcx(tcx), def_id)).is_some());
+    });
+  }
+
+  #[test]
+  fn test_try_get_body_with_borrowck_facts() {
+    let input = r#"
+struct NoBody;
+fn has_body() {}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let hir = tcx.hir();
+      for id in hir.items() {
+        let def_id = id.owner_id.def_id;
+        match hir.item(id).kind {
+          ItemKind::Fn(..) => {
+            assert!(try_get_body_with_borrowck_facts(tcx, def_id.to_def_id()).is_ok())
+          }
+          ItemKind::Struct(..) => assert_eq!(
+            try_get_body_with_borrowck_facts(tcx, def_id.to_def_id()).err(),
+            Some(FactsError::NoBody(def_id))
+          ),
+          _ => {}
+        }
+      }
+
+      let extern_def_id = tcx.lang_items().clone_trait().unwrap();
+      assert_eq!(
+        try_get_body_with_borrowck_facts(tcx, extern_def_id).err(),
+        Some(FactsError::NotLocal(extern_def_id))
+      );
+    });
+  }
+
+  #[test]
+  fn test_filter() {
+    // The filter is global, so only exclude an item no other test defines.
+    set_filter(|tcx, def_id| {
+      tcx.opt_item_name(def_id.to_def_id()) != Some(Symbol::intern("skip_facts"))
+    });
+    let input = r#"
+fn skip_facts() {}
+fn keep_facts() {}
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let hir = tcx.hir();
+      for id in hir.items() {
+        if !matches!(hir.item(id).kind, ItemKind::Fn(..)) {
+          continue;
+        }
+        let def_id = id.owner_id.def_id;
+        let _ = tcx.mir_borrowck(def_id);
+        let stored = MIR_BODIES.get(&(SessionId::of_tcx(tcx), def_id)).is_some();
+        match tcx.item_name(def_id.to_def_id()).as_str() {
+          "skip_facts" => assert!(!stored),
+          "keep_facts" => assert!(stored),
+          _ => {}
+        }
+      }
+    });
+  }
+}
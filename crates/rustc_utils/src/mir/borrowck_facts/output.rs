@@ -0,0 +1,177 @@
+//! Running the Polonius engine on collected input facts.
+
+use std::{
+  sync::{
+    atomic::{AtomicU8, AtomicUsize, Ordering},
+    mpsc, Arc,
+  },
+  thread,
+  time::Duration,
+};
+
+pub use polonius_engine::Algorithm;
+use rustc_borrowck::consumers::{PoloniusInput, PoloniusOutput};
+use rustc_hir::def_id::LocalDefId;
+use rustc_middle::ty::TyCtxt;
+
+use super::{
+  disk_cache, get_body_with_borrowck_facts, is_location_insensitive,
+  store::{OutputKey, SessionId, POLONIUS_OUTPUTS},
+};
+use crate::block_timer;
+
+/// Runs Polonius with the given [`Algorithm`] on the input facts for `def_id`.
+///
+/// The returned [`PoloniusOutput`] contains, among others, the loans live at each
+/// point (`loan_live_at`), borrowck errors (`errors`), and illegal subset relations
+/// between placeholder regions (`subset_errors`). Note that the location-insensitive
+/// algorithm only computes `errors`. Outputs are cached per body and
+/// algorithm, so repeated calls are cheap, and can be persisted across runs with
+/// [`enable_disk_cache`](super::enable_disk_cache).
+///
+/// # Panics
+///
+/// If input facts were not collected for the body, e.g. because of
+/// [`set_consumer_options`](super::set_consumer_options).
+#[allow(clippy::needless_lifetimes)]
+pub fn compute_output<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  def_id: LocalDefId,
+  algorithm: Algorithm,
+) -> &'tcx PoloniusOutput {
+  let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+  let location_insensitive = is_location_insensitive(tcx, def_id);
+  let key = OutputKey {
+    session: SessionId::of_tcx(tcx),
+    def_id,
+    algorithm: std::mem::discriminant(&algorithm),
+    location_insensitive,
+  };
+
+  let output = POLONIUS_OUTPUTS.get(&key).unwrap_or_else(|| {
+    let input_facts = body_with_facts
+      .input_facts
+      .as_ref()
+      .unwrap_or_else(|| panic!("input facts were not collected for item: {def_id:?}"));
+
+    let output = disk_cache::load(input_facts, algorithm, location_insensitive)
+      .unwrap_or_else(|| {
+        block_timer!(&format!(
+          "Polonius ({algorithm:?}) for {}",
+          tcx.def_path_debug_str(def_id.to_def_id())
+        ));
+        // Polonius only records intermediate relations like `loan_live_at` when
+        // dumping is enabled, and those are the relations most useful to analyses.
+        let output = PoloniusOutput::compute(input_facts, algorithm, true);
+        disk_cache::save(input_facts, algorithm, location_insensitive, &output);
+        output
+      });
+    POLONIUS_OUTPUTS.insert(key, output)
+  });
+
+  // SAFETY: outputs are kept alive by the store until they are removed by
+  // `release_body` or `clear_all`, whose callers promise not to use references into
+  // the removed data.
+  unsafe { &*Arc::as_ptr(&output) }
+}
+
+/// The maximum number of Polonius threads abandoned by [`compute_output_within`] that
+/// may still be running. Further bodies are considered over budget right away.
+pub(super) const MAX_ABANDONED_THREADS: usize = 4;
+
+/// The number of abandoned Polonius threads that are still running.
+static ABANDONED_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+const RUNNING: u8 = 0;
+const FINISHED: u8 = 1;
+const ABANDONED: u8 = 2;
+
+/// Runs Polonius with `algorithm` the way rustc does for
+/// [`ConsumerOptions::PoloniusOutputFacts`], giving up if it does not finish within
+/// `budget`.
+///
+/// Polonius cannot be cancelled, so on timeout its thread is left to run to completion
+/// in the background and its result is discarded. Returns `None` without running
+/// Polonius if [`MAX_ABANDONED_THREADS`] abandoned threads are still running.
+///
+/// [`ConsumerOptions::PoloniusOutputFacts`]: rustc_borrowck::consumers::ConsumerOptions::PoloniusOutputFacts
+pub(super) fn compute_output_within(
+  input_facts: &PoloniusInput,
+  algorithm: Algorithm,
+  budget: Duration,
+) -> Option<PoloniusOutput> {
+  let abandoned = ABANDONED_THREADS.load(Ordering::SeqCst);
+  if abandoned >= MAX_ABANDONED_THREADS {
+    log::warn!(
+      "Skipping Polonius, since {abandoned} threads abandoned for exceeding the time budget are still running"
+    );
+    return None;
+  }
+
+  let input_facts = input_facts.clone();
+  let state = Arc::new(AtomicU8::new(RUNNING));
+  let (tx, rx) = mpsc::channel();
+  thread::spawn({
+    let state = Arc::clone(&state);
+    move || {
+      let output = PoloniusOutput::compute(&input_facts, algorithm, false);
+      let _ = tx.send(output);
+      if state.swap(FINISHED, Ordering::SeqCst) == ABANDONED {
+        ABANDONED_THREADS.fetch_sub(1, Ordering::SeqCst);
+      }
+    }
+  });
+
+  match rx.recv_timeout(budget) {
+    Ok(output) => Some(output),
+    Err(_) => {
+      // Counted before it is marked as abandoned, so the thread never decrements the
+      // count before it is incremented.
+      let abandoned = ABANDONED_THREADS.fetch_add(1, Ordering::SeqCst) + 1;
+      let marked = state
+        .compare_exchange(RUNNING, ABANDONED, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok();
+      if marked {
+        log::warn!(
+          "Abandoned a Polonius thread that exceeded the time budget, {abandoned} are still running"
+        );
+        None
+      } else {
+        // The thread finished just after the budget ran out.
+        ABANDONED_THREADS.fetch_sub(1, Ordering::SeqCst);
+        rx.try_recv().ok()
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test_utils;
+
+  #[test]
+  fn test_compute_output() {
+    let input = r#"
+fn main() {
+  let mut x = 1;
+  let y = &mut x;
+  *y += 1;
+  let z = &x;
+}
+"#;
+    test_utils::compile_body(input, |tcx, body_id, _| {
+      let def_id = tcx.hir().body_owner_def_id(body_id);
+      let naive = compute_output(tcx, def_id, Algorithm::Naive);
+      assert!(naive.errors.is_empty());
+      assert!(!naive.loan_live_at.is_empty());
+
+      let naive_again = compute_output(tcx, def_id, Algorithm::Naive);
+      assert!(std::ptr::eq(naive, naive_again));
+
+      let opt = compute_output(tcx, def_id, Algorithm::DatafrogOpt);
+      assert!(!std::ptr::eq(naive, opt));
+      assert_eq!(naive.loan_live_at, opt.loan_live_at);
+    });
+  }
+}
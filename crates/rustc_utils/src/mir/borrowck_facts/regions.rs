@@ -0,0 +1,228 @@
+//! Region-level views of [`BodyWithBorrowckFacts`].
+
+use std::collections::VecDeque;
+
+use rustc_borrowck::consumers::{
+  BodyWithBorrowckFacts, BorrowIndex, OutlivesConstraint, RegionInferenceContext,
+};
+use rustc_data_structures::{captures::Captures, fx::FxHashSet as HashSet};
+use rustc_index::IndexVec;
+use rustc_middle::ty::RegionVid;
+
+use super::loans::LoanInfo;
+
+/// Extension trait for [`BodyWithBorrowckFacts`].
+pub trait BodyFactsExt<'tcx> {
+  /// Returns the region inference context computed by the borrow checker.
+  fn region_inference_context(&self) -> &RegionInferenceContext<'tcx>;
+
+  /// Returns the universal regions of the body, i.e. `'static`, the lifetime parameters
+  /// of the function, and the region of the function body.
+  ///
+  /// Rustc does not expose the universal regions directly, so they are read from the
+  /// Polonius input facts. Returns `None` if input facts were not collected.
+  fn universal_regions(&self) -> Option<Vec<RegionVid>>;
+
+  /// Builds the graph of outlives constraints generated by the borrow checker.
+  fn outlives_graph(&self) -> OutlivesGraph<'tcx>;
+
+  /// Describes the loan `index`, i.e. a [`BorrowIndex`] or Polonius `Loan`.
+  ///
+  /// Returns `None` if the statement of the loan was removed from the stored body by
+  /// [`simplify_mir`](super::simplify_mir) or a
+  /// [body transform](super::register_body_transform).
+  fn loan_info(&self, index: BorrowIndex) -> Option<LoanInfo<'_, 'tcx>>;
+
+  type LoansIter<'a>: Iterator<Item = LoanInfo<'a, 'tcx>>
+  where
+    Self: 'a,
+    'tcx: 'a;
+
+  /// Returns every loan in the body, in order of their indices, skipping the loans for
+  /// which [`loan_info`](BodyFactsExt::loan_info) returns `None`.
+  fn loans(&self) -> Self::LoansIter<'_>;
+}
+
+impl<'tcx> BodyFactsExt<'tcx> for BodyWithBorrowckFacts<'tcx> {
+  fn region_inference_context(&self) -> &RegionInferenceContext<'tcx> {
+    &self.region_inference_context
+  }
+
+  fn universal_regions(&self) -> Option<Vec<RegionVid>> {
+    let input_facts = self.input_facts.as_ref()?;
+    Some(
+      input_facts
+        .universal_region
+        .iter()
+        .map(|region| RegionVid::from(*region))
+        .collect(),
+    )
+  }
+
+  fn outlives_graph(&self) -> OutlivesGraph<'tcx> {
+    OutlivesGraph::new(&self.region_inference_context)
+  }
+
+  fn loan_info(&self, index: BorrowIndex) -> Option<LoanInfo<'_, 'tcx>> {
+    LoanInfo::new(self, index)
+  }
+
+  type LoansIter<'a>
+    = impl Iterator<Item = LoanInfo<'a, 'tcx>>
+  where
+    Self: 'a,
+    'tcx: 'a;
+  fn loans(&self) -> Self::LoansIter<'_> {
+    (0 .. self.borrow_set.len())
+      .filter_map(|i| LoanInfo::new(self, BorrowIndex::from_usize(i)))
+  }
+}
+
+/// Graph of outlives constraints, with an edge from `sup` to `sub` for every
+/// constraint `sup: sub`.
+pub struct OutlivesGraph<'tcx> {
+  constraints: Vec<OutlivesConstraint<'tcx>>,
+  successors: IndexVec<RegionVid, Vec<usize>>,
+}
+
+impl<'tcx> OutlivesGraph<'tcx> {
+  pub fn new(regioncx: &RegionInferenceContext<'tcx>) -> Self {
+    let constraints = regioncx.outlives_constraints().collect::<Vec<_>>();
+    let mut successors = IndexVec::from_elem_n(Vec::new(), regioncx.regions().count());
+    for (i, constraint) in constraints.iter().enumerate() {
+      successors[constraint.sup].push(i);
+    }
+    OutlivesGraph {
+      constraints,
+      successors,
+    }
+  }
+
+  /// Returns every outlives constraint in the graph.
+  pub fn constraints(&self) -> &[OutlivesConstraint<'tcx>] {
+    &self.constraints
+  }
+
+  /// Returns the constraints of the form `region: sub`.
+  pub fn outgoing(
+    &self,
+    region: RegionVid,
+  ) -> impl Iterator<Item = &OutlivesConstraint<'tcx>> + '_ {
+    self.successors[region]
+      .iter()
+      .map(|i| &self.constraints[*i])
+  }
+
+  /// Returns the regions that `region` directly outlives.
+  pub fn successors(
+    &self,
+    region: RegionVid,
+  ) -> impl Iterator<Item = RegionVid> + Captures<'tcx> + '_ {
+    self.outgoing(region).map(|constraint| constraint.sub)
+  }
+
+  /// Returns every region that `region` transitively outlives, including itself.
+  pub fn reachable(&self, region: RegionVid) -> HashSet<RegionVid> {
+    let mut visited = HashSet::default();
+    let mut queue = vec![region];
+    while let Some(region) = queue.pop() {
+      if visited.insert(region) {
+        queue.extend(self.successors(region));
+      }
+    }
+    visited
+  }
+
+  /// Returns true if the constraints imply `sup: sub`.
+  pub fn outlives(&self, sup: RegionVid, sub: RegionVid) -> bool {
+    self.path(sup, sub).is_some()
+  }
+
+  /// Returns a shortest chain of constraints proving `sup: sub`, or `None` if there is
+  /// none. The chain is empty if `sup == sub`.
+  pub fn path(
+    &self,
+    sup: RegionVid,
+    sub: RegionVid,
+  ) -> Option<Vec<&OutlivesConstraint<'tcx>>> {
+    let mut parent =
+      IndexVec::<RegionVid, Option<usize>>::from_elem_n(None, self.successors.len());
+    let mut queue = VecDeque::from([sup]);
+    while let Some(region) = queue.pop_front() {
+      if region == sub {
+        let mut path = Vec::new();
+        let mut region = sub;
+        while let Some(i) = parent[region] {
+          path.push(&self.constraints[i]);
+          region = self.constraints[i].sup;
+        }
+        path.reverse();
+        return Some(path);
+      }
+
+      for i in &self.successors[region] {
+        let next = self.constraints[*i].sub;
+        if next != sup && parent[next].is_none() {
+          parent[next] = Some(*i);
+          queue.push_back(next);
+        }
+      }
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_hir::ItemKind;
+
+  use super::*;
+  use crate::{
+    mir::borrowck_facts::get_body_with_borrowck_facts,
+    test_utils::{CompileBuilder, CompileResult},
+  };
+
+  #[test]
+  fn test_outlives_graph() {
+    let input = r#"
+fn shorten<'a, 'b: 'a>(x: &'b i32) -> &'a i32 { x }
+"#;
+    CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let hir = tcx.hir();
+      let def_id = hir
+        .items()
+        .find(|id| matches!(hir.item(*id).kind, ItemKind::Fn(..)))
+        .unwrap()
+        .owner_id
+        .def_id;
+      let body_with_facts = get_body_with_borrowck_facts(tcx, def_id);
+      let regioncx = body_with_facts.region_inference_context();
+
+      let sig = tcx.fn_sig(def_id).instantiate_identity().skip_binder();
+      let b = regioncx.to_region_vid(
+        sig.inputs()[0]
+          .walk()
+          .find_map(|arg| arg.as_region())
+          .unwrap(),
+      );
+      let a = regioncx
+        .to_region_vid(sig.output().walk().find_map(|arg| arg.as_region()).unwrap());
+
+      let universal = body_with_facts.universal_regions().unwrap();
+      assert!(universal.contains(&a) && universal.contains(&b));
+
+      let graph = body_with_facts.outlives_graph();
+      assert_eq!(
+        graph.constraints().len(),
+        regioncx.outlives_constraints().count()
+      );
+      assert!(graph.outlives(b, a));
+      assert!(graph.reachable(b).contains(&a));
+
+      let path = graph.path(b, a).unwrap();
+      assert_eq!(path.first().unwrap().sup, b);
+      assert_eq!(path.last().unwrap().sub, a);
+      assert!(path.windows(2).all(|w| w[0].sub == w[1].sup));
+    });
+  }
+}
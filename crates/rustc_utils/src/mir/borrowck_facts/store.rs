@@ -0,0 +1,360 @@
+//! Process-wide storage for bodies produced by the `mir_borrowck` override.
+//!
+//! The store can be shared between threads, so a body stored by one thread of the
+//! parallel compiler is found by every other thread. The bodies themselves hold `Rc`s,
+//! so each one can still only be used on the thread that stored it.
+
+use std::{
+  hash::{Hash, Hasher},
+  mem::ManuallyDrop,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, LazyLock, RwLock,
+  },
+  thread::{self, ThreadId},
+};
+
+use rustc_borrowck::consumers::{BodyWithBorrowckFacts, PoloniusOutput};
+use rustc_data_structures::fx::{FxHashMap as HashMap, FxHasher};
+use rustc_hir::def_id::LocalDefId;
+use rustc_middle::ty::TyCtxt;
+use rustc_mir_dataflow::move_paths::MoveData;
+use rustc_session::Session;
+
+/// Identifies the compiler session that a body belongs to.
+///
+/// Several sessions may be alive in one process (e.g. when tests are run in parallel),
+/// so a [`LocalDefId`] alone is not a unique key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct SessionId(usize);
+
+impl SessionId {
+  pub fn of(sess: &Session) -> Self {
+    SessionId(sess as *const Session as usize)
+  }
+
+  pub fn of_tcx(tcx: TyCtxt<'_>) -> Self {
+    SessionId::of(tcx.sess)
+  }
+}
+
+/// Keys of a [`ShardedMap`] which belong to a compiler session.
+pub(super) trait SessionKey: Hash + Eq {
+  fn session(&self) -> SessionId;
+  fn def_id(&self) -> LocalDefId;
+}
+
+impl SessionKey for (SessionId, LocalDefId) {
+  fn session(&self) -> SessionId {
+    self.0
+  }
+
+  fn def_id(&self) -> LocalDefId {
+    self.1
+  }
+}
+
+const NUM_SHARDS: usize = 32;
+
+/// A hash map split into shards that are each guarded by its own [`RwLock`], so
+/// threads of the parallel compiler accessing different keys rarely contend.
+///
+/// Keys are assigned to shards by their session and item only, so all the values for
+/// one item are in the same shard and can be removed together.
+///
+/// Values are reference-counted, so references to them remain valid at least until
+/// they are removed.
+pub(super) struct ShardedMap<K, V> {
+  shards: [RwLock<HashMap<K, Arc<V>>>; NUM_SHARDS],
+}
+
+impl<K: SessionKey, V> ShardedMap<K, V> {
+  pub fn new() -> Self {
+    ShardedMap {
+      shards: std::array::from_fn(|_| RwLock::new(HashMap::default())),
+    }
+  }
+
+  fn shard(&self, key: &K) -> &RwLock<HashMap<K, Arc<V>>> {
+    self.item_shard(key.session(), key.def_id())
+  }
+
+  fn item_shard(
+    &self,
+    session: SessionId,
+    def_id: LocalDefId,
+  ) -> &RwLock<HashMap<K, Arc<V>>> {
+    let mut hasher = FxHasher::default();
+    (session, def_id).hash(&mut hasher);
+    &self.shards[hasher.finish() as usize % NUM_SHARDS]
+  }
+
+  /// Stores `value` unless a value is already stored for `key`, and returns the
+  /// stored value.
+  pub fn insert(&self, key: K, value: V) -> Arc<V> {
+    let mut shard = self.shard(&key).write().unwrap();
+    Arc::clone(shard.entry(key).or_insert_with(|| Arc::new(value)))
+  }
+
+  pub fn get(&self, key: &K) -> Option<Arc<V>> {
+    let shard = self.shard(key).read().unwrap();
+    shard.get(key).cloned()
+  }
+
+  pub fn remove(&self, key: &K) -> Option<Arc<V>> {
+    self.shard(key).write().unwrap().remove(key)
+  }
+
+  /// Returns every value belonging to `session`, with the item it belongs to.
+  pub fn session_values(&self, session: SessionId) -> Vec<(LocalDefId, Arc<V>)> {
+    self
+      .shards
+      .iter()
+      .flat_map(|shard| {
+        let shard = shard.read().unwrap();
+        shard
+          .iter()
+          .filter(|(key, _)| key.session() == session)
+          .map(|(key, value)| (key.def_id(), Arc::clone(value)))
+          .collect::<Vec<_>>()
+      })
+      .collect()
+  }
+
+  /// Removes every value for which `predicate` returns false.
+  pub fn retain(&self, mut predicate: impl FnMut(&K) -> bool) {
+    for shard in &self.shards {
+      shard.write().unwrap().retain(|key, _| predicate(key));
+    }
+  }
+
+  /// Removes every value for `def_id` in `session`.
+  pub fn remove_def_id(&self, session: SessionId, def_id: LocalDefId) {
+    let mut shard = self.item_shard(session, def_id).write().unwrap();
+    shard.retain(|key, _| key.session() != session || key.def_id() != def_id);
+  }
+
+  /// Removes every value belonging to `session`.
+  pub fn clear_session(&self, session: SessionId) {
+    self.retain(|key| key.session() != session);
+  }
+
+  pub fn clear(&self) {
+    self.retain(|_| false);
+  }
+}
+
+/// A value that can only be used on the thread that created it.
+///
+/// Callers must check [`ThreadBound::is_owned`] before accessing the value, which
+/// otherwise panics. Dropping the value on another thread leaks it with a warning, so
+/// the value is never used by two threads even if it is not `Send` or `Sync`.
+struct ThreadBound<T> {
+  value: ManuallyDrop<T>,
+  owner: ThreadId,
+}
+
+// SAFETY: the value is only accessed and dropped on the `owner` thread.
+unsafe impl<T> Send for ThreadBound<T> {}
+unsafe impl<T> Sync for ThreadBound<T> {}
+
+impl<T> ThreadBound<T> {
+  fn new(value: T) -> Self {
+    ThreadBound {
+      value: ManuallyDrop::new(value),
+      owner: thread::current().id(),
+    }
+  }
+
+  fn is_owned(&self) -> bool {
+    thread::current().id() == self.owner
+  }
+
+  fn check_thread(&self) {
+    assert!(
+      self.is_owned(),
+      "a body with borrowck facts can only be used on the thread that computed it"
+    );
+  }
+
+  fn get(&self) -> &T {
+    self.check_thread();
+    &self.value
+  }
+
+  fn into_inner(self) -> T {
+    self.check_thread();
+    let mut this = ManuallyDrop::new(self);
+    // SAFETY: `this` is never used or dropped again.
+    unsafe { ManuallyDrop::take(&mut this.value) }
+  }
+}
+
+impl<T> Drop for ThreadBound<T> {
+  fn drop(&mut self) {
+    if self.is_owned() {
+      // SAFETY: the value is not used again.
+      unsafe { ManuallyDrop::drop(&mut self.value) }
+    } else {
+      log::warn!(
+        "Leaking a {} released on {:?}, which did not compute it",
+        std::any::type_name::<T>(),
+        thread::current().id()
+      );
+    }
+  }
+}
+
+/// A body stored by the `mir_borrowck` override, with its lifetime erased to `'static`.
+///
+/// This is the only place where the lifetime is erased and restored, and the erased
+/// body is never exposed.
+///
+/// `BodyWithBorrowckFacts` is neither `Send` nor `Sync` because it holds `Rc`s to the
+/// borrow set and region inference context, which callers can clone, and it cannot be
+/// converted into a shareable type without changing the type handed out to callers. So
+/// the body can only be used on the thread that stored it, and is leaked (with a
+/// warning) if the last reference to it is released on another thread.
+pub(super) struct StoredBody {
+  body: ThreadBound<BodyWithBorrowckFacts<'static>>,
+  move_data: ThreadBound<Option<MoveData<'static>>>,
+  /// True if a reference to `body` has been handed out by `get_body_with_borrowck_facts`.
+  pub lent: AtomicBool,
+  /// True if the Polonius facts were dropped for exceeding the time budget.
+  pub timed_out: bool,
+  /// True if only the location-insensitive facts were collected.
+  pub location_insensitive: bool,
+}
+
+impl StoredBody {
+  pub fn new(
+    body: BodyWithBorrowckFacts<'_>,
+    move_data: Option<MoveData<'_>>,
+    timed_out: bool,
+    location_insensitive: bool,
+  ) -> Self {
+    StoredBody {
+      // SAFETY: the lifetime is restored by `borrow`, `lend` and `into_body`, whose
+      // callers promise that it is the lifetime of the original body.
+      body: ThreadBound::new(unsafe {
+        std::mem::transmute::<BodyWithBorrowckFacts<'_>, BodyWithBorrowckFacts<'static>>(
+          body,
+        )
+      }),
+      // SAFETY: see above.
+      move_data: ThreadBound::new(unsafe {
+        std::mem::transmute::<Option<MoveData<'_>>, Option<MoveData<'static>>>(move_data)
+      }),
+      lent: AtomicBool::new(false),
+      timed_out,
+      location_insensitive,
+    }
+  }
+
+  /// Returns the body with its original lifetime.
+  ///
+  /// # Safety
+  ///
+  /// `'tcx` must be the lifetime of the `TyCtxt` that produced the body.
+  pub unsafe fn borrow<'tcx>(&self) -> &BodyWithBorrowckFacts<'tcx> {
+    unsafe {
+      std::mem::transmute::<&BodyWithBorrowckFacts<'static>, &BodyWithBorrowckFacts<'tcx>>(
+        self.body.get(),
+      )
+    }
+  }
+
+  /// Returns the move data of the body with its original lifetime for as long as `'a`.
+  ///
+  /// # Safety
+  ///
+  /// The same requirements as [`StoredBody::lend`] apply.
+  pub unsafe fn lend_move_data<'a, 'tcx>(&self) -> Option<&'a MoveData<'tcx>> {
+    let move_data = self.move_data.get().as_ref()?;
+    let move_data =
+      unsafe { std::mem::transmute::<&MoveData<'static>, &MoveData<'tcx>>(move_data) };
+    Some(unsafe { &*(move_data as *const MoveData<'tcx>) })
+  }
+
+  /// Returns the body with its original lifetime for as long as `'a`, marking it as
+  /// lent so it can no longer be taken.
+  ///
+  /// # Safety
+  ///
+  /// `'tcx` must be the lifetime of the `TyCtxt` that produced the body, and the
+  /// `StoredBody` must not be dropped during `'a`.
+  pub unsafe fn lend<'a, 'tcx>(&self) -> &'a BodyWithBorrowckFacts<'tcx> {
+    self.lent.store(true, Ordering::SeqCst);
+    let body: &BodyWithBorrowckFacts<'tcx> = unsafe { self.borrow() };
+    unsafe { &*(body as *const BodyWithBorrowckFacts<'tcx>) }
+  }
+
+  /// Returns the owned body with its original lifetime.
+  ///
+  /// # Safety
+  ///
+  /// `'tcx` must be the lifetime of the `TyCtxt` that produced the body.
+  pub unsafe fn into_body<'tcx>(self) -> BodyWithBorrowckFacts<'tcx> {
+    unsafe {
+      std::mem::transmute::<BodyWithBorrowckFacts<'static>, BodyWithBorrowckFacts<'tcx>>(
+        self.body.into_inner(),
+      )
+    }
+  }
+
+  /// Returns true if the body was stored by the current thread, so it can be used.
+  pub fn is_on_current_thread(&self) -> bool {
+    self.body.is_owned()
+  }
+
+  pub fn is_lent(&self) -> bool {
+    self.lent.load(Ordering::SeqCst)
+  }
+}
+
+pub(super) type BodyKey = (SessionId, LocalDefId);
+
+pub(super) static MIR_BODIES: LazyLock<ShardedMap<BodyKey, StoredBody>> =
+  LazyLock::new(ShardedMap::new);
+
+/// Key for the outputs of Polonius, which may be computed with several algorithms.
+#[derive(PartialEq, Eq, Hash)]
+pub(super) struct OutputKey {
+  pub session: SessionId,
+  pub def_id: LocalDefId,
+  pub algorithm: std::mem::Discriminant<polonius_engine::Algorithm>,
+  /// True if the output was computed from location-insensitive facts, which differ from
+  /// the full facts for the same body.
+  pub location_insensitive: bool,
+}
+
+impl SessionKey for OutputKey {
+  fn session(&self) -> SessionId {
+    self.session
+  }
+
+  fn def_id(&self) -> LocalDefId {
+    self.def_id
+  }
+}
+
+pub(super) static POLONIUS_OUTPUTS: LazyLock<ShardedMap<OutputKey, PoloniusOutput>> =
+  LazyLock::new(ShardedMap::new);
+
+/// Removes all data stored for `session`.
+pub(super) fn clear_session(session: SessionId) {
+  MIR_BODIES.clear_session(session);
+  POLONIUS_OUTPUTS.clear_session(session);
+}
+
+/// Removes all data stored for `def_id` in `session`, returning true if a body was stored.
+pub(super) fn remove_def_id(session: SessionId, def_id: LocalDefId) -> bool {
+  POLONIUS_OUTPUTS.remove_def_id(session, def_id);
+  MIR_BODIES.remove(&(session, def_id)).is_some()
+}
+
+/// Removes all stored data.
+pub(super) fn clear() {
+  MIR_BODIES.clear();
+  POLONIUS_OUTPUTS.clear();
+}
@@ -0,0 +1,323 @@
+//! Mapping the `SwitchInt` terminators of a body back to the source-level constructs
+//! that they branch for.
+//!
+//! MIR building doesn't record which construct a `SwitchInt` came from, so
+//! [`BranchMap`] finds the branching constructs of the HIR and matches each switch to
+//! the innermost construct whose span contains the span of the switch. Desugared
+//! constructs like `while`, `for` and `?` are recognized by the shape of their
+//! lowering, and the `&&` and `||` chains of conditions are attributed to the
+//! condition's `if` or `while`.
+
+use rustc_data_structures::{captures::Captures, fx::FxHashSet as HashSet};
+use rustc_hir::{
+  def_id::LocalDefId,
+  intravisit::{self, Visitor},
+  Arm, BinOpKind, Expr, ExprKind, HirId, LetStmt, LoopSource, MatchSource,
+};
+use rustc_middle::{
+  mir::{BasicBlock, Body, Location, TerminatorKind},
+  ty::TyCtxt,
+};
+use rustc_span::Span;
+
+/// The kind of source construct that a `SwitchInt` branches for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BranchKind {
+  /// The condition of an `if`.
+  If,
+  /// The pattern of an `if let`.
+  IfLet,
+  /// The condition of a `while`.
+  While,
+  /// The pattern of a `while let`.
+  WhileLet,
+  /// The patterns of a `match`.
+  Match,
+  /// The guard of a match arm.
+  Guard,
+  /// The check of a `?` for whether to return early.
+  Try,
+  /// The check of a `for` loop for whether the iterator is exhausted.
+  For,
+  /// The pattern of a `let ... else`.
+  LetElse,
+  /// A `&&` or `||` outside of the condition of an `if` or `while`.
+  LazyBool,
+}
+
+/// The source construct of a `SwitchInt`, see [`BranchMap::branch_of`].
+#[derive(Debug, Clone)]
+pub struct Branch {
+  /// The kind of construct.
+  pub kind: BranchKind,
+  /// The construct, i.e. the `if`, `match`, loop, arm or `let` statement.
+  pub hir_id: HirId,
+  /// The span of the construct. Desugared constructs like `?` have a desugared span.
+  pub span: Span,
+  /// The span of the expression that decides the branch, e.g. the condition of an
+  /// `if`, the scrutinee of a `match` or the operand of a `?`.
+  pub condition: Span,
+  /// The targets of the switch, with `None` for the `otherwise` target.
+  pub targets: Vec<(Option<u128>, BasicBlock)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Construct {
+  kind: BranchKind,
+  hir_id: HirId,
+  span: Span,
+  condition: Span,
+}
+
+/// Maps the switches of a body to their source constructs, see the
+/// [module documentation](self).
+pub struct BranchMap {
+  constructs: Vec<Construct>,
+}
+
+impl BranchMap {
+  /// Finds the branching constructs in the body of `def_id`, excluding those of nested
+  /// closures and coroutines.
+  pub fn build(tcx: TyCtxt<'_>, def_id: LocalDefId) -> Self {
+    let mut finder = ConstructFinder {
+      constructs: Vec::new(),
+      conditions: HashSet::default(),
+    };
+    if let Some(body) = tcx.hir().maybe_body_owned_by(def_id) {
+      finder.visit_expr(body.value);
+    }
+    BranchMap {
+      constructs: finder.constructs,
+    }
+  }
+
+  /// Returns the source construct of the terminator at `location`, or `None` if it is
+  /// not a `SwitchInt` or the switch isn't within any construct, e.g. from code
+  /// generated by a macro with unrelated spans.
+  pub fn branch_of(&self, body: &Body<'_>, location: Location) -> Option<Branch> {
+    let terminator = body.stmt_at(location).right()?;
+    let TerminatorKind::SwitchInt { targets, .. } = &terminator.kind else {
+      return None;
+    };
+    let span = terminator.source_info.span;
+    let construct = self
+      .constructs
+      .iter()
+      .filter(|construct| construct.span.contains(span))
+      .min_by_key(|construct| construct.span.hi() - construct.span.lo())?;
+    Some(Branch {
+      kind: construct.kind,
+      hir_id: construct.hir_id,
+      span: construct.span,
+      condition: construct.condition,
+      targets: targets
+        .iter()
+        .map(|(value, block)| (Some(value), block))
+        .chain([(None, targets.otherwise())])
+        .collect(),
+    })
+  }
+
+  /// Returns the source construct of every `SwitchInt` in `body` that has one, along
+  /// with the block of the switch.
+  pub fn branches<'a, 'tcx>(
+    &'a self,
+    body: &'a Body<'tcx>,
+  ) -> impl Iterator<Item = (BasicBlock, Branch)> + Captures<'tcx> + 'a {
+    body.basic_blocks.indices().filter_map(move |block| {
+      let location = body.terminator_loc(block);
+      Some((block, self.branch_of(body, location)?))
+    })
+  }
+}
+
+struct ConstructFinder {
+  constructs: Vec<Construct>,
+  // The `&&` and `||` expressions that are part of a condition of an `if` or `while`,
+  // and the `if` of each `while`.
+  conditions: HashSet<HirId>,
+}
+
+impl ConstructFinder {
+  fn add(&mut self, kind: BranchKind, hir_id: HirId, span: Span, condition: &Expr) {
+    self.constructs.push(Construct {
+      kind,
+      hir_id,
+      span,
+      condition: condition.span,
+    });
+  }
+
+  // Marks the `&&` and `||` operators of the condition `cond` as part of it, and
+  // returns whether the condition contains a `let`.
+  fn condition(&mut self, cond: &Expr) -> bool {
+    match cond.kind {
+      ExprKind::DropTemps(inner) => self.condition(inner),
+      ExprKind::Let(..) => true,
+      ExprKind::Binary(op, lhs, rhs)
+        if matches!(op.node, BinOpKind::And | BinOpKind::Or) =>
+      {
+        self.conditions.insert(cond.hir_id);
+        let lhs = self.condition(lhs);
+        self.condition(rhs) || lhs
+      }
+      _ => false,
+    }
+  }
+}
+
+fn strip_drop_temps<'a, 'hir>(expr: &'a Expr<'hir>) -> &'a Expr<'hir> {
+  match expr.kind {
+    ExprKind::DropTemps(inner) => strip_drop_temps(inner),
+    _ => expr,
+  }
+}
+
+impl<'hir> Visitor<'hir> for ConstructFinder {
+  fn visit_expr(&mut self, expr: &'hir Expr<'hir>) {
+    match expr.kind {
+      ExprKind::If(cond, ..) if !self.conditions.contains(&expr.hir_id) => {
+        let kind = if self.condition(cond) {
+          BranchKind::IfLet
+        } else {
+          BranchKind::If
+        };
+        self.add(kind, expr.hir_id, expr.span, strip_drop_temps(cond));
+      }
+      ExprKind::Loop(block, _, LoopSource::While, _) => {
+        if let Some(
+          inner @ Expr {
+            kind: ExprKind::If(cond, ..),
+            ..
+          },
+        ) = block.expr
+        {
+          self.conditions.insert(inner.hir_id);
+          let kind = if self.condition(cond) {
+            BranchKind::WhileLet
+          } else {
+            BranchKind::While
+          };
+          self.add(kind, expr.hir_id, expr.span, strip_drop_temps(cond));
+        }
+      }
+      ExprKind::Match(scrutinee, arms, source) => {
+        // A `?` matches on `Try::branch(operand)`, and a `for` on
+        // `IntoIterator::into_iter(head)`. The inner match of a `for` on the next
+        // element has the span of the head, so it's attributed to the outer match.
+        let operand = match scrutinee.kind {
+          ExprKind::Call(_, [operand]) => operand,
+          _ => scrutinee,
+        };
+        match source {
+          MatchSource::Normal | MatchSource::FormatArgs | MatchSource::Postfix => {
+            self.add(BranchKind::Match, expr.hir_id, expr.span, scrutinee)
+          }
+          MatchSource::TryDesugar(_) => {
+            self.add(BranchKind::Try, expr.hir_id, expr.span, operand)
+          }
+          MatchSource::ForLoopDesugar if arms.len() == 1 => {
+            self.add(BranchKind::For, expr.hir_id, expr.span, operand)
+          }
+          _ => {}
+        }
+      }
+      ExprKind::Binary(op, ..)
+        if matches!(op.node, BinOpKind::And | BinOpKind::Or)
+          && !self.conditions.contains(&expr.hir_id) =>
+      {
+        self.add(BranchKind::LazyBool, expr.hir_id, expr.span, expr);
+      }
+      // Closures and coroutines have their own bodies.
+      ExprKind::Closure(..) => return,
+      _ => {}
+    }
+    intravisit::walk_expr(self, expr);
+  }
+
+  fn visit_arm(&mut self, arm: &'hir Arm<'hir>) {
+    if let Some(guard) = arm.guard {
+      self.condition(guard);
+      self.add(BranchKind::Guard, arm.hir_id, guard.span, guard);
+    }
+    intravisit::walk_arm(self, arm);
+  }
+
+  fn visit_local(&mut self, local: &'hir LetStmt<'hir>) {
+    if let (Some(init), Some(_)) = (local.init, local.els) {
+      self.add(BranchKind::LetElse, local.hir_id, local.span, init);
+    }
+    intravisit::walk_local(self, local);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test_utils;
+
+  #[test]
+  fn test_branch_map() {
+    let input = r#"
+fn f(x: Option<i32>, v: Vec<i32>) -> Option<i32> {
+  let y = x?;
+  if y > 0 && y < 5 { println!("a"); }
+  if let Some(z) = x { let _ = z; }
+  match y { 1 => {}, n if n > 3 => {}, _ => {} }
+  let mut i = 0;
+  while i < 3 { i += 1; }
+  while let Some(_) = x { break; }
+  for k in v { let _ = k; }
+  let b = y > 1 || y < 0;
+  let Some(w) = x else { return None; };
+  Some(y + w)
+}
+"#;
+    test_utils::compile_body(input, |tcx, body_id, body_with_facts| {
+      let body = &body_with_facts.body;
+      let source_map = tcx.sess.source_map();
+      let snippet = |span| source_map.span_to_snippet(span).unwrap();
+      let def_id = tcx.hir().body_owner_def_id(body_id);
+      let branches = BranchMap::build(tcx, def_id)
+        .branches(body)
+        .map(|(_, branch)| branch)
+        .collect::<Vec<_>>();
+      let kinds = branches
+        .iter()
+        .map(|branch| (branch.kind, snippet(branch.condition)))
+        .collect::<Vec<_>>();
+      let expected = [
+        (BranchKind::Try, "x"),
+        (BranchKind::If, "y > 0 && y < 5"),
+        (BranchKind::If, "y > 0 && y < 5"),
+        (BranchKind::IfLet, "let Some(z) = x"),
+        (BranchKind::Match, "y"),
+        (BranchKind::Guard, "n > 3"),
+        (BranchKind::While, "i < 3"),
+        (BranchKind::WhileLet, "let Some(_) = x"),
+        (BranchKind::For, "v"),
+        (BranchKind::LazyBool, "y > 1 || y < 0"),
+        (BranchKind::LetElse, "x"),
+      ];
+      assert_eq!(
+        kinds,
+        expected
+          .iter()
+          .map(|(kind, s)| (*kind, s.to_string()))
+          .collect::<Vec<_>>()
+      );
+
+      let try_branch = &branches[0];
+      assert_eq!(snippet(try_branch.span), "x?");
+      assert_eq!(try_branch.targets.last().unwrap().0, None);
+      assert!(branches.iter().all(|branch| branch.targets.len() >= 2));
+      assert!(matches!(
+        tcx.hir_node(branches[4].hir_id),
+        rustc_hir::Node::Expr(Expr {
+          kind: ExprKind::Match(..),
+          ..
+        })
+      ));
+    });
+  }
+}
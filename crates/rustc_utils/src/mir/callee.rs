@@ -0,0 +1,199 @@
+//! Resolving the callees of call terminators.
+
+use rustc_hir::def_id::DefId;
+use rustc_middle::{
+  mir::{Body, Terminator, TerminatorKind},
+  ty::{
+    EarlyBinder, GenericArgsRef, Instance, InstanceKind, ParamEnv, PolyFnSig, TyCtxt,
+    TyKind,
+  },
+};
+
+/// The function called by a call terminator, see [`resolve_call`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Callee<'tcx> {
+  /// The call resolves to exactly this instance.
+  Instance(Instance<'tcx>),
+  /// The call dispatches through a trait object to an implementation of `method`.
+  Virtual {
+    method: DefId,
+    args: GenericArgsRef<'tcx>,
+  },
+  /// The call is through a function pointer of this signature.
+  FnPtr(PolyFnSig<'tcx>),
+  /// The call could not be resolved, e.g. because it calls a method of a generic
+  /// parameter that is not instantiated.
+  Unknown {
+    def_id: DefId,
+    args: GenericArgsRef<'tcx>,
+  },
+}
+
+impl Callee<'_> {
+  /// Returns the function or method called, if known.
+  pub fn def_id(&self) -> Option<DefId> {
+    match self {
+      Callee::Instance(instance) => Some(instance.def_id()),
+      Callee::Virtual { method, .. } => Some(*method),
+      Callee::Unknown { def_id, .. } => Some(*def_id),
+      Callee::FnPtr(_) => None,
+    }
+  }
+}
+
+/// Resolves the callee of `terminator` in `body`, or returns `None` if it is not a
+/// call.
+///
+/// `args` instantiates the generic parameters of `body`, e.g. the identity arguments
+/// for a polymorphic analysis or concrete types for a monomorphic one, and `param_env`
+/// is the environment the call is resolved in, e.g.
+/// [`ParamEnv::reveal_all`] for concrete types.
+pub fn resolve_call<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  body: &Body<'tcx>,
+  terminator: &Terminator<'tcx>,
+  param_env: ParamEnv<'tcx>,
+  args: GenericArgsRef<'tcx>,
+) -> Option<Callee<'tcx>> {
+  let TerminatorKind::Call { func, .. } = &terminator.kind else {
+    return None;
+  };
+  let func_ty = tcx.instantiate_and_normalize_erasing_regions(
+    args,
+    param_env,
+    EarlyBinder::bind(func.ty(body, tcx)),
+  );
+  let callee = match func_ty.kind() {
+    TyKind::FnDef(def_id, args) => {
+      match Instance::try_resolve(tcx, param_env, *def_id, args) {
+        Ok(Some(Instance {
+          def: InstanceKind::Virtual(method, _),
+          args,
+        })) => Callee::Virtual { method, args },
+        Ok(Some(instance)) => Callee::Instance(instance),
+        _ => Callee::Unknown {
+          def_id: *def_id,
+          args,
+        },
+      }
+    }
+    TyKind::FnPtr(..) => Callee::FnPtr(func_ty.fn_sig(tcx)),
+    _ => return None,
+  };
+  Some(callee)
+}
+
+/// Returns the MIR of `instance`, including generated shims, or `None` if it has no
+/// MIR, e.g. an intrinsic or a function from a crate compiled without MIR.
+///
+/// The body is not instantiated with the instance's arguments, see
+/// [`Instance::instantiate_mir_and_normalize_erasing_regions`].
+pub fn callee_mir<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  instance: Instance<'tcx>,
+) -> Option<&'tcx Body<'tcx>> {
+  match instance.def {
+    InstanceKind::Item(def_id) => tcx
+      .is_mir_available(def_id)
+      .then(|| tcx.instance_mir(instance.def)),
+    InstanceKind::Intrinsic(_) | InstanceKind::Virtual(..) => None,
+    _ => Some(tcx.instance_mir(instance.def)),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::ty::GenericArgs;
+
+  use super::*;
+  use crate::test_utils::CompileBuilder;
+
+  fn resolve_only<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    def_id: DefId,
+    param_env: ParamEnv<'tcx>,
+    args: GenericArgsRef<'tcx>,
+  ) -> Callee<'tcx> {
+    let body = tcx.optimized_mir(def_id);
+    let calls = body
+      .basic_blocks
+      .iter()
+      .filter_map(|data| resolve_call(tcx, body, data.terminator(), param_env, args))
+      .collect::<Vec<_>>();
+    assert_eq!(calls.len(), 1);
+    calls[0]
+  }
+
+  #[test]
+  fn test_resolve_call() {
+    let input = r#"
+trait Animal { fn speak(&self) -> u32; }
+struct Dog;
+impl Animal for Dog { fn speak(&self) -> u32 { 1 } }
+
+fn speak<A: Animal>(a: &A) -> u32 { a.speak() }
+fn speak_dyn(a: &dyn Animal) -> u32 { a.speak() }
+fn apply(f: fn() -> u32) -> u32 { f() }
+fn double(n: u32) -> u32 { n * 2 }
+fn main() { double(1); }
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let tcx = result.tcx;
+      let find = |name: &str| {
+        tcx
+          .hir()
+          .body_owners()
+          .map(|def_id| def_id.to_def_id())
+          .find(|def_id| tcx.def_path_str(*def_id) == name)
+          .unwrap()
+      };
+      let identity = |def_id| GenericArgs::identity_for_item(tcx, def_id);
+      let path = |def_id: Option<DefId>| tcx.def_path_str(def_id.unwrap());
+
+      let speak = find("speak");
+      let callee = resolve_only(tcx, speak, tcx.param_env(speak), identity(speak));
+      assert!(matches!(callee, Callee::Unknown { .. }));
+      assert_eq!(path(callee.def_id()), "Animal::speak");
+
+      let dog = tcx.hir().items().find_map(|id| {
+        let def_id = id.owner_id.to_def_id();
+        (tcx.def_path_str(def_id) == "Dog")
+          .then(|| tcx.type_of(def_id).instantiate_identity())
+      });
+      let args = tcx.mk_args(&[dog.unwrap().into()]);
+      let callee = resolve_only(tcx, speak, ParamEnv::reveal_all(), args);
+      let Callee::Instance(instance) = callee else {
+        panic!("{callee:?}")
+      };
+      assert_eq!(path(callee.def_id()), "<Dog as Animal>::speak");
+      assert!(callee_mir(tcx, instance).is_some());
+
+      let speak_dyn = find("speak_dyn");
+      let callee = resolve_only(
+        tcx,
+        speak_dyn,
+        tcx.param_env(speak_dyn),
+        identity(speak_dyn),
+      );
+      assert!(matches!(callee, Callee::Virtual { .. }));
+      assert_eq!(path(callee.def_id()), "Animal::speak");
+
+      let apply = find("apply");
+      let callee = resolve_only(tcx, apply, tcx.param_env(apply), identity(apply));
+      let Callee::FnPtr(sig) = callee else {
+        panic!("{callee:?}")
+      };
+      assert_eq!(sig.inputs().skip_binder().len(), 0);
+      assert_eq!(callee.def_id(), None);
+
+      let main = find("main");
+      let Callee::Instance(instance) =
+        resolve_only(tcx, main, tcx.param_env(main), identity(main))
+      else {
+        unreachable!()
+      };
+      assert_eq!(instance.def_id(), find("double"));
+      assert!(callee_mir(tcx, instance).is_some());
+    });
+  }
+}
@@ -0,0 +1,476 @@
+//! Call graphs of the local crate.
+//!
+//! The call graph has an edge for every call terminator in the optimized MIR of a
+//! local function or closure. Calls are resolved with [`resolve_call`]:
+//!
+//! * A call that resolves to a single function is a [`CallKind::Static`] edge to it.
+//! * A call through a trait object is a [`CallKind::Virtual`] edge to every
+//!   implementation of the method, or to the method's default body.
+//! * A call through a function pointer is a [`CallKind::FnPtr`] edge to every function
+//!   or closure of the same arity that is coerced to a function pointer in the crate.
+//! * A call that cannot be resolved, e.g. of a method of a generic parameter, is a
+//!   [`CallKind::Unresolved`] edge to the called item, e.g. the trait method.
+//!
+//! Calls through `dyn Fn` are treated as calls of the trait method, since closures
+//! don't have impls.
+
+use rustc_data_structures::{
+  captures::Captures,
+  fx::{FxHashMap as HashMap, FxHashSet as HashSet},
+  graph::{scc::Sccs, vec_graph::VecGraph},
+};
+use rustc_graphviz as dot;
+use rustc_hir::def_id::DefId;
+use rustc_middle::{
+  mir::{CastKind, Location, Rvalue},
+  ty::{adjustment::PointerCoercion, GenericArgs, Instance, TyCtxt, TyKind},
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use super::callee::{resolve_call, Callee};
+use crate::BodyExt;
+
+/// How the callee of a [`CallEdge`] was determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum CallKind {
+  /// The call resolves to exactly this callee.
+  Static,
+  /// The call is through a trait object and may dispatch to this callee.
+  Virtual,
+  /// The call is through a function pointer that may point to this callee.
+  FnPtr,
+  /// The call could not be resolved and the callee is the called item.
+  Unresolved,
+}
+
+/// A possible call from `caller` to `callee` at `location` in the caller's body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CallEdge {
+  pub caller: DefId,
+  pub callee: DefId,
+  pub location: Location,
+  pub kind: CallKind,
+}
+
+/// The call graph of the local crate, see the [module documentation](self).
+pub struct CallGraph<'tcx> {
+  tcx: TyCtxt<'tcx>,
+  nodes: Vec<DefId>,
+  indices: HashMap<DefId, usize>,
+  edges: Vec<CallEdge>,
+  callees: HashMap<DefId, Vec<usize>>,
+  callers: HashMap<DefId, Vec<usize>>,
+}
+
+impl<'tcx> CallGraph<'tcx> {
+  /// Builds the call graph of every local function and closure.
+  pub fn build(tcx: TyCtxt<'tcx>) -> Self {
+    let bodies = tcx
+      .hir()
+      .body_owners()
+      .map(|def_id| def_id.to_def_id())
+      .filter(|def_id| tcx.def_kind(*def_id).is_fn_like())
+      .collect::<Vec<_>>();
+
+    // Functions and closures that are coerced to function pointers, with their arity.
+    let mut reified = HashSet::default();
+    for def_id in &bodies {
+      let body = tcx.optimized_mir(*def_id);
+      let param_env = tcx.param_env(*def_id);
+      for location in body.all_locations() {
+        let Some(statement) = body.stmt_at(location).left() else {
+          continue;
+        };
+        let Some((_, Rvalue::Cast(CastKind::PointerCoercion(coercion, _), op, ty))) =
+          statement.kind.as_assign()
+        else {
+          continue;
+        };
+        if !matches!(
+          coercion,
+          PointerCoercion::ReifyFnPointer | PointerCoercion::ClosureFnPointer(_)
+        ) {
+          continue;
+        }
+        let target = match op.ty(body, tcx).kind() {
+          TyKind::FnDef(def_id, args) => {
+            match Instance::try_resolve(tcx, param_env, *def_id, args) {
+              Ok(Some(instance)) => instance.def_id(),
+              _ => *def_id,
+            }
+          }
+          TyKind::Closure(def_id, _) => *def_id,
+          _ => continue,
+        };
+        let arity = ty.fn_sig(tcx).inputs().skip_binder().len();
+        reified.insert((target, arity));
+      }
+    }
+
+    let mut graph = CallGraph {
+      tcx,
+      nodes: Vec::new(),
+      indices: HashMap::default(),
+      edges: Vec::new(),
+      callees: HashMap::default(),
+      callers: HashMap::default(),
+    };
+    for caller in bodies {
+      graph.add_node(caller);
+      let body = tcx.optimized_mir(caller);
+      let param_env = tcx.param_env(caller);
+      let args = GenericArgs::identity_for_item(tcx, caller);
+      for (block, data) in body.basic_blocks.iter_enumerated() {
+        let Some(callee) = resolve_call(tcx, body, data.terminator(), param_env, args)
+        else {
+          continue;
+        };
+        let location = body.terminator_loc(block);
+        let mut add = |callee, kind| {
+          graph.add_edge(CallEdge {
+            caller,
+            callee,
+            location,
+            kind,
+          })
+        };
+        match callee {
+          Callee::Instance(instance) => add(instance.def_id(), CallKind::Static),
+          Callee::Virtual { method, .. } => {
+            for callee in implementations(tcx, method) {
+              add(callee, CallKind::Virtual);
+            }
+          }
+          Callee::FnPtr(sig) => {
+            let arity = sig.inputs().skip_binder().len();
+            let mut targets = reified
+              .iter()
+              .filter(|(_, n)| *n == arity)
+              .map(|(target, _)| *target)
+              .collect::<Vec<_>>();
+            targets.sort_by_key(|target| tcx.def_path_str(*target));
+            for target in targets {
+              add(target, CallKind::FnPtr);
+            }
+          }
+          Callee::Unknown { def_id, .. } => add(def_id, CallKind::Unresolved),
+        }
+      }
+    }
+    graph
+  }
+
+  fn add_node(&mut self, def_id: DefId) -> usize {
+    *self.indices.entry(def_id).or_insert_with(|| {
+      self.nodes.push(def_id);
+      self.nodes.len() - 1
+    })
+  }
+
+  fn add_edge(&mut self, edge: CallEdge) {
+    self.add_node(edge.callee);
+    let index = self.edges.len();
+    self.edges.push(edge);
+    self.callees.entry(edge.caller).or_default().push(index);
+    self.callers.entry(edge.callee).or_default().push(index);
+  }
+
+  /// Returns the functions and closures in the graph, in the order they were added.
+  pub fn nodes(&self) -> &[DefId] {
+    &self.nodes
+  }
+
+  /// Returns every edge in the graph.
+  pub fn edges(&self) -> &[CallEdge] {
+    &self.edges
+  }
+
+  /// Returns the edges of the calls in the body of `def_id`.
+  pub fn calls_from(
+    &self,
+    def_id: DefId,
+  ) -> impl Iterator<Item = &CallEdge> + Captures<'tcx> + '_ {
+    self.edges_in(&self.callees, def_id)
+  }
+
+  /// Returns the edges of the calls that may call `def_id`.
+  pub fn calls_to(
+    &self,
+    def_id: DefId,
+  ) -> impl Iterator<Item = &CallEdge> + Captures<'tcx> + '_ {
+    self.edges_in(&self.callers, def_id)
+  }
+
+  fn edges_in<'a>(
+    &'a self,
+    map: &'a HashMap<DefId, Vec<usize>>,
+    def_id: DefId,
+  ) -> impl Iterator<Item = &'a CallEdge> + Captures<'tcx> + 'a {
+    map
+      .get(&def_id)
+      .into_iter()
+      .flatten()
+      .map(|index| &self.edges[*index])
+  }
+
+  /// Returns the functions that may be called by `def_id`, without duplicates.
+  pub fn callees_of(&self, def_id: DefId) -> Vec<DefId> {
+    let mut callees = self
+      .calls_from(def_id)
+      .map(|edge| edge.callee)
+      .collect::<Vec<_>>();
+    dedup(&mut callees);
+    callees
+  }
+
+  /// Returns the functions that may call `def_id`, without duplicates.
+  pub fn callers_of(&self, def_id: DefId) -> Vec<DefId> {
+    let mut callers = self
+      .calls_to(def_id)
+      .map(|edge| edge.caller)
+      .collect::<Vec<_>>();
+    dedup(&mut callers);
+    callers
+  }
+
+  /// Returns the strongly-connected components of the graph in reverse topological
+  /// order, i.e. every component comes after the components it calls. A component
+  /// with more than one function, or with a function that calls itself, is recursive.
+  pub fn sccs(&self) -> Vec<Vec<DefId>> {
+    let edges = self
+      .edges
+      .iter()
+      .map(|edge| (self.indices[&edge.caller], self.indices[&edge.callee]))
+      .collect::<Vec<_>>();
+    let graph: VecGraph<usize> = VecGraph::new(self.nodes.len(), edges);
+    let sccs: Sccs<usize, usize> = Sccs::new(&graph);
+    let mut components = vec![Vec::new(); sccs.num_sccs()];
+    for (index, def_id) in self.nodes.iter().enumerate() {
+      components[sccs.scc(index)].push(*def_id);
+    }
+    components
+  }
+
+  /// Renders the graph to Graphviz DOT, with edges labeled by their [`CallKind`]
+  /// unless they are static.
+  pub fn to_dot(&self) -> String {
+    let mut buf = Vec::new();
+    dot::render(self, &mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+  }
+
+  /// Serializes the graph to JSON, with functions identified by their path and edges
+  /// by the indices of their functions in `nodes`.
+  #[cfg(feature = "serde")]
+  pub fn to_json(&self) -> serde_json::Result<String> {
+    #[derive(Serialize)]
+    struct JsonEdge {
+      caller: usize,
+      callee: usize,
+      kind: CallKind,
+    }
+
+    #[derive(Serialize)]
+    struct JsonGraph {
+      nodes: Vec<String>,
+      edges: Vec<JsonEdge>,
+    }
+
+    let graph = JsonGraph {
+      nodes: (self.nodes.iter())
+        .map(|def_id| self.tcx.def_path_str(*def_id))
+        .collect(),
+      edges: (self.edges.iter())
+        .map(|edge| JsonEdge {
+          caller: self.indices[&edge.caller],
+          callee: self.indices[&edge.callee],
+          kind: edge.kind,
+        })
+        .collect(),
+    };
+    serde_json::to_string(&graph)
+  }
+}
+
+/// Returns the methods that a call of the trait method `method` through a trait
+/// object may dispatch to.
+pub(super) fn implementations(tcx: TyCtxt, method: DefId) -> Vec<DefId> {
+  let Some(trait_id) = tcx.trait_of_item(method) else {
+    return vec![method];
+  };
+  let mut implementations = tcx
+    .all_impls(trait_id)
+    .map(|impl_id| {
+      tcx
+        .impl_item_implementor_ids(impl_id)
+        .get(&method)
+        .copied()
+        .unwrap_or(method)
+    })
+    .collect::<Vec<_>>();
+  if implementations.is_empty() {
+    implementations.push(method);
+  }
+  dedup(&mut implementations);
+  implementations
+}
+
+fn dedup(def_ids: &mut Vec<DefId>) {
+  let mut seen = HashSet::default();
+  def_ids.retain(|def_id| seen.insert(*def_id));
+}
+
+impl<'a> dot::Labeller<'a> for CallGraph<'_> {
+  type Node = usize;
+  type Edge = usize;
+
+  fn graph_id(&'a self) -> dot::Id<'a> {
+    dot::Id::new("callgraph").unwrap()
+  }
+
+  fn node_id(&'a self, node: &usize) -> dot::Id<'a> {
+    dot::Id::new(format!("f{node}")).unwrap()
+  }
+
+  fn node_label(&'a self, node: &usize) -> dot::LabelText<'a> {
+    dot::LabelText::label(self.tcx.def_path_str(self.nodes[*node]))
+  }
+
+  fn edge_label(&'a self, edge: &usize) -> dot::LabelText<'a> {
+    let label = match self.edges[*edge].kind {
+      CallKind::Static => "",
+      CallKind::Virtual => "virtual",
+      CallKind::FnPtr => "fn ptr",
+      CallKind::Unresolved => "unresolved",
+    };
+    dot::LabelText::label(label)
+  }
+}
+
+impl<'a> dot::GraphWalk<'a> for CallGraph<'_> {
+  type Node = usize;
+  type Edge = usize;
+
+  fn nodes(&'a self) -> dot::Nodes<'a, usize> {
+    (0 .. self.nodes.len()).collect()
+  }
+
+  fn edges(&'a self) -> dot::Edges<'a, usize> {
+    (0 .. self.edges.len()).collect()
+  }
+
+  fn source(&'a self, edge: &usize) -> usize {
+    self.indices[&self.edges[*edge].caller]
+  }
+
+  fn target(&'a self, edge: &usize) -> usize {
+    self.indices[&self.edges[*edge].callee]
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test_utils::CompileBuilder;
+
+  #[test]
+  fn test_callgraph() {
+    let input = r#"
+trait Animal { fn speak(&self) -> u32; }
+struct Dog;
+struct Cat;
+impl Animal for Dog { fn speak(&self) -> u32 { 1 } }
+impl Animal for Cat { fn speak(&self) -> u32 { 2 } }
+
+fn is_even(n: u32) -> bool { if n == 0 { true } else { is_odd(n - 1) } }
+fn is_odd(n: u32) -> bool { if n == 0 { false } else { is_even(n - 1) } }
+
+fn double(n: u32) -> u32 { n * 2 }
+fn apply(f: fn(u32) -> u32, n: u32) -> u32 { f(n) }
+
+fn speak_dyn(a: &dyn Animal) -> u32 { a.speak() }
+fn speak_generic<A: Animal>(a: &A) -> u32 { a.speak() }
+
+fn main() {
+  is_even(3);
+  apply(double, 1);
+  speak_dyn(&Dog);
+  Dog.speak();
+  speak_generic(&Cat);
+}
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let tcx = result.tcx;
+      let graph = CallGraph::build(tcx);
+      let find = |name: &str| {
+        *graph
+          .nodes()
+          .iter()
+          .find(|def_id| tcx.def_path_str(**def_id) == name)
+          .unwrap_or_else(|| panic!("no node {name}"))
+      };
+      let names = |def_ids: Vec<DefId>| {
+        def_ids
+          .into_iter()
+          .map(|def_id| tcx.def_path_str(def_id))
+          .collect::<HashSet<_>>()
+      };
+      let set = |names: &[&str]| {
+        names
+          .iter()
+          .map(|name| name.to_string())
+          .collect::<HashSet<_>>()
+      };
+
+      assert_eq!(
+        names(graph.callees_of(find("main"))),
+        set(&[
+          "is_even",
+          "apply",
+          "speak_dyn",
+          "<Dog as Animal>::speak",
+          "speak_generic"
+        ])
+      );
+      assert_eq!(
+        names(graph.callers_of(find("is_even"))),
+        set(&["main", "is_odd"])
+      );
+      assert_eq!(names(graph.callees_of(find("apply"))), set(&["double"]));
+      assert!(graph
+        .calls_from(find("apply"))
+        .all(|edge| edge.kind == CallKind::FnPtr));
+      assert_eq!(
+        names(graph.callees_of(find("speak_dyn"))),
+        set(&["<Dog as Animal>::speak", "<Cat as Animal>::speak"])
+      );
+      assert_eq!(
+        names(graph.callees_of(find("speak_generic"))),
+        set(&["Animal::speak"])
+      );
+      assert!(graph
+        .calls_from(find("speak_generic"))
+        .all(|edge| edge.kind == CallKind::Unresolved));
+
+      let sccs = graph.sccs();
+      let recursive = sccs
+        .iter()
+        .find(|scc| scc.contains(&find("is_even")))
+        .unwrap();
+      assert_eq!(names(recursive.clone()), set(&["is_even", "is_odd"]));
+      let position = |name| {
+        sccs
+          .iter()
+          .position(|scc| scc.contains(&find(name)))
+          .unwrap()
+      };
+      assert!(position("apply") > position("double"));
+      assert!(position("main") > position("apply"));
+
+      let dot = graph.to_dot();
+      assert!(dot.contains("label=\"fn ptr\""));
+    });
+  }
+}
@@ -0,0 +1,139 @@
+//! Memoized queries of whether places overlap.
+
+use rustc_borrowck::consumers::{places_conflict, PlaceConflictBias};
+use rustc_index::bit_set::BitSet;
+use rustc_middle::{
+  mir::{Body, Place},
+  ty::TyCtxt,
+};
+
+use crate::cache::CopyCache;
+
+/// Checks whether places of a body conflict with [`places_conflict`], caching the
+/// result for each pair of places.
+///
+/// As with [`places_conflict`], the first place of a query is treated as borrowed and
+/// the second as accessed, i.e. read or written entirely. Places with different locals
+/// never conflict and a local conflicts with every place of it, which are checked
+/// without caching.
+pub struct ConflictChecker<'a, 'tcx> {
+  tcx: TyCtxt<'tcx>,
+  body: &'a Body<'tcx>,
+  bias: PlaceConflictBias,
+  cache: CopyCache<(Place<'tcx>, Place<'tcx>), bool>,
+}
+
+impl<'a, 'tcx> ConflictChecker<'a, 'tcx> {
+  /// Creates a checker for places of `body` which assumes that indexes may overlap,
+  /// e.g. that `a[i]` conflicts with `a[j]`.
+  pub fn new(tcx: TyCtxt<'tcx>, body: &'a Body<'tcx>) -> Self {
+    Self::with_bias(tcx, body, PlaceConflictBias::Overlap)
+  }
+
+  /// Creates a checker with the given bias for indexes that may or may not be equal.
+  pub fn with_bias(
+    tcx: TyCtxt<'tcx>,
+    body: &'a Body<'tcx>,
+    bias: PlaceConflictBias,
+  ) -> Self {
+    ConflictChecker {
+      tcx,
+      body,
+      bias,
+      cache: CopyCache::default(),
+    }
+  }
+
+  /// Returns true if `borrowed` conflicts with `accessed`.
+  pub fn conflicts(&self, borrowed: Place<'tcx>, accessed: Place<'tcx>) -> bool {
+    if borrowed.local != accessed.local {
+      return false;
+    }
+    if borrowed.projection.is_empty() || accessed.projection.is_empty() {
+      return true;
+    }
+    self
+      .cache
+      .get(&(borrowed, accessed), |(borrowed, accessed)| {
+        places_conflict(self.tcx, self.body, *borrowed, *accessed, self.bias)
+      })
+  }
+
+  /// Returns the indices of the `candidates` that conflict with `place`, where each
+  /// candidate is borrowed and `place` is accessed.
+  pub fn all_conflicting(
+    &self,
+    place: Place<'tcx>,
+    candidates: &[Place<'tcx>],
+  ) -> BitSet<usize> {
+    let mut conflicting = BitSet::new_empty(candidates.len());
+    for (index, candidate) in candidates.iter().enumerate() {
+      if self.conflicts(*candidate, place) {
+        conflicting.insert(index);
+      }
+    }
+    conflicting
+  }
+
+  /// Returns the number of pairs of places whose conflict has been computed.
+  pub fn len(&self) -> usize {
+    self.cache.len()
+  }
+
+  /// Returns true if no conflicts have been computed.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test_utils::{self, Placer};
+
+  #[test]
+  fn test_conflict_checker() {
+    let input = r#"
+fn main() {
+  let mut x = (0, 1);
+  let r = &mut x;
+  let mut a = [0; 4];
+  let i = 0;
+  let j = 1;
+}
+"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let p = Placer::new(tcx, body);
+      let checker = ConflictChecker::new(tcx, body);
+
+      let x = p.local("x").mk();
+      let x0 = p.local("x").field(0).mk();
+      let x1 = p.local("x").field(1).mk();
+      let r = p.local("r").mk();
+      let r0 = p.local("r").deref().field(0).mk();
+      assert!(checker.conflicts(x, x0));
+      assert!(checker.conflicts(x0, x));
+      assert!(!checker.conflicts(x0, x1));
+      assert!(!checker.conflicts(x, r));
+      assert!(checker.conflicts(r0, r));
+      assert_eq!(checker.len(), 1);
+
+      let candidates = [x, x0, x1, r, r0];
+      let conflicting = checker.all_conflicting(x0, &candidates);
+      assert_eq!(conflicting.iter().collect::<Vec<_>>(), [0, 1]);
+      let cached = checker.len();
+      assert_eq!(cached, 3);
+      checker.all_conflicting(x0, &candidates);
+      assert_eq!(checker.len(), cached);
+
+      let i = p.local("i").mk().local.as_usize();
+      let j = p.local("j").mk().local.as_usize();
+      let a_i = p.local("a").index(i).mk();
+      let a_j = p.local("a").index(j).mk();
+      assert!(checker.conflicts(a_i, a_j));
+      let disjoint = ConflictChecker::with_bias(tcx, body, PlaceConflictBias::NoOverlap);
+      assert!(!disjoint.conflicts(a_i, a_j));
+    });
+  }
+}
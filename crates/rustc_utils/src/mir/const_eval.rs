@@ -0,0 +1,348 @@
+//! Hooks into rustc's const-eval interpreter, and running it with custom machines.
+//!
+//! rustc evaluates constants with an interpreter that is generic over a
+//! [`Machine`], which decides how calls, intrinsics, memory accesses and the stack
+//! behave. Miri is one such machine, and an abstract interpreter can be another.
+//! This module provides two ways for plugins to reuse the interpreter:
+//!
+//! * [`register_const_eval_hook`] observes each constant that the compiler evaluates,
+//!   once [`override_queries`] is installed in [`rustc_driver::Callbacks::config`].
+//! * [`call_with_machine`] runs a function in a fresh interpreter with a plugin's own
+//!   machine, taking care of the stack frame and return place.
+
+use std::sync::{LazyLock, RwLock};
+
+use rustc_const_eval::interpret::{
+  EvalToAllocationRawResult, FnArg, GlobalId, InterpCx, InterpErrorInfo, InterpResult,
+  MPlaceTy, Machine, MemoryKind, StackPopCleanup,
+};
+use rustc_data_structures::fx::FxHashMap as HashMap;
+use rustc_middle::{
+  mir::interpret::interp_ok,
+  ty::{self, layout::FnAbiOf, Instance, ParamEnv, ParamEnvAnd, TyCtxt},
+  util::Providers,
+};
+
+use super::borrowck_facts::SessionId;
+
+/// A constant evaluated by rustc, i.e. the key of the `eval_to_allocation_raw` query.
+pub type ConstEvalKey<'tcx> = ParamEnvAnd<'tcx, GlobalId<'tcx>>;
+
+/// Called with each constant evaluated by rustc and the result of its evaluation, see
+/// [`register_const_eval_hook`].
+pub type ConstEvalHook =
+  for<'tcx> fn(TyCtxt<'tcx>, ConstEvalKey<'tcx>, &EvalToAllocationRawResult<'tcx>);
+
+static CONST_EVAL_HOOKS: RwLock<Vec<ConstEvalHook>> = RwLock::new(Vec::new());
+
+/// Registers a hook called after rustc evaluates a constant or promoted, in sessions
+/// that installed [`override_queries`]. Hooks run in registration order.
+///
+/// Evaluations are cached by the query system, so a hook is called once per constant
+/// and set of generic arguments. The initializers of statics are not evaluated through
+/// this query, and aren't seen by hooks.
+pub fn register_const_eval_hook(hook: ConstEvalHook) {
+  CONST_EVAL_HOOKS.write().unwrap().push(hook);
+}
+
+type EvalToAllocationRaw =
+  for<'tcx> fn(TyCtxt<'tcx>, ConstEvalKey<'tcx>) -> EvalToAllocationRawResult<'tcx>;
+
+/// The `eval_to_allocation_raw` provider replaced by [`override_queries`] in each
+/// session.
+static PREV_EVAL_TO_ALLOCATION_RAW: LazyLock<
+  RwLock<HashMap<SessionId, EvalToAllocationRaw>>,
+> = LazyLock::new(Default::default);
+
+/// You must use this function in [`rustc_driver::Callbacks::config`] for hooks from
+/// [`register_const_eval_hook`] to be called.
+///
+/// Like [`borrowck_facts::override_queries`](super::borrowck_facts::override_queries),
+/// the provider already in `local` is wrapped rather than replaced. Both can be
+/// installed with
+/// [`borrowck_facts::override_queries_with`](super::borrowck_facts::override_queries_with).
+pub fn override_queries(session: &rustc_session::Session, local: &mut Providers) {
+  let prev = local.eval_to_allocation_raw;
+  if prev as usize != eval_to_allocation_raw as usize {
+    PREV_EVAL_TO_ALLOCATION_RAW
+      .write()
+      .unwrap()
+      .insert(SessionId::of(session), prev);
+  }
+  local.eval_to_allocation_raw = eval_to_allocation_raw;
+}
+
+fn eval_to_allocation_raw<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  key: ConstEvalKey<'tcx>,
+) -> EvalToAllocationRawResult<'tcx> {
+  let prev = PREV_EVAL_TO_ALLOCATION_RAW
+    .read()
+    .unwrap()
+    .get(&SessionId::of_tcx(tcx))
+    .copied();
+  let prev =
+    prev.unwrap_or(rustc_const_eval::const_eval::eval_to_allocation_raw_provider);
+  let result = prev(tcx, key);
+  for hook in CONST_EVAL_HOOKS.read().unwrap().iter() {
+    hook(tcx, key, &result);
+  }
+  result
+}
+
+/// Calls the function `instance` with the arguments created by `args`, interpreting it
+/// with `machine` until it returns.
+///
+/// Returns the interpreter along with the place of the return value, so the caller can
+/// read the result and inspect the state of its machine, or the error that stopped the
+/// interpreter, e.g. a [`Machine`] method that threw.
+///
+/// ```ignore
+/// let instance = Instance::mono(tcx, def_id);
+/// let (ecx, ret) =
+///   call_with_machine(tcx, ParamEnv::reveal_all(), instance, MyMachine::new(), |_| {
+///     interp_ok(Vec::new())
+///   })?;
+/// let value = ecx.read_scalar(&ret).report_err()?;
+/// ```
+pub fn call_with_machine<'tcx, M: Machine<'tcx>>(
+  tcx: TyCtxt<'tcx>,
+  param_env: ParamEnv<'tcx>,
+  instance: Instance<'tcx>,
+  machine: M,
+  args: impl FnOnce(
+    &mut InterpCx<'tcx, M>,
+  ) -> InterpResult<'tcx, Vec<FnArg<'tcx, M::Provenance>>>,
+) -> Result<(InterpCx<'tcx, M>, MPlaceTy<'tcx, M::Provenance>), InterpErrorInfo<'tcx>> {
+  let span = tcx.def_span(instance.def_id());
+  let mut ecx = InterpCx::new(tcx, span, param_env, machine);
+  let ret = run_call(&mut ecx, instance, args).report_err()?;
+  Ok((ecx, ret))
+}
+
+fn run_call<'tcx, M: Machine<'tcx>>(
+  ecx: &mut InterpCx<'tcx, M>,
+  instance: Instance<'tcx>,
+  args: impl FnOnce(
+    &mut InterpCx<'tcx, M>,
+  ) -> InterpResult<'tcx, Vec<FnArg<'tcx, M::Provenance>>>,
+) -> InterpResult<'tcx, MPlaceTy<'tcx, M::Provenance>> {
+  let body = ecx.load_mir(instance.def, None)?;
+  let fn_abi = ecx.fn_abi_of_instance(instance, ty::List::empty())?;
+  let ret = ecx.allocate(fn_abi.ret.layout, MemoryKind::Stack)?;
+  let args = args(ecx)?;
+  ecx.init_stack_frame(
+    instance,
+    body,
+    fn_abi,
+    &args,
+    false,
+    &ret,
+    StackPopCleanup::Root { cleanup: false },
+  )?;
+  while ecx.step()? {}
+  interp_ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+  use std::sync::Mutex;
+
+  use rustc_const_eval::interpret::{
+    self, compile_time_machine, throw_unsup_format, AllocId, ConstAllocation, Frame,
+    HasStaticRootDefId, ImmTy, OpTy, Pointer,
+  };
+  use rustc_hir::def_id::LocalDefId;
+  use rustc_middle::{
+    mir::{AssertMessage, BasicBlock, BinOp, Body, UnwindAction},
+    query::TyCtxtAt,
+    ty::layout::TyAndLayout,
+  };
+  use rustc_span::def_id::DefId;
+  use rustc_target::spec::abi::Abi;
+
+  use super::*;
+  use crate::{mir::borrowck_facts, test_utils::CompileBuilder};
+
+  /// A machine that counts the terminators it executes.
+  struct StepCounter<'tcx> {
+    stack: Vec<Frame<'tcx>>,
+    terminators: usize,
+  }
+
+  impl HasStaticRootDefId for StepCounter<'_> {
+    fn static_def_id(&self) -> Option<LocalDefId> {
+      None
+    }
+  }
+
+  impl<'tcx> Machine<'tcx> for StepCounter<'tcx> {
+    compile_time_machine!(<'tcx>);
+
+    type MemoryKind = !;
+
+    const PANIC_ON_ALLOC_FAIL: bool = true;
+
+    fn enforce_alignment(_ecx: &InterpCx<'tcx, Self>) -> bool {
+      false
+    }
+
+    fn enforce_validity(_ecx: &InterpCx<'tcx, Self>, _layout: TyAndLayout<'tcx>) -> bool {
+      false
+    }
+
+    fn before_access_global(
+      _tcx: TyCtxtAt<'tcx>,
+      _machine: &Self,
+      _alloc_id: AllocId,
+      _alloc: ConstAllocation<'tcx>,
+      _static_def_id: Option<DefId>,
+      _is_write: bool,
+    ) -> InterpResult<'tcx> {
+      interp_ok(())
+    }
+
+    fn find_mir_or_eval_fn(
+      ecx: &mut InterpCx<'tcx, Self>,
+      instance: Instance<'tcx>,
+      _abi: Abi,
+      _args: &[FnArg<'tcx>],
+      _destination: &MPlaceTy<'tcx>,
+      _target: Option<BasicBlock>,
+      _unwind: UnwindAction,
+    ) -> InterpResult<'tcx, Option<(&'tcx Body<'tcx>, Instance<'tcx>)>> {
+      interp_ok(Some((ecx.load_mir(instance.def, None)?, instance)))
+    }
+
+    fn panic_nounwind(_ecx: &mut InterpCx<'tcx, Self>, _msg: &str) -> InterpResult<'tcx> {
+      throw_unsup_format!("panics are not supported by the step counter")
+    }
+
+    fn call_intrinsic(
+      _ecx: &mut InterpCx<'tcx, Self>,
+      _instance: Instance<'tcx>,
+      _args: &[OpTy<'tcx>],
+      _destination: &MPlaceTy<'tcx>,
+      _target: Option<BasicBlock>,
+      _unwind: UnwindAction,
+    ) -> InterpResult<'tcx, Option<Instance<'tcx>>> {
+      throw_unsup_format!("intrinsics are not supported by the step counter")
+    }
+
+    fn assert_panic(
+      _ecx: &mut InterpCx<'tcx, Self>,
+      _msg: &AssertMessage<'tcx>,
+      _unwind: UnwindAction,
+    ) -> InterpResult<'tcx> {
+      throw_unsup_format!("assertions are not supported by the step counter")
+    }
+
+    fn binary_ptr_op(
+      _ecx: &InterpCx<'tcx, Self>,
+      _bin_op: BinOp,
+      _left: &ImmTy<'tcx>,
+      _right: &ImmTy<'tcx>,
+    ) -> InterpResult<'tcx, ImmTy<'tcx>> {
+      throw_unsup_format!("pointer arithmetic is not supported by the step counter")
+    }
+
+    fn expose_ptr(_ecx: &mut InterpCx<'tcx, Self>, _ptr: Pointer) -> InterpResult<'tcx> {
+      throw_unsup_format!("exposing pointers is not supported by the step counter")
+    }
+
+    fn init_frame(
+      _ecx: &mut InterpCx<'tcx, Self>,
+      frame: Frame<'tcx>,
+    ) -> InterpResult<'tcx, Frame<'tcx>> {
+      interp_ok(frame)
+    }
+
+    fn stack<'a>(ecx: &'a InterpCx<'tcx, Self>) -> &'a [Frame<'tcx>] {
+      &ecx.machine.stack
+    }
+
+    fn stack_mut<'a>(ecx: &'a mut InterpCx<'tcx, Self>) -> &'a mut Vec<Frame<'tcx>> {
+      &mut ecx.machine.stack
+    }
+
+    fn before_terminator(ecx: &mut InterpCx<'tcx, Self>) -> InterpResult<'tcx> {
+      ecx.machine.terminators += 1;
+      interp_ok(())
+    }
+  }
+
+  #[test]
+  fn test_call_with_machine() {
+    let input = r#"
+fn add(a: i32, b: i32) -> i32 { a + b }
+fn sum() -> i32 {
+  let mut total = 0;
+  let mut i = 0;
+  while i < 4 { total = add(total, i); i += 1; }
+  total
+}
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let tcx = result.tcx;
+      let sum = tcx
+        .hir()
+        .body_owners()
+        .find(|def_id| tcx.def_path_str(*def_id) == "sum")
+        .unwrap();
+      let instance = Instance::mono(tcx, sum.to_def_id());
+      let machine = StepCounter {
+        stack: Vec::new(),
+        terminators: 0,
+      };
+      let (ecx, ret) =
+        call_with_machine(tcx, ParamEnv::reveal_all(), instance, machine, |_| {
+          interp_ok(Vec::new())
+        })
+        .unwrap();
+      let value = ecx.read_scalar(&ret).report_err().unwrap();
+      assert_eq!(value.to_i32().report_err().unwrap(), 6);
+      assert!(ecx.machine.terminators > 4);
+      assert!(interpret::Machine::stack(&ecx).is_empty());
+    });
+  }
+
+  static EVALUATED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+  fn record_evaluation<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    key: ConstEvalKey<'tcx>,
+    result: &EvalToAllocationRawResult<'tcx>,
+  ) {
+    if result.is_ok() {
+      let path = tcx.def_path_str(key.value.instance.def_id());
+      EVALUATED.lock().unwrap().push(path);
+    }
+  }
+
+  fn with_const_eval_hooks(session: &rustc_session::Session, local: &mut Providers) {
+    borrowck_facts::override_queries_with(session, local, override_queries);
+  }
+
+  #[test]
+  fn test_const_eval_hook() {
+    register_const_eval_hook(record_evaluation);
+    let input = r#"
+const HOOKED_CONST: i32 = 1 + 2;
+"#;
+    CompileBuilder::new(input)
+      .with_override_queries(with_const_eval_hooks)
+      .compile(|result| {
+        let tcx = result.tcx;
+        let def_id = tcx
+          .hir()
+          .body_owners()
+          .find(|def_id| tcx.def_path_str(*def_id) == "HOOKED_CONST")
+          .unwrap();
+        tcx.const_eval_poly(def_id.to_def_id()).unwrap();
+        assert!(EVALUATED
+          .lock()
+          .unwrap()
+          .contains(&"HOOKED_CONST".to_string()));
+      });
+  }
+}
@@ -5,16 +5,158 @@
 //!
 //! See Section 3.1 of "The Program Dependence Graph and Its Use in Optimization" (Ferrante et al. 1987)
 //! for more on how to define and analyze control-dependence.
+//!
+//! Functions can have several exits, e.g. a return and a resumed unwind, so both
+//! analyses are computed with respect to a virtual exit node that succeeds every exit.
+//! Blocks that cannot reach an exit, e.g. those ending in `unreachable` or an infinite
+//! loop, have no post-dominators and are not control-dependent on any block.
 
 use std::fmt;
 
-use rustc_data_structures::graph::{dominators::Dominators, vec_graph::VecGraph, *};
+use rustc_data_structures::{
+  captures::Captures,
+  graph::{dominators::Dominators, vec_graph::VecGraph, *},
+};
 use rustc_index::{
   bit_set::{BitSet, HybridBitSet, SparseBitMatrix},
   Idx,
 };
+use rustc_middle::mir::{BasicBlock, Body, TerminatorKind};
 use smallvec::SmallVec;
 
+/// Which parts of a MIR body are included in its control-flow graph, see [`BodyCfg`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CfgOptions {
+  /// If true, the edges to cleanup blocks taken when a call unwinds are included, and
+  /// resuming or terminating the unwind is an exit of the body. Otherwise, cleanup
+  /// blocks are ignored.
+  pub include_unwind: bool,
+}
+
+/// The control-flow graph of a MIR body, with the edges selected by [`CfgOptions`].
+pub struct BodyCfg<'a, 'tcx> {
+  body: &'a Body<'tcx>,
+  options: CfgOptions,
+}
+
+impl<'a, 'tcx> BodyCfg<'a, 'tcx> {
+  pub fn new(body: &'a Body<'tcx>, options: CfgOptions) -> Self {
+    BodyCfg { body, options }
+  }
+
+  fn has_edge(&self, from: BasicBlock, to: BasicBlock) -> bool {
+    let blocks = &self.body.basic_blocks;
+    self.options.include_unwind || !(blocks[from].is_cleanup || blocks[to].is_cleanup)
+  }
+
+  /// Returns the blocks that exit the body, e.g. with a [`TerminatorKind::Return`].
+  pub fn exits(&self) -> impl Iterator<Item = BasicBlock> + Captures<'tcx> + '_ {
+    self
+      .body
+      .basic_blocks
+      .iter_enumerated()
+      .filter(|(_, data)| self.options.include_unwind || !data.is_cleanup)
+      .filter_map(|(block, data)| match data.terminator().kind {
+        TerminatorKind::Return
+        | TerminatorKind::TailCall { .. }
+        | TerminatorKind::CoroutineDrop => Some(block),
+        TerminatorKind::UnwindResume | TerminatorKind::UnwindTerminate(_)
+          if self.options.include_unwind =>
+        {
+          Some(block)
+        }
+        _ => None,
+      })
+  }
+}
+
+impl DirectedGraph for BodyCfg<'_, '_> {
+  type Node = BasicBlock;
+
+  fn num_nodes(&self) -> usize {
+    self.body.basic_blocks.len()
+  }
+}
+
+impl StartNode for BodyCfg<'_, '_> {
+  fn start_node(&self) -> Self::Node {
+    self.body.basic_blocks.start_node()
+  }
+}
+
+impl Successors for BodyCfg<'_, '_> {
+  fn successors(&self, node: Self::Node) -> impl Iterator<Item = Self::Node> {
+    self.body.basic_blocks[node]
+      .terminator()
+      .successors()
+      .filter(|succ| self.has_edge(node, *succ))
+      .collect::<SmallVec<[BasicBlock; 4]>>()
+      .into_iter()
+  }
+}
+
+impl Predecessors for BodyCfg<'_, '_> {
+  fn predecessors(&self, node: Self::Node) -> impl Iterator<Item = Self::Node> {
+    self.body.basic_blocks.predecessors()[node]
+      .iter()
+      .copied()
+      .filter(|pred| self.has_edge(*pred, node))
+      .collect::<SmallVec<[BasicBlock; 4]>>()
+      .into_iter()
+  }
+}
+
+/// A graph with an additional exit node, whose predecessors are the exits of the graph.
+struct ExitGraph<'a, G: ControlFlowGraph> {
+  graph: &'a G,
+  exits: Vec<G::Node>,
+}
+
+impl<G: ControlFlowGraph> ExitGraph<'_, G> {
+  fn exit(&self) -> G::Node {
+    G::Node::new(self.graph.num_nodes())
+  }
+}
+
+impl<G: ControlFlowGraph> DirectedGraph for ExitGraph<'_, G> {
+  type Node = G::Node;
+
+  fn num_nodes(&self) -> usize {
+    self.graph.num_nodes() + 1
+  }
+}
+
+impl<G: ControlFlowGraph> StartNode for ExitGraph<'_, G> {
+  fn start_node(&self) -> Self::Node {
+    self.graph.start_node()
+  }
+}
+
+impl<G: ControlFlowGraph> Successors for ExitGraph<'_, G> {
+  fn successors(&self, node: Self::Node) -> impl Iterator<Item = Self::Node> {
+    let mut succs = SmallVec::<[G::Node; 4]>::new();
+    if node != self.exit() {
+      succs.extend(self.graph.successors(node));
+      if self.exits.contains(&node) {
+        succs.push(self.exit());
+      }
+    }
+    succs.into_iter()
+  }
+}
+
+impl<G: ControlFlowGraph> Predecessors for ExitGraph<'_, G> {
+  fn predecessors(&self, node: Self::Node) -> impl Iterator<Item = Self::Node> {
+    let mut preds = SmallVec::<[G::Node; 4]>::new();
+    if node == self.exit() {
+      preds.extend(self.exits.iter().copied());
+    } else {
+      preds.extend(self.graph.predecessors(node));
+    }
+    preds.into_iter()
+  }
+}
+
 struct ReversedGraph<'a, G: ControlFlowGraph> {
   graph: &'a G,
   exit: G::Node,
@@ -60,10 +202,13 @@ impl<G: ControlFlowGraph> Predecessors for ReversedGraph<'_, G> {
   }
 }
 
-/// Represents the post-dominators of a graph's nodes with respect to a particular exit.
+/// Represents the post-dominators of a graph's nodes with respect to a particular exit,
+/// or to a set of exits.
 pub struct PostDominators<Node: Idx> {
   dominators: Dominators<Node>,
   num_nodes: usize,
+  /// The virtual exit node added by [`PostDominators::build_many`].
+  virtual_exit: Option<Node>,
 }
 
 impl<Node: Idx> PostDominators<Node> {
@@ -86,12 +231,60 @@ impl<Node: Idx> PostDominators<Node> {
     PostDominators {
       dominators,
       num_nodes,
+      virtual_exit: None,
+    }
+  }
+
+  /// Constructs the post-dominators with respect to every node in `exits`. The exits
+  /// are the roots of the post-dominator tree.
+  pub fn build_many<G: ControlFlowGraph<Node = Node>>(
+    graph: &G,
+    exits: impl IntoIterator<Item = Node>,
+  ) -> Self {
+    let graph = ExitGraph {
+      graph,
+      exits: exits.into_iter().collect(),
+    };
+    let exit = graph.exit();
+    let post_dominators = PostDominators::build(&graph, exit);
+    PostDominators {
+      num_nodes: graph.graph.num_nodes(),
+      virtual_exit: Some(exit),
+      ..post_dominators
     }
   }
 
   /// Gets the node that immediately post-dominators `node`, if one exists.
   pub fn immediate_post_dominator(&self, node: Node) -> Option<Node> {
-    self.dominators.immediate_dominator(node)
+    self
+      .dominators
+      .immediate_dominator(node)
+      .filter(|idom| Some(*idom) != self.virtual_exit)
+  }
+
+  /// Returns true if `a` post-dominates `b`, i.e. every path from `b` to an exit goes
+  /// through `a`. Always false if `b` cannot reach an exit.
+  pub fn post_dominates(&self, a: Node, b: Node) -> bool {
+    self.dominators.is_reachable(b) && self.dominators.dominates(a, b)
+  }
+
+  /// Gets the children of `node` in the post-dominator tree, i.e. the nodes that `node`
+  /// immediately post-dominates.
+  pub fn children(&self, node: Node) -> impl Iterator<Item = Node> + '_ {
+    (0 .. self.num_nodes)
+      .map(Node::new)
+      .filter(move |child| self.immediate_post_dominator(*child) == Some(node))
+  }
+
+  /// Gets the roots of the post-dominator tree, i.e. the exits that can be reached.
+  pub fn roots(&self) -> impl Iterator<Item = Node> + '_ {
+    (0 .. self.num_nodes).map(Node::new).filter(move |node| {
+      let idom = self.dominators.immediate_dominator(*node);
+      match self.virtual_exit {
+        Some(exit) => idom == Some(exit),
+        None => idom.is_none() && self.dominators.is_reachable(*node),
+      }
+    })
   }
 
   /// Gets all nodes that post-dominate `node`, if they exist.
@@ -173,36 +366,38 @@ impl<Node: Idx + Ord> ControlDependencies<Node> {
     ControlDependencies(df)
   }
 
-  /// Compute the union of control dependencies from multiple exits.
+  /// Compute control dependencies with respect to multiple exits.
   pub fn build_many<G: ControlFlowGraph<Node = Node>>(
     graph: &G,
     exits: impl IntoIterator<Item = Node>,
   ) -> Self {
-    let mut all_deps = SparseBitMatrix::new(graph.num_nodes());
-    for exit in exits {
-      let deps = ControlDependencies::build(graph, exit);
-      for node in deps.0.rows() {
-        if let Some(set) = deps.0.row(node) {
-          all_deps.union_row(node, set);
-        }
-      }
-    }
-    ControlDependencies(all_deps)
+    let graph = ExitGraph {
+      graph,
+      exits: exits.into_iter().collect(),
+    };
+    ControlDependencies::build(&graph, graph.exit())
   }
 
-  /// Returns the set of all node that are control-dependent on the given `node`.
+  /// Returns the set of all nodes that the given `node` is control-dependent on.
   pub fn dependent_on(&self, node: Node) -> Option<&HybridBitSet<Node>> {
     self.0.row(node)
   }
+
+  /// Returns true if `a` is control-dependent on `b`, i.e. whether `a` executes
+  /// depends on the branch taken at `b`.
+  pub fn is_control_dependent(&self, a: Node, b: Node) -> bool {
+    self.0.contains(a, b)
+  }
 }
 
 #[cfg(test)]
 mod test {
   use log::debug;
   use rustc_data_structures::fx::{FxHashMap as HashMap, FxHashSet as HashSet};
-  use rustc_middle::mir::Location;
+  use rustc_middle::mir::{Location, TerminatorKind};
   use test_log::test;
 
+  use super::CfgOptions;
   use crate::{test_utils, BodyExt};
 
   #[test]
@@ -287,4 +482,53 @@ mod test {
       }
     });
   }
+
+  #[test]
+  fn test_post_dominators() {
+    let input = r#"
+    fn main() {
+      let s = String::new();
+      if s.is_empty() { panic!(); }
+      s.len();
+    }"#;
+    test_utils::compile_body(input, move |_, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let blocks_with = |f: fn(&TerminatorKind<'_>) -> bool| {
+        body
+          .basic_blocks
+          .iter_enumerated()
+          .filter(|(_, data)| f(&data.terminator().kind))
+          .map(|(block, _)| block)
+          .collect::<Vec<_>>()
+      };
+      let returns = blocks_with(|kind| matches!(kind, TerminatorKind::Return));
+      let resumes = blocks_with(|kind| matches!(kind, TerminatorKind::UnwindResume));
+      let panics =
+        blocks_with(|kind| matches!(kind, TerminatorKind::Call { target: None, .. }));
+      let switches = blocks_with(|kind| matches!(kind, TerminatorKind::SwitchInt { .. }));
+      assert_eq!((returns.len(), panics.len(), switches.len()), (1, 1, 1));
+      assert!(!resumes.is_empty());
+      let (ret, panic, switch) = (returns[0], panics[0], switches[0]);
+
+      // Without unwind edges, the panic never reaches an exit.
+      let post_doms = body.post_dominators(CfgOptions::default());
+      assert_eq!(post_doms.roots().collect::<Vec<_>>(), [ret]);
+      assert!(post_doms.post_dominators(panic).is_none());
+      assert!(post_doms.post_dominates(ret, switch));
+      assert!(post_doms.children(ret).next().is_some());
+
+      let options = CfgOptions {
+        include_unwind: true,
+      };
+      let post_doms = body.post_dominators(options);
+      let roots = post_doms.roots().collect::<HashSet<_>>();
+      assert!(roots.contains(&ret) && resumes.iter().all(|bb| roots.contains(bb)));
+      assert!(post_doms.post_dominators(panic).is_some());
+      assert!(!post_doms.post_dominates(ret, switch));
+
+      let control_deps = body.control_dependencies_with(options);
+      assert!(control_deps.is_control_dependent(panic, switch));
+      assert!(!control_deps.is_control_dependent(switch, panic));
+    });
+  }
 }
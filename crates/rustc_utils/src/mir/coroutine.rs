@@ -0,0 +1,273 @@
+//! Utilities for the coroutines (formerly generators) that async functions and blocks
+//! are lowered to.
+//!
+//! An `async fn` is lowered to a function that only creates a coroutine, whose body
+//! contains the function's code. Each `.await` in it is lowered to a loop that polls
+//! the awaited future and yields, and the arguments of the function become upvars of
+//! the coroutine that are moved into locals at its start.
+
+use rustc_data_structures::fx::FxHashMap as HashMap;
+use rustc_hir::{
+  def_id::LocalDefId,
+  intravisit::{self, Visitor},
+  ClosureKind, CoroutineDesugaring, CoroutineKind, CoroutineSource, Expr, ExprKind,
+  HirId, MatchSource,
+};
+use rustc_middle::{
+  mir::{Body, Local, Location, Operand, ProjectionElem, Rvalue, RETURN_PLACE},
+  ty::TyCtxt,
+};
+use rustc_span::{DesugaringKind, Span, Symbol};
+use rustc_target::abi::FieldIdx;
+
+use crate::BodyExt;
+
+/// Returns the coroutine that contains the body of the async function `def_id`, or
+/// `None` if `def_id` is not an async function.
+pub fn async_body(tcx: TyCtxt<'_>, def_id: LocalDefId) -> Option<LocalDefId> {
+  let body = tcx.hir().maybe_body_owned_by(def_id)?;
+  match body.value.kind {
+    ExprKind::Closure(closure)
+      if matches!(
+        closure.kind,
+        ClosureKind::Coroutine(CoroutineKind::Desugared(
+          CoroutineDesugaring::Async,
+          CoroutineSource::Fn
+        ))
+      ) =>
+    {
+      Some(closure.def_id)
+    }
+    _ => None,
+  }
+}
+
+/// Returns the async function whose body is the coroutine `def_id`, i.e. the inverse
+/// of [`async_body`].
+pub fn async_fn_of(tcx: TyCtxt<'_>, def_id: LocalDefId) -> Option<LocalDefId> {
+  match tcx.coroutine_kind(def_id) {
+    Some(CoroutineKind::Desugared(CoroutineDesugaring::Async, CoroutineSource::Fn)) => {
+      Some(tcx.local_parent(def_id))
+    }
+    _ => None,
+  }
+}
+
+/// A variable captured by a coroutine or closure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capture {
+  /// The field of the upvar in the coroutine.
+  pub field: FieldIdx,
+  /// The name of the captured place, e.g. `x` or `x.0`.
+  pub name: Symbol,
+  /// The captured variable.
+  pub var: HirId,
+}
+
+/// Returns the locals of the coroutine or closure `def_id` that are initialized with
+/// an upvar, e.g. the locals of the arguments of an async function, along with the
+/// variable captured by the upvar.
+pub fn captured_locals(
+  tcx: TyCtxt<'_>,
+  def_id: LocalDefId,
+  body: &Body<'_>,
+) -> HashMap<Local, Capture> {
+  let captures = tcx.closure_captures(def_id);
+  let upvars = Local::from_usize(1);
+  let mut locals = HashMap::default();
+  for location in body.all_locations() {
+    let Some(statement) = body.stmt_at(location).left() else {
+      continue;
+    };
+    let Some((place, Rvalue::Use(Operand::Copy(upvar) | Operand::Move(upvar)))) =
+      statement.kind.as_assign()
+    else {
+      continue;
+    };
+    let (Some(local), [ProjectionElem::Field(field, _)]) =
+      (place.as_local(), upvar.projection.as_slice())
+    else {
+      continue;
+    };
+    if upvar.local != upvars || local == RETURN_PLACE {
+      continue;
+    }
+    let Some(captured) = captures.get(field.as_usize()) else {
+      continue;
+    };
+    locals.entry(local).or_insert(Capture {
+      field: *field,
+      name: captured.to_symbol(),
+      var: captured.get_root_variable(),
+    });
+  }
+  locals
+}
+
+/// Maps the locations of a coroutine to source spans that a user would recognize, see
+/// [`AwaitMap::user_span`].
+pub struct AwaitMap {
+  awaits: Vec<Span>,
+}
+
+impl AwaitMap {
+  /// Finds the `.await` expressions in the body of `def_id`, excluding those of
+  /// nested closures and coroutines.
+  pub fn build(tcx: TyCtxt<'_>, def_id: LocalDefId) -> Self {
+    struct AwaitFinder {
+      awaits: Vec<Span>,
+    }
+
+    impl Visitor<'_> for AwaitFinder {
+      fn visit_expr(&mut self, expr: &Expr) {
+        // The loop in the arm of an `.await` contains another await-desugared match
+        // of the poll, so only the awaited expression can contain other awaits.
+        if let ExprKind::Match(awaited, _, MatchSource::AwaitDesugar) = expr.kind {
+          self.awaits.push(expr.span.source_callsite());
+          self.visit_expr(awaited);
+        } else {
+          intravisit::walk_expr(self, expr);
+        }
+      }
+    }
+
+    let mut finder = AwaitFinder { awaits: Vec::new() };
+    if let Some(body) = tcx.hir().maybe_body_owned_by(def_id) {
+      finder.visit_expr(body.value);
+    }
+    AwaitMap {
+      awaits: finder.awaits,
+    }
+  }
+
+  /// Returns the `.await` expressions of the body, e.g. `fut.await`.
+  pub fn awaits(&self) -> &[Span] {
+    &self.awaits
+  }
+
+  /// Returns the innermost `.await` expression that contains `span`, if `span` is
+  /// produced by lowering an `.await`.
+  pub fn await_of(&self, span: Span) -> Option<Span> {
+    if span.desugaring_kind() != Some(DesugaringKind::Await) {
+      return None;
+    }
+    let span = span.source_callsite();
+    self
+      .awaits
+      .iter()
+      .filter(|await_span| await_span.contains(span))
+      .min_by_key(|await_span| await_span.hi() - await_span.lo())
+      .copied()
+  }
+
+  /// Returns the span of `location` in the user's code. The locations produced by
+  /// lowering an `.await`, such as the calls to `poll` and the yield, are mapped to
+  /// the `.await` expression, and other compiler-generated code is mapped to the code
+  /// that it was generated from.
+  pub fn user_span(&self, body: &Body<'_>, location: Location) -> Span {
+    let span = body.source_info(location).span;
+    self
+      .await_of(span)
+      .unwrap_or_else(|| span.source_callsite())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::mir::{TerminatorKind, VarDebugInfoContents};
+
+  use super::*;
+  use crate::{
+    mir::borrowck_facts::get_body_with_borrowck_facts, test_utils::CompileBuilder,
+  };
+
+  #[test]
+  fn test_async_fn() {
+    let input = r#"
+async fn f() -> i32 { 1 }
+async fn g(x: i32, (y, z): (i32, i32)) -> i32 {
+  let a = f().await;
+  let b = f().await + x + y;
+  a + b
+}
+fn h() {}
+async fn id(n: i32) -> i32 { n }
+async fn k() -> i32 { id(f().await).await }
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let tcx = result.tcx;
+      let find = |name: &str| {
+        tcx
+          .hir()
+          .body_owners()
+          .find(|def_id| tcx.def_path_str(*def_id) == name)
+          .unwrap()
+      };
+      let g = find("g");
+      let coroutine = async_body(tcx, g).unwrap();
+      assert_eq!(async_fn_of(tcx, coroutine), Some(g));
+      assert_eq!(async_body(tcx, find("h")), None);
+      assert_eq!(async_fn_of(tcx, g), None);
+
+      let body = &get_body_with_borrowck_facts(tcx, coroutine).body;
+      let local_name = |local: Local| {
+        body
+          .var_debug_info
+          .iter()
+          .find(|info| {
+            matches!(
+              info.value,
+              VarDebugInfoContents::Place(place) if place.as_local() == Some(local)
+            )
+          })
+          .map(|info| info.name.to_string())
+      };
+      let captured = captured_locals(tcx, coroutine, body);
+      let mut names = captured
+        .iter()
+        .map(|(local, capture)| (local_name(*local).unwrap(), capture.name.to_string()))
+        .collect::<Vec<_>>();
+      names.sort();
+      assert_eq!(names, [
+        ("__arg1".to_string(), "__arg1".to_string()),
+        ("x".to_string(), "x".to_string())
+      ]);
+
+      let source_map = tcx.sess.source_map();
+      let snippet = |span| source_map.span_to_snippet(span).unwrap();
+      let awaits = AwaitMap::build(tcx, coroutine);
+      let await_snippets = awaits
+        .awaits()
+        .iter()
+        .map(|span| snippet(*span))
+        .collect::<Vec<_>>();
+      assert_eq!(await_snippets, ["f().await", "f().await"]);
+
+      let yields = body
+        .basic_blocks
+        .iter_enumerated()
+        .filter(|(_, data)| {
+          matches!(data.terminator().kind, TerminatorKind::Yield { .. })
+        })
+        .map(|(block, _)| awaits.user_span(body, body.terminator_loc(block)))
+        .collect::<Vec<_>>();
+      assert_eq!(yields.len(), 2);
+      assert_ne!(yields[0], yields[1]);
+      assert!(yields.iter().all(|span| snippet(*span) == "f().await"));
+
+      for location in body.all_locations() {
+        let span = awaits.user_span(body, location);
+        assert!(!span.from_expansion(), "{span:?}");
+      }
+
+      let coroutine = async_body(tcx, find("k")).unwrap();
+      let awaits = AwaitMap::build(tcx, coroutine);
+      let await_snippets = awaits
+        .awaits()
+        .iter()
+        .map(|span| snippet(*span))
+        .collect::<Vec<_>>();
+      assert_eq!(await_snippets, ["id(f().await).await", "f().await"]);
+    });
+  }
+}
@@ -0,0 +1,136 @@
+//! Conveniences for running analyses from [`rustc_mir_dataflow`].
+
+use rustc_middle::{mir::Body, ty::TyCtxt};
+use rustc_mir_dataflow::{
+  fmt::{DebugWithAdapter, DebugWithContext},
+  Analysis, Results, ResultsCursor,
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::BodyExt;
+
+/// Runs `analysis` on `body` to a fixpoint.
+pub fn run_dataflow<'tcx, A>(
+  tcx: TyCtxt<'tcx>,
+  body: &Body<'tcx>,
+  analysis: A,
+) -> Results<'tcx, A>
+where
+  A: Analysis<'tcx>,
+  A::Domain: DebugWithContext<A>,
+{
+  analysis.into_engine(tcx, body).iterate_to_fixpoint()
+}
+
+/// Runs `analysis` on `body` to a fixpoint and returns a cursor over its results.
+pub fn run_dataflow_cursor<'mir, 'tcx, A>(
+  tcx: TyCtxt<'tcx>,
+  body: &'mir Body<'tcx>,
+  analysis: A,
+) -> ResultsCursor<'mir, 'tcx, A>
+where
+  A: Analysis<'tcx>,
+  A::Domain: DebugWithContext<A>,
+{
+  run_dataflow(tcx, body, analysis).into_results_cursor(body)
+}
+
+/// The formatted dataflow state around a location, e.g. for snapshot tests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct StateSnapshot {
+  pub block: usize,
+  pub statement_index: usize,
+  /// The state before the location's effect.
+  pub before: String,
+  /// The state after the location's effect.
+  pub after: String,
+}
+
+/// Formats the state of `results` before and after every location of `body`, in
+/// order of [`BodyExt::all_locations`].
+pub fn state_snapshots<'tcx, A>(
+  body: &Body<'tcx>,
+  results: Results<'tcx, A>,
+) -> Vec<StateSnapshot>
+where
+  A: Analysis<'tcx>,
+  A::Domain: DebugWithContext<A>,
+{
+  let mut cursor = results.into_results_cursor(body);
+  let format = |cursor: &ResultsCursor<'_, 'tcx, A>| {
+    format!("{:?}", DebugWithAdapter {
+      this: cursor.get(),
+      ctxt: cursor.analysis()
+    })
+  };
+  body
+    .all_locations()
+    .map(|location| {
+      cursor.seek_before_primary_effect(location);
+      let before = format(&cursor);
+      cursor.seek_after_primary_effect(location);
+      let after = format(&cursor);
+      StateSnapshot {
+        block: location.block.as_usize(),
+        statement_index: location.statement_index,
+        before,
+        after,
+      }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::mir::Location;
+  use rustc_mir_dataflow::impls::MaybeBorrowedLocals;
+
+  use super::*;
+  use crate::test_utils::{self, Placer};
+
+  #[test]
+  fn test_dataflow() {
+    let input = r#"
+fn main() {
+  let x = 1;
+  let y = &x;
+}
+"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let x = Placer::new(tcx, body).local("x").mk().local;
+      let source_map = tcx.sess.source_map();
+      let borrow = body
+        .all_locations()
+        .find(|location: &Location| {
+          source_map
+            .span_to_snippet(body.source_info(*location).span)
+            .unwrap()
+            == "&x"
+        })
+        .unwrap();
+
+      let mut cursor = run_dataflow_cursor(tcx, body, MaybeBorrowedLocals);
+      cursor.seek_before_primary_effect(borrow);
+      assert!(cursor.get().is_empty());
+      cursor.seek_after_primary_effect(borrow);
+      assert!(cursor.get().contains(x));
+      assert_eq!(cursor.get().count(), 1);
+
+      let results = run_dataflow(tcx, body, MaybeBorrowedLocals);
+      let snapshots = state_snapshots(body, results);
+      assert_eq!(snapshots.len(), body.all_locations().count());
+      let snapshot = snapshots
+        .iter()
+        .find(|snapshot| {
+          snapshot.block == borrow.block.as_usize()
+            && snapshot.statement_index == borrow.statement_index
+        })
+        .unwrap();
+      assert_eq!(snapshot.before, "{}");
+      assert_eq!(snapshot.after, format!("{{{x:?}}}"));
+    });
+  }
+}
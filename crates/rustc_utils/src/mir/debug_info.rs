@@ -0,0 +1,307 @@
+//! Mapping MIR places to the source variables they hold, based on a body's
+//! [`VarDebugInfo`](rustc_middle::mir::VarDebugInfo).
+//!
+//! Each source variable is described by one or more debug info entries. Usually the
+//! variable lives in a single place, e.g. a local or, for a captured variable, a field
+//! of a closure's upvars. Optimizations like scalar replacement of aggregates split a
+//! variable across several locals, and then each entry describes a fragment of the
+//! variable, e.g. the local holding `p.x`.
+
+use rustc_data_structures::fx::FxHashMap as HashMap;
+use rustc_middle::{
+  mir::{
+    tcx::PlaceTy, Body, Local, Place, PlaceElem, ProjectionElem, SourceScope,
+    VarDebugInfoContents,
+  },
+  ty::{AdtKind, Ty, TyCtxt, TyKind},
+};
+use rustc_span::{Span, Symbol};
+
+/// A variable of the source code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugVar<'tcx> {
+  /// The name of the variable.
+  pub name: Symbol,
+  /// The span of the variable's declaration.
+  pub span: Span,
+  /// The scope in which the variable is visible.
+  pub scope: SourceScope,
+  /// The type of the variable.
+  pub ty: Ty<'tcx>,
+  /// The 1-based index of the argument if the variable is an argument.
+  pub argument_index: Option<u16>,
+}
+
+/// A place as a projection of a source variable, see [`DebugInfoMap::lookup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarPlace<'a, 'tcx> {
+  pub var: &'a DebugVar<'tcx>,
+  /// The projection from the variable to the place, e.g. `[.0]` for `x.0`.
+  pub projection: Vec<PlaceElem<'tcx>>,
+}
+
+struct Fragment<'tcx> {
+  var: usize,
+  // The projection from the local to the fragment.
+  place: &'tcx [PlaceElem<'tcx>],
+  // The projection from the variable to the fragment.
+  projection: Vec<PlaceElem<'tcx>>,
+}
+
+/// The source variables of a body and the places that hold them, see the
+/// [module documentation](self).
+pub struct DebugInfoMap<'tcx> {
+  tcx: TyCtxt<'tcx>,
+  vars: Vec<DebugVar<'tcx>>,
+  fragments: HashMap<Local, Vec<Fragment<'tcx>>>,
+}
+
+impl<'tcx> DebugInfoMap<'tcx> {
+  /// Collects the variables described by the debug info of `body`.
+  pub fn build(tcx: TyCtxt<'tcx>, body: &Body<'tcx>) -> Self {
+    let mut vars: Vec<DebugVar<'tcx>> = Vec::new();
+    let mut fragments: HashMap<Local, Vec<Fragment<'tcx>>> = HashMap::default();
+    for info in &body.var_debug_info {
+      let ty = match (&info.composite, &info.value) {
+        (Some(composite), _) => composite.ty,
+        (None, VarDebugInfoContents::Place(place)) => place.ty(body, tcx).ty,
+        (None, VarDebugInfoContents::Const(constant)) => constant.ty(),
+      };
+      let var = DebugVar {
+        name: info.name,
+        span: info.source_info.span,
+        scope: info.source_info.scope,
+        ty,
+        argument_index: info.argument_index,
+      };
+
+      // The fragments of a variable have one entry each with the same source info.
+      let index = match vars.iter().position(|other| *other == var) {
+        Some(index) if info.composite.is_some() => index,
+        _ => {
+          vars.push(var);
+          vars.len() - 1
+        }
+      };
+
+      if let VarDebugInfoContents::Place(place) = info.value {
+        fragments.entry(place.local).or_default().push(Fragment {
+          var: index,
+          place: place.projection,
+          projection: info
+            .composite
+            .as_ref()
+            .map(|composite| composite.projection.clone())
+            .unwrap_or_default(),
+        });
+      }
+    }
+    DebugInfoMap {
+      tcx,
+      vars,
+      fragments,
+    }
+  }
+
+  /// Returns every variable of the body, including variables that were optimized to
+  /// constants and so are not held by any place.
+  pub fn vars(&self) -> &[DebugVar<'tcx>] {
+    &self.vars
+  }
+
+  /// Returns the variable held by `local`, if the local holds an entire variable.
+  pub fn var_of_local(&self, local: Local) -> Option<&DebugVar<'tcx>> {
+    let var_place = self.lookup(Place::from(local))?;
+    var_place.projection.is_empty().then_some(var_place.var)
+  }
+
+  /// Returns `place` as a projection of the variable that contains it, or `None` if
+  /// it is not within a variable, e.g. because it is a temporary.
+  pub fn lookup(&self, place: Place<'tcx>) -> Option<VarPlace<'_, 'tcx>> {
+    let fragment = self
+      .fragments
+      .get(&place.local)?
+      .iter()
+      .filter(|fragment| place.projection.starts_with(fragment.place))
+      .max_by_key(|fragment| fragment.place.len())?;
+    let projection = fragment
+      .projection
+      .iter()
+      .chain(&place.projection[fragment.place.len() ..])
+      .copied()
+      .collect();
+    Some(VarPlace {
+      var: &self.vars[fragment.var],
+      projection,
+    })
+  }
+
+  /// Returns a representation of `place` in terms of source variables, e.g. `x.field`
+  /// or `(*x).0`, or `None` if it is not within a variable.
+  pub fn place_to_string(&self, place: Place<'tcx>) -> Option<String> {
+    let VarPlace { var, projection } = self.lookup(place)?;
+    let mut string = var.name.to_string();
+    let mut place_ty = PlaceTy::from_ty(var.ty);
+    for (index, elem) in projection.iter().enumerate() {
+      match elem {
+        ProjectionElem::Deref => {
+          let is_prefix = matches!(
+            projection.get(index + 1),
+            Some(next) if *next != ProjectionElem::Deref
+          );
+          string = if is_prefix {
+            format!("(*{string})")
+          } else {
+            format!("*{string}")
+          };
+        }
+        ProjectionElem::Field(field, _) => {
+          let name = match place_ty.ty.kind() {
+            TyKind::Adt(def, _) if def.adt_kind() != AdtKind::Enum => {
+              def.non_enum_variant().fields[*field]
+                .ident(self.tcx)
+                .to_string()
+            }
+            TyKind::Adt(def, _) => match place_ty.variant_index {
+              Some(variant) => def.variant(variant).fields[*field]
+                .ident(self.tcx)
+                .to_string(),
+              None => field.as_usize().to_string(),
+            },
+            TyKind::Closure(def_id, _) => match def_id.as_local() {
+              Some(def_id) => self.tcx.closure_captures(def_id)[field.as_usize()]
+                .var_ident
+                .to_string(),
+              None => field.as_usize().to_string(),
+            },
+            _ => field.as_usize().to_string(),
+          };
+          string = format!("{string}.{name}");
+        }
+        ProjectionElem::Downcast(symbol, _) => {
+          let variant = symbol.map(|s| s.to_string()).unwrap_or_else(|| "??".into());
+          string = format!("{string}@{variant}");
+        }
+        ProjectionElem::Index(_) => string.push_str("[_]"),
+        ProjectionElem::ConstantIndex {
+          offset, from_end, ..
+        } => {
+          string = if *from_end {
+            format!("{string}[-{offset}]")
+          } else {
+            format!("{string}[{offset}]")
+          }
+        }
+        ProjectionElem::Subslice { .. } => string.push_str("[..]"),
+        ProjectionElem::OpaqueCast(_) | ProjectionElem::Subtype(_) => {}
+      }
+      place_ty = place_ty.projection_ty(self.tcx, *elem);
+    }
+    Some(string)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::mir::PlaceElem;
+  use rustc_target::abi::FieldIdx;
+
+  use super::*;
+  use crate::{
+    test_utils::{self, CompileBuilder, Placer},
+    BodyExt,
+  };
+
+  #[test]
+  fn test_debug_info_map() {
+    let input = r#"
+struct Point { x: i32, y: i32 }
+fn main() {
+  let p = Point { x: 1, y: 2 };
+  let r = &p;
+  let t = (0, Some(p.x));
+  let n = r.y;
+}
+"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let map = DebugInfoMap::build(tcx, body);
+      let names = map
+        .vars()
+        .iter()
+        .map(|var| var.name.to_string())
+        .collect::<Vec<_>>();
+      assert_eq!(names, ["p", "r", "t", "n"]);
+
+      let name_map = body.debug_info_name_map();
+      let p = map.var_of_local(name_map["p"]).unwrap();
+      assert_eq!(p.name.as_str(), "p");
+      assert_eq!(tcx.sess.source_map().span_to_snippet(p.span).unwrap(), "p");
+
+      let placer = Placer::new(tcx, body);
+      let render = |place| map.place_to_string(place).unwrap();
+      assert_eq!(render(placer.local("p").field(1).mk()), "p.y");
+      assert_eq!(render(placer.local("r").deref().field(0).mk()), "(*r).x");
+      assert_eq!(render(placer.local("r").deref().mk()), "*r");
+
+      let t = placer.local("t").field(1).mk();
+      let downcast = t.project_deeper(
+        &[
+          PlaceElem::Downcast(Some(Symbol::intern("Some")), 1u32.into()),
+          PlaceElem::Field(FieldIdx::from_usize(0), tcx.types.i32),
+        ],
+        tcx,
+      );
+      assert_eq!(render(downcast), "t.1@Some.0");
+
+      let temp = body
+        .local_decls
+        .indices()
+        .find(|local| !body.local_decls[*local].is_user_variable())
+        .unwrap();
+      assert!(map.lookup(Place::from(temp)).is_none());
+    });
+  }
+
+  #[test]
+  fn test_composite_debug_info() {
+    let input = r#"
+struct Point { x: i32, y: i32 }
+#[inline(never)]
+fn id(n: i32) -> i32 { n }
+pub fn sum(a: i32, b: i32) -> i32 {
+  let p = Point { x: a, y: b };
+  id(p.x) + id(p.y)
+}
+"#;
+    CompileBuilder::new(input)
+      .with_args(["-Zmir-opt-level=2".to_string()])
+      .compile(|result| {
+        let tcx = result.tcx;
+        let sum = tcx
+          .hir()
+          .body_owners()
+          .find(|def_id| tcx.def_path_str(*def_id) == "sum")
+          .unwrap();
+        let body = tcx.optimized_mir(sum);
+        let map = DebugInfoMap::build(tcx, body);
+        let p = map
+          .vars()
+          .iter()
+          .find(|var| var.name.as_str() == "p")
+          .unwrap();
+        assert!(matches!(p.ty.kind(), TyKind::Adt(..)));
+
+        // The fields of `p` are split into locals of their own.
+        let fields = body
+          .local_decls
+          .indices()
+          .filter_map(|local| {
+            let var_place = map.lookup(Place::from(local))?;
+            (var_place.var == p).then(|| map.place_to_string(Place::from(local)).unwrap())
+          })
+          .collect::<Vec<_>>();
+        assert_eq!(fields, ["p.x", "p.y"]);
+      });
+  }
+}
@@ -0,0 +1,394 @@
+//! Definitions and uses of places in a MIR body.
+//!
+//! A definition of a place is a statement or terminator that writes to it, e.g. an
+//! assignment or the destination of a call, or the start of the function for an
+//! argument. A use is any other access that reads it, including borrows and drops.
+//! Places are matched with a [`ConflictChecker`], so a definition of `x.0` is a definition
+//! of `x`, and a use of `x` is a use of `x.0`. Dereferences of different locals are not
+//! considered to conflict, i.e. the analysis does not account for aliasing.
+
+use rustc_data_structures::fx::{FxHashMap as HashMap, FxHashSet as HashSet};
+use rustc_index::bit_set::BitSet;
+use rustc_middle::{
+  mir::{
+    visit::{MutatingUseContext, NonMutatingUseContext, PlaceContext, Visitor},
+    Body, Local, Location, Place, ProjectionElem, Statement, StatementKind, START_BLOCK,
+  },
+  ty::TyCtxt,
+};
+
+use super::{conflict::ConflictChecker, location_or_arg::LocationOrArg};
+use crate::PlaceExt;
+
+/// A write to a place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Def<'tcx> {
+  /// The place that is written to.
+  pub place: Place<'tcx>,
+  /// Where the place is written, or the argument it is a projection of.
+  pub location: LocationOrArg,
+  /// True if the write overwrites the whole place, e.g. an assignment, rather than
+  /// only a part of it, e.g. setting the discriminant of an enum.
+  pub overwrites: bool,
+}
+
+/// A read of a place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Use<'tcx> {
+  /// The place that is read.
+  pub place: Place<'tcx>,
+  /// Where the place is read.
+  pub location: Location,
+  /// How the place is read.
+  pub context: PlaceContext,
+}
+
+/// The definitions and uses of every local in a body.
+pub struct DefUseAnalysis<'a, 'tcx> {
+  body: &'a Body<'tcx>,
+  defs: HashMap<Local, Vec<Def<'tcx>>>,
+  uses: HashMap<Local, Vec<Use<'tcx>>>,
+  defs_at: HashMap<Location, Vec<Def<'tcx>>>,
+  uses_at: HashMap<Location, Vec<Use<'tcx>>>,
+  checker: ConflictChecker<'a, 'tcx>,
+}
+
+impl<'a, 'tcx> DefUseAnalysis<'a, 'tcx> {
+  /// Collects the definitions and uses of every place in `body`.
+  pub fn new(tcx: TyCtxt<'tcx>, body: &'a Body<'tcx>) -> Self {
+    let mut collector = DefUseCollector {
+      tcx,
+      defs: Vec::new(),
+      uses: Vec::new(),
+    };
+    collector.visit_body(body);
+
+    let arg_defs = body.args_iter().map(|local| Def {
+      place: Place::from_local(local, tcx),
+      location: LocationOrArg::Arg(local),
+      overwrites: true,
+    });
+    let mut defs: HashMap<_, Vec<_>> = HashMap::default();
+    let mut defs_at: HashMap<_, Vec<_>> = HashMap::default();
+    for def in arg_defs.chain(collector.defs) {
+      defs.entry(def.place.local).or_default().push(def);
+      if let LocationOrArg::Location(location) = def.location {
+        defs_at.entry(location).or_default().push(def);
+      }
+    }
+    let mut uses: HashMap<_, Vec<_>> = HashMap::default();
+    let mut uses_at: HashMap<_, Vec<_>> = HashMap::default();
+    for use_ in collector.uses {
+      uses.entry(use_.place.local).or_default().push(use_);
+      uses_at.entry(use_.location).or_default().push(use_);
+    }
+
+    DefUseAnalysis {
+      body,
+      defs,
+      uses,
+      defs_at,
+      uses_at,
+      checker: ConflictChecker::new(tcx, body),
+    }
+  }
+
+  fn conflicts(&self, a: Place<'tcx>, b: Place<'tcx>) -> bool {
+    self.checker.conflicts(a, b)
+  }
+
+  /// Returns every definition of a place that conflicts with `place`.
+  pub fn defs_of(&self, place: Place<'tcx>) -> impl Iterator<Item = &Def<'tcx>> + '_ {
+    self
+      .defs
+      .get(&place.local)
+      .into_iter()
+      .flatten()
+      .filter(move |def| self.conflicts(def.place, place))
+  }
+
+  /// Returns every definition in the body, including those of the arguments.
+  pub fn defs(&self) -> impl Iterator<Item = &Def<'tcx>> + '_ {
+    self.defs.values().flatten()
+  }
+
+  /// Returns the definitions at `location`.
+  pub fn defs_at(&self, location: Location) -> &[Def<'tcx>] {
+    self.defs_at.get(&location).map_or(&[], Vec::as_slice)
+  }
+
+  /// Returns the uses at `location`.
+  pub fn uses_at(&self, location: Location) -> &[Use<'tcx>] {
+    self.uses_at.get(&location).map_or(&[], Vec::as_slice)
+  }
+
+  /// Returns every use of a place that conflicts with `place`.
+  pub fn uses_of(&self, place: Place<'tcx>) -> impl Iterator<Item = &Use<'tcx>> + '_ {
+    self
+      .uses
+      .get(&place.local)
+      .into_iter()
+      .flatten()
+      .filter(move |use_| self.conflicts(use_.place, place))
+  }
+
+  /// Returns the definitions of places conflicting with `place` that reach
+  /// `location`, i.e. that are followed by a path to `location` on which `place` is
+  /// not overwritten. The definitions at `location` itself are not included, unless
+  /// `location` is in a loop.
+  pub fn reaching_defs(
+    &self,
+    location: Location,
+    place: Place<'tcx>,
+  ) -> HashSet<LocationOrArg> {
+    let mut reaching = HashSet::default();
+    let mut visited = BitSet::new_empty(self.body.basic_blocks.len());
+    let mut stack = vec![(location.block, location.statement_index)];
+
+    // Searches backwards from the end of each block, ignoring the location itself.
+    while let Some((block, end)) = stack.pop() {
+      let killed = (0 .. end).rev().any(|statement_index| {
+        let defs = self.defs_at(Location {
+          block,
+          statement_index,
+        });
+        let mut killed = false;
+        for def in defs {
+          if self.conflicts(def.place, place) {
+            reaching.insert(def.location);
+            killed |= def.overwrites && is_prefix_without_deref(def.place, place);
+          }
+        }
+        killed
+      });
+      if killed {
+        continue;
+      }
+
+      if block == START_BLOCK {
+        reaching.extend(
+          self
+            .defs_of(place)
+            .filter(|def| matches!(def.location, LocationOrArg::Arg(_)))
+            .map(|def| def.location),
+        );
+      }
+      for &pred in &self.body.basic_blocks.predecessors()[block] {
+        if visited.insert(pred) {
+          let end = self.body.basic_blocks[pred].statements.len() + 1;
+          stack.push((pred, end));
+        }
+      }
+    }
+
+    reaching
+  }
+}
+
+/// Returns true if `prefix` is a prefix of `place` that doesn't dereference a pointer,
+/// so writing to `prefix` overwrites `place`.
+fn is_prefix_without_deref<'tcx>(prefix: Place<'tcx>, place: Place<'tcx>) -> bool {
+  prefix.local == place.local
+    && prefix.projection.len() <= place.projection.len()
+    && prefix
+      .projection
+      .iter()
+      .zip(place.projection)
+      .all(|(a, b)| {
+        a == b && !matches!(a, ProjectionElem::Deref | ProjectionElem::Index(_))
+      })
+}
+
+struct DefUseCollector<'tcx> {
+  tcx: TyCtxt<'tcx>,
+  defs: Vec<Def<'tcx>>,
+  uses: Vec<Use<'tcx>>,
+}
+
+impl<'tcx> DefUseCollector<'tcx> {
+  fn add_use(&mut self, place: Place<'tcx>, location: Location, context: PlaceContext) {
+    self.uses.push(Use {
+      place,
+      location,
+      context,
+    });
+  }
+}
+
+impl<'tcx> Visitor<'tcx> for DefUseCollector<'tcx> {
+  fn visit_statement(&mut self, statement: &Statement<'tcx>, location: Location) {
+    // These only exist for borrowck and don't read the place.
+    if !matches!(
+      statement.kind,
+      StatementKind::FakeRead(_) | StatementKind::PlaceMention(_)
+    ) {
+      self.super_statement(statement, location);
+    }
+  }
+
+  fn visit_place(
+    &mut self,
+    place: &Place<'tcx>,
+    context: PlaceContext,
+    location: Location,
+  ) {
+    let overwrites = match context {
+      PlaceContext::MutatingUse(
+        MutatingUseContext::Store
+        | MutatingUseContext::AsmOutput
+        | MutatingUseContext::Call
+        | MutatingUseContext::Yield
+        | MutatingUseContext::Deinit,
+      ) => Some(true),
+      PlaceContext::MutatingUse(MutatingUseContext::SetDiscriminant) => Some(false),
+      PlaceContext::MutatingUse(
+        MutatingUseContext::Borrow
+        | MutatingUseContext::RawBorrow
+        | MutatingUseContext::Drop,
+      )
+      | PlaceContext::NonMutatingUse(_) => None,
+      PlaceContext::MutatingUse(
+        MutatingUseContext::Retag | MutatingUseContext::Projection,
+      )
+      | PlaceContext::NonUse(_) => return,
+    };
+
+    match overwrites {
+      Some(overwrites) => self.defs.push(Def {
+        place: *place,
+        location: LocationOrArg::Location(location),
+        overwrites,
+      }),
+      None => self.add_use(*place, location, context),
+    }
+
+    // Writing through a pointer reads the pointer, and indexing reads the index.
+    let copy = PlaceContext::NonMutatingUse(NonMutatingUseContext::Copy);
+    for (prefix, elem) in place.iter_projections() {
+      match elem {
+        ProjectionElem::Deref if overwrites.is_some() => {
+          self.add_use(Place::from_ref(prefix, self.tcx), location, copy)
+        }
+        ProjectionElem::Index(local) => {
+          self.add_use(Place::from_local(local, self.tcx), location, copy)
+        }
+        _ => {}
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_data_structures::fx::FxHashSet as HashSet;
+  use rustc_middle::mir::Location;
+
+  use super::*;
+  use crate::{test_utils, BodyExt};
+
+  #[test]
+  fn test_def_use() {
+    let input = r#"
+fn main() {
+  let mut x = (1, 2);
+  x.0 = 3;
+  let y = x.1;
+  if y > 0 { x = (4, 5); }
+  let z = x.0;
+  let w = &x;
+}"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let analysis = DefUseAnalysis::new(tcx, body);
+      let p = test_utils::Placer::new(tcx, body);
+      let snippet = |location| {
+        tcx
+          .sess
+          .source_map()
+          .span_to_snippet(body.source_info(location).span)
+          .unwrap()
+      };
+      let snippets = |locations: HashSet<LocationOrArg>| {
+        let mut snippets = locations
+          .into_iter()
+          .map(|location| match location {
+            LocationOrArg::Location(location) => snippet(location),
+            LocationOrArg::Arg(local) => format!("{local:?}"),
+          })
+          .collect::<Vec<_>>();
+        snippets.sort();
+        snippets
+      };
+
+      let x = p.local("x").mk();
+      let x0 = p.local("x").field(0).mk();
+      let x1 = p.local("x").field(1).mk();
+
+      let def_snippets =
+        |place| snippets(analysis.defs_of(place).map(|def| def.location).collect());
+      assert_eq!(def_snippets(x), ["(1, 2)", "x = (4, 5)", "x.0 = 3"]);
+      assert_eq!(def_snippets(x1), ["(1, 2)", "x = (4, 5)"]);
+
+      let use_snippets = |place| {
+        snippets(
+          analysis
+            .uses_of(place)
+            .map(|use_| LocationOrArg::Location(use_.location))
+            .collect(),
+        )
+      };
+      assert_eq!(use_snippets(x0), ["&x", "x.0"]);
+      assert_eq!(use_snippets(x1), ["&x", "x.1"]);
+
+      let location_of = |s: &str| {
+        body
+          .all_locations()
+          .find(|location: &Location| snippet(*location) == s)
+          .unwrap()
+      };
+      let at_z = location_of("x.0");
+      assert_eq!(snippets(analysis.reaching_defs(at_z, x0)), [
+        "x = (4, 5)",
+        "x.0 = 3"
+      ]);
+      assert_eq!(snippets(analysis.reaching_defs(at_z, x1)), [
+        "(1, 2)",
+        "x = (4, 5)"
+      ]);
+    });
+  }
+
+  #[test]
+  fn test_def_use_args() {
+    let input = r#"
+fn foo(mut x: i32, v: &mut [i32], i: usize) {
+  let y = x;
+  x = 1;
+  v[i] = x;
+}"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let analysis = DefUseAnalysis::new(tcx, body);
+      let p = test_utils::Placer::new(tcx, body);
+
+      let x = p.local("x").mk();
+      let i = p.local("i").mk();
+      let v = p.local("v").mk();
+      let x_local = x.local;
+
+      let y_loc = analysis.uses_of(x).map(|use_| use_.location).min().unwrap();
+      assert_eq!(
+        analysis.reaching_defs(y_loc, x),
+        [LocationOrArg::Arg(x_local)].into_iter().collect()
+      );
+
+      let exit = body.all_returns().next().unwrap();
+      let reaching = analysis.reaching_defs(exit, x);
+      assert_eq!(reaching.len(), 1);
+      assert!(!reaching.contains(&LocationOrArg::Arg(x_local)));
+
+      assert!(analysis.uses_of(i).next().is_some());
+      assert!(analysis.uses_of(v).next().is_some());
+    });
+  }
+}
@@ -0,0 +1,336 @@
+//! Structural diffs of MIR bodies, e.g. for regression tests of plugins that rewrite
+//! MIR.
+//!
+//! Bodies are compared through a [`MirSnapshot`], which renders the locals, statements
+//! and terminators of a body to text along with their line and column in the source.
+//! Snapshots don't refer to the compilation that produced them, so bodies can be
+//! compared before and after a transformation, or across compilations with different
+//! toolchains when the `serde` feature is enabled to save snapshots.
+//!
+//! Blocks are matched by index, and the lines of matching blocks are aligned by their
+//! text with a longest common subsequence. A line with the same text but a different
+//! span is reported as moved rather than as removed and added.
+
+use std::fmt;
+
+use rustc_middle::{
+  mir::{BasicBlock, Body},
+  ty::TyCtxt,
+};
+use rustc_span::{Pos, Span};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A line of a [`MirSnapshot`], i.e. a local, statement or terminator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MirLine {
+  /// The MIR of the line, e.g. `_1 = const 1_i32` or `let _2: &i32`.
+  pub text: String,
+  /// The source position of the line as `line:col-line:col`, or `<dummy>`.
+  pub span: String,
+}
+
+/// A basic block of a [`MirSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BlockSnapshot {
+  /// True if the block runs while unwinding. Diffs only compare the lines of blocks.
+  pub is_cleanup: bool,
+  /// The statements of the block followed by its terminator.
+  pub lines: Vec<MirLine>,
+}
+
+/// A body rendered to text, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MirSnapshot {
+  pub locals: Vec<MirLine>,
+  pub blocks: Vec<BlockSnapshot>,
+}
+
+impl MirSnapshot {
+  /// Renders `body`, which must belong to the compilation of `tcx`.
+  pub fn new<'tcx>(tcx: TyCtxt<'tcx>, body: &Body<'tcx>) -> Self {
+    let line = |text: String, span: Span| MirLine {
+      text,
+      span: span_position(tcx, span),
+    };
+    let locals = body
+      .local_decls
+      .iter_enumerated()
+      .map(|(local, decl)| {
+        line(format!("let {local:?}: {}", decl.ty), decl.source_info.span)
+      })
+      .collect();
+    let blocks = body
+      .basic_blocks
+      .iter()
+      .map(|data| {
+        let statements = data
+          .statements
+          .iter()
+          .map(|statement| line(format!("{statement:?}"), statement.source_info.span));
+        let terminator = data.terminator();
+        let terminator = line(
+          format!("{:?}", terminator.kind),
+          terminator.source_info.span,
+        );
+        BlockSnapshot {
+          is_cleanup: data.is_cleanup,
+          lines: statements.chain([terminator]).collect(),
+        }
+      })
+      .collect();
+    MirSnapshot { locals, blocks }
+  }
+
+  /// Returns the changes from `self` to `after`.
+  pub fn diff<'a>(&'a self, after: &'a MirSnapshot) -> MirDiff {
+    let locals = diff_lines(&self.locals, &after.locals);
+    let block_count = self.blocks.len().max(after.blocks.len());
+    let blocks = (0 .. block_count)
+      .filter_map(|index| {
+        let before = self.blocks.get(index);
+        let after = after.blocks.get(index);
+        let lines = |block: Option<&'a BlockSnapshot>| match block {
+          Some(block) => block.lines.as_slice(),
+          None => &[],
+        };
+        let changes = diff_lines(lines(before), lines(after));
+        (!changes.is_empty()).then(|| BlockDiff {
+          block: BasicBlock::from_usize(index),
+          kind: match (before, after) {
+            (None, _) => BlockChange::Added,
+            (_, None) => BlockChange::Removed,
+            _ => BlockChange::Changed,
+          },
+          changes,
+        })
+      })
+      .collect();
+    MirDiff { locals, blocks }
+  }
+}
+
+fn span_position(tcx: TyCtxt<'_>, span: Span) -> String {
+  let span = span.source_callsite();
+  if span.is_dummy() {
+    return "<dummy>".into();
+  }
+  let source_map = tcx.sess.source_map();
+  let lo = source_map.lookup_char_pos(span.lo());
+  let hi = source_map.lookup_char_pos(span.hi());
+  format!(
+    "{}:{}-{}:{}",
+    lo.line,
+    lo.col.to_usize() + 1,
+    hi.line,
+    hi.col.to_usize() + 1
+  )
+}
+
+/// A change to a line of a [`MirSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineChange {
+  Added(MirLine),
+  Removed(MirLine),
+  /// The line kept its text but moved from the span `before` to the span of `line`.
+  Moved {
+    line: MirLine,
+    before: String,
+  },
+}
+
+/// Whether a block exists before and after a [`MirDiff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockChange {
+  Added,
+  Removed,
+  Changed,
+}
+
+/// The changes to a block, see [`MirDiff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDiff {
+  pub block: BasicBlock,
+  pub kind: BlockChange,
+  /// The changed lines of the block, in order.
+  pub changes: Vec<LineChange>,
+}
+
+/// The changes between two bodies, see [`MirSnapshot::diff`].
+///
+/// A diff is displayed like a unified diff without context, with `+` for added lines,
+/// `-` for removed lines and `~` for moved lines, e.g.
+///
+/// ```text
+/// bb0:
+/// -     _1 = const 1_i32  // 2:11-2:12
+/// +     _1 = const 2_i32  // 2:11-2:12
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirDiff {
+  /// The changed locals, in order.
+  pub locals: Vec<LineChange>,
+  /// The changed blocks, in order.
+  pub blocks: Vec<BlockDiff>,
+}
+
+impl MirDiff {
+  /// Returns true if the bodies are the same.
+  pub fn is_empty(&self) -> bool {
+    self.locals.is_empty() && self.blocks.is_empty()
+  }
+
+  /// Returns the diff without changes that only moved lines, e.g. to compare bodies
+  /// of sources that differ in formatting.
+  pub fn without_moves(mut self) -> Self {
+    let is_move = |change: &LineChange| matches!(change, LineChange::Moved { .. });
+    self.locals.retain(|change| !is_move(change));
+    for block in &mut self.blocks {
+      block.changes.retain(|change| !is_move(change));
+    }
+    self
+      .blocks
+      .retain(|block| !block.changes.is_empty() || block.kind != BlockChange::Changed);
+    self
+  }
+}
+
+impl fmt::Display for LineChange {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (sign, line) = match self {
+      LineChange::Added(line) => ('+', line),
+      LineChange::Removed(line) => ('-', line),
+      LineChange::Moved { line, before } => {
+        return write!(f, "~     {}  // {before} -> {}", line.text, line.span);
+      }
+    };
+    write!(f, "{sign}     {}  // {}", line.text, line.span)
+  }
+}
+
+impl fmt::Display for MirDiff {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if !self.locals.is_empty() {
+      writeln!(f, "locals:")?;
+      for change in &self.locals {
+        writeln!(f, "{change}")?;
+      }
+    }
+    for block in &self.blocks {
+      let suffix = match block.kind {
+        BlockChange::Added => " (added)",
+        BlockChange::Removed => " (removed)",
+        BlockChange::Changed => "",
+      };
+      writeln!(f, "{:?}{suffix}:", block.block)?;
+      for change in &block.changes {
+        writeln!(f, "{change}")?;
+      }
+    }
+    Ok(())
+  }
+}
+
+// Aligns `before` and `after` by the longest common subsequence of their texts.
+fn diff_lines(before: &[MirLine], after: &[MirLine]) -> Vec<LineChange> {
+  let (n, m) = (before.len(), after.len());
+  // `lcs[i][j]` is the length of the longest common subsequence of `before[i..]` and
+  // `after[j..]`.
+  let mut lcs = vec![vec![0; m + 1]; n + 1];
+  for i in (0 .. n).rev() {
+    for j in (0 .. m).rev() {
+      lcs[i][j] = if before[i].text == after[j].text {
+        lcs[i + 1][j + 1] + 1
+      } else {
+        lcs[i + 1][j].max(lcs[i][j + 1])
+      };
+    }
+  }
+
+  let mut changes = Vec::new();
+  let (mut i, mut j) = (0, 0);
+  while i < n || j < m {
+    if i < n && j < m && before[i].text == after[j].text {
+      if before[i].span != after[j].span {
+        changes.push(LineChange::Moved {
+          line: after[j].clone(),
+          before: before[i].span.clone(),
+        });
+      }
+      i += 1;
+      j += 1;
+    } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+      changes.push(LineChange::Added(after[j].clone()));
+      j += 1;
+    } else {
+      changes.push(LineChange::Removed(before[i].clone()));
+      i += 1;
+    }
+  }
+  changes
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::mir::{Statement, StatementKind};
+
+  use super::*;
+  use crate::test_utils;
+
+  #[test]
+  fn test_mir_diff() {
+    let input = r#"
+fn main() {
+  let x = 1;
+  let y = x + 2;
+}
+"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let before = MirSnapshot::new(tcx, body);
+      assert!(before.diff(&before).is_empty());
+
+      // Insert a nop at the start of the body and move the first statement.
+      let mut changed = body.clone();
+      let statements =
+        &mut changed.basic_blocks_mut()[BasicBlock::from_u32(0)].statements;
+      let moved = statements[0].source_info.span.shrink_to_lo();
+      let source_info = statements[0].source_info;
+      statements[0].source_info.span = moved;
+      statements.insert(0, Statement {
+        source_info,
+        kind: StatementKind::Nop,
+      });
+      let after = MirSnapshot::new(tcx, &changed);
+      let diff = before.diff(&after);
+      assert!(diff.locals.is_empty());
+      assert_eq!(diff.blocks.len(), 1);
+      assert_eq!(diff.blocks[0].kind, BlockChange::Changed);
+      let changes = &diff.blocks[0].changes;
+      assert!(matches!(&changes[0], LineChange::Added(line) if line.text == "nop"));
+      assert!(matches!(&changes[1], LineChange::Moved { .. }));
+      assert_eq!(changes.len(), 2);
+
+      let diff = diff.without_moves();
+      assert_eq!(diff.blocks[0].changes.len(), 1);
+      let rendered = diff.to_string();
+      assert!(rendered.starts_with("bb0:\n+     nop  // "), "{rendered}");
+
+      // A removed block is reported with all of its lines.
+      let mut truncated = body.clone();
+      let last = truncated.basic_blocks.last_index().unwrap();
+      truncated.basic_blocks_mut().raw.pop();
+      let diff = before.diff(&MirSnapshot::new(tcx, &truncated));
+      let removed = diff.blocks.last().unwrap();
+      assert_eq!(removed.block, last);
+      assert_eq!(removed.kind, BlockChange::Removed);
+      assert!(removed
+        .changes
+        .iter()
+        .all(|change| matches!(change, LineChange::Removed(_))));
+    });
+  }
+}
@@ -0,0 +1,156 @@
+//! Rendering the control-flow graph of a MIR body to Graphviz DOT, see
+//! [`BodyExt::to_dot`](crate::BodyExt::to_dot).
+
+use std::borrow::Cow;
+
+use rustc_graphviz as dot;
+use rustc_middle::mir::{BasicBlock, Body, Location};
+
+/// Options for [`BodyExt::to_dot`](crate::BodyExt::to_dot).
+#[derive(Default)]
+pub struct DotOptions<'a> {
+  /// If true, the span of each statement and terminator is shown below it.
+  pub spans: bool,
+
+  /// If true, cleanup blocks and the unwind edges to them are included.
+  pub cleanup: bool,
+
+  /// Returns an annotation for a location, e.g. the dataflow state before it, which is
+  /// shown below its statement or terminator.
+  pub annotate: Option<Box<dyn Fn(Location) -> Option<String> + 'a>>,
+}
+
+pub(crate) struct DotBody<'a, 'tcx> {
+  pub(crate) body: &'a Body<'tcx>,
+  pub(crate) options: &'a DotOptions<'a>,
+}
+
+/// An edge from a block to its `n`-th successor.
+#[derive(Clone, Copy)]
+pub(crate) struct Edge {
+  source: BasicBlock,
+  index: usize,
+}
+
+impl DotBody<'_, '_> {
+  fn shown(&self, block: BasicBlock) -> bool {
+    self.options.cleanup || !self.body.basic_blocks[block].is_cleanup
+  }
+
+  fn row(&self, rows: &mut String, location: Location, text: &str) {
+    rows.push_str(&format!(
+      r#"<tr><td align="right">{}</td><td align="left">{}</td></tr>"#,
+      location.statement_index,
+      dot::escape_html(text)
+    ));
+    let span = self
+      .options
+      .spans
+      .then(|| format!("{:?}", self.body.source_info(location).span));
+    let annotation = self
+      .options
+      .annotate
+      .as_ref()
+      .and_then(|annotate| annotate(location));
+    for (extra, color) in [(span, "gray50"), (annotation, "blue")] {
+      if let Some(extra) = extra {
+        rows.push_str(&format!(
+          r#"<tr><td></td><td align="left"><font color="{color}">{}</font></td></tr>"#,
+          dot::escape_html(&extra).replace('\n', "<br/>")
+        ));
+      }
+    }
+  }
+}
+
+impl<'a> dot::Labeller<'a> for DotBody<'_, '_> {
+  type Node = BasicBlock;
+  type Edge = Edge;
+
+  fn graph_id(&'a self) -> dot::Id<'a> {
+    dot::Id::new("mir").unwrap()
+  }
+
+  fn node_id(&'a self, block: &BasicBlock) -> dot::Id<'a> {
+    dot::Id::new(format!("bb{}", block.as_usize())).unwrap()
+  }
+
+  fn node_shape(&'a self, _block: &BasicBlock) -> Option<dot::LabelText<'a>> {
+    Some(dot::LabelText::label("none"))
+  }
+
+  fn node_label(&'a self, block: &BasicBlock) -> dot::LabelText<'a> {
+    let data = &self.body.basic_blocks[*block];
+    let cleanup = if data.is_cleanup { " (cleanup)" } else { "" };
+    let mut rows = format!(
+      r#"<table border="0" cellborder="1" cellspacing="0"><tr><td colspan="2" bgcolor="gray"><b>{block:?}{cleanup}</b></td></tr>"#
+    );
+    for (statement_index, statement) in data.statements.iter().enumerate() {
+      let location = Location {
+        block: *block,
+        statement_index,
+      };
+      self.row(&mut rows, location, &format!("{:?}", statement.kind));
+    }
+    let mut head = String::new();
+    data.terminator().kind.fmt_head(&mut head).unwrap();
+    self.row(&mut rows, self.body.terminator_loc(*block), &head);
+    rows.push_str("</table>");
+    dot::LabelText::html(rows)
+  }
+
+  fn edge_label(&'a self, edge: &Edge) -> dot::LabelText<'a> {
+    let labels = self.body.basic_blocks[edge.source]
+      .terminator()
+      .kind
+      .fmt_successor_labels();
+    let label = labels
+      .into_iter()
+      .nth(edge.index)
+      .unwrap_or(Cow::Borrowed(""));
+    dot::LabelText::label(label)
+  }
+}
+
+impl<'a> dot::GraphWalk<'a> for DotBody<'_, '_> {
+  type Node = BasicBlock;
+  type Edge = Edge;
+
+  fn nodes(&'a self) -> dot::Nodes<'a, BasicBlock> {
+    self
+      .body
+      .basic_blocks
+      .indices()
+      .filter(|block| self.shown(*block))
+      .collect()
+  }
+
+  fn edges(&'a self) -> dot::Edges<'a, Edge> {
+    self
+      .body
+      .basic_blocks
+      .indices()
+      .filter(|block| self.shown(*block))
+      .flat_map(|source| {
+        self.body.basic_blocks[source]
+          .terminator()
+          .successors()
+          .enumerate()
+          .filter(|(_, target)| self.shown(*target))
+          .map(move |(index, _)| Edge { source, index })
+      })
+      .collect()
+  }
+
+  fn source(&'a self, edge: &Edge) -> BasicBlock {
+    edge.source
+  }
+
+  fn target(&'a self, edge: &Edge) -> BasicBlock {
+    self.body.basic_blocks[edge.source]
+      .terminator()
+      .successors()
+      .nth(edge.index)
+      .unwrap()
+  }
+}
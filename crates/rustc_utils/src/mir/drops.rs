@@ -0,0 +1,249 @@
+//! Finding where places are dropped and whether their drops matter.
+//!
+//! Before drop elaboration, a body has a `Drop` for every place that goes out of scope,
+//! even if it may have been moved. Elaboration removes the drops of moved places and
+//! guards the drops of maybe-moved places with drop flags, i.e. boolean locals that
+//! are set when the place is initialized and cleared when it is moved. A drop is
+//! conditional if its block is only reachable by a switch on a drop flag.
+
+use rustc_data_structures::fx::FxHashSet as HashSet;
+use rustc_middle::{
+  mir::{
+    BasicBlock, Body, Const, Local, Location, Operand, Place, Rvalue, StatementKind,
+    TerminatorKind, VarDebugInfoContents,
+  },
+  ty::{ParamEnv, Ty, TyCtxt},
+};
+
+/// Whether a drop always runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropKind {
+  /// The drop runs whenever its location is reached.
+  Unconditional,
+  /// The drop only runs if `flag` is set, i.e. if the place is still initialized.
+  Conditional { flag: Local },
+}
+
+/// A `Drop` terminator, see [`drops`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DropSite<'tcx> {
+  /// The dropped place.
+  pub place: Place<'tcx>,
+  /// The location of the `Drop`.
+  pub location: Location,
+  pub kind: DropKind,
+  /// True if the drop is of the old value of an assignment.
+  pub replace: bool,
+  /// True if the drop runs while unwinding.
+  pub is_cleanup: bool,
+}
+
+/// Returns every drop of `body`, in order of location.
+pub fn drops<'tcx>(body: &Body<'tcx>) -> Vec<DropSite<'tcx>> {
+  let flags = drop_flags(body);
+  body
+    .basic_blocks
+    .iter_enumerated()
+    .filter_map(|(block, data)| {
+      let TerminatorKind::Drop { place, replace, .. } = data.terminator().kind else {
+        return None;
+      };
+      let kind = match guarding_flag(body, block, &flags) {
+        Some(flag) => DropKind::Conditional { flag },
+        None => DropKind::Unconditional,
+      };
+      Some(DropSite {
+        place,
+        location: body.terminator_loc(block),
+        kind,
+        replace,
+        is_cleanup: data.is_cleanup,
+      })
+    })
+    .collect()
+}
+
+/// Returns the drop flags of `body`, i.e. the boolean temporaries that are only ever
+/// assigned constants and are not read by other assignments. A body without drop
+/// elaboration has no drop flags.
+pub fn drop_flags(body: &Body<'_>) -> HashSet<Local> {
+  let mut assigned = HashSet::default();
+  let mut invalid = HashSet::default();
+  for data in body.basic_blocks.iter() {
+    for statement in &data.statements {
+      let StatementKind::Assign(box (place, rvalue)) = &statement.kind else {
+        continue;
+      };
+      let is_const_bool = matches!(
+        rvalue,
+        Rvalue::Use(Operand::Constant(constant))
+          if constant.ty().is_bool() && matches!(constant.const_, Const::Val(..))
+      );
+      match place.as_local() {
+        Some(local) if is_const_bool => {
+          assigned.insert(local);
+        }
+        _ => {
+          invalid.insert(place.local);
+        }
+      }
+      rvalue_locals(rvalue, &mut invalid);
+    }
+  }
+
+  // User variables are excluded by their debug info, since the local info of user
+  // variables is cleared after borrowck.
+  for info in &body.var_debug_info {
+    if let VarDebugInfoContents::Place(place) = info.value {
+      invalid.insert(place.local);
+    }
+  }
+
+  assigned
+    .into_iter()
+    .filter(|local| {
+      !invalid.contains(local)
+        && body.local_decls[*local].ty.is_bool()
+        && body.arg_count < local.as_usize()
+    })
+    .collect()
+}
+
+fn rvalue_locals(rvalue: &Rvalue<'_>, locals: &mut HashSet<Local>) {
+  let mut add = |operand: &Operand<'_>| {
+    if let Some(place) = operand.place() {
+      locals.insert(place.local);
+    }
+  };
+  match rvalue {
+    Rvalue::Use(operand)
+    | Rvalue::Repeat(operand, _)
+    | Rvalue::Cast(_, operand, _)
+    | Rvalue::UnaryOp(_, operand)
+    | Rvalue::ShallowInitBox(operand, _) => add(operand),
+    Rvalue::BinaryOp(_, box (lhs, rhs)) => {
+      add(lhs);
+      add(rhs);
+    }
+    Rvalue::Aggregate(_, operands) => operands.iter().for_each(add),
+    Rvalue::Ref(_, _, place)
+    | Rvalue::RawPtr(_, place)
+    | Rvalue::Len(place)
+    | Rvalue::Discriminant(place)
+    | Rvalue::CopyForDeref(place) => {
+      locals.insert(place.local);
+    }
+    Rvalue::ThreadLocalRef(_) | Rvalue::NullaryOp(..) => {}
+  }
+}
+
+// Returns the flag that every predecessor of `block` switches on to reach it.
+fn guarding_flag(
+  body: &Body<'_>,
+  block: BasicBlock,
+  flags: &HashSet<Local>,
+) -> Option<Local> {
+  let predecessors = &body.basic_blocks.predecessors()[block];
+  let mut flag = None;
+  for predecessor in predecessors {
+    let TerminatorKind::SwitchInt { discr, .. } =
+      &body.basic_blocks[*predecessor].terminator().kind
+    else {
+      return None;
+    };
+    let local = discr.place()?.as_local()?;
+    if !flags.contains(&local) || flag.is_some_and(|flag| flag != local) {
+      return None;
+    }
+    flag = Some(local);
+  }
+  flag
+}
+
+/// How much running the destructor of a type matters to a program, see
+/// [`drop_significance`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DropSignificance {
+  /// Dropping the type does nothing, e.g. for `i32` or `&String`.
+  None,
+  /// Dropping the type only frees memory, e.g. for `String` or `Vec<i32>`.
+  Insignificant,
+  /// Dropping the type may have observable effects, e.g. for `MutexGuard` or `File`.
+  Significant,
+}
+
+/// Returns whether dropping a value of type `ty` does anything, and if so, whether
+/// the drop has effects beyond freeing memory, as determined by the types marked
+/// `#[rustc_insignificant_dtor]` in the standard library.
+pub fn drop_significance<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  param_env: ParamEnv<'tcx>,
+  ty: Ty<'tcx>,
+) -> DropSignificance {
+  if !ty.needs_drop(tcx, param_env) {
+    DropSignificance::None
+  } else if ty.has_significant_drop(tcx, param_env) {
+    DropSignificance::Significant
+  } else {
+    DropSignificance::Insignificant
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{test_utils::CompileBuilder, BodyExt};
+
+  #[test]
+  fn test_drops() {
+    let input = r#"
+use std::sync::{Mutex, MutexGuard};
+fn consume(s: String) {}
+fn main(b: bool, m: &Mutex<i32>) {
+  let s = String::new();
+  let t = String::new();
+  if b { consume(s); }
+  let g: MutexGuard<i32> = m.lock().unwrap();
+  let n = 0;
+}
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let tcx = result.tcx;
+      let main = tcx
+        .hir()
+        .body_owners()
+        .find(|def_id| tcx.def_path_str(*def_id) == "main")
+        .unwrap();
+      let body = tcx.optimized_mir(main);
+      let name_map = body.debug_info_name_map();
+      let drops = drops(body);
+      let kinds = |name: &str| {
+        drops
+          .iter()
+          .filter(|drop| {
+            !drop.is_cleanup && drop.place.as_local() == Some(name_map[name])
+          })
+          .map(|drop| drop.kind)
+          .collect::<Vec<_>>()
+      };
+
+      let flags = drop_flags(body);
+      assert_eq!(flags.len(), 1);
+      let flag = *flags.iter().next().unwrap();
+      assert_eq!(kinds("s"), [DropKind::Conditional { flag }]);
+      assert_eq!(kinds("t"), [DropKind::Unconditional]);
+      assert_eq!(kinds("g"), [DropKind::Unconditional]);
+      assert!(kinds("n").is_empty());
+      assert!(drops.iter().any(|drop| drop.is_cleanup));
+
+      let param_env = tcx.param_env(main);
+      let significance = |name: &str| {
+        drop_significance(tcx, param_env, body.local_decls[name_map[name]].ty)
+      };
+      assert_eq!(significance("n"), DropSignificance::None);
+      assert_eq!(significance("m"), DropSignificance::None);
+      assert_eq!(significance("s"), DropSignificance::Insignificant);
+      assert_eq!(significance("g"), DropSignificance::Significant);
+    });
+  }
+}
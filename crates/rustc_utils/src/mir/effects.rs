@@ -0,0 +1,266 @@
+//! Classifying the statements and terminators of a body by their effect.
+//!
+//! Many analyses start by sorting the instructions of a body into the ones they care
+//! about, e.g. a purity analysis looks for writes through references and calls, and a
+//! memory analysis for allocations and dereferences. [`classify`] gives the effects of
+//! one instruction along with the places involved, and [`body_effects`] those of every
+//! instruction of a body.
+//!
+//! An instruction can have several effects: `*a = *b` both reads and writes through a
+//! reference. Dereferences are found syntactically in the places of the instruction, so
+//! borrowing `&(*a).0` doesn't read or write `*a`.
+
+use either::Either;
+use rustc_data_structures::captures::Captures;
+use rustc_hir::{def_id::DefId, LangItem};
+use rustc_middle::{
+  middle::codegen_fn_attrs::CodegenFnAttrFlags,
+  mir::{
+    visit::{MutatingUseContext, NonMutatingUseContext, PlaceContext, Visitor},
+    Body, Location, NonDivergingIntrinsic, Place, Rvalue, StatementKind, TerminatorKind,
+  },
+  ty::TyCtxt,
+};
+use rustc_span::sym;
+
+use crate::BodyExt;
+
+/// The effect of a statement or terminator, see [`classify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Effect {
+  /// A statement with no effect on the execution, e.g. `StorageLive` or `FakeRead`.
+  Marker,
+  /// A write of locals by moves, copies and operations on values, without reading or
+  /// writing through a pointer.
+  Pure,
+  /// An allocation on the heap, i.e. a call to `Box::new`, to `exchange_malloc` or to
+  /// the global allocator. Allocations within other callees are only seen as a
+  /// [`Effect::Call`].
+  Allocation,
+  /// A read through a reference or raw pointer.
+  DerefRead,
+  /// A write through a reference or raw pointer.
+  DerefWrite,
+  /// A call of a function, which may have any side effect.
+  Call,
+  /// An `asm!` block.
+  InlineAsm,
+  /// A drop of a place, which may run its `Drop` impl.
+  Drop,
+  /// A terminator that only transfers control, e.g. `goto`, `switchInt` or `return`.
+  Control,
+}
+
+/// Returns the effects of the instruction at `location`, each with the places that
+/// have the effect.
+///
+/// A [`Effect::DerefRead`] or [`Effect::DerefWrite`] is given with the dereferenced
+/// places, and is combined with the other effects of an instruction, except for
+/// [`Effect::Pure`]. Every other effect is given with every place of the instruction.
+/// `copy_nonoverlapping` is given as a read through its source and a write through its
+/// destination, with the pointer operands as places.
+pub fn classify<'tcx>(
+  tcx: TyCtxt<'tcx>,
+  body: &Body<'tcx>,
+  location: Location,
+) -> Vec<(Effect, Vec<Place<'tcx>>)> {
+  let mut collector = PlaceCollector { places: Vec::new() };
+  let effect = match body.stmt_at(location) {
+    Either::Left(statement) => {
+      collector.visit_statement(statement, location);
+      match &statement.kind {
+        StatementKind::Assign(box (_, Rvalue::ShallowInitBox(..))) => {
+          Some(Effect::Allocation)
+        }
+        StatementKind::Assign(..)
+        | StatementKind::SetDiscriminant { .. }
+        | StatementKind::Deinit(..) => None,
+        StatementKind::Intrinsic(box NonDivergingIntrinsic::CopyNonOverlapping(copy)) => {
+          return vec![
+            (Effect::DerefRead, copy.src.place().into_iter().collect()),
+            (Effect::DerefWrite, copy.dst.place().into_iter().collect()),
+          ];
+        }
+        _ => return vec![(Effect::Marker, collector.all())],
+      }
+    }
+    Either::Right(terminator) => {
+      collector.visit_terminator(terminator, location);
+      Some(match &terminator.kind {
+        TerminatorKind::Call { func, .. } | TerminatorKind::TailCall { func, .. } => {
+          match func.const_fn_def() {
+            Some((def_id, _)) if is_allocation(tcx, def_id) => Effect::Allocation,
+            _ => Effect::Call,
+          }
+        }
+        TerminatorKind::InlineAsm { .. } => Effect::InlineAsm,
+        TerminatorKind::Drop { .. } => Effect::Drop,
+        _ => Effect::Control,
+      })
+    }
+  };
+
+  let reads = collector.derefs(is_read);
+  let writes = collector.derefs(is_write);
+  let mut effects = Vec::new();
+  match effect {
+    Some(effect) => effects.push((effect, collector.all())),
+    None if reads.is_empty() && writes.is_empty() => {
+      effects.push((Effect::Pure, collector.all()))
+    }
+    None => {}
+  }
+  if !reads.is_empty() {
+    effects.push((Effect::DerefRead, reads));
+  }
+  if !writes.is_empty() {
+    effects.push((Effect::DerefWrite, writes));
+  }
+  effects
+}
+
+/// Returns the effects of every instruction in `body`, see [`classify`].
+pub fn body_effects<'a, 'tcx>(
+  tcx: TyCtxt<'tcx>,
+  body: &'a Body<'tcx>,
+) -> impl Iterator<Item = (Location, Effect, Vec<Place<'tcx>>)> + Captures<'tcx> + 'a {
+  body.all_locations().flat_map(move |location| {
+    classify(tcx, body, location)
+      .into_iter()
+      .map(move |(effect, places)| (location, effect, places))
+  })
+}
+
+fn is_allocation(tcx: TyCtxt<'_>, def_id: DefId) -> bool {
+  tcx.is_lang_item(def_id, LangItem::ExchangeMalloc)
+    || tcx.is_diagnostic_item(sym::box_new, def_id)
+    || tcx
+      .codegen_fn_attrs(def_id)
+      .flags
+      .intersects(CodegenFnAttrFlags::ALLOCATOR | CodegenFnAttrFlags::ALLOCATOR_ZEROED)
+}
+
+fn is_read(context: PlaceContext) -> bool {
+  matches!(
+    context,
+    PlaceContext::NonMutatingUse(
+      NonMutatingUseContext::Copy
+        | NonMutatingUseContext::Move
+        | NonMutatingUseContext::Inspect
+    )
+  )
+}
+
+fn is_write(context: PlaceContext) -> bool {
+  matches!(
+    context,
+    PlaceContext::MutatingUse(
+      MutatingUseContext::Store
+        | MutatingUseContext::Call
+        | MutatingUseContext::AsmOutput
+        | MutatingUseContext::SetDiscriminant
+        | MutatingUseContext::Deinit
+        | MutatingUseContext::Yield
+    )
+  )
+}
+
+struct PlaceCollector<'tcx> {
+  places: Vec<(Place<'tcx>, PlaceContext)>,
+}
+
+impl<'tcx> PlaceCollector<'tcx> {
+  fn all(&self) -> Vec<Place<'tcx>> {
+    let mut places = Vec::new();
+    for (place, _) in &self.places {
+      if !places.contains(place) {
+        places.push(*place);
+      }
+    }
+    places
+  }
+
+  fn derefs(&self, filter: impl Fn(PlaceContext) -> bool) -> Vec<Place<'tcx>> {
+    let mut places = Vec::new();
+    for (place, context) in &self.places {
+      if place.is_indirect() && filter(*context) && !places.contains(place) {
+        places.push(*place);
+      }
+    }
+    places
+  }
+}
+
+impl<'tcx> Visitor<'tcx> for PlaceCollector<'tcx> {
+  fn visit_place(&mut self, place: &Place<'tcx>, context: PlaceContext, _: Location) {
+    self.places.push((*place, context));
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_data_structures::fx::FxHashSet as HashSet;
+
+  use super::*;
+  use crate::test_utils;
+
+  #[test]
+  fn test_effects() {
+    let input = r#"
+fn main() {
+  let mut x = 1;
+  let y = x + 1;
+  let r = &mut x;
+  *r = y;
+  let z = *r;
+  let b = Box::new(z);
+  callee();
+  let s = String::new();
+  drop(b);
+  unsafe { std::arch::asm!("nop"); }
+}
+fn callee() {}
+"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let effects = body_effects(tcx, body).collect::<Vec<_>>();
+      let kinds = effects
+        .iter()
+        .map(|(_, effect, _)| *effect)
+        .collect::<HashSet<_>>();
+      for effect in [
+        Effect::Marker,
+        Effect::Pure,
+        Effect::Allocation,
+        Effect::DerefRead,
+        Effect::DerefWrite,
+        Effect::Call,
+        Effect::InlineAsm,
+        Effect::Drop,
+        Effect::Control,
+      ] {
+        assert!(kinds.contains(&effect), "{effect:?}");
+      }
+
+      let with_effect = |effect: Effect| {
+        effects
+          .iter()
+          .filter(move |(_, other, _)| *other == effect)
+          .map(|(location, _, places)| (*location, places))
+      };
+
+      // `*r = y` writes through `r` and is not pure.
+      let (location, places) = with_effect(Effect::DerefWrite).next().unwrap();
+      assert_eq!(places.len(), 1);
+      assert!(places[0].is_indirect());
+      assert_eq!(classify(tcx, body, location), [(
+        Effect::DerefWrite,
+        places.clone()
+      )]);
+
+      // `Box::new` is an allocation and not a call, while `String::new` is a call.
+      assert_eq!(with_effect(Effect::Allocation).count(), 1);
+      assert_eq!(with_effect(Effect::Call).count(), 3);
+    });
+  }
+}
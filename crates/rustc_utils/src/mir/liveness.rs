@@ -0,0 +1,259 @@
+//! Liveness of places in a MIR body.
+//!
+//! A place is live at a location if its current value may be read later. Unlike
+//! the liveness of locals, this is tracked per place, so after the last read of `x.0`,
+//! `x.0` is dead even if `x.1` is still live. Assigning to a field of a live struct or
+//! tuple splits it into its fields, e.g. `x.0 = 1` turns a live `x` into a live `x.1`.
+//!
+//! Reads and writes come from [`DefUseAnalysis`]. Writes through a dereference never
+//! kill a place, and reads through references are attributed to the reference only,
+//! i.e. the analysis does not account for aliasing. Drops are not reads.
+
+use rustc_borrowck::consumers::{places_conflict, PlaceConflictBias};
+use rustc_data_structures::fx::{FxHashMap as HashMap, FxHashSet as HashSet};
+use rustc_index::IndexVec;
+use rustc_middle::{
+  mir::{
+    visit::{MutatingUseContext, PlaceContext},
+    BasicBlock, Body, Location, Place, ProjectionElem,
+  },
+  ty::{Ty, TyCtxt, TyKind},
+};
+use rustc_target::abi::FieldIdx;
+
+use super::def_use::DefUseAnalysis;
+
+/// The live places at every location of a body, see the
+/// [module documentation](self).
+pub struct PlaceLiveness<'a, 'tcx> {
+  tcx: TyCtxt<'tcx>,
+  body: &'a Body<'tcx>,
+  def_use: DefUseAnalysis<'a, 'tcx>,
+  live: HashMap<Location, HashSet<Place<'tcx>>>,
+}
+
+impl<'a, 'tcx> PlaceLiveness<'a, 'tcx> {
+  /// Computes the live places of `body`.
+  pub fn new(tcx: TyCtxt<'tcx>, body: &'a Body<'tcx>) -> Self {
+    let mut liveness = PlaceLiveness {
+      tcx,
+      body,
+      def_use: DefUseAnalysis::new(tcx, body),
+      live: HashMap::default(),
+    };
+
+    let mut entry: IndexVec<BasicBlock, HashSet<Place<'tcx>>> =
+      IndexVec::from_elem_n(HashSet::default(), body.basic_blocks.len());
+    let mut worklist = body.basic_blocks.indices().rev().collect::<Vec<_>>();
+    while let Some(block) = worklist.pop() {
+      let state = liveness.transfer_block(block, &entry, |_, _| {});
+      if state != entry[block] {
+        entry[block] = state;
+        worklist.extend(body.basic_blocks.predecessors()[block].iter().copied());
+      }
+    }
+
+    let mut live = HashMap::default();
+    for block in body.basic_blocks.indices() {
+      liveness.transfer_block(block, &entry, |location, state| {
+        live.insert(location, state.clone());
+      });
+    }
+    liveness.live = live;
+    liveness
+  }
+
+  /// Returns the places that are live before `location` executes.
+  pub fn live_at(&self, location: Location) -> &HashSet<Place<'tcx>> {
+    &self.live[&location]
+  }
+
+  /// Returns true if any part of `place` is live before `location` executes.
+  pub fn is_live_at(&self, place: Place<'tcx>, location: Location) -> bool {
+    self.live_at(location).iter().any(|live| {
+      places_conflict(
+        self.tcx,
+        self.body,
+        *live,
+        place,
+        PlaceConflictBias::Overlap,
+      )
+    })
+  }
+
+  /// Computes the live places at the start of `block` from the entry states of its
+  /// successors, calling `f` with the state before each location.
+  fn transfer_block(
+    &self,
+    block: BasicBlock,
+    entry: &IndexVec<BasicBlock, HashSet<Place<'tcx>>>,
+    mut f: impl FnMut(Location, &HashSet<Place<'tcx>>),
+  ) -> HashSet<Place<'tcx>> {
+    let data = &self.body.basic_blocks[block];
+    let mut state = data
+      .terminator()
+      .successors()
+      .flat_map(|succ| entry[succ].iter().copied())
+      .collect::<HashSet<_>>();
+    for statement_index in (0 ..= data.statements.len()).rev() {
+      let location = Location {
+        block,
+        statement_index,
+      };
+      self.transfer(location, &mut state);
+      f(location, &state);
+    }
+    state
+  }
+
+  fn transfer(&self, location: Location, state: &mut HashSet<Place<'tcx>>) {
+    for def in self.def_use.defs_at(location) {
+      if def.overwrites && !def.place.is_indirect() {
+        self.kill(state, def.place);
+      }
+    }
+    for use_ in self.def_use.uses_at(location) {
+      if !matches!(
+        use_.context,
+        PlaceContext::MutatingUse(MutatingUseContext::Drop)
+      ) {
+        state.insert(use_.place);
+      }
+    }
+  }
+
+  fn kill(&self, state: &mut HashSet<Place<'tcx>>, killed: Place<'tcx>) {
+    let mut split = Vec::new();
+    state.retain(|live| {
+      if is_prefix(killed, *live) {
+        return false;
+      }
+      let only_fields = || {
+        killed.projection[live.projection.len() ..]
+          .iter()
+          .all(|elem| matches!(elem, ProjectionElem::Field(..)))
+      };
+      if is_prefix(*live, killed) && only_fields() {
+        split.push(*live);
+        return false;
+      }
+      true
+    });
+    for live in split {
+      self.split(state, live, killed);
+    }
+  }
+
+  /// Replaces `live`, a prefix of `killed`, with its fields that are disjoint from
+  /// `killed`.
+  fn split(
+    &self,
+    state: &mut HashSet<Place<'tcx>>,
+    live: Place<'tcx>,
+    killed: Place<'tcx>,
+  ) {
+    let ProjectionElem::Field(next, _) = killed.projection[live.projection.len()] else {
+      unreachable!()
+    };
+    let Some(fields) = self.fields(live) else {
+      state.insert(live);
+      return;
+    };
+    for (index, ty) in fields.into_iter().enumerate() {
+      let field = self
+        .tcx
+        .mk_place_field(live, FieldIdx::from_usize(index), ty);
+      if index != next.as_usize() {
+        state.insert(field);
+      } else if field != killed {
+        self.split(state, field, killed);
+      }
+    }
+  }
+
+  fn fields(&self, place: Place<'tcx>) -> Option<Vec<Ty<'tcx>>> {
+    let ty = place.ty(self.body, self.tcx).ty;
+    match ty.kind() {
+      TyKind::Tuple(tys) => Some(tys.to_vec()),
+      TyKind::Adt(adt_def, args) if adt_def.is_struct() => Some(
+        adt_def
+          .non_enum_variant()
+          .fields
+          .iter()
+          .map(|field| field.ty(self.tcx, args))
+          .collect(),
+      ),
+      _ => None,
+    }
+  }
+}
+
+/// Returns true if `prefix` is `place` or `place` is a projection of it.
+fn is_prefix<'tcx>(prefix: Place<'tcx>, place: Place<'tcx>) -> bool {
+  prefix.local == place.local && place.projection.starts_with(prefix.projection)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    test_utils::{self, Placer},
+    BodyExt,
+  };
+
+  #[test]
+  fn test_liveness() {
+    let input = r#"
+fn main() {
+  let mut t = (1, 2);
+  let a = t.0;
+  t.0 = 3;
+  let b = t.1;
+  let c = t.0;
+  let mut u = (4, 5);
+  u.1 = 6;
+  let d = u;
+}
+"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let liveness = PlaceLiveness::new(tcx, body);
+      let p = Placer::new(tcx, body);
+      let source_map = tcx.sess.source_map();
+      let location_of = |s: &str| {
+        body
+          .all_locations()
+          .find(|location| {
+            source_map
+              .span_to_snippet(body.source_info(*location).span)
+              .unwrap()
+              == s
+          })
+          .unwrap()
+      };
+
+      let t = p.local("t");
+      let u = p.local("u");
+
+      let init = location_of("(1, 2)");
+      assert!(!liveness.is_live_at(t.mk(), init));
+
+      let read = location_of("t.0");
+      assert!(liveness.is_live_at(t.field(0).mk(), read));
+      assert!(liveness.is_live_at(t.field(1).mk(), read));
+
+      let write = location_of("t.0 = 3");
+      assert!(!liveness.is_live_at(t.field(0).mk(), write));
+      assert!(liveness.is_live_at(t.field(1).mk(), write));
+      assert!(liveness.is_live_at(t.mk(), write));
+
+      let read = location_of("t.1");
+      assert!(liveness.is_live_at(t.field(0).mk(), read));
+
+      let write = location_of("u.1 = 6");
+      assert!(liveness.is_live_at(u.field(0).mk(), write));
+      assert!(!liveness.is_live_at(u.field(1).mk(), write));
+      assert!(liveness.live_at(write).contains(&u.field(0).mk()));
+    });
+  }
+}
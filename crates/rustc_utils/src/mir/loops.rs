@@ -0,0 +1,208 @@
+//! Natural loops of a MIR body, see [`BodyExt::loops`](crate::BodyExt::loops).
+//!
+//! A back edge is an edge `a -> h` where `h` dominates `a`, and its natural loop is `h`
+//! along with the blocks that can reach `a` without going through `h`. Back edges with
+//! the same header form a single loop. Since MIR is built from structured code, every
+//! cycle in its CFG belongs to a natural loop.
+
+use rustc_index::{bit_set::BitSet, IndexVec};
+use rustc_middle::mir::{BasicBlock, Body};
+
+/// A natural loop.
+#[derive(Debug, Clone)]
+pub struct Loop {
+  /// The block that dominates every block of the loop.
+  pub header: BasicBlock,
+  /// The edges `(source, header)` that jump back to the header.
+  pub back_edges: Vec<(BasicBlock, BasicBlock)>,
+  /// The blocks of the loop, including the header and the blocks of nested loops.
+  pub blocks: BitSet<BasicBlock>,
+  /// The blocks outside the loop that are targets of edges from the loop, excluding
+  /// cleanup blocks.
+  pub exits: Vec<BasicBlock>,
+  /// The index of the innermost loop that contains this one, see [`Loops::loops`].
+  pub parent: Option<usize>,
+}
+
+/// The natural loops of a body, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct Loops {
+  loops: Vec<Loop>,
+  innermost: IndexVec<BasicBlock, Option<usize>>,
+  depth: IndexVec<BasicBlock, usize>,
+}
+
+impl Loops {
+  /// Computes the natural loops of `body`.
+  pub fn build(body: &Body<'_>) -> Self {
+    let blocks = &body.basic_blocks;
+    let dominators = blocks.dominators();
+    let mut loops: Vec<Loop> = Vec::new();
+    for (source, data) in blocks.iter_enumerated() {
+      for header in data.terminator().successors() {
+        if !dominators.is_reachable(source) || !dominators.dominates(header, source) {
+          continue;
+        }
+        let index = match loops.iter().position(|l| l.header == header) {
+          Some(index) => index,
+          None => {
+            let mut loop_blocks = BitSet::new_empty(blocks.len());
+            loop_blocks.insert(header);
+            loops.push(Loop {
+              header,
+              back_edges: Vec::new(),
+              blocks: loop_blocks,
+              exits: Vec::new(),
+              parent: None,
+            });
+            loops.len() - 1
+          }
+        };
+        let l = &mut loops[index];
+        l.back_edges.push((source, header));
+        let mut stack = vec![source];
+        while let Some(block) = stack.pop() {
+          if l.blocks.insert(block) {
+            stack.extend(blocks.predecessors()[block].iter().copied());
+          }
+        }
+      }
+    }
+
+    // Outer loops have more blocks than the loops they contain.
+    loops.sort_by_key(|l| std::cmp::Reverse(l.blocks.count()));
+    for index in 0 .. loops.len() {
+      let header = loops[index].header;
+      loops[index].parent = (0 .. index)
+        .rev()
+        .find(|outer| loops[*outer].blocks.contains(header));
+
+      let l = &loops[index];
+      let mut exits = l
+        .blocks
+        .iter()
+        .flat_map(|block| blocks[block].terminator().successors())
+        .filter(|succ| !l.blocks.contains(*succ) && !blocks[*succ].is_cleanup)
+        .collect::<Vec<_>>();
+      exits.sort();
+      exits.dedup();
+      loops[index].exits = exits;
+    }
+
+    let mut innermost = IndexVec::from_elem_n(None, blocks.len());
+    let mut depth = IndexVec::from_elem_n(0, blocks.len());
+    for (index, l) in loops.iter().enumerate() {
+      for block in l.blocks.iter() {
+        innermost[block] = Some(index);
+        depth[block] += 1;
+      }
+    }
+
+    Loops {
+      loops,
+      innermost,
+      depth,
+    }
+  }
+
+  /// Returns every loop, with outer loops before the loops they contain.
+  pub fn loops(&self) -> &[Loop] {
+    &self.loops
+  }
+
+  /// Returns the header of every loop.
+  pub fn headers(&self) -> impl Iterator<Item = BasicBlock> + '_ {
+    self.loops.iter().map(|l| l.header)
+  }
+
+  /// Returns every back edge as `(source, header)`.
+  pub fn back_edges(&self) -> impl Iterator<Item = (BasicBlock, BasicBlock)> + '_ {
+    self.loops.iter().flat_map(|l| l.back_edges.iter().copied())
+  }
+
+  /// Returns true if `block` is the header of a loop.
+  pub fn is_header(&self, block: BasicBlock) -> bool {
+    self.loops.iter().any(|l| l.header == block)
+  }
+
+  /// Returns the innermost loop that contains `block`.
+  pub fn innermost_loop(&self, block: BasicBlock) -> Option<&Loop> {
+    self.innermost[block].map(|index| &self.loops[index])
+  }
+
+  /// Returns the number of loops that contain `block`, i.e. 0 outside of any loop.
+  pub fn depth(&self, block: BasicBlock) -> usize {
+    self.depth[block]
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::mir::Location;
+
+  use crate::{test_utils, BodyExt};
+
+  #[test]
+  fn test_loops() {
+    let input = r#"
+fn main() {
+  let mut i = 0;
+  while i < 10 {
+    let mut j = 0;
+    loop {
+      j += 1;
+      if j > 5 { break; }
+    }
+    i += 1;
+  }
+  let done = i;
+}
+"#;
+    test_utils::compile_body(input, |tcx, _, body_with_facts| {
+      let body = &body_with_facts.body;
+      let loops = body.loops();
+      assert_eq!(loops.loops().len(), 2);
+      assert_eq!(loops.headers().count(), 2);
+      assert!(loops
+        .back_edges()
+        .all(|(_, header)| loops.is_header(header)));
+
+      let source_map = tcx.sess.source_map();
+      let block_of = |s: &str| {
+        body
+          .all_locations()
+          .find(|location: &Location| {
+            source_map
+              .span_to_snippet(body.source_info(*location).span)
+              .unwrap()
+              == s
+          })
+          .unwrap()
+          .block
+      };
+
+      let outer = &loops.loops()[0];
+      let inner = &loops.loops()[1];
+      assert_eq!(outer.parent, None);
+      assert_eq!(inner.parent, Some(0));
+      assert!(outer.blocks.superset(&inner.blocks));
+
+      assert_eq!(loops.depth(block_of("0")), 0);
+      assert_eq!(loops.depth(block_of("i < 10")), 1);
+      assert_eq!(loops.depth(block_of("j > 5")), 2);
+      assert_eq!(loops.depth(block_of("done")), 0);
+      assert_eq!(
+        loops.innermost_loop(block_of("j > 5")).unwrap().header,
+        inner.header
+      );
+      assert!(loops.innermost_loop(block_of("done")).is_none());
+
+      // The outer loop exits when the condition is false, and the inner loop exits
+      // at the break, into the outer loop.
+      assert_eq!(outer.exits.len(), 1);
+      assert!(outer.exits.iter().all(|exit| loops.depth(*exit) == 0));
+      assert!(!inner.exits.is_empty());
+      assert!(inner.exits.iter().all(|exit| outer.blocks.contains(*exit)));
+    });
+  }
+}
@@ -1,10 +1,29 @@
 //! Utilities for MIR-level data structures.
 
 pub mod adt_def;
+pub mod aliases;
 pub mod body;
 pub mod borrowck_facts;
+pub mod branch;
+pub mod callee;
+pub mod callgraph;
+pub mod conflict;
+pub mod const_eval;
 pub mod control_dependencies;
+pub mod coroutine;
+pub mod dataflow;
+pub mod debug_info;
+pub mod def_use;
+pub mod diff;
+pub mod dot;
+pub mod drops;
+pub mod effects;
+pub mod liveness;
 pub mod location_or_arg;
+pub mod loops;
 pub mod mutability;
 pub mod operand;
+pub mod patch;
 pub mod place;
+pub mod reachability;
+pub mod slice;
@@ -0,0 +1,271 @@
+//! Instrumenting MIR bodies with new statements and calls.
+//!
+//! A [`BodyPatch`] collects insertions against the locations of an unmodified body and
+//! then applies them all at once, so locations don't need to be adjusted for the
+//! insertions before them. Inserting a call splits its block in two: the call becomes
+//! the terminator of the first half and returns to the second half.
+//!
+//! Patches are meant to be applied by a body transform, see
+//! [`register_body_transform`](super::borrowck_facts::register_body_transform).
+
+use rustc_index::IndexVec;
+use rustc_middle::{
+  mir::{
+    BasicBlock, BasicBlockData, Body, CallSource, Local, LocalDecl, Location, Operand,
+    Place, SourceInfo, Statement, StatementKind, Terminator, TerminatorKind,
+    UnwindAction, UnwindTerminateReason,
+  },
+  ty::Ty,
+};
+use rustc_span::{source_map::Spanned, Span};
+
+enum Insertion<'tcx> {
+  Statement(StatementKind<'tcx>),
+  Call {
+    func: Operand<'tcx>,
+    args: Vec<Operand<'tcx>>,
+    destination: Place<'tcx>,
+  },
+}
+
+/// A set of insertions into a body, see the [module documentation](self).
+pub struct BodyPatch<'tcx> {
+  num_locals: usize,
+  new_locals: Vec<LocalDecl<'tcx>>,
+  insertions: Vec<(Location, Insertion<'tcx>)>,
+}
+
+impl<'tcx> BodyPatch<'tcx> {
+  /// Creates an empty patch for `body`.
+  pub fn new(body: &Body<'tcx>) -> Self {
+    BodyPatch {
+      num_locals: body.local_decls.len(),
+      new_locals: Vec::new(),
+      insertions: Vec::new(),
+    }
+  }
+
+  /// Allocates a new local of type `ty`, which is added to the body when the patch
+  /// is applied.
+  pub fn new_local(&mut self, ty: Ty<'tcx>, span: Span) -> Local {
+    self.new_locals.push(LocalDecl::new(ty, span));
+    Local::from_usize(self.num_locals + self.new_locals.len() - 1)
+  }
+
+  /// Inserts a statement before `location`.
+  pub fn insert_statement(&mut self, location: Location, kind: StatementKind<'tcx>) {
+    self.insertions.push((location, Insertion::Statement(kind)));
+  }
+
+  /// Inserts a call of `func` with `args` that writes its result to `destination`
+  /// before `location`. If the call unwinds, the function unwinds without running
+  /// any drops, or aborts if `location` is in a cleanup block.
+  pub fn insert_call(
+    &mut self,
+    location: Location,
+    func: Operand<'tcx>,
+    args: Vec<Operand<'tcx>>,
+    destination: Place<'tcx>,
+  ) {
+    self.insertions.push((location, Insertion::Call {
+      func,
+      args,
+      destination,
+    }));
+  }
+
+  /// Applies the patch to `body`, which must be the body the patch was created for.
+  ///
+  /// Insertions at the same location are inserted in the order they were added. The
+  /// first half of a split block keeps its index, so existing edges into the block
+  /// remain valid, and the other halves are appended to the body.
+  pub fn apply(self, body: &mut Body<'tcx>) {
+    assert_eq!(body.local_decls.len(), self.num_locals);
+    body.local_decls.extend(self.new_locals);
+
+    let mut insertions: IndexVec<BasicBlock, Vec<Vec<Insertion<'tcx>>>> = body
+      .basic_blocks
+      .iter()
+      .map(|data| (0 ..= data.statements.len()).map(|_| Vec::new()).collect())
+      .collect();
+    for (location, insertion) in self.insertions {
+      insertions[location.block][location.statement_index].push(insertion);
+    }
+
+    for (block, insertions) in insertions.into_iter_enumerated() {
+      if insertions.iter().all(|at| at.is_empty()) {
+        continue;
+      }
+
+      let blocks = body.basic_blocks_mut();
+      let is_cleanup = blocks[block].is_cleanup;
+      let terminator = blocks[block].terminator.take().unwrap();
+      let original = std::mem::take(&mut blocks[block].statements);
+      let source_infos = original
+        .iter()
+        .map(|statement| statement.source_info)
+        .chain([terminator.source_info])
+        .collect::<Vec<_>>();
+
+      // Blocks that end in a call, whose targets are the next block.
+      let mut finished = Vec::new();
+      let mut statements = Vec::new();
+      let mut original = original.into_iter();
+      for (index, insertions) in insertions.into_iter().enumerate() {
+        let source_info = source_infos[index];
+        for insertion in insertions {
+          match insertion {
+            Insertion::Statement(kind) => {
+              statements.push(Statement { source_info, kind })
+            }
+            Insertion::Call {
+              func,
+              args,
+              destination,
+            } => {
+              let call =
+                call_terminator(source_info, func, args, destination, is_cleanup);
+              finished.push((std::mem::take(&mut statements), call));
+            }
+          }
+        }
+        statements.extend(original.next());
+      }
+
+      let mut current = block;
+      let mut last = BasicBlockData::new(Some(terminator));
+      last.is_cleanup = is_cleanup;
+      last.statements = statements;
+      for (statements, mut call) in finished {
+        let next = blocks.next_index();
+        let TerminatorKind::Call { target, .. } = &mut call.kind else {
+          unreachable!()
+        };
+        *target = Some(next);
+        blocks[current].statements = statements;
+        blocks[current].terminator = Some(call);
+        let mut data = BasicBlockData::new(None);
+        data.is_cleanup = is_cleanup;
+        blocks.push(data);
+        current = next;
+      }
+      blocks[current] = last;
+    }
+  }
+}
+
+fn call_terminator<'tcx>(
+  source_info: SourceInfo,
+  func: Operand<'tcx>,
+  args: Vec<Operand<'tcx>>,
+  destination: Place<'tcx>,
+  is_cleanup: bool,
+) -> Terminator<'tcx> {
+  let span = source_info.span;
+  let unwind = if is_cleanup {
+    UnwindAction::Terminate(UnwindTerminateReason::InCleanup)
+  } else {
+    UnwindAction::Continue
+  };
+  Terminator {
+    source_info,
+    kind: TerminatorKind::Call {
+      func,
+      args: args
+        .into_iter()
+        .map(|node| Spanned { node, span })
+        .collect(),
+      destination,
+      target: None,
+      unwind,
+      call_source: CallSource::Misc,
+      fn_span: span,
+    },
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::{
+    mir::{Const, ConstOperand},
+    ty::{ParamEnv, TyCtxt},
+  };
+  use rustc_span::DUMMY_SP;
+
+  use super::*;
+  use crate::test_utils::CompileBuilder;
+
+  #[test]
+  fn test_patch() {
+    let input = r#"
+fn probe(n: i32) -> i32 { n }
+fn main() {
+  let x = 1;
+  let y = x + 2;
+}
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let tcx: TyCtxt = result.tcx;
+      let find = |name: &str| {
+        tcx
+          .hir()
+          .body_owners()
+          .find(|def_id| tcx.def_path_str(*def_id) == name)
+          .unwrap()
+      };
+      let probe = find("probe").to_def_id();
+      let mut body = tcx.optimized_mir(find("main")).clone();
+      let num_blocks = body.basic_blocks.len();
+      let start = Location {
+        block: BasicBlock::from_usize(0),
+        statement_index: 0,
+      };
+      let end = body.terminator_loc(start.block);
+      let terminator = body.basic_blocks[start.block].terminator().kind.clone();
+
+      let mut patch = BodyPatch::new(&body);
+      let i32_ty = tcx.types.i32;
+      let result = patch.new_local(i32_ty, DUMMY_SP);
+      let arg = Operand::Constant(Box::new(ConstOperand {
+        span: DUMMY_SP,
+        user_ty: None,
+        const_: Const::from_bits(tcx, 7, ParamEnv::reveal_all().and(i32_ty)),
+      }));
+      let func = Operand::function_handle(tcx, probe, [], DUMMY_SP);
+      patch.insert_statement(start, StatementKind::Nop);
+      patch.insert_call(start, func.clone(), vec![arg.clone()], result.into());
+      patch.insert_statement(start, StatementKind::StorageLive(result));
+      patch.insert_call(end, func, vec![arg], result.into());
+      patch.apply(&mut body);
+
+      assert_eq!(body.local_decls.len(), result.as_usize() + 1);
+      assert_eq!(body.local_decls[result].ty, i32_ty);
+      assert_eq!(body.basic_blocks.len(), num_blocks + 2);
+
+      // bb0 now holds the nop and the first call.
+      let bb0 = &body.basic_blocks[start.block];
+      assert!(matches!(bb0.statements[..], [Statement {
+        kind: StatementKind::Nop,
+        ..
+      }]));
+      let TerminatorKind::Call { target, func, .. } = &bb0.terminator().kind else {
+        panic!()
+      };
+      assert_eq!(func.const_fn_def().unwrap().0, probe);
+
+      // The second half starts with the storage marker and ends with the second call,
+      // which returns to the original terminator.
+      let second = &body.basic_blocks[target.unwrap()];
+      assert!(matches!(
+        second.statements[0].kind,
+        StatementKind::StorageLive(local) if local == result
+      ));
+      let TerminatorKind::Call { target, .. } = &second.terminator().kind else {
+        panic!()
+      };
+      let third = &body.basic_blocks[target.unwrap()];
+      assert!(third.statements.is_empty());
+      assert_eq!(third.terminator().kind, terminator);
+    });
+  }
+}
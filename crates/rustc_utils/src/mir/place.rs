@@ -4,14 +4,13 @@ use std::{borrow::Cow, collections::VecDeque};
 
 use log::{trace, warn};
 use rustc_data_structures::fx::{FxHashMap as HashMap, FxHashSet as HashSet};
-use rustc_hir::def_id::DefId;
+use rustc_hir::{def_id::DefId, LangItem};
 use rustc_infer::infer::TyCtxtInferExt;
 use rustc_middle::{
   mir::{
     visit::{PlaceContext, Visitor},
-    Body, HasLocalDecls, Local, Location, Mutability, Place, PlaceElem,
-    PlaceRef, ProjectionElem, VarDebugInfo,
-    VarDebugInfoContents, RETURN_PLACE,
+    Body, HasLocalDecls, Local, Location, Mutability, Place, PlaceElem, PlaceRef,
+    ProjectionElem, VarDebugInfo, VarDebugInfoContents, RETURN_PLACE,
   },
   traits::ObligationCause,
   ty::{self, AdtKind, Region, RegionKind, RegionVid, Ty, TyCtxt, TyKind, TypeVisitor},
@@ -19,7 +18,8 @@ use rustc_middle::{
 use rustc_target::abi::{FieldIdx, VariantIdx};
 use rustc_trait_selection::traits::NormalizeExt;
 
-use crate::{AdtDefExt, SpanExt};
+use super::body::user_debug_info;
+use crate::{cache::Cache, AdtDefExt, SpanExt};
 
 /// A MIR [`Visitor`] which collects all [`Place`]s that appear in the visited object.
 #[derive(Default)]
@@ -84,19 +84,29 @@ pub trait PlaceExt<'tcx> {
 
   /// Returns all possible projections of `self` that do not go through a reference,
   /// i.e. the set of fields directly in the structure referred by `self`.
+  ///
+  /// Raw pointers are not references, so their dereferences are included.
+  ///
+  /// Projections are at most `depth_limit` elements longer than `self`, if given. See
+  /// [`InteriorPlaces`] to enumerate the places of many places with the same types.
   fn interior_places(
     &self,
     tcx: TyCtxt<'tcx>,
     body: &Body<'tcx>,
     def_id: DefId,
+    depth_limit: Option<usize>,
   ) -> HashSet<Place<'tcx>>;
 
-  /// Returns all possible projections of `self`.
+  /// Returns all possible projections of `self`, including dereferences of references
+  /// and raw pointers.
+  ///
+  /// Projections are at most `depth_limit` elements longer than `self`, if given.
   fn interior_paths(
     &self,
     tcx: TyCtxt<'tcx>,
     body: &Body<'tcx>,
     def_id: DefId,
+    depth_limit: Option<usize>,
   ) -> HashSet<Place<'tcx>>;
 
   /// Returns a pretty representation of a place that uses debug info when available.
@@ -109,10 +119,22 @@ pub trait PlaceExt<'tcx> {
   ///   We might encounter this type with a more specific type for the associated type, e.g. `&'1 [i32][0]`.
   /// To account for this variation, we normalize associated types,
   ///   erase regions, and normalize projections.
+  ///
+  /// Projections are normalized by mapping every index to `[0]`, removing subslices
+  /// and subtyping, and mapping the pointer derefs of boxes introduced by box
+  /// deref elaboration, e.g. `*b.0.0.0`, back to box derefs like `*b`. So the places of
+  /// the same path in a body before and after elaboration are normalized to the
+  /// same place.
   fn normalize(&self, tcx: TyCtxt<'tcx>, def_id: DefId) -> Place<'tcx>;
 
   /// Returns true if this place's base [`Local`] corresponds to code that is visible in the source.
   fn is_source_visible(&self, tcx: TyCtxt, body: &Body) -> bool;
+
+  /// Returns true if this place is part of a variable of the source code, i.e. its
+  /// local is one of the [`user_locals`](crate::BodyExt::user_locals), it is within
+  /// a variable captured by a closure or coroutine, or it is a match binding read
+  /// through the reference used by a guard.
+  fn is_user_visible(&self, body: &Body<'tcx>) -> bool;
 }
 
 impl<'tcx> PlaceExt<'tcx> for Place<'tcx> {
@@ -142,7 +164,10 @@ impl<'tcx> PlaceExt<'tcx> for Place<'tcx> {
       || self.refs_in_projection(body, tcx).next().is_none()
   }
 
-  type RefsInProjectionIter<'a> = impl Iterator<Item = (PlaceRef<'tcx>, &'tcx [PlaceElem<'tcx>])> + 'a where Self: 'a;
+  type RefsInProjectionIter<'a>
+    = impl Iterator<Item = (PlaceRef<'tcx>, &'tcx [PlaceElem<'tcx>])> + 'a
+  where
+    Self: 'a;
   fn refs_in_projection(
     &self,
     body: &Body<'tcx>,
@@ -201,16 +226,9 @@ impl<'tcx> PlaceExt<'tcx> for Place<'tcx> {
     tcx: TyCtxt<'tcx>,
     body: &Body<'tcx>,
     def_id: DefId,
+    depth_limit: Option<usize>,
   ) -> HashSet<Place<'tcx>> {
-    let ty = self.ty(body.local_decls(), tcx).ty;
-    let mut region_collector = RegionVisitor::<VisitedPlacesCollector>::new(
-      tcx,
-      def_id,
-      *self,
-      StoppingCondition::BeforeRefs,
-    );
-    region_collector.visit_ty(ty);
-    region_collector.into_inner().0
+    InteriorPlaces::new(tcx, def_id, depth_limit).places(*self, body)
   }
 
   fn interior_paths(
@@ -218,16 +236,9 @@ impl<'tcx> PlaceExt<'tcx> for Place<'tcx> {
     tcx: TyCtxt<'tcx>,
     body: &Body<'tcx>,
     def_id: DefId,
+    depth_limit: Option<usize>,
   ) -> HashSet<Place<'tcx>> {
-    let ty = self.ty(body.local_decls(), tcx).ty;
-    let mut region_collector = RegionVisitor::<VisitedPlacesCollector>::new(
-      tcx,
-      def_id,
-      *self,
-      StoppingCondition::None,
-    );
-    region_collector.visit_ty(ty);
-    region_collector.into_inner().0
+    InteriorPlaces::new(tcx, def_id, depth_limit).paths(*self, body)
   }
 
   fn to_string(&self, tcx: TyCtxt<'tcx>, body: &Body<'tcx>) -> Option<String> {
@@ -343,20 +354,28 @@ impl<'tcx> PlaceExt<'tcx> for Place<'tcx> {
       .normalize(place)
       .value;
 
-    let projection = place
-      .projection
-      .into_iter()
-      .filter_map(|elem| match elem {
+    let mut projection = Vec::new();
+    let mut elems = place.projection.as_slice();
+    while let Some((elem, rest)) = elems.split_first() {
+      // Box deref elaboration replaces the deref of a box `*b` with a deref of the
+      // pointer within the box, `*b.0.0.0`, so map it back to `*b`.
+      if is_box_pointer_deref(tcx, elems) {
+        projection.push(ProjectionElem::Deref);
+        elems = &elems[4 ..];
+        continue;
+      }
+      elems = rest;
+      match elem {
         // Map all indexes [i] to [0] since they should be considered equal
         ProjectionElem::Index(_) | ProjectionElem::ConstantIndex { .. } => {
-          Some(ProjectionElem::Index(Local::from_usize(0)))
+          projection.push(ProjectionElem::Index(Local::from_usize(0)))
         }
         // Ignore subslices, they should be treated the same as the
-        // full slice
-        ProjectionElem::Subslice { .. } => None,
-        _ => Some(elem),
-      })
-      .collect::<Vec<_>>();
+        // full slice, and subtyping, which only changes the regions of the type
+        ProjectionElem::Subslice { .. } | ProjectionElem::Subtype(_) => {}
+        _ => projection.push(*elem),
+      }
+    }
 
     Place::make(place.local, &projection, tcx)
   }
@@ -374,6 +393,121 @@ impl<'tcx> PlaceExt<'tcx> for Place<'tcx> {
     // 3. Not be from a macro expansion (basically also a desugaring).
     is_loc && !from_desugaring && !from_expansion
   }
+
+  fn is_user_visible(&self, body: &Body<'tcx>) -> bool {
+    if user_debug_info(body, self.local).is_some() {
+      return true;
+    }
+
+    // A guard reads the bindings of its arm through references to them.
+    if body.local_decls[self.local].is_ref_for_guard() {
+      return self.projection.first() == Some(&ProjectionElem::Deref);
+    }
+
+    // Captured variables are projections of the closure's upvars.
+    body.var_debug_info.iter().any(|info| match info.value {
+      VarDebugInfoContents::Place(place) => {
+        place.local == self.local
+          && !place.projection.is_empty()
+          && self.projection.starts_with(place.projection)
+          && !info.source_info.span.from_expansion()
+      }
+      _ => false,
+    })
+  }
+}
+
+// Returns true if `elems` starts with the projection to the pointer of a box followed
+// by a deref, i.e. `.0: Unique<T>`, `.0: NonNull<T>`, `.0: *const T`, `*`.
+fn is_box_pointer_deref<'tcx>(tcx: TyCtxt<'tcx>, elems: &[PlaceElem<'tcx>]) -> bool {
+  let Some([unique, nonnull, ptr, ProjectionElem::Deref]) = elems.get(.. 4) else {
+    return false;
+  };
+  let (
+    ProjectionElem::Field(_, unique),
+    ProjectionElem::Field(..),
+    ProjectionElem::Field(_, ptr),
+  ) = (unique, nonnull, ptr)
+  else {
+    return false;
+  };
+  let is_unique = matches!(
+    unique.kind(),
+    TyKind::Adt(def, _) if tcx.is_lang_item(def.did(), LangItem::PtrUnique)
+  );
+  is_unique && ptr.is_unsafe_ptr()
+}
+
+/// Enumerates the [`interior_places`](PlaceExt::interior_places) or
+/// [`interior_paths`](PlaceExt::interior_paths) of many places, caching the projections
+/// found for each type.
+///
+/// Recursive types are only unfolded once along each path, e.g. the paths of a linked
+/// list `l` include `*l.next@Some.0` but not `(*l.next@Some.0).next`.
+/// Every field of a union is included, since each of them may be the active one.
+pub struct InteriorPlaces<'tcx> {
+  tcx: TyCtxt<'tcx>,
+  def_id: DefId,
+  depth_limit: Option<usize>,
+  cache: Cache<(Ty<'tcx>, bool), Vec<Vec<PlaceElem<'tcx>>>>,
+}
+
+impl<'tcx> InteriorPlaces<'tcx> {
+  /// Creates an enumerator for places in the body of `def_id`, which determines the
+  /// visible fields of structs, with projections of at most `depth_limit` elements.
+  pub fn new(tcx: TyCtxt<'tcx>, def_id: DefId, depth_limit: Option<usize>) -> Self {
+    InteriorPlaces {
+      tcx,
+      def_id,
+      depth_limit,
+      cache: Cache::default(),
+    }
+  }
+
+  /// Returns the projections of `place` that don't go through a reference, see
+  /// [`PlaceExt::interior_places`].
+  pub fn places(&self, place: Place<'tcx>, body: &Body<'tcx>) -> HashSet<Place<'tcx>> {
+    self.project(place, body, false)
+  }
+
+  /// Returns all projections of `place`, see [`PlaceExt::interior_paths`].
+  pub fn paths(&self, place: Place<'tcx>, body: &Body<'tcx>) -> HashSet<Place<'tcx>> {
+    self.project(place, body, true)
+  }
+
+  fn project(
+    &self,
+    place: Place<'tcx>,
+    body: &Body<'tcx>,
+    through_refs: bool,
+  ) -> HashSet<Place<'tcx>> {
+    let ty = place.ty(body.local_decls(), self.tcx).ty;
+    let projections = self.cache.get(&(ty, through_refs), |_| {
+      let stop_at = if through_refs {
+        StoppingCondition::None
+      } else {
+        StoppingCondition::BeforeRefs
+      };
+      let mut collector = RegionVisitor::<VisitedPlacesCollector>::new(
+        self.tcx,
+        self.def_id,
+        place,
+        stop_at,
+      );
+      collector.depth_limit = self.depth_limit;
+      collector.visit_ty(ty);
+      collector
+        .into_inner()
+        .0
+        .into_iter()
+        .map(|interior| interior.projection[place.projection.len() ..].to_vec())
+        .collect()
+    });
+    projections
+      .iter()
+      .map(|projection| place.project_deeper(projection, self.tcx))
+      .collect()
+  }
 }
 
 #[derive(Copy, Clone)]
@@ -438,6 +572,10 @@ struct RegionVisitor<'tcx, Dispatcher> {
   /// Callbacks
   dispatcher: Dispatcher,
   stop_at: StoppingCondition,
+  /// Number of projections in the input place.
+  base_depth: usize,
+  /// Maximum number of projections to add to the input place.
+  depth_limit: Option<usize>,
 }
 
 impl<'tcx, Dispatcher: Default> RegionVisitor<'tcx, Dispatcher> {
@@ -459,6 +597,8 @@ impl<'tcx, Dispatcher: Default> RegionVisitor<'tcx, Dispatcher> {
       ty_stack: Vec::new(),
       dispatcher: Default::default(),
       stop_at,
+      base_depth: place.projection.len(),
+      depth_limit: None,
     }
   }
 
@@ -475,7 +615,11 @@ impl<'tcx, Dispatcher: RegionVisitorDispatcher<'tcx>> TypeVisitor<TyCtxt<'tcx>>
 {
   fn visit_ty(&mut self, ty: Ty<'tcx>) {
     let tcx = self.tcx;
+    // Recursive types are unfolded once, so the place is included but not its fields.
     if self.ty_stack.iter().any(|visited_ty| ty == *visited_ty) {
+      self
+        .dispatcher
+        .on_visit_place(Place::make(self.local, &self.place_stack, tcx));
       return;
     }
 
@@ -486,7 +630,10 @@ impl<'tcx, Dispatcher: RegionVisitorDispatcher<'tcx>> TypeVisitor<TyCtxt<'tcx>>
 
     self.ty_stack.push(ty);
 
+    let depth = self.place_stack.len() - self.base_depth;
     match ty.kind() {
+      _ if self.depth_limit.is_some_and(|limit| depth >= limit) => {}
+
       _ if ty.is_box() => {
         self.place_stack.push(ProjectionElem::Deref);
         self.visit_ty(ty.expect_boxed_ty());
@@ -515,7 +662,15 @@ impl<'tcx, Dispatcher: RegionVisitorDispatcher<'tcx>> TypeVisitor<TyCtxt<'tcx>>
           }
         }
         ty::AdtKind::Union => {
-          // unsafe, so ignore
+          // Any field may be active, so conservatively include all of them.
+          for (i, field) in adt_def.non_enum_variant().fields.iter().enumerate() {
+            let ty = field.ty(tcx, subst);
+            self
+              .place_stack
+              .push(ProjectionElem::Field(FieldIdx::from_usize(i), ty));
+            self.visit_ty(ty);
+            self.place_stack.pop();
+          }
         }
         ty::AdtKind::Enum => {
           for (i, variant) in adt_def.variants().iter().enumerate() {
@@ -661,12 +816,17 @@ mod test {
   use rustc_borrowck::consumers::BodyWithBorrowckFacts;
   use rustc_hir::BodyId;
   use rustc_middle::{
-    mir::{Place, PlaceElem},
+    mir::{visit::Visitor, Place, PlaceElem},
     ty::TyCtxt,
   };
+  use rustc_target::abi::FieldIdx;
 
-  use super::{BodyExt, PlaceExt};
-  use crate::test_utils::{self, compare_sets, Placer};
+  use super::{InteriorPlaces, PlaceCollector, PlaceExt};
+  use crate::{
+    mir::borrowck_facts::get_body_with_borrowck_facts,
+    test_utils::{self, compare_sets, CompileBuilder, Placer},
+    BodyExt,
+  };
 
   #[test]
   fn test_place_arg_direct() {
@@ -794,9 +954,13 @@ fn main() {
       let y1 = p.local("y").field(1).mk();
       let y1_deref = p.local("y").field(1).deref().mk();
 
-      compare_sets(y.interior_paths(tcx, body, def_id), [y, y0, y1, y1_deref]);
+      compare_sets(y.interior_paths(tcx, body, def_id, None), [
+        y, y0, y1, y1_deref,
+      ]);
 
-      compare_sets(y.interior_places(tcx, body, def_id), [y, y0, y1]);
+      compare_sets(y.interior_places(tcx, body, def_id, None), [y, y0, y1]);
+      compare_sets(y.interior_paths(tcx, body, def_id, Some(1)), [y, y0, y1]);
+      compare_sets(y.interior_places(tcx, body, def_id, Some(0)), [y]);
 
       compare_sets(
         y.interior_pointers(tcx, body, def_id)
@@ -807,4 +971,130 @@ fn main() {
     }
     test_utils::compile_body(input, callback);
   }
+
+  #[test]
+  fn test_interior_places() {
+    let input = r#"
+struct List { value: i32, next: Option<Box<List>> }
+union U { a: u32, b: (u16, u16) }
+fn main() {
+  let l = List { value: 0, next: None };
+  let u = U { a: 0 };
+  let p = (&0 as *const i32, 1);
+}
+"#;
+    test_utils::compile_body(input, |tcx, body_id, body_with_facts| {
+      let body = &body_with_facts.body;
+      let def_id = tcx.hir().body_owner_def_id(body_id).to_def_id();
+      let p = Placer::new(tcx, body);
+
+      let l = p.local("l").mk();
+      let l_value = p.local("l").field(0).mk();
+      let l_next = p.local("l").field(1).mk();
+      let l_next_box = p.local("l").field(1).downcast(1).field(0);
+      let l_next_0 = l_next_box.mk();
+      let l_next_0_deref = l_next_box.deref().mk();
+      compare_sets(l.interior_places(tcx, body, def_id, None), [
+        l,
+        l_value,
+        l_next,
+        l_next_0,
+        l_next_0_deref,
+      ]);
+      compare_sets(l.interior_places(tcx, body, def_id, Some(3)), [
+        l, l_value, l_next, l_next_0,
+      ]);
+
+      let u = p.local("u").mk();
+      compare_sets(u.interior_places(tcx, body, def_id, None), [
+        u,
+        p.local("u").field(0).mk(),
+        p.local("u").field(1).mk(),
+        p.local("u").field(1).field(0).mk(),
+        p.local("u").field(1).field(1).mk(),
+      ]);
+
+      let ptr = p.local("p").mk();
+      let ptr_places = [
+        ptr,
+        p.local("p").field(0).mk(),
+        p.local("p").field(1).mk(),
+        p.local("p").field(0).deref().mk(),
+      ];
+      compare_sets(ptr.interior_places(tcx, body, def_id, None), ptr_places);
+      compare_sets(ptr.interior_paths(tcx, body, def_id, None), ptr_places);
+
+      // The projections of a type are reused for other places of the same type.
+      let interior = InteriorPlaces::new(tcx, def_id, None);
+      let inner = l_next_box.deref();
+      let inner_next_box = inner.field(1).downcast(1).field(0);
+      compare_sets(interior.places(l_next_0_deref, body), [
+        l_next_0_deref,
+        inner.field(0).mk(),
+        inner.field(1).mk(),
+        inner_next_box.mk(),
+        inner_next_box.deref().mk(),
+      ]);
+      assert_eq!(interior.places(l, body).len(), 5);
+    });
+  }
+
+  #[test]
+  fn test_normalize() {
+    let input = r#"
+fn main() {
+  let b = Box::new((1, 2));
+  let v = [1, 2, 3];
+  let i = 1;
+  let x = (*b).0 + v[i];
+}
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let tcx = result.tcx;
+      let main = tcx
+        .hir()
+        .body_owners()
+        .find(|def_id| tcx.def_path_str(*def_id) == "main")
+        .unwrap();
+      let def_id = main.to_def_id();
+      let body = &get_body_with_borrowck_facts(tcx, main).body;
+      let p = Placer::new(tcx, body);
+
+      let b_field = p.local("b").deref().field(0).mk();
+      let v = p.local("v").mk();
+      let i = p.local("i").mk().local;
+      let v_i = p.local("v").index(i.as_usize()).mk();
+      let v_0 = Place::make(
+        v.local,
+        &[PlaceElem::ConstantIndex {
+          offset: 0,
+          min_length: 3,
+          from_end: false,
+        }],
+        tcx,
+      );
+      assert_eq!(v_i.normalize(tcx, def_id), v_0.normalize(tcx, def_id));
+      assert_eq!(b_field.normalize(tcx, def_id), b_field);
+
+      // Box deref elaboration reads the pointer of `b` into a temporary, but the same
+      // projection is used inline in debug info, e.g. `(*b.0.0.0).0`.
+      let optimized = tcx.optimized_mir(main);
+      let mut collector = PlaceCollector::default();
+      collector.visit_body(optimized);
+      let b = optimized.debug_info_name_map()["b"];
+      let b_pointer = collector
+        .0
+        .into_iter()
+        .find(|place| place.local == b && place.projection.len() == 3)
+        .unwrap();
+      let b_field_elaborated = b_pointer.project_deeper(
+        &[
+          PlaceElem::Deref,
+          PlaceElem::Field(FieldIdx::from_usize(0), tcx.types.i32),
+        ],
+        tcx,
+      );
+      assert_eq!(b_field_elaborated.normalize(tcx, def_id), b_field);
+    });
+  }
 }
@@ -0,0 +1,328 @@
+//! The local functions that are reachable from the entry points of a crate.
+//!
+//! Reachability follows the edges of the [`CallGraph`], along with the uses of
+//! functions that the call graph can't see as calls:
+//!
+//! * A closure or coroutine is reachable from the body that creates it, since it may
+//!   be called by code outside of the crate, e.g. by `Iterator::map`.
+//! * A function used as a value in a reachable body, e.g. passed as `impl Fn`, is
+//!   reachable.
+//! * A call of a trait method that can't be resolved reaches every implementation of
+//!   the method.
+//!
+//! Functions that are only called by drop glue, from other crates through a trait, or
+//! through function pointers created in other crates are only reachable if they're
+//! selected as [`EntryPoints`].
+
+use rustc_data_structures::fx::FxHashSet as HashSet;
+use rustc_hir::def_id::{DefId, LocalDefId};
+use rustc_middle::{
+  mir::{visit::Visitor, Body, ConstOperand, Location},
+  ty::{Instance, Ty, TyCtxt, TyKind},
+};
+use rustc_span::sym;
+
+use super::callgraph::{implementations, CallGraph};
+
+/// Which functions of the crate are the roots of a [`Reachability`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EntryPoints {
+  /// If true, the `main` function is an entry point. With `--test`, this is the
+  /// generated test harness, which doesn't call the tests directly.
+  pub main: bool,
+
+  /// If true, every function that can be named from outside of the crate is an entry
+  /// point, e.g. a `pub fn` in a `pub mod`.
+  pub public: bool,
+
+  /// If true, every `#[test]` function is an entry point. Tests only exist when the
+  /// crate is compiled with `--test`.
+  pub tests: bool,
+
+  /// If true, every method of a trait impl is an entry point, including `Drop::drop`.
+  pub trait_impls: bool,
+}
+
+impl EntryPoints {
+  /// Selects every kind of entry point.
+  pub fn all() -> Self {
+    EntryPoints {
+      main: true,
+      public: true,
+      tests: true,
+      trait_impls: true,
+    }
+  }
+
+  /// Returns the selected entry points of the local crate, in order of definition.
+  pub fn find(&self, tcx: TyCtxt<'_>) -> Vec<LocalDefId> {
+    let main = tcx.entry_fn(()).and_then(|(def_id, _)| def_id.as_local());
+    let tests = if self.tests {
+      test_functions(tcx)
+    } else {
+      HashSet::default()
+    };
+    let visibilities = tcx.effective_visibilities(());
+    fn_bodies(tcx)
+      .filter(|def_id| {
+        (self.main && main == Some(*def_id))
+          || (self.public && visibilities.is_exported(*def_id))
+          || (self.tests && tests.contains(def_id))
+          || (self.trait_impls
+            && tcx
+              .impl_of_method(def_id.to_def_id())
+              .is_some_and(|impl_id| tcx.trait_id_of_impl(impl_id).is_some()))
+      })
+      .collect()
+  }
+}
+
+/// The local functions and closures with a body.
+fn fn_bodies(tcx: TyCtxt<'_>) -> impl Iterator<Item = LocalDefId> + '_ {
+  tcx
+    .hir()
+    .body_owners()
+    .filter(move |def_id| tcx.def_kind(*def_id).is_fn_like())
+}
+
+/// Returns the `#[test]` functions, which are found through the constants that the
+/// test harness generates for them, with the same name in the same module.
+fn test_functions(tcx: TyCtxt<'_>) -> HashSet<LocalDefId> {
+  let markers = tcx
+    .hir()
+    .body_owners()
+    .filter(|def_id| tcx.get_attr(*def_id, sym::rustc_test_marker).is_some())
+    .map(|def_id| {
+      (
+        tcx.parent(def_id.to_def_id()),
+        tcx.item_name(def_id.to_def_id()),
+      )
+    })
+    .collect::<HashSet<_>>();
+  fn_bodies(tcx)
+    .filter(|def_id| {
+      let def_id = def_id.to_def_id();
+      !tcx.is_closure_like(def_id)
+        && markers.contains(&(tcx.parent(def_id), tcx.item_name(def_id)))
+    })
+    .collect()
+}
+
+/// The local functions reachable from a set of entry points, see the
+/// [module documentation](self).
+pub struct Reachability {
+  entries: Vec<LocalDefId>,
+  reachable: HashSet<LocalDefId>,
+  unreachable: Vec<LocalDefId>,
+}
+
+impl Reachability {
+  /// Finds the functions reachable from the `entries` of the crate through `graph`.
+  pub fn compute(tcx: TyCtxt<'_>, graph: &CallGraph<'_>, entries: EntryPoints) -> Self {
+    Self::from_entries(tcx, graph, entries.find(tcx))
+  }
+
+  /// Finds the functions reachable from the given functions through `graph`.
+  pub fn from_entries(
+    tcx: TyCtxt<'_>,
+    graph: &CallGraph<'_>,
+    entries: Vec<LocalDefId>,
+  ) -> Self {
+    let mut reachable = HashSet::default();
+    let mut visited = HashSet::default();
+    let mut stack = entries
+      .iter()
+      .map(|def_id| def_id.to_def_id())
+      .collect::<Vec<_>>();
+    while let Some(def_id) = stack.pop() {
+      if !visited.insert(def_id) {
+        continue;
+      }
+      if tcx.trait_of_item(def_id).is_some() {
+        stack.extend(implementations(tcx, def_id));
+      }
+      let Some(local_id) = def_id.as_local() else {
+        continue;
+      };
+      if !tcx.def_kind(def_id).is_fn_like() || !tcx.is_mir_available(def_id) {
+        continue;
+      }
+      reachable.insert(local_id);
+      stack.extend(graph.callees_of(def_id));
+      stack.extend(mentioned_functions(tcx, def_id));
+    }
+
+    let unreachable = fn_bodies(tcx)
+      .filter(|def_id| !reachable.contains(def_id))
+      .collect();
+    Reachability {
+      entries,
+      reachable,
+      unreachable,
+    }
+  }
+
+  /// Returns the entry points of the analysis.
+  pub fn entries(&self) -> &[LocalDefId] {
+    &self.entries
+  }
+
+  /// Returns true if `def_id` is a local function or closure that may be reached from
+  /// an entry point.
+  pub fn is_reachable(&self, def_id: DefId) -> bool {
+    def_id
+      .as_local()
+      .is_some_and(|def_id| self.reachable.contains(&def_id))
+  }
+
+  /// Returns the reachable functions and closures, in no particular order.
+  pub fn reachable(&self) -> impl Iterator<Item = LocalDefId> + '_ {
+    self.reachable.iter().copied()
+  }
+
+  /// Returns the local functions and closures that can't be reached from an entry
+  /// point, in order of definition.
+  pub fn unreachable(&self) -> &[LocalDefId] {
+    &self.unreachable
+  }
+}
+
+/// Returns the functions, closures and coroutines that the body of `def_id` uses as
+/// values.
+fn mentioned_functions(tcx: TyCtxt<'_>, def_id: DefId) -> Vec<DefId> {
+  struct MentionCollector<'tcx> {
+    tcx: TyCtxt<'tcx>,
+    body: &'tcx Body<'tcx>,
+    mentioned: Vec<DefId>,
+  }
+
+  impl<'tcx> MentionCollector<'tcx> {
+    fn add_ty(&mut self, ty: Ty<'tcx>) {
+      for arg in ty.walk() {
+        let Some(ty) = arg.as_type() else {
+          continue;
+        };
+        match ty.kind() {
+          TyKind::FnDef(def_id, args) => {
+            let param_env = self.tcx.param_env(self.body.source.def_id());
+            let def_id = match Instance::try_resolve(self.tcx, param_env, *def_id, args) {
+              Ok(Some(instance)) => instance.def_id(),
+              _ => *def_id,
+            };
+            self.mentioned.push(def_id);
+          }
+          TyKind::Closure(def_id, _)
+          | TyKind::Coroutine(def_id, _)
+          | TyKind::CoroutineClosure(def_id, _) => self.mentioned.push(*def_id),
+          _ => {}
+        }
+      }
+    }
+  }
+
+  impl<'tcx> Visitor<'tcx> for MentionCollector<'tcx> {
+    fn visit_const_operand(&mut self, constant: &ConstOperand<'tcx>, _: Location) {
+      self.add_ty(constant.const_.ty());
+    }
+  }
+
+  let body = tcx.optimized_mir(def_id);
+  let mut collector = MentionCollector {
+    tcx,
+    body,
+    mentioned: Vec::new(),
+  };
+  for decl in &body.local_decls {
+    collector.add_ty(decl.ty);
+  }
+  collector.visit_body(body);
+  collector.mentioned
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test_utils::CompileBuilder;
+
+  #[test]
+  fn test_reachability() {
+    let input = r#"
+pub trait Animal { fn speak(&self) -> u32; }
+struct Dog;
+impl Animal for Dog { fn speak(&self) -> u32 { 1 } }
+impl Drop for Dog { fn drop(&mut self) {} }
+
+pub fn api() -> u32 { helper() + speak_generic(&Dog) + closures() }
+fn helper() -> u32 { 0 }
+fn speak_generic<A: Animal>(a: &A) -> u32 { a.speak() }
+fn closures() -> u32 {
+  let f = |x: &u32| *x + 1;
+  [1].iter().map(f).map(double).sum()
+}
+fn double(n: u32) -> u32 { n * 2 }
+
+fn dead() { dead_callee(); }
+fn dead_callee() {}
+
+#[test]
+fn my_test() { tested(); }
+fn tested() {}
+"#;
+    CompileBuilder::new(input)
+      .with_args(["--test".to_string()])
+      .compile(|result| {
+        let tcx = result.tcx;
+        let graph = CallGraph::build(tcx);
+        let names = |def_ids: &mut dyn Iterator<Item = LocalDefId>| {
+          let mut names = def_ids
+            .map(|def_id| tcx.def_path_str(def_id))
+            .collect::<Vec<_>>();
+          names.sort();
+          names
+        };
+
+        let public = EntryPoints {
+          public: true,
+          ..Default::default()
+        };
+        let reachability = Reachability::compute(tcx, &graph, public);
+        // The `main` of the test harness is public, but doesn't call the tests.
+        assert_eq!(names(&mut reachability.entries().iter().copied()), [
+          "api", "main"
+        ]);
+        assert_eq!(names(&mut reachability.reachable()), [
+          "<Dog as Animal>::speak",
+          "api",
+          "closures",
+          "closures::{closure#0}",
+          "double",
+          "helper",
+          "main",
+          "speak_generic",
+        ]);
+        let unreachable = names(&mut reachability.unreachable().iter().copied());
+        for name in [
+          "dead",
+          "dead_callee",
+          "my_test",
+          "tested",
+          "<Dog as std::ops::Drop>::drop",
+        ] {
+          assert!(unreachable.contains(&name.to_string()), "{name}");
+        }
+
+        let tests = EntryPoints {
+          tests: true,
+          trait_impls: true,
+          ..Default::default()
+        };
+        let reachability = Reachability::compute(tcx, &graph, tests);
+        assert_eq!(names(&mut reachability.reachable()), [
+          "<Dog as Animal>::speak",
+          "<Dog as std::ops::Drop>::drop",
+          "my_test",
+          "tested",
+        ]);
+      });
+  }
+}
@@ -0,0 +1,321 @@
+//! Forward and backward slicing of MIR bodies.
+//!
+//! A backward slice of a seed `(location, place)` contains the locations that may
+//! influence the value of `place` at `location`, i.e. the definitions of the place that
+//! reach it, the definitions of the places read by those, and so on, along with the
+//! branches that decide whether they execute. A forward slice contains the locations
+//! that may be influenced by the definition of `place` at `location`.
+//!
+//! Dependencies are computed with [`DefUseAnalysis`] and [`ControlDependencies`]. With
+//! [`Slicer::with_aliases`], writes and reads through references are resolved with
+//! [`Aliases`], otherwise a place is only influenced by definitions of conflicting
+//! places.
+
+use rustc_data_structures::{captures::Captures, fx::FxHashSet as HashSet};
+use rustc_middle::{
+  mir::{BasicBlock, Body, Location, Place},
+  ty::TyCtxt,
+};
+use rustc_span::Span;
+
+use super::{
+  aliases::Aliases, control_dependencies::ControlDependencies, def_use::DefUseAnalysis,
+  location_or_arg::LocationOrArg,
+};
+use crate::{BodyExt, SpanExt};
+
+/// Which way [`Slicer::slice`] follows dependencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+  /// Find the locations that influence the seeds.
+  Backward,
+  /// Find the locations that the seeds influence.
+  Forward,
+}
+
+/// The result of [`Slicer::slice`].
+#[derive(Debug, Clone)]
+pub struct Slice {
+  /// The locations in the slice, including the locations of the seeds.
+  pub locations: HashSet<Location>,
+  /// The source spans of the locations, moved out of macro expansions into the body
+  /// and merged where they overlap.
+  pub spans: Vec<Span>,
+}
+
+/// Computes slices of a body, see the [module documentation](self).
+pub struct Slicer<'a, 'tcx> {
+  body: &'a Body<'tcx>,
+  def_use: DefUseAnalysis<'a, 'tcx>,
+  control_deps: ControlDependencies<BasicBlock>,
+  aliases: Option<&'a Aliases<'a, 'tcx>>,
+}
+
+impl<'a, 'tcx> Slicer<'a, 'tcx> {
+  pub fn new(tcx: TyCtxt<'tcx>, body: &'a Body<'tcx>) -> Self {
+    Slicer {
+      body,
+      def_use: DefUseAnalysis::new(tcx, body),
+      control_deps: body.control_dependencies(),
+      aliases: None,
+    }
+  }
+
+  /// Resolves references with `aliases`, which must be computed for the same body.
+  pub fn with_aliases(mut self, aliases: &'a Aliases<'a, 'tcx>) -> Self {
+    self.aliases = Some(aliases);
+    self
+  }
+
+  /// Computes the slice of `seeds` in the given direction.
+  pub fn slice(
+    &self,
+    seeds: impl IntoIterator<Item = (Location, Place<'tcx>)>,
+    direction: Direction,
+  ) -> Slice {
+    let seeds = seeds.into_iter().collect::<Vec<_>>();
+    let mut locations = match direction {
+      Direction::Backward => self.backward(&seeds),
+      Direction::Forward => self.forward(&seeds),
+    };
+    locations.extend(seeds.iter().map(|(location, _)| *location));
+
+    let spans = locations
+      .iter()
+      .filter_map(|location| {
+        let span = self.body.source_info(*location).span;
+        span.as_local(self.body.span)
+      })
+      .collect::<Vec<_>>();
+    Slice {
+      locations,
+      spans: Span::merge_overlaps(spans),
+    }
+  }
+
+  /// Returns the places whose definitions may change `place`: itself, its aliases,
+  /// and the dereferences that may alias it.
+  fn targets(&self, place: Place<'tcx>) -> Vec<Place<'tcx>> {
+    let mut targets = vec![place];
+    if let Some(aliases) = self.aliases {
+      targets.extend(aliases.aliases_of(place).iter().copied());
+      targets.extend(
+        self
+          .def_use
+          .defs()
+          .map(|def| def.place)
+          .filter(|def| def.is_indirect() && aliases.may_alias(*def, place)),
+      );
+    }
+    targets
+  }
+
+  /// Returns the definitions that may determine the value of `place` at `location`.
+  fn dependencies(
+    &self,
+    location: Location,
+    place: Place<'tcx>,
+  ) -> HashSet<LocationOrArg> {
+    self
+      .targets(place)
+      .into_iter()
+      .flat_map(|target| self.def_use.reaching_defs(location, target))
+      .collect()
+  }
+
+  /// Returns the terminators of the blocks that the block of `location` is
+  /// control-dependent on.
+  fn control_parents(
+    &self,
+    location: Location,
+  ) -> impl Iterator<Item = Location> + Captures<'tcx> + '_ {
+    self
+      .control_deps
+      .dependent_on(location.block)
+      .into_iter()
+      .flat_map(|blocks| blocks.iter())
+      .map(|block| self.body.terminator_loc(block))
+  }
+
+  fn backward(&self, seeds: &[(Location, Place<'tcx>)]) -> HashSet<Location> {
+    let mut slice = HashSet::default();
+    let mut visited = HashSet::default();
+    let mut needed = seeds.to_vec();
+    let mut added = seeds
+      .iter()
+      .flat_map(|(location, _)| self.control_parents(*location))
+      .collect::<Vec<_>>();
+
+    loop {
+      // Everything read by a location in the slice is needed.
+      while let Some(location) = added.pop() {
+        if slice.insert(location) {
+          needed.extend(
+            (self.def_use.uses_at(location).iter()).map(|use_| (location, use_.place)),
+          );
+          added.extend(self.control_parents(location));
+        }
+      }
+      let Some((location, place)) = needed.pop() else {
+        break;
+      };
+      if visited.insert((location, place)) {
+        added.extend(
+          self
+            .dependencies(location, place)
+            .into_iter()
+            .filter_map(|def| match def {
+              LocationOrArg::Location(location) => Some(location),
+              LocationOrArg::Arg(_) => None,
+            }),
+        );
+      }
+    }
+
+    slice
+  }
+
+  fn forward(&self, seeds: &[(Location, Place<'tcx>)]) -> HashSet<Location> {
+    // A seed is the definition of its place at its location, or else the definitions
+    // that reach its location.
+    let mut sources = HashSet::default();
+    for (location, place) in seeds {
+      let defines = self
+        .def_use
+        .defs_of(*place)
+        .any(|def| def.location == LocationOrArg::Location(*location));
+      if defines {
+        sources.insert(LocationOrArg::Location(*location));
+      } else {
+        sources.extend(self.def_use.reaching_defs(*location, *place));
+      }
+    }
+
+    let uses = self
+      .body
+      .all_locations()
+      .flat_map(|location| self.def_use.uses_at(location))
+      .map(|use_| (use_.location, self.dependencies(use_.location, use_.place)))
+      .collect::<Vec<_>>();
+
+    let mut slice = HashSet::default();
+    loop {
+      let mut added = uses
+        .iter()
+        .filter(|(location, deps)| {
+          !slice.contains(location) && deps.iter().any(|dep| sources.contains(dep))
+        })
+        .map(|(location, _)| *location)
+        .collect::<Vec<_>>();
+      if added.is_empty() {
+        break;
+      }
+      while let Some(location) = added.pop() {
+        if !slice.insert(location) {
+          continue;
+        }
+        sources.insert(LocationOrArg::Location(location));
+        // Branching on an influenced value influences everything that depends on the
+        // branch.
+        if location == self.body.terminator_loc(location.block) {
+          let dependents = self.body.basic_blocks.indices().filter(|block| {
+            self
+              .control_deps
+              .is_control_dependent(*block, location.block)
+          });
+          added.extend(dependents.flat_map(|block| self.body.locations_in_block(block)));
+        }
+      }
+    }
+
+    slice
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_middle::mir::Location;
+
+  use super::*;
+  use crate::{
+    mir::aliases::Aliases,
+    test_utils::{self, Placer},
+  };
+
+  #[test]
+  fn test_slice() {
+    let input = r#"
+fn main() {
+  let mut x = 1;
+  let y = 2;
+  let z = 3;
+  if y > 0 { x = 4; }
+  let a = &mut x;
+  *a += z;
+  let w = x;
+  let v = y + 1;
+}
+"#;
+    test_utils::compile_body(input, |tcx, body_id, body_with_facts| {
+      let body = &body_with_facts.body;
+      let def_id = tcx.hir().body_owner_def_id(body_id).to_def_id();
+      let aliases = Aliases::build(tcx, def_id, body_with_facts);
+      let p = Placer::new(tcx, body);
+      let source_map = tcx.sess.source_map();
+      let snippet = |location| {
+        source_map
+          .span_to_snippet(body.source_info(location).span)
+          .unwrap()
+      };
+      let location_of = |s: &str| {
+        body
+          .all_locations()
+          .find(|location: &Location| snippet(*location) == s)
+          .unwrap()
+      };
+      let snippets = |slice: &Slice| {
+        slice
+          .locations
+          .iter()
+          .map(|location| snippet(*location))
+          .collect::<HashSet<_>>()
+      };
+
+      let x = p.local("x").mk();
+      let seed = (location_of("x"), x);
+
+      let slicer = Slicer::new(tcx, body);
+      let backward = snippets(&slicer.slice([seed], Direction::Backward));
+      for s in ["1", "x = 4", "y > 0", "2"] {
+        assert!(backward.contains(s), "{s} not in {backward:?}");
+      }
+      for s in ["*a += z", "y + 1"] {
+        assert!(!backward.contains(s), "{s} in {backward:?}");
+      }
+
+      let slicer = Slicer::new(tcx, body).with_aliases(&aliases);
+      let slice = slicer.slice([seed], Direction::Backward);
+      let backward = snippets(&slice);
+      for s in ["1", "x = 4", "*a += z", "3", "&mut x"] {
+        assert!(backward.contains(s), "{s} not in {backward:?}");
+      }
+      assert!(!backward.contains("y + 1"));
+      assert!(!slice.spans.is_empty());
+
+      let z = p.local("z").mk();
+      let forward = snippets(&slicer.slice([(location_of("3"), z)], Direction::Forward));
+      for s in ["3", "*a += z", "x"] {
+        assert!(forward.contains(s), "{s} not in {forward:?}");
+      }
+      for s in ["1", "y + 1", "x = 4"] {
+        assert!(!forward.contains(s), "{s} in {forward:?}");
+      }
+
+      let y = p.local("y").mk();
+      let forward = snippets(&slicer.slice([(location_of("2"), y)], Direction::Forward));
+      for s in ["y > 0", "x = 4", "y + 1", "x"] {
+        assert!(forward.contains(s), "{s} not in {forward:?}");
+      }
+    });
+  }
+}
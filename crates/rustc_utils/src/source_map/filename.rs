@@ -1,4 +1,6 @@
-use std::path::PathBuf;
+use std::path::{self, Path, PathBuf};
+
+use rustc_span::source_map::SourceMap;
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Filename(pub PathBuf);
@@ -9,3 +11,58 @@ rustc_index::newtype_index! {
   #[debug_format = "f{}"]
   pub struct FilenameIndex {}
 }
+
+/// How the path of a file is written when a range is shared outside of rustc, see
+/// [`Filename::remap`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PathRemapping {
+  /// The path as given to rustc, which is usually relative to the directory that
+  /// rustc runs in.
+  #[default]
+  Unchanged,
+
+  /// The absolute path, with symbolic links resolved if the file exists.
+  Absolute,
+
+  /// The path relative to a directory, e.g. the root of the workspace. Paths outside
+  /// of the directory are absolute.
+  RelativeTo(PathBuf),
+
+  /// The path with the `--remap-path-prefix` mappings of the session applied, as
+  /// rustc writes paths into diagnostics and debuginfo.
+  RemapPathPrefix,
+}
+
+impl Filename {
+  /// Returns the path of the file according to `remapping`, with `/` as the separator
+  /// so the same file has the same path on every platform.
+  pub fn remap(&self, source_map: &SourceMap, remapping: &PathRemapping) -> String {
+    let path = match remapping {
+      PathRemapping::Unchanged => self.0.clone(),
+      PathRemapping::Absolute => absolute(&self.0),
+      PathRemapping::RelativeTo(dir) => {
+        let path = absolute(&self.0);
+        match path.strip_prefix(absolute(dir)) {
+          Ok(relative) => relative.to_path_buf(),
+          Err(_) => path,
+        }
+      }
+      PathRemapping::RemapPathPrefix => {
+        source_map.path_mapping().map_prefix(&self.0).0.into_owned()
+      }
+    };
+    let path = path.to_string_lossy();
+    if cfg!(windows) {
+      path.replace('\\', "/")
+    } else {
+      path.into_owned()
+    }
+  }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+  path
+    .canonicalize()
+    .or_else(|_| path::absolute(path))
+    .unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,222 @@
+//! Mapping source spans to the MIR locations they cover.
+
+use rustc_data_structures::fx::FxHashMap as HashMap;
+use rustc_hir::{BodyId, ExprKind};
+use rustc_middle::{
+  mir::{Body, Location},
+  ty::TyCtxt,
+};
+use rustc_span::{source_map::Spanned, BytePos, Span};
+
+use super::spanner::span_tree::SpanTree;
+use crate::{BodyExt, SpanExt};
+
+struct BodyIndex {
+  span: Span,
+  locations: SpanTree<Location>,
+}
+
+/// A reverse index from source spans to the bodies and MIR locations that they cover,
+/// i.e. the inverse of [`Body::source_info`].
+///
+/// Locations are indexed by the span that a user would recognize: spans produced by
+/// desugaring keep their position in the source, and spans from a macro's definition
+/// are replaced by the macro's call site, so selecting `println!(..)` covers every
+/// location of its expansion. Locations whose span is the entire body, like the
+/// `return`, are not indexed.
+pub struct HirLocationMapper {
+  bodies: SpanTree<BodyId>,
+  indices: HashMap<BodyId, BodyIndex>,
+}
+
+impl HirLocationMapper {
+  /// Indexes the locations of `bodies`, e.g. the bodies with borrowck facts of every
+  /// body from [`find_bodies`](super::find_bodies::find_bodies).
+  pub fn new<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'tcx>,
+    bodies: impl IntoIterator<Item = (BodyId, &'a Body<'tcx>)>,
+  ) -> Self {
+    let hir = tcx.hir();
+    let mut body_spans = Vec::new();
+    let mut indices = HashMap::default();
+    for (body_id, body) in bodies {
+      let span = hir.span_with_body(hir.body_owner(body_id));
+      // The block of a body is the span of its return and the drops at its end, but a
+      // closure whose body is an expression may have locations for the expression.
+      let value = hir.body(body_id).value;
+      let block_span = matches!(value.kind, ExprKind::Block(..)).then_some(value.span);
+      let locations = body.all_locations().filter_map(|location| {
+        let loc_span = body.source_info(location).span;
+        let loc_span = loc_span.as_local(span)?;
+        let invalid = loc_span.is_dummy()
+          || loc_span.is_empty()
+          || loc_span.source_equal(span)
+          || block_span.is_some_and(|block| loc_span.source_equal(block));
+        (!invalid).then_some(Spanned {
+          span: loc_span,
+          node: location,
+        })
+      });
+      let locations = SpanTree::new(locations);
+      body_spans.push(Spanned {
+        span,
+        node: body_id,
+      });
+      indices.insert(body_id, BodyIndex { span, locations });
+    }
+    HirLocationMapper {
+      bodies: SpanTree::new(body_spans),
+      indices,
+    }
+  }
+
+  /// Returns the innermost indexed body that contains `span`.
+  pub fn enclosing_body(&self, span: Span) -> Option<BodyId> {
+    let span = query_span(span);
+    self
+      .bodies
+      .overlapping(span.data())
+      .filter(|(body_span, _)| body_span.span().contains(span))
+      .min_by_key(|(body_span, _)| body_span.span().size())
+      .map(|(_, body_id)| *body_id)
+  }
+
+  /// Returns the innermost body that contains `span` along with the locations of the
+  /// body whose spans intersect `span`, in order. An empty span is treated as the
+  /// character after it, i.e. a cursor position.
+  ///
+  /// The locations of closures nested in the body are in the body of the closure, so
+  /// a span that contains a closure doesn't include the closure's locations.
+  pub fn lookup(&self, span: Span) -> Option<(BodyId, Vec<Location>)> {
+    let body_id = self.enclosing_body(span)?;
+    Some((body_id, self.locations_in(body_id, span)))
+  }
+
+  /// Returns the locations of `body_id` whose spans intersect `span`, in order.
+  pub fn locations_in(&self, body_id: BodyId, span: Span) -> Vec<Location> {
+    let Some(index) = self.indices.get(&body_id) else {
+      return Vec::new();
+    };
+    let span = query_span(span);
+    if !index.span.overlaps(span) {
+      return Vec::new();
+    }
+    let mut locations = index
+      .locations
+      .overlapping(span.data())
+      .map(|(_, location)| *location)
+      .collect::<Vec<_>>();
+    locations.sort();
+    locations.dedup();
+    locations
+  }
+}
+
+fn query_span(span: Span) -> Span {
+  let span = span.source_callsite();
+  if span.is_empty() {
+    span.with_hi(span.hi() + BytePos(1))
+  } else {
+    span
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rustc_data_structures::fx::FxHashSet as HashSet;
+
+  use super::*;
+  use crate::{
+    mir::borrowck_facts::get_body_with_borrowck_facts,
+    source_map::{find_bodies::find_bodies, range::ToSpan},
+    test_utils::{self, CompileBuilder},
+  };
+
+  #[test]
+  fn test_location_mapper() {
+    let src = r#"fn f(x: i32) -> i32 {
+  let y = `(x + 1)`;
+  `(println!("{}", y))`;
+  let c = |z: i32| `(z * 2)`;
+  `()`c(y)
+}
+struct `(S)`;
+fn g() { let _ = `(f(1))`; }
+"#;
+    let (input, _) = test_utils::parse_ranges(src, [("`(", ")`")]).unwrap();
+    CompileBuilder::new(input).compile(move |result| {
+      let tcx = result.tcx;
+      let (_, mut ranges) = test_utils::parse_ranges(src, [("`(", ")`")]).unwrap();
+      let spans = ranges
+        .remove("`(")
+        .unwrap()
+        .into_iter()
+        .map(|range| range.to_span(tcx).unwrap())
+        .collect::<Vec<_>>();
+
+      let hir = tcx.hir();
+      let bodies = find_bodies(tcx)
+        .into_iter()
+        .map(|(_, body_id)| {
+          let def_id = hir.body_owner_def_id(body_id);
+          (body_id, &get_body_with_borrowck_facts(tcx, def_id).body)
+        })
+        .collect::<Vec<_>>();
+      let mapper = HirLocationMapper::new(tcx, bodies.iter().copied());
+      let body_of = |body_id: BodyId| {
+        bodies
+          .iter()
+          .find(|(other, _)| *other == body_id)
+          .unwrap()
+          .1
+      };
+      let name = |body_id: BodyId| tcx.def_path_str(hir.body_owner_def_id(body_id));
+
+      let source_map = tcx.sess.source_map();
+      let snippets = |body_id: BodyId, locations: &[Location]| {
+        let body = body_of(body_id);
+        locations
+          .iter()
+          .map(|location| {
+            let span = body.source_info(*location).span.source_callsite();
+            source_map.span_to_snippet(span).unwrap()
+          })
+          .collect::<HashSet<_>>()
+      };
+
+      let (body_id, locations) = mapper.lookup(spans[0]).unwrap();
+      assert_eq!(name(body_id), "f");
+      assert!(!locations.is_empty());
+      assert!(snippets(body_id, &locations).contains("x + 1"));
+
+      // The locations of a macro's expansion are mapped to the call site.
+      let (body_id, locations) = mapper.lookup(spans[1]).unwrap();
+      assert_eq!(name(body_id), "f");
+      assert!(locations.len() > 1);
+      let body = body_of(body_id);
+      assert!(locations
+        .iter()
+        .any(|location| body.source_info(*location).span.from_expansion()));
+
+      let (body_id, locations) = mapper.lookup(spans[2]).unwrap();
+      assert_eq!(name(body_id), "f::{closure#0}");
+      assert!(snippets(body_id, &locations).contains("z * 2"));
+
+      // An empty span is a cursor before `c`.
+      let (body_id, locations) = mapper.lookup(spans[3]).unwrap();
+      assert_eq!(name(body_id), "f");
+      assert!(snippets(body_id, &locations)
+        .iter()
+        .all(|s| s.starts_with('c')));
+      assert!(!locations.is_empty());
+
+      assert!(mapper.lookup(spans[4]).is_none());
+
+      let (body_id, locations) = mapper.lookup(spans[5]).unwrap();
+      assert_eq!(name(body_id), "g");
+      assert!(snippets(body_id, &locations).contains("f(1)"));
+      let f = mapper.enclosing_body(spans[0]).unwrap();
+      assert!(mapper.locations_in(f, spans[5]).is_empty());
+    });
+  }
+}
@@ -2,6 +2,7 @@
 
 pub mod filename;
 pub mod find_bodies;
+pub mod location_mapper;
 pub mod range;
 pub mod span;
 pub mod spanner;
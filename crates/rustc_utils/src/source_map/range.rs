@@ -1,6 +1,9 @@
 use std::{
-  cell::RefCell, collections::hash_map::Entry, default::Default, ffi::OsStr,
-  path::PathBuf,
+  cell::RefCell,
+  collections::hash_map::Entry,
+  default::Default,
+  ffi::OsStr,
+  path::{Path, PathBuf},
 };
 
 use anyhow::{bail, ensure, Context, Result};
@@ -11,24 +14,28 @@ use rustc_hir::{
 };
 use rustc_index::IndexVec;
 use rustc_middle::ty::TyCtxt;
-use rustc_span::{source_map::SourceMap, FileName, RealFileName, SourceFile, Span};
+use rustc_span::{source_map::SourceMap, FileName, SourceFile, Span};
 #[cfg(feature = "serde")]
 use serde::Serialize;
 #[cfg(feature = "ts-rs")]
 use ts_rs::TS;
 
-use super::filename::{Filename, FilenameIndex};
+use super::filename::{Filename, FilenameIndex, PathRemapping};
 use crate::cache::Cache;
 
 struct CharByteMapping {
   byte_to_char: HashMap<BytePos, CharPos>,
   char_to_byte: HashMap<CharPos, BytePos>,
+  byte_to_utf16: HashMap<BytePos, Utf16Pos>,
+  utf16_to_byte: HashMap<Utf16Pos, BytePos>,
 }
 
 impl CharByteMapping {
   pub fn build(file: &SourceFile) -> Self {
     let mut byte_to_char = HashMap::default();
     let mut char_to_byte = HashMap::default();
+    let mut byte_to_utf16 = HashMap::default();
+    let mut utf16_to_byte = HashMap::default();
 
     macro_rules! check_insert {
       ($bpos:expr, $cpos:expr) => {
@@ -55,12 +62,17 @@ impl CharByteMapping {
       let line_start = line_bounds.start.0 as usize;
       let mut last_column = 0;
       let mut last_offset = 0;
+      let mut character = 0;
       for (column, (byte_offset, c)) in line_str.char_indices().enumerate() {
         let bpos = BytePos(line_start + byte_offset);
         let cpos = CharPos { line, column };
         check_insert!(bpos, cpos);
+        let upos = Utf16Pos { line, character };
+        byte_to_utf16.insert(bpos, upos);
+        utf16_to_byte.insert(upos, bpos);
         last_column = column + 1;
         last_offset = byte_offset + c.len_utf8();
+        character += c.len_utf16();
       }
 
       let bpos = BytePos(line_start + last_offset);
@@ -69,11 +81,16 @@ impl CharByteMapping {
         column: last_column,
       };
       check_insert!(bpos, cpos);
+      let upos = Utf16Pos { line, character };
+      byte_to_utf16.insert(bpos, upos);
+      utf16_to_byte.insert(upos, bpos);
     }
 
     CharByteMapping {
       byte_to_char,
       char_to_byte,
+      byte_to_utf16,
+      utf16_to_byte,
     }
   }
 
@@ -91,6 +108,22 @@ impl CharByteMapping {
       .get(&pos)
       .unwrap_or_else(|| panic!("Could not find byte pos for {pos:?}"))
   }
+
+  pub fn byte_to_utf16(&self, pos: BytePos) -> Result<Utf16Pos> {
+    self
+      .byte_to_utf16
+      .get(&pos)
+      .copied()
+      .with_context(|| format!("Could not find UTF-16 pos for {pos:?}"))
+  }
+
+  pub fn utf16_to_byte(&self, pos: Utf16Pos) -> Result<BytePos> {
+    self
+      .utf16_to_byte
+      .get(&pos)
+      .copied()
+      .with_context(|| format!("Could not find byte pos for {pos:?}"))
+  }
 }
 
 #[derive(Default)]
@@ -135,18 +168,17 @@ impl FilenameIndex {
           let filename = filename.0
             .canonicalize()
             .unwrap_or_else(|_| filename.0.to_path_buf());
-          let rustc_filename = files
+          let file = files
             .iter()
-            .map(|file| &file.name)
-            .find(|name| match &name {
+            .find(|file| match local_path(&file.name) {
               // rustc seems to store relative paths to files in the workspace, so if filename is absolute,
               // we can compare them using Path::ends_with
-              FileName::Real(RealFileName::LocalPath(other)) => {
+              Some(other) => {
                 let canonical = other.canonicalize();
-                let other = canonical.as_ref().unwrap_or(other);
+                let other = canonical.as_deref().unwrap_or(other);
                 filename.ends_with(other)
               }
-              _ => false,
+              None => false,
             })
             .with_context(|| {
               format!(
@@ -154,22 +186,40 @@ impl FilenameIndex {
                 filename.display(),
                 files
                   .iter()
-                  .filter_map(|file| match &file.name {
-                    FileName::Real(RealFileName::LocalPath(other)) =>
-                      Some(format!("{}", other.display())),
-                    _ => None,
-                  })
+                  .filter_map(|file| local_path(&file.name)
+                    .map(|other| format!("{}", other.display())))
                   .collect::<Vec<_>>()
                   .join(", ")
               )
             })?;
-          let file = source_map.get_source_file(rustc_filename).unwrap();
+          let file = Lrc::clone(file);
           entry.insert(Lrc::clone(&file));
           Ok(file)
         }
       }
     })
   }
+
+  /// Returns the path of the file according to `remapping`, see [`Filename::remap`].
+  pub fn remap(
+    self,
+    source_map: &SourceMap,
+    remapping: &PathRemapping,
+  ) -> Result<String> {
+    CONTEXT.with(|ctx| {
+      let ctx = ctx.borrow();
+      let filename = ctx.filenames.get(self).context("Missing file index!")?;
+      Ok(filename.remap(source_map, remapping))
+    })
+  }
+}
+
+// The path of a file on disk, including files remapped by `--remap-path-prefix`.
+fn local_path(name: &FileName) -> Option<&Path> {
+  match name {
+    FileName::Real(real) => real.local_path(),
+    _ => None,
+  }
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -194,6 +244,21 @@ pub struct CharPos {
   pub column: usize,
 }
 
+/// Utf16Pos is designed to match the Language Server Protocol's `Position` type,
+/// with the default `utf-16` position encoding.
+/// Both line and character are 0-based, and `character` counts UTF-16 code units,
+/// so a character outside of the Basic Multilingual Plane like an emoji takes two.
+///
+/// Like [`CharPos`], positions are relative to the start of a line, so they don't
+/// depend on whether the file uses CRLF line endings.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "ts-rs", derive(TS))]
+pub struct Utf16Pos {
+  pub line: usize,
+  pub character: usize,
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize))]
 #[cfg_attr(feature = "ts-rs", derive(TS))]
@@ -218,7 +283,52 @@ pub struct CharRange {
   pub filename: FilenameIndex,
 }
 
+/// A range of [`Utf16Pos`], i.e. an LSP `Range` along with its file.
+///
+/// Unlike a [`CharRange`], the ends of a range can't be inside of a character, so
+/// conversions from a `Utf16Range` return an error if an end is in the middle of a
+/// surrogate pair or past the end of its line.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "ts-rs", derive(TS))]
+pub struct Utf16Range {
+  pub start: Utf16Pos,
+  pub end: Utf16Pos,
+  pub filename: FilenameIndex,
+}
+
+/// A range with the path of its file rather than a [`FilenameIndex`], which is only
+/// meaningful within the process that created it.
+///
+/// The path is written according to a [`PathRemapping`], so the output of a plugin can
+/// be the same across machines. Ranges are ordered by path and then position, so
+/// sorting them gives a stable order.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "ts-rs", derive(TS))]
+pub struct SerializedRange<P> {
+  pub path: String,
+  pub start: P,
+  pub end: P,
+}
+
 impl ByteRange {
+  /// Returns the range with the path of its file, and with byte offsets from the start
+  /// of the file rather than positions in the [`SourceMap`].
+  pub fn to_serialized(
+    &self,
+    source_map: &SourceMap,
+    remapping: &PathRemapping,
+  ) -> Result<SerializedRange<BytePos>> {
+    let file = self.filename.find_source_file(source_map)?;
+    let offset = |pos: BytePos| BytePos(pos.0 - file.start_pos.0 as usize);
+    Ok(SerializedRange {
+      path: self.filename.remap(source_map, remapping)?,
+      start: offset(self.start),
+      end: offset(self.end),
+    })
+  }
+
   pub fn as_char_range(&self, source_map: &SourceMap) -> CharRange {
     let file = self.filename.find_source_file(source_map).unwrap();
 
@@ -226,7 +336,7 @@ impl ByteRange {
       let ctx = ctx.borrow();
       let mapping: &CharByteMapping = ctx
         .char_byte_mapping
-        .get(self.filename, |_| CharByteMapping::build(&file));
+        .get(&self.filename, |_| CharByteMapping::build(&file));
 
       let char_start = mapping.byte_to_char(self.start);
       let char_end = mapping.byte_to_char(self.end);
@@ -251,7 +361,7 @@ impl ByteRange {
       let ctx = ctx.borrow();
       let mapping = ctx
         .char_byte_mapping
-        .get(filename, |_| CharByteMapping::build(&file));
+        .get(&filename, |_| CharByteMapping::build(&file));
       let byte_start = mapping.char_to_byte(char_start);
       let byte_end = mapping.char_to_byte(char_end);
       Ok(ByteRange {
@@ -262,17 +372,52 @@ impl ByteRange {
     })
   }
 
+  pub fn as_utf16_range(&self, source_map: &SourceMap) -> Result<Utf16Range> {
+    let file = self.filename.find_source_file(source_map)?;
+
+    CONTEXT.with(|ctx| {
+      let ctx = ctx.borrow();
+      let mapping = ctx
+        .char_byte_mapping
+        .get(&self.filename, |_| CharByteMapping::build(&file));
+      Ok(Utf16Range {
+        start: mapping.byte_to_utf16(self.start)?,
+        end: mapping.byte_to_utf16(self.end)?,
+        filename: self.filename,
+      })
+    })
+  }
+
+  pub fn from_utf16_range(
+    utf16_start: Utf16Pos,
+    utf16_end: Utf16Pos,
+    filename: FilenameIndex,
+    source_map: &SourceMap,
+  ) -> Result<ByteRange> {
+    let file = filename.find_source_file(source_map)?;
+
+    CONTEXT.with(|ctx| {
+      let ctx = ctx.borrow();
+      let mapping = ctx
+        .char_byte_mapping
+        .get(&filename, |_| CharByteMapping::build(&file));
+      Ok(ByteRange {
+        start: mapping.utf16_to_byte(utf16_start)?,
+        end: mapping.utf16_to_byte(utf16_end)?,
+        filename,
+      })
+    })
+  }
+
   pub fn from_span(span: Span, source_map: &SourceMap) -> Result<Self> {
     CONTEXT.with(|ctx| {
       let mut ctx = ctx.borrow_mut();
 
       log::trace!("Converting to range: {span:?}");
       let file = source_map.lookup_source_file(span.lo());
-      let filename = match &file.name {
-        FileName::Real(RealFileName::LocalPath(filename)) => {
-          Filename(filename.clone()).intern_with_ctx(&mut ctx)
-        }
-        filename => bail!("Range::from_span doesn't support {filename:?}"),
+      let filename = match local_path(&file.name) {
+        Some(filename) => Filename(filename.to_path_buf()).intern_with_ctx(&mut ctx),
+        None => bail!("Range::from_span doesn't support {:?}", file.name),
       };
 
       ensure!(
@@ -280,7 +425,7 @@ impl ByteRange {
         "Could not load source for file: {:?}",
         file.name
       );
-      
+
       let byte_start = BytePos(span.lo().0 as usize);
       let byte_end = BytePos(span.hi().0 as usize);
 
@@ -302,6 +447,51 @@ impl CharRange {
     let byte_range = ByteRange::from_span(span, source_map)?;
     Ok(byte_range.as_char_range(source_map))
   }
+
+  /// Returns the range with the path of its file.
+  pub fn to_serialized(
+    &self,
+    source_map: &SourceMap,
+    remapping: &PathRemapping,
+  ) -> Result<SerializedRange<CharPos>> {
+    Ok(SerializedRange {
+      path: self.filename.remap(source_map, remapping)?,
+      start: self.start,
+      end: self.end,
+    })
+  }
+
+  pub fn as_utf16_range(&self, source_map: &SourceMap) -> Result<Utf16Range> {
+    let byte_range =
+      ByteRange::from_char_range(self.start, self.end, self.filename, source_map)?;
+    byte_range.as_utf16_range(source_map)
+  }
+}
+
+impl Utf16Range {
+  pub fn from_span(span: Span, source_map: &SourceMap) -> Result<Self> {
+    let byte_range = ByteRange::from_span(span, source_map)?;
+    byte_range.as_utf16_range(source_map)
+  }
+
+  /// Returns the range with the path of its file.
+  pub fn to_serialized(
+    &self,
+    source_map: &SourceMap,
+    remapping: &PathRemapping,
+  ) -> Result<SerializedRange<Utf16Pos>> {
+    Ok(SerializedRange {
+      path: self.filename.remap(source_map, remapping)?,
+      start: self.start,
+      end: self.end,
+    })
+  }
+
+  pub fn as_char_range(&self, source_map: &SourceMap) -> Result<CharRange> {
+    let byte_range =
+      ByteRange::from_utf16_range(self.start, self.end, self.filename, source_map)?;
+    Ok(byte_range.as_char_range(source_map))
+  }
 }
 
 /// Used to convert objects into a [`Span`] with access to [`TyCtxt`]
@@ -330,6 +520,18 @@ impl ToSpan for CharRange {
   }
 }
 
+impl ToSpan for Utf16Range {
+  fn to_span(&self, tcx: TyCtxt) -> Result<Span> {
+    let range = ByteRange::from_utf16_range(
+      self.start,
+      self.end,
+      self.filename,
+      tcx.sess.source_map(),
+    )?;
+    range.to_span(tcx)
+  }
+}
+
 fn qpath_to_span(tcx: TyCtxt, qpath: String) -> Result<Span> {
   struct Finder<'tcx> {
     tcx: TyCtxt<'tcx>,
@@ -438,4 +640,112 @@ mod test {
       });
     });
   }
+
+  #[test]
+  fn test_utf16_range() {
+    // The emoji is one char and two UTF-16 code units, and `é` is one of each. The
+    // CRLF line endings are not counted in the columns.
+    let input = "fn main() {\r\n  let x = \"🦀é\"; let y = 1;\r\n}\r\n";
+
+    test_utils::CompileBuilder::new(input).compile(|CompileResult { tcx }| {
+      let source_map = tcx.sess.source_map();
+      let filename = Filename::intern("dummy.rs");
+      let snippet = |span: Span| source_map.span_to_snippet(span).unwrap();
+      let pos = |character| Utf16Pos { line: 1, character };
+
+      let range = Utf16Range {
+        start: pos(17),
+        end: pos(26),
+        filename,
+      };
+      assert_eq!(snippet(range.to_span(tcx).unwrap()), "let y = 1");
+
+      let char_range = range.as_char_range(source_map).unwrap();
+      assert_eq!(char_range.start, CharPos {
+        line: 1,
+        column: 16
+      });
+      assert_eq!(char_range.as_utf16_range(source_map).unwrap(), range);
+
+      let emoji = Utf16Range {
+        start: pos(11),
+        end: pos(13),
+        filename,
+      };
+      let span = emoji.to_span(tcx).unwrap();
+      assert_eq!(snippet(span), "🦀");
+      assert_eq!(Utf16Range::from_span(span, source_map).unwrap(), emoji);
+
+      // Neither the middle of the emoji nor a position past the end of the line is the
+      // start of a character.
+      let split = Utf16Range {
+        start: pos(12),
+        end: pos(13),
+        filename,
+      };
+      split.to_span(tcx).unwrap_err();
+      let past_end = Utf16Range {
+        start: pos(0),
+        end: pos(40),
+        filename,
+      };
+      past_end.to_span(tcx).unwrap_err();
+    });
+  }
+
+  #[test]
+  fn test_serialized_range() {
+    let input = "fn main() {\n  let x = 1;\n}\n";
+
+    test_utils::CompileBuilder::new(input)
+      .with_args(["--remap-path-prefix=dummy.rs=/remapped/lib.rs".to_string()])
+      .compile(|CompileResult { tcx }| {
+        let source_map = tcx.sess.source_map();
+        let filename = Filename::intern("dummy.rs");
+        // Remapped files are still found by their local path.
+        let start = filename.find_source_file(source_map).unwrap().start_pos;
+        let lo = input.find("let").unwrap();
+        let span = Span::with_root_ctxt(
+          start + rustc_span::BytePos(lo as u32),
+          start + rustc_span::BytePos(lo as u32 + 3),
+        );
+        let char_range = CharRange::from_span(span, source_map).unwrap();
+        assert_eq!(char_range.filename, filename);
+
+        let path = |remapping: &PathRemapping| {
+          char_range
+            .to_serialized(source_map, remapping)
+            .unwrap()
+            .path
+        };
+        assert_eq!(path(&PathRemapping::Unchanged), "dummy.rs");
+        assert_eq!(path(&PathRemapping::RemapPathPrefix), "/remapped/lib.rs");
+        let absolute = path(&PathRemapping::Absolute);
+        assert!(
+          Path::new(&absolute).is_absolute() && absolute.ends_with("/dummy.rs"),
+          "{absolute}"
+        );
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(path(&PathRemapping::RelativeTo(cwd)), "dummy.rs");
+        let elsewhere = PathRemapping::RelativeTo(PathBuf::from("/nonexistent"));
+        assert_eq!(path(&elsewhere), absolute);
+
+        let serialized = char_range
+          .to_serialized(source_map, &PathRemapping::Unchanged)
+          .unwrap();
+        assert_eq!(serialized, SerializedRange {
+          path: "dummy.rs".into(),
+          start: CharPos { line: 1, column: 2 },
+          end: CharPos { line: 1, column: 5 },
+        });
+        let byte_range = ByteRange::from_span(span, source_map)
+          .unwrap()
+          .to_serialized(source_map, &PathRemapping::Unchanged)
+          .unwrap();
+        assert_eq!(
+          (byte_range.start, byte_range.end),
+          (BytePos(lo), BytePos(lo + 3))
+        );
+      });
+  }
 }
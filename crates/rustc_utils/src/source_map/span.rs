@@ -1,8 +1,27 @@
 use std::cmp;
 
 use log::trace;
+use rustc_hir::def_id::DefId;
+use rustc_lexer::TokenKind;
 use rustc_middle::ty::TyCtxt;
-use rustc_span::{source_map::SourceMap, BytePos, Pos, Span, SpanData, SyntaxContext};
+use rustc_span::{
+  hygiene::ExpnKind, source_map::SourceMap, BytePos, Pos, Span, SpanData, SyntaxContext,
+};
+
+/// A macro expansion or desugaring that a span came from, see
+/// [`SpanExt::to_call_site_chain`].
+#[derive(Debug, Clone)]
+pub struct ExpansionFrame {
+  /// The kind of the expansion.
+  pub kind: ExpnKind,
+  /// A description of the expansion as it's written, e.g. `vec!`, `#[derive(Clone)]`
+  /// or `desugaring of `?``.
+  pub name: String,
+  /// The span of the invocation, e.g. `vec![1, 2]`.
+  pub call_site: Span,
+  /// The definition of the macro, if it has one.
+  pub macro_def_id: Option<DefId>,
+}
 
 /// Extension trait for [`Span`].
 pub trait SpanExt {
@@ -35,11 +54,41 @@ pub trait SpanExt {
   /// Returns `None` if [`SourceMap::span_to_snippet`] fails.
   fn trim_leading_whitespace(&self, source_map: &SourceMap) -> Option<Vec<Span>>;
 
+  /// Returns `self` without leading and trailing whitespace.
+  ///
+  /// Returns `None` if [`SourceMap::span_to_snippet`] fails or `self` only contains
+  /// whitespace.
+  fn trim_whitespace(&self, source_map: &SourceMap) -> Option<Span>;
+
+  /// Returns `self` without leading and trailing whitespace and comments, and without
+  /// trailing commas and semicolons, e.g. to highlight the span of a statement or
+  /// field in an editor. Doc comments are kept, since they're attributes.
+  ///
+  /// Returns `None` if [`SourceMap::span_to_snippet`] fails or nothing is left.
+  fn trim_trivia(&self, source_map: &SourceMap) -> Option<Span>;
+
   /// Returns a pretty debug representation of a span.
   fn to_string(&self, tcx: TyCtxt<'_>) -> String;
 
   /// Returns the size (in bytes) of the spanned text.
   fn size(&self) -> u32;
+
+  /// Returns the expansions that produced `self`, from the innermost to the outermost,
+  /// or an empty vector if `self` is not from an expansion.
+  ///
+  /// For example, a span within the expansion of `println!` has the frames
+  /// `$crate::format_args_nl!` and `println!`, where the call site of the latter is the
+  /// `println!(..)` written by the user. Recursive invocations of a macro are listed
+  /// once.
+  fn to_call_site_chain(&self) -> Vec<ExpansionFrame>;
+
+  /// Returns the span that a diagnostic about `self` should point to.
+  ///
+  /// The chain of call sites is followed until a span that the user wrote, i.e. one
+  /// that isn't from an expansion, that's from a desugaring of the user's code like
+  /// `?` or `for`, or that's within a macro defined in the local crate. So code
+  /// generated by `vec![]` or `#[derive(Clone)]` points to the invocation.
+  fn to_user_facing(&self) -> Span;
 }
 
 impl SpanExt for Span {
@@ -162,6 +211,74 @@ impl SpanExt for Span {
     }
     Some(spans)
   }
+
+  fn trim_whitespace(&self, source_map: &SourceMap) -> Option<Span> {
+    trim_tokens(*self, source_map, false)
+  }
+
+  fn trim_trivia(&self, source_map: &SourceMap) -> Option<Span> {
+    trim_tokens(*self, source_map, true)
+  }
+
+  fn to_call_site_chain(&self) -> Vec<ExpansionFrame> {
+    self
+      .macro_backtrace()
+      .map(|data| ExpansionFrame {
+        name: data.kind.descr(),
+        kind: data.kind,
+        call_site: data.call_site,
+        macro_def_id: data.macro_def_id,
+      })
+      .collect()
+  }
+
+  fn to_user_facing(&self) -> Span {
+    let mut span = *self;
+    while span.from_expansion() {
+      let data = span.ctxt().outer_expn_data();
+      let is_user_code = match data.kind {
+        ExpnKind::Desugaring(_) => true,
+        ExpnKind::Macro(..) => data.macro_def_id.is_some_and(|def_id| def_id.is_local()),
+        ExpnKind::Root | ExpnKind::AstPass(_) => false,
+      };
+      if is_user_code {
+        break;
+      }
+      span = data.call_site;
+    }
+    span
+  }
+}
+
+// Shrinks `span` to its first and last tokens that aren't whitespace, or with `trivia`
+// also aren't comments or trailing punctuation.
+fn trim_tokens(span: Span, source_map: &SourceMap, trivia: bool) -> Option<Span> {
+  let snippet = source_map.span_to_snippet(span).ok()?;
+  let is_trimmed = |kind: &TokenKind, trailing: bool| match kind {
+    TokenKind::Whitespace => true,
+    TokenKind::LineComment { doc_style: None }
+    | TokenKind::BlockComment {
+      doc_style: None, ..
+    } => trivia,
+    TokenKind::Comma | TokenKind::Semi => trivia && trailing,
+    _ => false,
+  };
+
+  let mut offset = 0;
+  let tokens = rustc_lexer::tokenize(&snippet)
+    .map(|token| {
+      let start = offset;
+      offset += token.len;
+      (token.kind, start, offset)
+    })
+    .collect::<Vec<_>>();
+  let (_, lo, _) = tokens.iter().find(|(kind, ..)| !is_trimmed(kind, false))?;
+  let (_, _, hi) = tokens.iter().rfind(|(kind, ..)| !is_trimmed(kind, true))?;
+  (lo < hi).then(|| {
+    span
+      .with_lo(span.lo() + BytePos(*lo))
+      .with_hi(span.lo() + BytePos(*hi))
+  })
 }
 
 /// Extension trait for [`SpanData`].
@@ -178,9 +295,11 @@ impl SpanDataExt for SpanData {
 
 #[cfg(test)]
 mod test {
+  use rustc_middle::mir::Body;
   use rustc_span::BytePos;
 
   use super::*;
+  use crate::{source_map::filename::Filename, test_utils::CompileBuilder, BodyExt};
 
   #[test]
   fn test_span_subtract() {
@@ -192,4 +311,126 @@ mod test {
       assert_eq!(outer.subtract(inner), desired);
     });
   }
+
+  #[test]
+  fn test_call_site_chain() {
+    let input = r#"
+macro_rules! double { ($e:expr) => { $e * 2 } }
+#[derive(Clone)]
+struct S;
+fn main() {
+  let v = vec![1, 2];
+  println!("{}", v.len());
+  for _ in 0 .. double!(1) {}
+}
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let tcx = result.tcx;
+      let source_map = tcx.sess.source_map();
+      let body_of = |name: &str| {
+        let def_id = tcx
+          .hir()
+          .body_owners()
+          .find(|def_id| tcx.def_path_str(*def_id) == name)
+          .unwrap();
+        tcx.optimized_mir(def_id)
+      };
+      // The names of the chain and the user-facing snippet of each span in a body.
+      let frames = |body: &Body<'_>| {
+        body
+          .all_locations()
+          .map(|location| {
+            let span = body.source_info(location).span;
+            let names = span
+              .to_call_site_chain()
+              .into_iter()
+              .map(|frame| frame.name)
+              .collect::<Vec<_>>();
+            let user_facing = span.to_user_facing();
+            (
+              names,
+              source_map.span_to_snippet(user_facing).unwrap(),
+              user_facing == span,
+            )
+          })
+          .collect::<Vec<_>>()
+      };
+
+      let main = frames(body_of("main"));
+      let has = |names: &[&str], snippet: &str, unchanged: bool| {
+        main.iter().any(|(other, other_snippet, other_unchanged)| {
+          other == names && other_snippet == snippet && *other_unchanged == unchanged
+        })
+      };
+      assert!(has(&[], "v", true));
+      assert!(has(&["vec!"], "vec![1, 2]", false));
+      assert!(main.iter().any(|(names, snippet, _)| {
+        names.last().is_some_and(|name| name == "println!")
+          && snippet == r#"println!("{}", v.len())"#
+      }));
+      assert!(has(
+        &["desugaring of `for` loop"],
+        "for _ in 0 .. double!(1) {}",
+        true
+      ));
+      // Code from a local macro points into its definition.
+      assert!(has(&["double!"], "$e * 2", true));
+
+      let clone = frames(body_of("<S as std::clone::Clone>::clone"));
+      assert!(clone.iter().all(|(names, snippet, _)| {
+        names == &["#[derive(Clone)]"] && snippet == "Clone"
+      }));
+    });
+  }
+
+  #[test]
+  fn test_trim_trivia() {
+    let input = r#"fn main() {
+  let x = 1; // one
+  /* two */ let y = (x, 2,) ;
+}
+
+/// Docs.
+fn f() {}
+"#;
+    CompileBuilder::new(input).compile(|result| {
+      let source_map = result.tcx.sess.source_map();
+      let start = Filename::intern("dummy.rs")
+        .find_source_file(source_map)
+        .unwrap()
+        .start_pos;
+      let span_of = |text: &str| {
+        let lo = input.find(text).unwrap() as u32;
+        Span::with_root_ctxt(start + BytePos(lo), start + BytePos(lo + text.len() as u32))
+      };
+      let snippet =
+        |span: Option<Span>| span.map(|span| source_map.span_to_snippet(span).unwrap());
+
+      let line = span_of("  let x = 1; // one\n");
+      assert_eq!(snippet(line.trim_trivia(source_map)).unwrap(), "let x = 1");
+      assert_eq!(
+        snippet(line.trim_whitespace(source_map)).unwrap(),
+        "let x = 1; // one"
+      );
+
+      let line = span_of("  /* two */ let y = (x, 2,) ;\n");
+      assert_eq!(
+        snippet(line.trim_trivia(source_map)).unwrap(),
+        "let y = (x, 2,)"
+      );
+
+      let item = span_of("\n/// Docs.\nfn f() {}\n");
+      assert_eq!(
+        snippet(item.trim_trivia(source_map)).unwrap(),
+        "/// Docs.\nfn f() {}"
+      );
+
+      let blank = span_of(" // one\n");
+      assert_eq!(blank.trim_trivia(source_map), None);
+      assert_eq!(
+        snippet(blank.trim_whitespace(source_map)).unwrap(),
+        "// one"
+      );
+    });
+  }
 }
@@ -16,7 +16,7 @@ struct ChildExprSpans {
   spans: Vec<Span>,
   item_span: Span,
 }
-impl<'hir> HirVisitor<'hir> for ChildExprSpans {
+impl HirVisitor<'_> for ChildExprSpans {
   fn visit_expr(&mut self, ex: &hir::Expr) {
     match ex.kind {
       // Don't take the span for the whole block, since we want to leave
@@ -84,7 +84,7 @@ macro_rules! try_span {
   };
 }
 
-impl<'tcx> Spanner<'tcx> {
+impl Spanner<'_> {
   pub fn hir_spans(&self, id: HirId, mode: EnclosingHirSpans) -> Option<Vec<Span>> {
     let hir = self.tcx.hir();
     let span = try_span!(self, hir.span(id));
@@ -20,7 +20,7 @@ use crate::{mir::location_or_arg::LocationOrArg, BodyExt, SpanDataExt, SpanExt};
 
 mod hir_span;
 mod mir_span;
-mod span_tree;
+pub(super) mod span_tree;
 
 /// Converts MIR locations to source spans using HIR information.
 pub struct Spanner<'tcx> {
@@ -284,7 +284,7 @@ mod test {
         &["w.0"],
         &["w.0"],
       ];
-      for (input_span, desired) in spans.into_iter().zip(expected.into_iter()) {
+      for (input_span, desired) in spans.into_iter().zip(expected.iter()) {
         let outputs = spanner.span_to_places(input_span);
         let snippets = outputs
           .into_iter()
@@ -15,6 +15,7 @@ use rustc_hir::{BodyId, ItemKind};
 use rustc_middle::{
   mir::{Body, HasLocalDecls, Local, Place},
   ty::TyCtxt,
+  util::Providers,
 };
 use rustc_span::source_map::FileLoader;
 use rustc_target::abi::{FieldIdx, VariantIdx};
@@ -73,6 +74,7 @@ thread_local! {
 pub struct CompileBuilder {
   input: String,
   arguments: Vec<String>,
+  override_queries: fn(&rustc_session::Session, &mut Providers),
 }
 
 impl CompileBuilder {
@@ -81,6 +83,7 @@ impl CompileBuilder {
     Self {
       input: input.into(),
       arguments: vec![],
+      override_queries: borrowck_facts::override_queries,
     }
   }
 
@@ -90,11 +93,22 @@ impl CompileBuilder {
     self
   }
 
+  /// Replace the query overrides of the compilation, which are
+  /// [`borrowck_facts::override_queries`] by default.
+  pub fn with_override_queries(
+    &mut self,
+    override_queries: fn(&rustc_session::Session, &mut Providers),
+  ) -> &mut Self {
+    self.override_queries = override_queries;
+    self
+  }
+
   /// Perform the compilation, providing access to it's intermediates state to
   /// the provided closure
   pub fn compile(&self, f: impl for<'tcx> FnOnce(CompileResult<'tcx>) + Send) {
     let mut callbacks = TestCallbacks {
       callback: Some(move |tcx: TyCtxt<'_>| f(CompileResult { tcx })),
+      override_queries: self.override_queries,
     };
     let args = [
       "rustc",
@@ -184,6 +198,7 @@ impl<'tcx> CompileResult<'tcx> {
 
 struct TestCallbacks<Cb> {
   callback: Option<Cb>,
+  override_queries: fn(&rustc_session::Session, &mut Providers),
 }
 
 impl<Cb> rustc_driver::Callbacks for TestCallbacks<Cb>
@@ -191,7 +206,7 @@ where
   Cb: FnOnce(TyCtxt<'_>),
 {
   fn config(&mut self, config: &mut rustc_interface::Config) {
-    config.override_queries = Some(borrowck_facts::override_queries);
+    config.override_queries = Some(self.override_queries);
   }
 
   fn after_expansion<'tcx>(
@@ -350,7 +365,7 @@ pub struct PlaceBuilder<'a, 'tcx> {
   place: Place<'tcx>,
 }
 
-impl<'a, 'tcx> PlaceBuilder<'a, 'tcx> {
+impl<'tcx> PlaceBuilder<'_, 'tcx> {
   pub fn field(mut self, i: usize) -> Self {
     let f = FieldIdx::from_usize(i);
     let ty = self